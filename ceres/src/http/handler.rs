@@ -9,13 +9,13 @@ use anyhow::Result;
 use axum::body::Body;
 use axum::http::response::Builder;
 use axum::http::{Request, Response, StatusCode};
-use bytes::{Bytes, BytesMut};
+use bytes::BytesMut;
 use futures::TryStreamExt;
-use tokio::io::AsyncReadExt;
 
 use common::model::GetParams;
 
-use crate::protocol::{smart, SmartProtocol, ServiceType};
+use crate::protocol::side_band::SideBand;
+use crate::protocol::{pktline, SmartProtocol, ServiceType};
 
 // # Discovering Reference
 // HTTP clients that support the "smart" protocol (or both the "smart" and "dumb" protocols) MUST
@@ -30,7 +30,10 @@ pub async fn git_info_refs(
     let service_name = params.service.unwrap();
     pack_protocol.service_type = service_name.parse::<ServiceType>().unwrap();
     let resp = build_res_header(format!("application/x-{}-advertisement", service_name));
-    let pkt_line_stream = pack_protocol.git_info_refs().await;
+    let pkt_line_stream = pack_protocol
+        .git_info_refs()
+        .await
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
     let body = Body::from(pkt_line_stream.freeze());
     Ok(resp.body(body).unwrap())
 }
@@ -81,21 +84,17 @@ pub async fn git_upload_pack(
 
     tracing::info!("send response");
 
-    let mut reader = send_pack_data.as_slice();
-    loop {
-        let mut temp = BytesMut::new();
-        temp.reserve(65500);
-        let length = reader.read_buf(&mut temp).await.unwrap();
-        if length == 0 {
-            let bytes_out = Bytes::from_static(smart::PKT_LINE_END_MARKER);
-            tracing::info!("send back pkt-flush line '0000', actually: {:?}", bytes_out);
-            res_bytes.extend(bytes_out);
-            break;
+    match SideBand::new(&pack_protocol.capabilities) {
+        Some(mut side_band) => {
+            side_band.pack(&send_pack_data);
+            res_bytes.extend(side_band.finish());
+        }
+        None => {
+            res_bytes.extend(send_pack_data);
+            res_bytes.extend(pktline::encode_flush());
         }
-        let bytes_out = pack_protocol.build_side_band_format(temp, length);
-        tracing::info!("send pack file: length: {:?}", bytes_out.len());
-        res_bytes.extend(bytes_out);
     }
+    tracing::info!("send pack file: length: {:?}", res_bytes.len());
     let body = Body::from(res_bytes.freeze());
     let resp = resp.body(body).unwrap();
     Ok(resp)
@@ -4,6 +4,7 @@ use jupiter::{context::Context, raw_storage::RawStorage};
 
 pub mod handler;
 pub mod lfs_structs;
+pub mod pointer;
 
 #[derive(Clone)]
 pub struct LfsConfig {
@@ -0,0 +1,115 @@
+//! Git LFS pointer files.
+//!
+//! A file tracked by LFS is committed as a small pointer blob, the content itself is uploaded to
+//! the LFS store separately. A pointer looks like:
+//!
+//! ```text
+//! version https://git-lfs.github.com/spec/v1
+//! oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393
+//! size 12345
+//! ```
+use venus::internal::object::types::ObjectType;
+use venus::internal::pack::entry::Entry;
+
+const VERSION_LINE: &str = "version https://git-lfs.github.com/spec/v1";
+
+/// Pointers are tiny, larger blobs aren't even parsed.
+const MAX_POINTER_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LfsPointer {
+    /// The hex encoded SHA-256 of the content, without the `sha256:` prefix.
+    pub oid: String,
+    pub size: i64,
+}
+
+impl LfsPointer {
+    /// Parse `data` as an LFS pointer, `None` if it is ordinary content.
+    pub fn parse(data: &[u8]) -> Option<LfsPointer> {
+        if data.len() > MAX_POINTER_SIZE {
+            return None;
+        }
+        let content = std::str::from_utf8(data).ok()?;
+        let mut lines = content.lines();
+        if lines.next()? != VERSION_LINE {
+            return None;
+        }
+        let (mut oid, mut size) = (None, None);
+        for line in lines {
+            match line.split_once(' ')? {
+                ("oid", value) => {
+                    let hash = value.strip_prefix("sha256:")?;
+                    if hash.len() != 64 || !hash.bytes().all(|x| x.is_ascii_hexdigit()) {
+                        return None;
+                    }
+                    oid = Some(hash.to_ascii_lowercase());
+                }
+                ("size", value) => size = Some(value.parse::<i64>().ok()?),
+                // extension keys like `ext-0-foo` are allowed
+                _ => {}
+            }
+        }
+        Some(LfsPointer {
+            oid: oid?,
+            size: size?,
+        })
+    }
+}
+
+/// The LFS pointers among the blobs in `entries`.
+pub fn find_pointers(entries: &[Entry]) -> Vec<LfsPointer> {
+    entries
+        .iter()
+        .filter(|x| x.obj_type == ObjectType::Blob)
+        .filter_map(|x| LfsPointer::parse(&x.data))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use venus::internal::object::blob::Blob;
+    use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+    use venus::internal::pack::entry::Entry;
+
+    use super::{find_pointers, LfsPointer};
+
+    const OID: &str = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+
+    fn pointer_content(size: &str) -> String {
+        format!(
+            "version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n",
+            OID, size
+        )
+    }
+
+    #[test]
+    fn test_parse_pointer() {
+        let pointer = LfsPointer::parse(pointer_content("12345").as_bytes()).unwrap();
+        assert_eq!(pointer.oid, OID);
+        assert_eq!(pointer.size, 12345);
+
+        assert!(LfsPointer::parse(pointer_content("large").as_bytes()).is_none());
+        assert!(LfsPointer::parse(b"version 1\nsize 10\n").is_none());
+        assert!(LfsPointer::parse(b"fn main() {}\n").is_none());
+    }
+
+    #[test]
+    fn test_find_pointers_in_pushed_tree() {
+        let pointer = Blob::from_content(&pointer_content("2048"));
+        let source = Blob::from_content("fn main() {}\n");
+        let tree = Tree::from_tree_items(vec![
+            TreeItem::new(TreeItemMode::Blob, pointer.id, "model.bin".to_owned()),
+            TreeItem::new(TreeItemMode::Blob, source.id, "main.rs".to_owned()),
+        ])
+        .unwrap();
+        let entries: Vec<Entry> = vec![tree.into(), pointer.into(), source.into()];
+
+        assert_eq!(
+            find_pointers(&entries),
+            vec![LfsPointer {
+                oid: OID.to_owned(),
+                size: 2048,
+            }]
+        );
+    }
+}
@@ -3,3 +3,6 @@ pub mod lfs;
 pub mod protocol;
 pub mod pack;
 pub mod monorepo;
+
+#[cfg(test)]
+mod test_util;
@@ -0,0 +1,173 @@
+//! `.gitattributes` lookups for rendering diffs.
+//!
+//! Each line is a pattern followed by attributes, `name` sets an attribute, `-name` unsets it,
+//! `name=value` gives it a value and `!name` leaves it unspecified again. Of the lines matching a
+//! path, the last one mentioning an attribute decides it. Only the built-in `binary` macro,
+//! standing for `-diff -merge -text`, is expanded.
+use jupiter::storage::object_storage::ObjectStorage;
+use mercury::internal::blob::is_binary;
+use venus::errors::GitError;
+use venus::hash::SHA1;
+
+use crate::pack::sparse::glob_match;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeState {
+    Set,
+    Unset,
+    Value(String),
+}
+
+/// A single line, the attributes are `None` where unspecified.
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: String,
+    attributes: Vec<(String, Option<AttributeState>)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Attributes {
+    rules: Vec<Rule>,
+}
+
+impl Attributes {
+    pub fn parse(content: &str) -> Attributes {
+        let mut rules = Vec::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let Some(pattern) = fields.next() else {
+                continue;
+            };
+            let mut attributes = Vec::new();
+            for field in fields {
+                if field == "binary" {
+                    for name in ["diff", "merge", "text"] {
+                        attributes.push((name.to_owned(), Some(AttributeState::Unset)));
+                    }
+                }
+                let (name, state) = if let Some(name) = field.strip_prefix('-') {
+                    (name, Some(AttributeState::Unset))
+                } else if let Some(name) = field.strip_prefix('!') {
+                    (name, None)
+                } else if let Some((name, value)) = field.split_once('=') {
+                    (name, Some(AttributeState::Value(value.to_owned())))
+                } else {
+                    (field, Some(AttributeState::Set))
+                };
+                attributes.push((name.to_owned(), state));
+            }
+            rules.push(Rule {
+                pattern: pattern.to_owned(),
+                attributes,
+            });
+        }
+        Attributes { rules }
+    }
+
+    /// The state of the attribute `name` for the file `path`, `None` if unspecified.
+    pub fn get(&self, path: &str, name: &str) -> Option<&AttributeState> {
+        for rule in self.rules.iter().rev() {
+            if !pattern_matches(&rule.pattern, path) {
+                continue;
+            }
+            if let Some((_, state)) = rule.attributes.iter().rev().find(|(x, _)| x == name) {
+                return state.as_ref();
+            }
+        }
+        None
+    }
+}
+
+/// The attributes of the `.gitattributes` file at the root of the tree `tree`, empty if there is
+/// none. Files in subdirectories are not read.
+pub async fn root_attributes(
+    storage: &dyn ObjectStorage,
+    tree: SHA1,
+) -> Result<Attributes, GitError> {
+    let tree = storage
+        .get_tree(&tree)
+        .await?
+        .ok_or_else(|| GitError::NotFountHashValue(tree.to_plain_str()))?;
+    let Some(item) = tree
+        .tree_items
+        .iter()
+        .find(|x| x.name == ".gitattributes" && x.mode.is_blob())
+    else {
+        return Ok(Attributes::default());
+    };
+    let blob = storage
+        .get_blob(&item.id)
+        .await?
+        .ok_or_else(|| GitError::NotFountHashValue(item.id.to_plain_str()))?;
+    Ok(Attributes::parse(&String::from_utf8_lossy(&blob.data)))
+}
+
+/// A pattern without a slash matches the file name at any depth, otherwise it matches the whole
+/// path from the root.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    if pattern.contains('/') {
+        let pattern = pattern.trim_start_matches('/');
+        glob_match(pattern.as_bytes(), path.as_bytes())
+    } else {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        glob_match(pattern.as_bytes(), name.as_bytes())
+    }
+}
+
+/// Whether `data` at `path` is shown as a binary file in diffs. An unset `diff` attribute (or
+/// `binary`) forces binary, a set `diff` attribute or a diff driver forces text. Without either
+/// the file is binary if its start contains a NUL byte, like git guesses.
+pub fn is_binary_for_diff(path: &str, data: &[u8], attributes: &Attributes) -> bool {
+    match attributes.get(path, "diff") {
+        Some(AttributeState::Unset) => true,
+        Some(AttributeState::Set | AttributeState::Value(_)) => false,
+        None => is_binary(data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_binary_for_diff, AttributeState, Attributes};
+
+    #[test]
+    fn test_attribute_forces_binary() {
+        let attributes = Attributes::parse("*.svg binary\nassets/*.json -diff\n");
+        assert!(is_binary_for_diff("logo.svg", b"<svg/>", &attributes));
+        assert!(is_binary_for_diff("assets/data.json", b"{}", &attributes));
+        assert!(!is_binary_for_diff("data.json", b"{}", &attributes));
+        assert_eq!(
+            attributes.get("img/logo.svg", "text"),
+            Some(&AttributeState::Unset)
+        );
+    }
+
+    #[test]
+    fn test_attribute_forces_text() {
+        let attributes =
+            Attributes::parse("*.pdf diff=pdf\n*.dat diff\n*.bin binary\n*.bin !diff\n");
+        assert!(!is_binary_for_diff("manual.pdf", b"%PDF\0", &attributes));
+        assert!(!is_binary_for_diff("data/table.dat", b"\0\0", &attributes));
+        // a later `!diff` falls back to the heuristic
+        assert!(is_binary_for_diff("firmware.bin", b"\0", &attributes));
+        assert!(!is_binary_for_diff("firmware.bin", b"text", &attributes));
+    }
+
+    #[test]
+    fn test_heuristic_fallback() {
+        let attributes = Attributes::default();
+        assert!(!is_binary_for_diff("README.md", b"# readme\n", &attributes));
+        assert!(is_binary_for_diff(
+            "image.png",
+            b"\x89PNG\r\n\x1a\n\0\0",
+            &attributes
+        ));
+        // a NUL byte past the probed start doesn't count
+        let mut data = vec![b'a'; 9000];
+        data.push(0);
+        assert!(!is_binary_for_diff("large.txt", &data, &attributes));
+    }
+}
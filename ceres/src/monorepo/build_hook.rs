@@ -0,0 +1,172 @@
+//! Building a project once an MR of it merged.
+//!
+//! The hook only hands the monorepo path of the project to a [`BuildQueue`] and records the id it
+//! gets back on the MR, the merge doesn't wait for the build. The build itself is up to the queue,
+//! e.g. a command wrapping `bazel_build::build` of the build tool, which clones the project at that
+//! path and builds it with bazel. The queue reports how the build went as the [`BuildStatus`] of
+//! the MR.
+use std::env;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+
+use tokio::process::Command;
+
+use callisto::db_enums::BuildState;
+use common::errors::MegaError;
+use common::utils::generate_id;
+use jupiter::storage::mega_storage::MegaStorage;
+use venus::monorepo::mr::BuildStatus;
+
+/// Starts builds of monorepo projects.
+pub trait BuildQueue: Send + Sync {
+    /// Start a build of the project at `repo_path`, e.g. `/project/mega`, merged by the MR
+    /// `mr_id` in the background and return the id of the build.
+    fn enqueue(&self, mr_id: i64, repo_path: PathBuf) -> String;
+}
+
+/// Runs `<program> <repo_path>` for each build, with the build id in `MEGA_BUILD_ID`. The build
+/// failed if the command exits with an error, and the first line it prints is the url of its log.
+#[derive(Clone)]
+pub struct CommandBuildQueue {
+    pub program: String,
+    pub storage: Arc<MegaStorage>,
+}
+
+impl CommandBuildQueue {
+    /// Run the build `build_id` of the MR `mr_id`, saving its status when it starts and when it
+    /// finishes. Returns how the build went.
+    pub async fn run(
+        &self,
+        mr_id: i64,
+        build_id: String,
+        repo_path: PathBuf,
+    ) -> Result<BuildState, MegaError> {
+        let mut status = BuildStatus::pending(mr_id, build_id);
+        self.storage.save_build_status(status.clone()).await?;
+
+        let output = Command::new(&self.program)
+            .arg(&repo_path)
+            .env("MEGA_BUILD_ID", &status.build_id)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .await;
+        status.state = match output {
+            Ok(output) => {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                status.log_url = stdout
+                    .lines()
+                    .next()
+                    .map(|x| x.trim().to_owned())
+                    .filter(|x| !x.is_empty());
+                if output.status.success() {
+                    tracing::info!("build {} of {:?} succeeded", status.build_id, repo_path);
+                    BuildState::Success
+                } else {
+                    tracing::error!(
+                        "build {} of {:?} failed: {}",
+                        status.build_id,
+                        repo_path,
+                        output.status
+                    );
+                    BuildState::Failed
+                }
+            }
+            Err(err) => {
+                tracing::error!(
+                    "build {} of {:?} didn't start: {}",
+                    status.build_id,
+                    repo_path,
+                    err
+                );
+                BuildState::Failed
+            }
+        };
+        status.updated_at = chrono::Utc::now().naive_utc();
+        self.storage.save_build_status(status.clone()).await?;
+        Ok(status.state)
+    }
+}
+
+impl BuildQueue for CommandBuildQueue {
+    fn enqueue(&self, mr_id: i64, repo_path: PathBuf) -> String {
+        let id = generate_id().to_string();
+        let queue = self.clone();
+        let build_id = id.clone();
+        tokio::spawn(async move {
+            if let Err(err) = queue.run(mr_id, build_id.clone(), repo_path).await {
+                tracing::error!("can't save the status of build {}: {}", build_id, err);
+            }
+        });
+        id
+    }
+}
+
+/// The build queue of merged MRs, running the command in `MEGA_POST_MERGE_BUILD`. Unset means no
+/// builds after merges.
+pub fn build_queue(storage: Arc<MegaStorage>) -> Option<Arc<dyn BuildQueue>> {
+    let program = env::var("MEGA_POST_MERGE_BUILD").ok()?;
+    let program = program.trim();
+    if program.is_empty() {
+        return None;
+    }
+    Some(Arc::new(CommandBuildQueue {
+        program: program.to_owned(),
+        storage,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult};
+
+    use callisto::db_enums::BuildState;
+    use callisto::mega_mr_build;
+    use jupiter::storage::mega_storage::MegaStorage;
+    use venus::monorepo::mr::BuildStatus;
+
+    use super::CommandBuildQueue;
+    use crate::test_util::transaction_log;
+
+    #[tokio::test]
+    async fn test_failed_build_status() {
+        let saved = MockExecResult {
+            last_insert_id: 0,
+            rows_affected: 1,
+        };
+        let failed = BuildStatus {
+            state: BuildState::Failed,
+            ..BuildStatus::pending(1, "build-1".to_owned())
+        };
+        // the pending and the failed status, then reading it back
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results([saved.clone(), saved])
+            .append_query_results([vec![mega_mr_build::Model::from(failed.clone())]])
+            .into_connection();
+        let queue = CommandBuildQueue {
+            program: "false".to_owned(),
+            storage: Arc::new(MegaStorage {
+                connection: Arc::new(db),
+                ..MegaStorage::mock()
+            }),
+        };
+
+        let state = queue
+            .run(1, "build-1".to_owned(), PathBuf::from("/project/mega"))
+            .await
+            .unwrap();
+        assert_eq!(state, BuildState::Failed);
+        assert_eq!(
+            queue.storage.get_build_status(1).await.unwrap(),
+            Some(failed)
+        );
+
+        let log: Vec<String> = transaction_log(queue.storage);
+        assert!(log[0].contains("ON CONFLICT") && log[0].contains("\"pending\""));
+        assert!(log[1].contains("ON CONFLICT") && log[1].contains("\"failed\""));
+    }
+}
@@ -0,0 +1,617 @@
+//! Writing a tree out to a directory.
+//!
+//! The directories are created first, one after the other, so the blobs can then be loaded and
+//! written in parallel without racing on their parents. A checkout can be cancelled with its
+//! [`CancellationToken`], which stops it before the next blob is written.
+//!
+//! Symlinks are never followed while writing: a symlink already at the path of a directory or
+//! a file is replaced. Unless allowed by [`CheckoutOptions::allow_escaping_symlinks`], symlinks
+//! pointing outside of the checkout or through another symlink are rejected. Entries whose name
+//! could leave the directory they are written to, like `..`, are rejected before anything is
+//! written.
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::fs;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+use jupiter::storage::object_storage::ObjectStorage;
+use venus::errors::GitError;
+use venus::hash::SHA1;
+use venus::internal::object::tree::{TreeItem, TreeItemMode};
+
+use crate::monorepo::walk::{join_path, walk_batched};
+
+#[derive(Debug, Clone)]
+pub struct CheckoutOptions {
+    /// The most blobs loaded and written at the same time.
+    pub concurrency: usize,
+    /// Write symlinks with absolute targets outside of the destination, relative targets leaving
+    /// it through `..` or targets going through another symlink, which are rejected by default.
+    pub allow_escaping_symlinks: bool,
+}
+
+impl Default for CheckoutOptions {
+    fn default() -> Self {
+        CheckoutOptions {
+            concurrency: 16,
+            allow_escaping_symlinks: false,
+        }
+    }
+}
+
+/// Resolve the `.` and `..` components of `path` without looking at the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Whether the symlink at `link`, a slash separated path relative to `dest`, stays inside of
+/// `dest` when pointing at `target`. `is_link` tells whether a slash separated path relative to
+/// `dest` is another symlink: where a target going through one leads can't be told from the
+/// target alone, so such targets are rejected.
+pub fn is_safe_symlink(
+    dest: &Path,
+    link: &str,
+    target: &str,
+    is_link: impl Fn(&str) -> bool,
+) -> bool {
+    let target = Path::new(target);
+    // the path the target resolves to so far, below dest
+    let mut resolved: Vec<String>;
+    let rest = if target.has_root() {
+        resolved = Vec::new();
+        match target.strip_prefix(normalize(dest)) {
+            Ok(rest) => rest,
+            Err(_) => return false,
+        }
+    } else {
+        resolved = link
+            .split('/')
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_owned())
+            .collect();
+        resolved.pop();
+        target
+    };
+    let mut components = rest.components().peekable();
+    while let Some(component) = components.next() {
+        match component {
+            Component::Normal(name) => {
+                resolved.push(name.to_string_lossy().into_owned());
+                if components.peek().is_some() && is_link(&resolved.join("/")) {
+                    return false;
+                }
+            }
+            Component::ParentDir => {
+                if resolved.pop().is_none() {
+                    return false;
+                }
+            }
+            Component::CurDir => {}
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+/// Whether the tree entry name `name` names a file right in the directory it is written to.
+/// Names with a slash or NUL, `.`, `..` and `.git` in any case are rejected like git does.
+pub fn is_safe_entry_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains(['/', '\0'])
+        && name != "."
+        && name != ".."
+        && !name.eq_ignore_ascii_case(".git")
+}
+
+/// Create the directory `path` whose parent exists, replacing a symlink or a file in its way.
+async fn create_dir(path: &Path) -> Result<(), GitError> {
+    match fs::symlink_metadata(path).await {
+        Ok(metadata) if metadata.is_dir() => return Ok(()),
+        Ok(_) => fs::remove_file(path).await?,
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err.into()),
+    }
+    fs::create_dir(path).await?;
+    Ok(())
+}
+
+/// Write the tree `tree` into the directory `dest`, returning the number of files written.
+///
+/// Submodules become empty directories like in a git checkout. Files already in `dest` are
+/// overwritten, others are left alone. A tree entry with an unsafe name, see
+/// [`is_safe_entry_name`], fails the checkout with [`GitError::UnsafeEntryName`] before any file
+/// is written. If `cancel` is cancelled, the writes in flight are aborted
+/// and [`GitError::Cancelled`] is returned, leaving a partial checkout behind.
+pub async fn checkout_tree(
+    storage: Arc<dyn ObjectStorage>,
+    tree: SHA1,
+    dest: &Path,
+    options: &CheckoutOptions,
+    cancel: &CancellationToken,
+) -> Result<usize, GitError> {
+    let mut files = Vec::new();
+    let mut links = HashSet::new();
+    fs::create_dir_all(dest).await?;
+    // trees come parents first, so every parent is a real directory by the time it is used
+    for (prefix, tree) in walk_batched(storage.as_ref(), tree).await? {
+        for item in tree.tree_items {
+            let name = join_path(&prefix, &item.name);
+            if !is_safe_entry_name(&item.name) || !dest.join(&name).starts_with(dest) {
+                return Err(GitError::UnsafeEntryName(name));
+            }
+            match item.mode {
+                TreeItemMode::Tree | TreeItemMode::Commit => create_dir(&dest.join(&name)).await?,
+                _ => {
+                    if item.mode == TreeItemMode::Link {
+                        links.insert(name.clone());
+                    }
+                    files.push((name, item))
+                }
+            }
+        }
+    }
+    let symlink_root = (!options.allow_escaping_symlinks).then(|| dest.to_path_buf());
+    let links = Arc::new(links);
+
+    // dropping the JoinSet on an early return aborts the writes in flight
+    let mut tasks = JoinSet::new();
+    let concurrency = options.concurrency.max(1);
+    let count = files.len();
+    for (name, item) in files {
+        while tasks.len() >= concurrency {
+            join_next(&mut tasks).await?;
+        }
+        if cancel.is_cancelled() {
+            return Err(GitError::Cancelled);
+        }
+        tasks.spawn(write_blob(
+            storage.clone(),
+            dest.join(&name),
+            name,
+            item,
+            symlink_root.clone(),
+            links.clone(),
+            cancel.clone(),
+        ));
+    }
+    while !tasks.is_empty() {
+        join_next(&mut tasks).await?;
+    }
+    Ok(count)
+}
+
+/// Wait for the next write of `tasks`, propagating its error and panics.
+async fn join_next(tasks: &mut JoinSet<Result<(), GitError>>) -> Result<(), GitError> {
+    match tasks.join_next().await {
+        Some(Ok(result)) => result,
+        Some(Err(err)) => std::panic::resume_unwind(err.into_panic()),
+        None => Ok(()),
+    }
+}
+
+/// Write the blob of `item` to `path`, the checkout path `name` below the destination.
+/// Symlinks must stay in `symlink_root`, if any, without going through the symlinks `links` of
+/// the tree or one already in `symlink_root`.
+async fn write_blob(
+    storage: Arc<dyn ObjectStorage>,
+    path: PathBuf,
+    name: String,
+    item: TreeItem,
+    symlink_root: Option<PathBuf>,
+    links: Arc<HashSet<String>>,
+    cancel: CancellationToken,
+) -> Result<(), GitError> {
+    if cancel.is_cancelled() {
+        return Err(GitError::Cancelled);
+    }
+    let blob = storage
+        .get_blob(&item.id)
+        .await?
+        .ok_or_else(|| GitError::NotFountHashValue(item.id.to_plain_str()))?;
+    if cancel.is_cancelled() {
+        return Err(GitError::Cancelled);
+    }
+    // writing through a symlink left by an earlier checkout could land anywhere
+    if fs::symlink_metadata(&path)
+        .await
+        .is_ok_and(|x| x.is_symlink())
+    {
+        fs::remove_file(&path).await?;
+    }
+    if let (TreeItemMode::Link, Some(root)) = (item.mode, symlink_root) {
+        let target = String::from_utf8_lossy(&blob.data);
+        let is_link = |path: &str| {
+            links.contains(path)
+                || std::fs::symlink_metadata(root.join(path)).is_ok_and(|x| x.is_symlink())
+        };
+        if !is_safe_symlink(&root, &name, &target, is_link) {
+            return Err(GitError::UnsafeSymlink(name, target.into_owned()));
+        }
+    }
+    match item.mode {
+        #[cfg(unix)]
+        TreeItemMode::Link => {
+            let target = String::from_utf8_lossy(&blob.data).into_owned();
+            let _ = fs::remove_file(&path).await;
+            fs::symlink(target, &path).await?;
+        }
+        #[cfg(unix)]
+        mode @ (TreeItemMode::BlobExecutable
+        | TreeItemMode::BlobGroupWriteable
+        | TreeItemMode::BlobGroupReadable) => {
+            use std::os::unix::fs::PermissionsExt;
+
+            let permissions = match mode {
+                TreeItemMode::BlobExecutable => 0o755,
+                TreeItemMode::BlobGroupWriteable => 0o664,
+                _ => 0o640,
+            };
+            fs::write(&path, &blob.data).await?;
+            fs::set_permissions(&path, std::fs::Permissions::from_mode(permissions)).await?;
+        }
+        _ => fs::write(&path, &blob.data).await?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use tokio_util::sync::CancellationToken;
+
+    use common::errors::MegaError;
+    use jupiter::storage::object_storage::{MemoryStorage, ObjectStorage};
+    use venus::errors::GitError;
+    use venus::hash::SHA1;
+    use venus::internal::object::blob::Blob;
+    use venus::internal::object::commit::Commit;
+    use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+
+    use super::{checkout_tree, is_safe_entry_name, is_safe_symlink, CheckoutOptions};
+    use crate::monorepo::diff::{build_tree, FlatTree};
+
+    /// Cancels `cancel` once `limit` blobs have been loaded.
+    struct CancellingStorage {
+        inner: MemoryStorage,
+        loaded: AtomicUsize,
+        limit: usize,
+        cancel: CancellationToken,
+    }
+
+    #[async_trait]
+    impl ObjectStorage for CancellingStorage {
+        async fn get_commit(&self, hash: &SHA1) -> Result<Option<Commit>, MegaError> {
+            self.inner.get_commit(hash).await
+        }
+
+        async fn get_tree(&self, hash: &SHA1) -> Result<Option<Tree>, MegaError> {
+            self.inner.get_tree(hash).await
+        }
+
+        async fn get_blob(&self, hash: &SHA1) -> Result<Option<Blob>, MegaError> {
+            if self.loaded.fetch_add(1, Ordering::SeqCst) + 1 >= self.limit {
+                self.cancel.cancel();
+            }
+            self.inner.get_blob(hash).await
+        }
+
+        async fn save_commits(&self, commits: Vec<Commit>) -> Result<(), MegaError> {
+            self.inner.save_commits(commits).await
+        }
+
+        async fn save_trees(&self, trees: Vec<Tree>) -> Result<(), MegaError> {
+            self.inner.save_trees(trees).await
+        }
+    }
+
+    /// 10 directories of 10 files each.
+    fn many_files(storage: &MemoryStorage) -> SHA1 {
+        let mut flat = FlatTree::new();
+        for i in 0..10 {
+            for j in 0..10 {
+                let blob = Blob::from_content(&format!("file {} of dir {}\n", j, i));
+                let item = TreeItem::new(TreeItemMode::Blob, blob.id, String::new());
+                flat.insert(format!("dir{}/file{}.txt", i, j), item);
+                storage.insert_blob(blob);
+            }
+        }
+        let (root, trees) = build_tree(&flat).unwrap();
+        for tree in trees {
+            storage.insert_tree(tree);
+        }
+        root
+    }
+
+    fn count_files(dir: &std::path::Path) -> usize {
+        let mut count = 0;
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            count += if path.is_dir() { count_files(&path) } else { 1 };
+        }
+        count
+    }
+
+    #[tokio::test]
+    async fn test_checkout_many_files() {
+        let dir = env::temp_dir().join("mega_checkout_tree");
+        let _ = std::fs::remove_dir_all(&dir);
+        let storage = MemoryStorage::default();
+        let root = many_files(&storage);
+
+        let options = CheckoutOptions {
+            concurrency: 4,
+            ..Default::default()
+        };
+        let cancel = CancellationToken::new();
+        let written = checkout_tree(Arc::new(storage), root, &dir, &options, &cancel)
+            .await
+            .unwrap();
+        assert_eq!(written, 100);
+        assert_eq!(count_files(&dir), 100);
+        let content = std::fs::read_to_string(dir.join("dir3/file7.txt")).unwrap();
+        assert_eq!(content, "file 7 of dir 3\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cancel_checkout() {
+        let dir = env::temp_dir().join("mega_checkout_tree_cancelled");
+        let _ = std::fs::remove_dir_all(&dir);
+        let inner = MemoryStorage::default();
+        let root = many_files(&inner);
+        let cancel = CancellationToken::new();
+        let storage = CancellingStorage {
+            inner,
+            loaded: AtomicUsize::new(0),
+            limit: 10,
+            cancel: cancel.clone(),
+        };
+
+        let options = CheckoutOptions {
+            concurrency: 2,
+            ..Default::default()
+        };
+        let result = checkout_tree(Arc::new(storage), root, &dir, &options, &cancel).await;
+        assert!(matches!(result, Err(GitError::Cancelled)));
+        // the directories exist, but most files were never written
+        assert!(count_files(&dir) < 10);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A tree with `README.md` and the symlink `docs/link` pointing at `target`.
+    #[cfg(unix)]
+    fn tree_with_link(storage: &MemoryStorage, target: &str) -> SHA1 {
+        let readme = Blob::from_content("# mega\n");
+        let link = Blob::from_content(target);
+        let mut flat = FlatTree::new();
+        flat.insert(
+            "README.md".to_owned(),
+            TreeItem::new(TreeItemMode::Blob, readme.id, String::new()),
+        );
+        flat.insert(
+            "docs/link".to_owned(),
+            TreeItem::new(TreeItemMode::Link, link.id, String::new()),
+        );
+        storage.insert_blob(readme);
+        storage.insert_blob(link);
+        let (root, trees) = build_tree(&flat).unwrap();
+        for tree in trees {
+            storage.insert_tree(tree);
+        }
+        root
+    }
+
+    #[tokio::test]
+    async fn test_reject_unsafe_entry_name() {
+        assert!(is_safe_entry_name("main.rs"));
+        assert!(is_safe_entry_name(".gitignore"));
+        for name in ["..", ".", ".GIT", "a/../x", "/etc", "a\0b"] {
+            assert!(!is_safe_entry_name(name), "{}", name);
+        }
+
+        let dir = env::temp_dir().join("mega_checkout_unsafe_name");
+        let _ = std::fs::remove_dir_all(&dir);
+        let storage = MemoryStorage::default();
+        let blob = Blob::from_content("escaped");
+        let src = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            blob.id,
+            "..".to_owned(),
+        )])
+        .unwrap();
+        let root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Tree,
+            src.id,
+            "src".to_owned(),
+        )])
+        .unwrap();
+        let root_id = root.id;
+        storage.insert_blob(blob);
+        storage.insert_tree(src);
+        storage.insert_tree(root);
+
+        let cancel = CancellationToken::new();
+        let result = checkout_tree(
+            Arc::new(storage),
+            root_id,
+            &dir,
+            &CheckoutOptions::default(),
+            &cancel,
+        )
+        .await;
+        assert!(matches!(result, Err(GitError::UnsafeEntryName(name)) if name == "src/.."));
+        assert_eq!(count_files(&dir), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_is_safe_symlink() {
+        let dir = env::temp_dir().join("mega_checkout");
+        let no_links = |_: &str| false;
+        assert!(is_safe_symlink(&dir, "docs/link", "../README.md", no_links));
+        assert!(is_safe_symlink(
+            &dir,
+            "link",
+            "./docs/../README.md",
+            no_links
+        ));
+        assert!(!is_safe_symlink(&dir, "link", "/etc/passwd", no_links));
+        assert!(!is_safe_symlink(
+            &dir,
+            "docs/link",
+            "../docs/../../x",
+            no_links
+        ));
+        assert!(is_safe_symlink(
+            &dir,
+            "link",
+            &format!("{}/a/../b", dir.display()),
+            no_links
+        ));
+
+        // `d/up` points at dest itself, so going up from it leaves dest
+        let links = |path: &str| path == "d/up";
+        assert!(is_safe_symlink(&dir, "d/up", "..", links));
+        assert!(!is_safe_symlink(&dir, "esc", "d/up/..", links));
+        assert!(!is_safe_symlink(
+            &dir,
+            "esc",
+            &format!("{}/d/up/x", dir.display()),
+            links
+        ));
+        // pointing at another symlink is fine, that one is checked on its own
+        assert!(is_safe_symlink(&dir, "other", "d/up", links));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_reject_symlink_through_symlink() {
+        let dir = env::temp_dir().join("mega_checkout_symlink_through_symlink");
+        let _ = std::fs::remove_dir_all(&dir);
+        let storage = MemoryStorage::default();
+        let mut flat = FlatTree::new();
+        for (name, target) in [("d/up", ".."), ("esc", "d/up/..")] {
+            let blob = Blob::from_content(target);
+            flat.insert(
+                name.to_owned(),
+                TreeItem::new(TreeItemMode::Link, blob.id, String::new()),
+            );
+            storage.insert_blob(blob);
+        }
+        let (root, trees) = build_tree(&flat).unwrap();
+        for tree in trees {
+            storage.insert_tree(tree);
+        }
+
+        let cancel = CancellationToken::new();
+        let result = checkout_tree(
+            Arc::new(storage),
+            root,
+            &dir,
+            &CheckoutOptions::default(),
+            &cancel,
+        )
+        .await;
+        assert!(matches!(result, Err(GitError::UnsafeSymlink(name, _)) if name == "esc"));
+        assert!(std::fs::symlink_metadata(dir.join("esc")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_reject_escaping_symlink() {
+        let dir = env::temp_dir().join("mega_checkout_escaping_symlink");
+        let _ = std::fs::remove_dir_all(&dir);
+        let storage = Arc::new(MemoryStorage::default());
+        let root = tree_with_link(&storage, "../../etc/passwd");
+
+        let cancel = CancellationToken::new();
+        let result = checkout_tree(
+            storage.clone(),
+            root,
+            &dir,
+            &CheckoutOptions::default(),
+            &cancel,
+        )
+        .await;
+        match result {
+            Err(GitError::UnsafeSymlink(name, target)) => {
+                assert_eq!(name, "docs/link");
+                assert_eq!(target, "../../etc/passwd");
+            }
+            other => panic!("symlink wasn't rejected: {:?}", other),
+        }
+        assert!(std::fs::symlink_metadata(dir.join("docs/link")).is_err());
+
+        let options = CheckoutOptions {
+            allow_escaping_symlinks: true,
+            ..Default::default()
+        };
+        checkout_tree(storage, root, &dir, &options, &cancel)
+            .await
+            .unwrap();
+        assert!(std::fs::symlink_metadata(dir.join("docs/link"))
+            .unwrap()
+            .is_symlink());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_checkout_relative_symlink() {
+        let dir = env::temp_dir().join("mega_checkout_relative_symlink");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // a symlink left in the way of a directory is replaced, not followed
+        let outside = env::temp_dir().join("mega_checkout_relative_symlink_outside");
+        let _ = std::fs::remove_dir_all(&outside);
+        std::fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, dir.join("docs")).unwrap();
+        let storage = MemoryStorage::default();
+        let root = tree_with_link(&storage, "../README.md");
+
+        let cancel = CancellationToken::new();
+        let written = checkout_tree(
+            Arc::new(storage),
+            root,
+            &dir,
+            &CheckoutOptions::default(),
+            &cancel,
+        )
+        .await
+        .unwrap();
+        assert_eq!(written, 2);
+        assert!(std::fs::symlink_metadata(dir.join("docs"))
+            .unwrap()
+            .is_dir());
+        assert_eq!(
+            std::fs::read_link(dir.join("docs/link")).unwrap(),
+            std::path::Path::new("../README.md")
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("docs/link")).unwrap(),
+            "# mega\n"
+        );
+        assert_eq!(std::fs::read_dir(&outside).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_dir_all(&outside).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+//! Checking that tree items refer to objects of the type their mode claims.
+//!
+//! A crafted tree can give a blob the `Tree` mode or a tree a file mode, which breaks every walk
+//! over it later. Objects of the push are checked directly, the others have to be stored already.
+use std::collections::HashMap;
+
+use jupiter::storage::object_storage::ObjectStorage;
+use venus::errors::GitError;
+use venus::hash::SHA1;
+use venus::internal::object::tree::{Tree, TreeItemMode};
+use venus::internal::object::types::ObjectType;
+use venus::internal::object::ObjectTrait;
+use venus::internal::pack::entry::Entry;
+
+/// The object type an item of `mode` must refer to, `None` for submodule commits, which live in
+/// other repositories.
+pub(crate) fn expected_type(mode: TreeItemMode) -> Option<ObjectType> {
+    match mode {
+        TreeItemMode::Tree => Some(ObjectType::Tree),
+        TreeItemMode::Commit => None,
+        TreeItemMode::Blob
+        | TreeItemMode::BlobExecutable
+        | TreeItemMode::BlobGroupWriteable
+        | TreeItemMode::BlobGroupReadable
+        | TreeItemMode::Link => Some(ObjectType::Blob),
+    }
+}
+
+/// The octal mode as written in trees, like `40000` for trees.
+pub(crate) fn mode_name(mode: TreeItemMode) -> String {
+    String::from_utf8_lossy(mode.to_bytes()).into_owned()
+}
+
+/// Check every item of the trees in `entries` refers to an object of the type of its mode,
+/// either among `entries` or in `storage`.
+pub async fn check_tree_items(
+    storage: &dyn ObjectStorage,
+    entries: &[Entry],
+) -> Result<(), GitError> {
+    let mut check = TreeItemCheck::default();
+    for entry in entries {
+        check.add_object(entry.hash, entry.obj_type);
+        if entry.obj_type == ObjectType::Tree {
+            check.add_tree(&Tree::from_bytes(entry.data.clone(), entry.hash)?);
+        }
+    }
+    check.finish(storage).await
+}
+
+/// The objects of a push and the items of its trees, collected one object at a time to check
+/// the items in [`TreeItemCheck::finish`] once every object of the push is known.
+#[derive(Default)]
+pub struct TreeItemCheck {
+    types: HashMap<SHA1, ObjectType>,
+    /// The name and mode of an item for each object and the type the items expect it to have.
+    items: HashMap<(SHA1, ObjectType), (String, TreeItemMode)>,
+}
+
+impl TreeItemCheck {
+    pub fn add_object(&mut self, hash: SHA1, obj_type: ObjectType) {
+        self.types.insert(hash, obj_type);
+    }
+
+    pub fn add_tree(&mut self, tree: &Tree) {
+        for item in &tree.tree_items {
+            if let Some(expected) = expected_type(item.mode) {
+                self.items
+                    .entry((item.id, expected))
+                    .or_insert_with(|| (item.name.clone(), item.mode));
+            }
+        }
+    }
+
+    /// Check the items against the objects added, and against `storage` for the others.
+    pub async fn finish(self, storage: &dyn ObjectStorage) -> Result<(), GitError> {
+        // items referring to objects outside of the push
+        let mut outside = Vec::new();
+        for ((id, expected), (name, mode)) in self.items {
+            match self.types.get(&id) {
+                Some(&actual) if actual != expected => {
+                    return Err(GitError::TreeItemTypeMismatch(
+                        name,
+                        mode_name(mode),
+                        actual.to_string(),
+                    ));
+                }
+                Some(_) => {}
+                None => outside.push((id, expected, name, mode)),
+            }
+        }
+        if outside.is_empty() {
+            return Ok(());
+        }
+
+        let hashes: Vec<SHA1> = outside.iter().map(|x| x.0).collect();
+        let mut stored: HashMap<SHA1, ObjectType> = HashMap::new();
+        for tree in storage.get_trees(&hashes).await? {
+            stored.insert(tree.id, ObjectType::Tree);
+        }
+        for blob in storage.existing_blobs(&hashes).await? {
+            stored.insert(blob, ObjectType::Blob);
+        }
+        for (id, expected, name, mode) in outside {
+            match stored.get(&id) {
+                Some(&actual) if actual != expected => {
+                    return Err(GitError::TreeItemTypeMismatch(
+                        name,
+                        mode_name(mode),
+                        actual.to_string(),
+                    ));
+                }
+                Some(_) => {}
+                None => return Err(GitError::MissingTreeItemObject(name, id.to_plain_str())),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use jupiter::storage::object_storage::MemoryStorage;
+    use venus::errors::GitError;
+    use venus::internal::object::blob::Blob;
+    use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+
+    use super::check_tree_items;
+
+    #[tokio::test]
+    async fn test_stored_objects_are_checked() {
+        let storage = MemoryStorage::default();
+        let lib = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            Blob::from_content("pub fn f() {}").id,
+            "lib.rs".to_owned(),
+        )])
+        .unwrap();
+        let readme = Blob::from_content("readme");
+        storage.insert_tree(lib.clone());
+        storage.insert_blob(readme.clone());
+
+        // a push only sending the new root tree
+        let root = Tree::from_tree_items(vec![
+            TreeItem::new(TreeItemMode::Blob, readme.id, "README.md".to_owned()),
+            TreeItem::new(TreeItemMode::Tree, lib.id, "src".to_owned()),
+        ])
+        .unwrap();
+        check_tree_items(&storage, &[root.into()]).await.unwrap();
+
+        let root = Tree::from_tree_items(vec![
+            TreeItem::new(TreeItemMode::Blob, lib.id, "README.md".to_owned()),
+            TreeItem::new(TreeItemMode::Tree, readme.id, "src".to_owned()),
+        ])
+        .unwrap();
+        let result = check_tree_items(&storage, &[root.into()]).await;
+        assert!(matches!(result, Err(GitError::TreeItemTypeMismatch(..))));
+
+        let unknown = Blob::from_content("unknown");
+        let root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            unknown.id,
+            "unknown.txt".to_owned(),
+        )])
+        .unwrap();
+        match check_tree_items(&storage, &[root.into()]).await {
+            Err(GitError::MissingTreeItemObject(name, id)) => {
+                assert_eq!(name, "unknown.txt");
+                assert_eq!(id, unknown.id.to_plain_str());
+            }
+            other => panic!("missing object wasn't rejected: {:?}", other),
+        }
+    }
+}
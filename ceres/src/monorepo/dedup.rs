@@ -0,0 +1,199 @@
+//! Finding file contents stored once but checked out at several paths.
+//!
+//! Objects are content addressed, so a file copied into several directories, or moved between
+//! commits, is stored as a single blob. The report covers every commit reachable from the
+//! scanned tips and tells which contents are found at the most paths and how many bytes storing
+//! each copy would have taken.
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use jupiter::storage::object_storage::ObjectStorage;
+use venus::errors::GitError;
+use venus::hash::SHA1;
+use venus::internal::object::tree::{Tree, TreeItemMode};
+
+use crate::monorepo::history;
+use crate::monorepo::walk::join_path;
+
+/// The number of duplicated contents [`crate::pack::monorepo::MonoRepo::dedup_report`] reports.
+pub const TOP_DUPLICATES: usize = 20;
+
+/// A blob found at more than one path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicatedBlob {
+    pub hash: String,
+    pub size: usize,
+    /// Slash separated paths relative to the root trees of the commits, sorted.
+    pub paths: Vec<String>,
+}
+
+impl DuplicatedBlob {
+    /// The bytes the copies would take if each was stored, all but the first copy.
+    pub fn saved_bytes(&self) -> usize {
+        self.size * (self.paths.len() - 1)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DedupReport {
+    /// The number of commits scanned.
+    pub commits: usize,
+    /// The number of distinct files in the commits, by path and content. A file unchanged
+    /// between commits is counted once.
+    pub files: usize,
+    /// The number of distinct contents of the files.
+    pub blobs: usize,
+    /// The bytes saved by storing every duplicated content once.
+    pub saved_bytes: usize,
+    /// The duplicated contents saving the most bytes, most first.
+    pub top: Vec<DuplicatedBlob>,
+}
+
+/// Count the files of every commit reachable from `tips` by their content and report the `top`
+/// contents found at several paths. Trees are loaded a level at a time, a tree found at the same
+/// path in several commits only once. Only the sizes of duplicated blobs are loaded.
+pub async fn dedup_report(
+    storage: &dyn ObjectStorage,
+    tips: &[SHA1],
+    top: usize,
+) -> Result<DedupReport, GitError> {
+    let commits = history::reachable_commits(storage, tips).await?;
+    let mut walked: HashSet<(String, SHA1)> = HashSet::new();
+    let mut level: Vec<(String, SHA1)> = commits
+        .iter()
+        .map(|x| (String::new(), x.tree_id))
+        .filter(|x| walked.insert(x.clone()))
+        .collect();
+
+    let mut paths: HashMap<SHA1, BTreeSet<String>> = HashMap::new();
+    while !level.is_empty() {
+        let mut hashes: Vec<SHA1> = level.iter().map(|(_, id)| *id).collect();
+        hashes.sort();
+        hashes.dedup();
+        let trees: HashMap<SHA1, Tree> = storage
+            .get_trees(&hashes)
+            .await?
+            .into_iter()
+            .map(|x| (x.id, x))
+            .collect();
+
+        let mut next = Vec::new();
+        for (prefix, id) in level {
+            let tree = trees
+                .get(&id)
+                .ok_or_else(|| GitError::NotFountHashValue(id.to_plain_str()))?;
+            for item in &tree.tree_items {
+                let path = join_path(&prefix, &item.name);
+                if item.mode.is_blob() {
+                    paths.entry(item.id).or_default().insert(path);
+                } else if item.mode == TreeItemMode::Tree && walked.insert((path.clone(), item.id))
+                {
+                    next.push((path, item.id));
+                }
+            }
+        }
+        level = next;
+    }
+    let files = paths.values().map(BTreeSet::len).sum();
+    let blobs = paths.len();
+
+    let duplicated: Vec<SHA1> = paths
+        .iter()
+        .filter(|(_, x)| x.len() > 1)
+        .map(|(id, _)| *id)
+        .collect();
+    let sizes = storage.blob_sizes(&duplicated).await?;
+    let mut duplicated: Vec<DuplicatedBlob> = duplicated
+        .into_iter()
+        .map(|id| DuplicatedBlob {
+            hash: id.to_plain_str(),
+            size: sizes.get(&id).copied().unwrap_or(0),
+            paths: paths.remove(&id).unwrap_or_default().into_iter().collect(),
+        })
+        .collect();
+    duplicated.sort_by(|a, b| {
+        b.saved_bytes()
+            .cmp(&a.saved_bytes())
+            .then_with(|| a.hash.cmp(&b.hash))
+    });
+    let saved_bytes = duplicated.iter().map(DuplicatedBlob::saved_bytes).sum();
+    duplicated.truncate(top);
+
+    Ok(DedupReport {
+        commits: commits.len(),
+        files,
+        blobs,
+        saved_bytes,
+        top: duplicated,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use jupiter::storage::object_storage::MemoryStorage;
+    use venus::internal::object::blob::Blob;
+    use venus::internal::object::commit::Commit;
+    use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+
+    use super::dedup_report;
+
+    #[tokio::test]
+    async fn test_report_file_copied_into_several_directories() {
+        let storage = MemoryStorage::new();
+        let license = Blob::from_content(&"Permission is hereby granted ".repeat(40));
+        let readme = Blob::from_content("# mega");
+        let project = |name: &str| {
+            let main = Blob::from_content(&format!("fn {}() {{}}", name));
+            let tree = Tree::from_tree_items(vec![
+                TreeItem::new(TreeItemMode::Blob, license.id, "LICENSE".to_owned()),
+                TreeItem::new(TreeItemMode::Blob, main.id, "main.rs".to_owned()),
+            ])
+            .unwrap();
+            storage.insert_blob(main);
+            tree
+        };
+        let (api, cli, web) = (project("api"), project("cli"), project("web"));
+        let root = |projects: &[(&Tree, &str)]| {
+            let mut items = vec![TreeItem::new(
+                TreeItemMode::Blob,
+                readme.id,
+                "README.md".to_owned(),
+            )];
+            for (tree, name) in projects {
+                items.push(TreeItem::new(TreeItemMode::Tree, tree.id, name.to_string()));
+            }
+            Tree::from_tree_items(items).unwrap()
+        };
+        // the second commit adds a project, the third renames it
+        let first = root(&[(&api, "api"), (&cli, "cli")]);
+        let second = root(&[(&api, "api"), (&cli, "cli"), (&web, "web")]);
+        let third = root(&[(&api, "api"), (&cli, "cli"), (&web, "www")]);
+        let init = Commit::from_tree_id(first.id, vec![], "\ninit\n");
+        let add = Commit::from_tree_id(second.id, vec![init.id], "\nadd web\n");
+        let rename = Commit::from_tree_id(third.id, vec![add.id], "\nrename web\n");
+        let tip = rename.id;
+        let size = license.data.len();
+        for tree in [api, cli, web, first, second, third] {
+            storage.insert_tree(tree);
+        }
+        for commit in [init, add, rename] {
+            storage.insert_commit(commit);
+        }
+        storage.insert_blob(license.clone());
+        storage.insert_blob(readme);
+
+        let report = dedup_report(&storage, &[tip], 10).await.unwrap();
+        assert_eq!(report.commits, 3);
+        // README.md and two files in each of api, cli, web and www
+        assert_eq!(report.files, 9);
+        assert_eq!(report.blobs, 5);
+        assert_eq!(report.top.len(), 2);
+        assert_eq!(report.top[0].hash, license.id.to_plain_str());
+        assert_eq!(report.top[0].size, size);
+        assert_eq!(
+            report.top[0].paths,
+            vec!["api/LICENSE", "cli/LICENSE", "web/LICENSE", "www/LICENSE"]
+        );
+        assert_eq!(report.top[1].paths, vec!["web/main.rs", "www/main.rs"]);
+        assert_eq!(report.saved_bytes, 3 * size + report.top[1].size);
+    }
+}
@@ -0,0 +1,497 @@
+//! Path level diffs between monorepo trees.
+//!
+//! Trees are flattened into a map from full path to the blob entry at that path, which makes
+//! diffing two trees and applying a diff onto a third one plain map operations. [`build_tree`]
+//! turns such a map back into tree objects.
+use std::collections::BTreeMap;
+
+use jupiter::storage::object_storage::ObjectStorage;
+use venus::errors::GitError;
+use venus::hash::SHA1;
+use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+
+use crate::monorepo::attributes::{is_binary_for_diff, Attributes};
+use crate::monorepo::history::load_commit;
+use crate::monorepo::rename::{detect_renames, DiffEntry, DEFAULT_RENAME_LIMIT};
+use crate::monorepo::walk::{join_path, walk_batched};
+
+/// All non-tree entries of a tree, keyed by their slash separated path.
+pub type FlatTree = BTreeMap<String, TreeItem>;
+
+/// The change of a single path, `None` meaning the path doesn't exist on that side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    pub path: String,
+    pub old: Option<TreeItem>,
+    pub new: Option<TreeItem>,
+}
+
+impl FileChange {
+    /// The change which undoes this one.
+    pub fn reverse(self) -> FileChange {
+        FileChange {
+            path: self.path,
+            old: self.new,
+            new: self.old,
+        }
+    }
+}
+
+pub async fn flatten_tree(
+    storage: &dyn ObjectStorage,
+    tree_id: SHA1,
+) -> Result<FlatTree, GitError> {
+    let mut flat = FlatTree::new();
+    for (prefix, tree) in walk_batched(storage, tree_id).await? {
+        for item in tree.tree_items {
+            if item.mode != TreeItemMode::Tree {
+                flat.insert(join_path(&prefix, &item.name), item);
+            }
+        }
+    }
+    Ok(flat)
+}
+
+/// The changes turning `old` into `new`, ordered by path.
+pub fn diff_flat_trees(old: &FlatTree, new: &FlatTree) -> Vec<FileChange> {
+    let mut changes = Vec::new();
+    for (path, item) in old {
+        match new.get(path) {
+            Some(new_item) if new_item == item => {}
+            new_item => changes.push(FileChange {
+                path: path.clone(),
+                old: Some(item.clone()),
+                new: new_item.cloned(),
+            }),
+        }
+    }
+    for (path, item) in new {
+        if !old.contains_key(path) {
+            changes.push(FileChange {
+                path: path.clone(),
+                old: None,
+                new: Some(item.clone()),
+            });
+        }
+    }
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+/// The changes from commit `from` to commit `to`, ordered by path. With a `rename_threshold`,
+/// deleted and added files at least that many percent similar are reported as renames.
+pub async fn commit_diff(
+    storage: &dyn ObjectStorage,
+    from: &SHA1,
+    to: &SHA1,
+    rename_threshold: Option<u8>,
+) -> Result<Vec<DiffEntry>, GitError> {
+    let old = flatten_tree(storage, load_commit(storage, from).await?.tree_id).await?;
+    let new = flatten_tree(storage, load_commit(storage, to).await?.tree_id).await?;
+    let changes = diff_flat_trees(&old, &new);
+    match rename_threshold {
+        Some(threshold) => detect_renames(storage, changes, threshold, DEFAULT_RENAME_LIMIT).await,
+        None => Ok(changes.into_iter().map(DiffEntry::Changed).collect()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+/// The paths of the non-tree entries which differ between the trees `old` and `new`, ordered by
+/// path, like `git diff --name-status`. Subtrees with the same id on both sides are skipped
+/// without loading them.
+pub async fn changed_paths(
+    storage: &dyn ObjectStorage,
+    old: SHA1,
+    new: SHA1,
+) -> Result<Vec<(String, ChangeKind)>, GitError> {
+    let mut changes = Vec::new();
+    let mut stack = vec![(String::new(), Some(old), Some(new))];
+    while let Some((prefix, old, new)) = stack.pop() {
+        if old == new {
+            continue;
+        }
+        let old_items = load_items(storage, old).await?;
+        let mut new_items = load_items(storage, new).await?;
+        let join = |name: &str| {
+            if prefix.is_empty() {
+                name.to_owned()
+            } else {
+                format!("{}/{}", prefix, name)
+            }
+        };
+
+        for (name, old_item) in old_items {
+            let path = join(&name);
+            let old_dir = (old_item.mode == TreeItemMode::Tree).then_some(old_item.id);
+            match new_items.remove(&name) {
+                Some(new_item) if new_item == old_item => {}
+                Some(new_item) => {
+                    let new_dir = (new_item.mode == TreeItemMode::Tree).then_some(new_item.id);
+                    match (old_dir, new_dir) {
+                        (None, None) => changes.push((path, ChangeKind::Modified)),
+                        (Some(_), Some(_)) => stack.push((path, old_dir, new_dir)),
+                        // a file replaced by a directory or the other way round
+                        (Some(_), None) => {
+                            stack.push((path.clone(), old_dir, None));
+                            changes.push((path, ChangeKind::Added));
+                        }
+                        (None, Some(_)) => {
+                            changes.push((path.clone(), ChangeKind::Deleted));
+                            stack.push((path, None, new_dir));
+                        }
+                    }
+                }
+                None if old_dir.is_some() => stack.push((path, old_dir, None)),
+                None => changes.push((path, ChangeKind::Deleted)),
+            }
+        }
+        for (name, new_item) in new_items {
+            let path = join(&name);
+            if new_item.mode == TreeItemMode::Tree {
+                stack.push((path, None, Some(new_item.id)));
+            } else {
+                changes.push((path, ChangeKind::Added));
+            }
+        }
+    }
+    changes.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(changes)
+}
+
+/// The lines added and deleted in a changed file, like `git diff --numstat`. Binary files have
+/// no line counts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStat {
+    pub path: String,
+    pub added: usize,
+    pub deleted: usize,
+    pub binary: bool,
+}
+
+/// The [`FileStat`] of every file which differs between the trees `old` and `new`, ordered by
+/// path. Whether a file is binary follows `attributes`, see [`is_binary_for_diff`].
+pub async fn diff_stat(
+    storage: &dyn ObjectStorage,
+    old: SHA1,
+    new: SHA1,
+    attributes: &Attributes,
+) -> Result<Vec<FileStat>, GitError> {
+    let old = flatten_tree(storage, old).await?;
+    let new = flatten_tree(storage, new).await?;
+    let mut stats = Vec::new();
+    for change in diff_flat_trees(&old, &new) {
+        let old_data = diff_content(storage, change.old.as_ref()).await?;
+        let new_data = diff_content(storage, change.new.as_ref()).await?;
+        let binary = is_binary_for_diff(&change.path, &old_data, attributes)
+            || is_binary_for_diff(&change.path, &new_data, attributes);
+        let (added, deleted) = if binary {
+            (0, 0)
+        } else {
+            count_lines(&old_data, &new_data)
+        };
+        stats.push(FileStat {
+            path: change.path,
+            added,
+            deleted,
+            binary,
+        });
+    }
+    Ok(stats)
+}
+
+/// The content of a file as diffed, empty for a missing side. A submodule is the single line
+/// `Subproject commit <id>` like in git.
+async fn diff_content(
+    storage: &dyn ObjectStorage,
+    item: Option<&TreeItem>,
+) -> Result<Vec<u8>, GitError> {
+    match item {
+        None => Ok(Vec::new()),
+        Some(item) if item.mode == TreeItemMode::Commit => {
+            Ok(format!("Subproject commit {}\n", item.id).into_bytes())
+        }
+        Some(item) => Ok(storage
+            .get_blob(&item.id)
+            .await?
+            .ok_or_else(|| GitError::NotFountHashValue(item.id.to_plain_str()))?
+            .data),
+    }
+}
+
+#[derive(Default)]
+struct LineCount {
+    added: usize,
+    deleted: usize,
+}
+
+impl diffs::Diff for LineCount {
+    type Error = ();
+
+    fn delete(&mut self, _old: usize, len: usize, _new: usize) -> Result<(), ()> {
+        self.deleted += len;
+        Ok(())
+    }
+
+    fn insert(&mut self, _old: usize, _new: usize, new_len: usize) -> Result<(), ()> {
+        self.added += new_len;
+        Ok(())
+    }
+}
+
+/// The number of lines added and deleted from `old` to `new`.
+fn count_lines(old: &[u8], new: &[u8]) -> (usize, usize) {
+    let old: Vec<&[u8]> = old.split_inclusive(|&x| x == b'\n').collect();
+    let new: Vec<&[u8]> = new.split_inclusive(|&x| x == b'\n').collect();
+    let mut count = LineCount::default();
+    diffs::myers::diff(&mut count, &old, 0, old.len(), &new, 0, new.len()).unwrap();
+    (count.added, count.deleted)
+}
+
+/// The entries of the tree `id` by name, none for a missing side.
+async fn load_items(
+    storage: &dyn ObjectStorage,
+    id: Option<SHA1>,
+) -> Result<BTreeMap<String, TreeItem>, GitError> {
+    let Some(id) = id else {
+        return Ok(BTreeMap::new());
+    };
+    let tree = storage
+        .get_tree(&id)
+        .await?
+        .ok_or_else(|| GitError::NotFountHashValue(id.to_plain_str()))?;
+    Ok(tree
+        .tree_items
+        .into_iter()
+        .map(|x| (x.name.clone(), x))
+        .collect())
+}
+
+/// Apply `changes` onto `base`. A change applies cleanly if `base` still has its old side at the
+/// path, or already has its new side. Otherwise the path is reported as conflicting.
+pub fn apply_changes(
+    mut base: FlatTree,
+    changes: Vec<FileChange>,
+) -> Result<FlatTree, Vec<String>> {
+    let mut conflicts = Vec::new();
+    for change in changes {
+        let current = base.get(&change.path);
+        if current == change.new.as_ref() {
+            continue;
+        }
+        if current != change.old.as_ref() {
+            conflicts.push(change.path);
+            continue;
+        }
+        match change.new {
+            Some(item) => base.insert(change.path, item),
+            None => base.remove(&change.path),
+        };
+    }
+    if conflicts.is_empty() {
+        Ok(base)
+    } else {
+        Err(conflicts)
+    }
+}
+
+/// Sort tree entries the way git does, as if tree names had a trailing slash.
+pub(crate) fn sort_tree_items(items: &mut [TreeItem]) {
+    items.sort_by_cached_key(tree_sort_key);
+}
+
+/// The key git sorts tree entries by, the name with a trailing slash for trees.
+pub(crate) fn tree_sort_key(item: &TreeItem) -> String {
+    let mut key = item.name.clone();
+    if item.mode == TreeItemMode::Tree {
+        key.push('/');
+    }
+    key
+}
+
+enum Node {
+    Leaf(TreeItem),
+    Dir(BTreeMap<String, Node>),
+}
+
+/// Build the tree objects for `flat`, returning the root tree id and every tree created, the
+/// root last. Directories left without entries are dropped.
+pub fn build_tree(flat: &FlatTree) -> Result<(SHA1, Vec<Tree>), GitError> {
+    let mut root = BTreeMap::new();
+    for (path, item) in flat {
+        let mut dir = &mut root;
+        let mut names: Vec<&str> = path.split('/').collect();
+        let name = names.pop().unwrap();
+        for name in names {
+            let node = dir
+                .entry(name.to_owned())
+                .or_insert_with(|| Node::Dir(BTreeMap::new()));
+            dir = match node {
+                Node::Dir(children) => children,
+                Node::Leaf(_) => return Err(GitError::InvalidPathError(path.clone())),
+            };
+        }
+        dir.insert(
+            name.to_owned(),
+            Node::Leaf(TreeItem::new(item.mode, item.id, name.to_owned())),
+        );
+    }
+
+    let mut trees = Vec::new();
+    match write_dir(root, &mut trees)? {
+        Some(id) => Ok((id, trees)),
+        None => Err(GitError::EmptyTreeItems(
+            "Can't build a tree without entries".to_owned(),
+        )),
+    }
+}
+
+fn write_dir(dir: BTreeMap<String, Node>, trees: &mut Vec<Tree>) -> Result<Option<SHA1>, GitError> {
+    let mut items = Vec::new();
+    for (name, node) in dir {
+        match node {
+            Node::Leaf(item) => items.push(item),
+            Node::Dir(children) => {
+                if let Some(id) = write_dir(children, trees)? {
+                    items.push(TreeItem::new(TreeItemMode::Tree, id, name));
+                }
+            }
+        }
+    }
+    if items.is_empty() {
+        return Ok(None);
+    }
+    sort_tree_items(&mut items);
+    let tree = Tree::from_tree_items(items)?;
+    let id = tree.id;
+    trees.push(tree);
+    Ok(Some(id))
+}
+
+#[cfg(test)]
+mod test {
+    use jupiter::storage::object_storage::MemoryStorage;
+    use venus::internal::object::blob::Blob;
+    use venus::internal::object::tree::{TreeItem, TreeItemMode};
+
+    use super::{build_tree, changed_paths, diff_stat, ChangeKind, FileStat, FlatTree};
+    use crate::monorepo::attributes::Attributes;
+
+    fn flat(files: &[(&str, &str)]) -> FlatTree {
+        files
+            .iter()
+            .map(|(path, content)| {
+                let blob = Blob::from_content(content);
+                let item = TreeItem::new(TreeItemMode::Blob, blob.id, String::new());
+                (path.to_string(), item)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_changed_paths() {
+        let storage = MemoryStorage::new();
+        let vendored = [
+            ("third_party/zlib/zlib.h", "zlib"),
+            ("third_party/zlib/inflate.c", "inflate"),
+        ];
+        let (old, old_trees) = build_tree(&flat(
+            &[
+                &vendored[..],
+                &[
+                    ("README.md", "readme"),
+                    ("src/lib.rs", "lib"),
+                    ("src/old.rs", "old"),
+                    ("docs", "a file which becomes a directory"),
+                ],
+            ]
+            .concat(),
+        ))
+        .unwrap();
+        let (new, new_trees) = build_tree(&flat(
+            &[
+                &vendored[..],
+                &[
+                    ("README.md", "readme"),
+                    ("src/lib.rs", "lib v2"),
+                    ("src/new/mod.rs", "new"),
+                    ("docs/guide.md", "guide"),
+                ],
+            ]
+            .concat(),
+        ))
+        .unwrap();
+        // the unchanged third_party tree is left out of the storage, loading it would fail
+        let third_party = build_tree(&flat(&vendored)).unwrap().1;
+        let skipped: Vec<_> = third_party[..third_party.len() - 1]
+            .iter()
+            .map(|x| x.id)
+            .collect();
+        for tree in old_trees.into_iter().chain(new_trees) {
+            if !skipped.contains(&tree.id) {
+                storage.insert_tree(tree);
+            }
+        }
+
+        let changes = changed_paths(&storage, old, new).await.unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                ("docs".to_owned(), ChangeKind::Deleted),
+                ("docs/guide.md".to_owned(), ChangeKind::Added),
+                ("src/lib.rs".to_owned(), ChangeKind::Modified),
+                ("src/new/mod.rs".to_owned(), ChangeKind::Added),
+                ("src/old.rs".to_owned(), ChangeKind::Deleted),
+            ]
+        );
+        assert!(changed_paths(&storage, old, old).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_diff_stat() {
+        let storage = MemoryStorage::new();
+        let old_files = [
+            ("src/lib.rs", "fn a() {}\nfn b() {}\nfn c() {}\n"),
+            ("logo.svg", "<svg>\n</svg>\n"),
+            ("image.png", "\u{0}png"),
+        ];
+        let new_files = [
+            (
+                "src/lib.rs",
+                "fn a() {}\nfn b2() {}\nfn c() {}\nfn d() {}\n",
+            ),
+            ("logo.svg", "<svg>\n<path/>\n</svg>\n"),
+            ("image.png", "\u{0}png v2"),
+        ];
+        for (_, content) in old_files.iter().chain(&new_files) {
+            storage.insert_blob(Blob::from_content(content));
+        }
+        let (old, old_trees) = build_tree(&flat(&old_files)).unwrap();
+        let (new, new_trees) = build_tree(&flat(&new_files)).unwrap();
+        for tree in old_trees.into_iter().chain(new_trees) {
+            storage.insert_tree(tree);
+        }
+
+        let attributes = Attributes::parse("*.svg binary\n");
+        let stats = diff_stat(&storage, old, new, &attributes).await.unwrap();
+        let stat = |path: &str, added, deleted, binary| FileStat {
+            path: path.to_owned(),
+            added,
+            deleted,
+            binary,
+        };
+        assert_eq!(
+            stats,
+            vec![
+                stat("image.png", 0, 0, true),
+                stat("logo.svg", 0, 0, true),
+                stat("src/lib.rs", 2, 1, false),
+            ]
+        );
+    }
+}
@@ -0,0 +1,265 @@
+//! Checking the integrity of the stored objects, like `git fsck`.
+//!
+//! Every object has to parse and hash to its id, every object it refers to has to be stored,
+//! and tree items have to refer to objects of the type of their mode. Objects nothing refers to,
+//! neither another object nor a ref, are reported as dangling. The objects are fed to [`Fsck`]
+//! one at a time, it only keeps the hashes and types of the stored objects and the hashes of the
+//! referenced ones.
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use callisto::{mega_commit, mega_tag, mega_tree, raw_blob};
+use venus::errors::GitError;
+use venus::hash::SHA1;
+use venus::internal::object::blob::Blob;
+use venus::internal::object::commit::Commit;
+use venus::internal::object::signature::Signature;
+use venus::internal::object::tag::Tag;
+use venus::internal::object::tree::Tree;
+use venus::internal::object::types::ObjectType;
+use venus::internal::object::{GitObject, ObjectTrait};
+use venus::internal::pack::entry::Entry;
+use venus::model::tag::parse_tagger;
+
+use crate::monorepo::connectivity::{expected_type, mode_name};
+
+/// Something wrong with a stored object or ref.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsckProblem {
+    /// The object doesn't parse or doesn't hash to its id.
+    Corrupt { hash: String, reason: String },
+    /// `from`, an object or a ref, refers to `to` as `name` but `to` isn't stored. The name is a
+    /// tree item name, `tree` or `parent` for commits, `object` for tags and `ref` for refs.
+    BrokenLink {
+        from: String,
+        name: String,
+        to: String,
+    },
+    /// The item `name` of `tree` has a mode which doesn't fit the type of its object.
+    ModeMismatch {
+        tree: String,
+        name: String,
+        mode: String,
+        actual: ObjectType,
+    },
+}
+
+impl fmt::Display for FsckProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FsckProblem::Corrupt { hash, reason } => write!(f, "corrupt {}: {}", hash, reason),
+            FsckProblem::BrokenLink { from, name, to } => {
+                write!(f, "broken link from {} to {} ({})", from, to, name)
+            }
+            FsckProblem::ModeMismatch {
+                tree,
+                name,
+                mode,
+                actual,
+            } => write!(
+                f,
+                "tree {}: `{}` has mode {} but is a {}",
+                tree, name, mode, actual
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FsckReport {
+    /// The number of objects checked.
+    pub objects: usize,
+    pub problems: Vec<FsckProblem>,
+    /// The hashes of the objects nothing refers to, sorted.
+    pub dangling: Vec<String>,
+}
+
+impl FsckReport {
+    /// Whether no problem was found, dangling objects aren't one.
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// The checks of a single fsck run, see the module docs.
+#[derive(Default)]
+pub struct Fsck {
+    stored: HashMap<SHA1, ObjectType>,
+    referenced: HashSet<SHA1>,
+    report: FsckReport,
+}
+
+impl Fsck {
+    /// Record that an object of `obj_type` is stored as `hash`, before any object is checked.
+    pub fn stored(&mut self, hash: &str, obj_type: ObjectType) {
+        match SHA1::from_str(hash) {
+            Ok(id) => {
+                self.stored.insert(id, obj_type);
+            }
+            Err(err) => self.corrupt(hash, err),
+        }
+    }
+
+    fn corrupt(&mut self, hash: &str, reason: impl ToString) {
+        self.report.problems.push(FsckProblem::Corrupt {
+            hash: hash.to_owned(),
+            reason: reason.to_string(),
+        });
+    }
+
+    /// Record that `from` refers to `to` as `name`, returning the type of `to` if it's stored.
+    fn link(&mut self, from: &str, name: &str, to: SHA1) -> Option<ObjectType> {
+        self.referenced.insert(to);
+        let actual = self.stored.get(&to).copied();
+        if actual.is_none() {
+            self.report.problems.push(FsckProblem::BrokenLink {
+                from: from.to_owned(),
+                name: name.to_owned(),
+                to: to.to_plain_str(),
+            });
+        }
+        actual
+    }
+
+    /// Like [`Fsck::link`], a stored `to` of another type than `expected` makes `from` corrupt.
+    fn typed_link(&mut self, from: &str, name: &str, to: SHA1, expected: ObjectType) {
+        match self.link(from, name, to) {
+            Some(actual) if actual != expected => {
+                let reason = format!("its {} {} is a {}", name, to.to_plain_str(), actual);
+                self.corrupt(from, reason);
+            }
+            _ => {}
+        }
+    }
+
+    /// Check the object stored as `hash`, or the error it failed to load with.
+    pub fn check(&mut self, hash: &str, object: Result<GitObject, GitError>) {
+        self.report.objects += 1;
+        let object = match object {
+            Ok(object) => object,
+            Err(err) => return self.corrupt(hash, err),
+        };
+        let entry: Entry = match object.clone() {
+            GitObject::Commit(commit) => commit.into(),
+            GitObject::Tree(tree) => tree.into(),
+            GitObject::Blob(blob) => blob.into(),
+            GitObject::Tag(tag) => tag.into(),
+        };
+        let actual = SHA1::from_type_and_data(entry.obj_type, &entry.data).to_plain_str();
+        if actual != hash {
+            return self.corrupt(hash, format!("hashes to {}", actual));
+        }
+
+        match object {
+            GitObject::Commit(commit) => {
+                self.typed_link(hash, "tree", commit.tree_id, ObjectType::Tree);
+                for parent in commit.parent_commit_ids {
+                    self.typed_link(hash, "parent", parent, ObjectType::Commit);
+                }
+            }
+            GitObject::Tree(tree) => {
+                for item in tree.tree_items {
+                    let Some(expected) = expected_type(item.mode) else {
+                        continue;
+                    };
+                    match self.link(hash, &item.name, item.id) {
+                        Some(actual) if actual != expected => {
+                            self.report.problems.push(FsckProblem::ModeMismatch {
+                                tree: hash.to_owned(),
+                                name: item.name,
+                                mode: mode_name(item.mode),
+                                actual,
+                            });
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            GitObject::Blob(_) => {}
+            GitObject::Tag(tag) => {
+                self.typed_link(hash, "object", tag.object_hash, tag.object_type);
+            }
+        }
+    }
+
+    /// Check the ref `name` points to a stored object.
+    pub fn check_ref(&mut self, name: &str, target: &str) {
+        let Ok(id) = SHA1::from_str(target) else {
+            return self.corrupt(name, format!("points to {}", target));
+        };
+        self.referenced.insert(id);
+        if !self.stored.contains_key(&id) {
+            self.report.problems.push(FsckProblem::BrokenLink {
+                from: name.to_owned(),
+                name: "ref".to_owned(),
+                to: target.to_owned(),
+            });
+        }
+    }
+
+    pub fn finish(mut self) -> FsckReport {
+        let mut dangling: Vec<String> = self
+            .stored
+            .keys()
+            .filter(|x| !self.referenced.contains(x))
+            .map(|x| x.to_plain_str())
+            .collect();
+        dangling.sort();
+        self.report.dangling = dangling;
+        self.report
+    }
+}
+
+fn parse_hash(hash: &str) -> Result<SHA1, GitError> {
+    SHA1::from_str(hash).map_err(GitError::InvalidHashValue)
+}
+
+fn parse_signature(signature: Option<String>, field: &str) -> Result<Signature, GitError> {
+    let signature =
+        signature.ok_or_else(|| GitError::InvalidCommitObject(format!("no {}", field)))?;
+    Signature::from_data(signature.into_bytes())
+}
+
+/// Load a stored commit, failing instead of panicking on malformed fields.
+pub fn parse_commit(model: mega_commit::Model) -> Result<GitObject, GitError> {
+    Ok(GitObject::Commit(Commit {
+        id: parse_hash(&model.commit_id)?,
+        tree_id: parse_hash(&model.tree)?,
+        parent_commit_ids: model
+            .parents_id
+            .iter()
+            .map(|x| parse_hash(x))
+            .collect::<Result<_, _>>()?,
+        author: parse_signature(model.author, "author")?,
+        committer: parse_signature(model.committer, "committer")?,
+        message: model
+            .content
+            .ok_or_else(|| GitError::InvalidCommitObject("no message".to_owned()))?,
+    }))
+}
+
+pub fn parse_tree(model: mega_tree::Model) -> Result<GitObject, GitError> {
+    let id = parse_hash(&model.tree_id)?;
+    Ok(GitObject::Tree(Tree::from_bytes(model.sub_trees, id)?))
+}
+
+/// Load a blob, `None` if its row has no content. The content of cold blobs has to be read from
+/// the raw object storage first, see [`MegaStorage::load_cold_blobs`].
+///
+/// [`MegaStorage::load_cold_blobs`]: jupiter::storage::mega_storage::MegaStorage::load_cold_blobs
+pub fn parse_blob(model: raw_blob::Model) -> Result<Option<GitObject>, GitError> {
+    let id = parse_hash(&model.sha1)?;
+    Ok(model.data.map(|data| GitObject::Blob(Blob { id, data })))
+}
+
+pub fn parse_tag(model: mega_tag::Model) -> Result<GitObject, GitError> {
+    Ok(GitObject::Tag(Tag {
+        id: parse_hash(&model.tag_id)?,
+        object_hash: parse_hash(&model.object_id)?,
+        object_type: ObjectType::from_string(&model.object_type)?,
+        tag_name: model.tag_name,
+        tagger: parse_tagger(&model.tagger)?,
+        message: model.message,
+    }))
+}
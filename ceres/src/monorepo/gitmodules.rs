@@ -0,0 +1,204 @@
+//! Parsing of `.gitmodules` files, mapping the gitlink entries found by
+//! [`list_submodules`](crate::monorepo::submodule::list_submodules) to their remote URLs.
+//!
+//! The format is the git config one, a submodule is described by a section like:
+//!
+//! ```text
+//! [submodule "libgit2"]
+//!     path = third_party/libgit2
+//!     url = https://github.com/libgit2/libgit2.git
+//!     branch = main
+//! ```
+//!
+//! The URLs are cloned later on, so only `https` and `ssh` ones are accepted. Other transports
+//! like `ext::` run commands or read local files, and control characters or shell
+//! metacharacters could be used to inject commands into the tools cloning them.
+use venus::errors::GitError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubmoduleConfig {
+    pub name: String,
+    pub path: String,
+    pub url: String,
+    pub branch: Option<String>,
+}
+
+#[derive(Default)]
+struct Section {
+    name: String,
+    path: Option<String>,
+    url: Option<String>,
+    branch: Option<String>,
+}
+
+impl Section {
+    fn into_config(self) -> Option<SubmoduleConfig> {
+        Some(SubmoduleConfig {
+            name: self.name,
+            path: self.path?,
+            url: self.url?,
+            branch: self.branch,
+        })
+    }
+}
+
+/// Returns the name of a `[submodule "<name>"]` section header, `None` for any other header.
+fn parse_header(line: &str) -> Option<String> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?.trim();
+    let name = inner.strip_prefix("submodule")?.trim();
+    let name = name.strip_prefix('"')?.strip_suffix('"')?;
+    Some(name.to_owned())
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|x| x.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+/// Whether `host`, of a URL or an scp-like address, can't be mistaken for an option of `ssh`.
+fn is_safe_host(host: &str) -> bool {
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+    !host.is_empty() && !host.starts_with('-')
+}
+
+/// Whether `url` is an `https` or `ssh` URL, or an scp-like ssh address like
+/// `git@github.com:madler/zlib.git`, without characters a shell would interpret.
+pub fn is_safe_url(url: &str) -> bool {
+    if url
+        .chars()
+        .any(|x| x.is_control() || x.is_whitespace() || "`$;|&<>\\".contains(x))
+    {
+        return false;
+    }
+    if let Some((scheme, rest)) = url.split_once("://") {
+        let host = rest.split(['/', ':']).next().unwrap_or_default();
+        return ["https", "ssh"].contains(&scheme.to_ascii_lowercase().as_str())
+            && is_safe_host(host);
+    }
+    // `<transport>::<address>` runs a remote helper
+    if url.contains("::") {
+        return false;
+    }
+    match url.split_once(':') {
+        Some((host, _)) => !host.contains('/') && is_safe_host(host),
+        // a local path
+        None => false,
+    }
+}
+
+/// Parse the submodule sections of a `.gitmodules` file, in file order. Sections of other kinds
+/// are ignored, as are submodules without a `path` or `url`, git can't use those either. A
+/// submodule with an URL failing [`is_safe_url`] fails with [`GitError::UnsafeSubmoduleUrl`].
+pub fn parse(content: &str) -> Result<Vec<SubmoduleConfig>, GitError> {
+    let mut configs = Vec::new();
+    let mut current: Option<Section> = None;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') {
+            if let Some(config) = current.take().and_then(Section::into_config) {
+                configs.push(config);
+            }
+            current = parse_header(line).map(|name| Section {
+                name,
+                ..Default::default()
+            });
+            continue;
+        }
+        let (Some(section), Some((key, value))) = (current.as_mut(), line.split_once('=')) else {
+            continue;
+        };
+        let value = unquote(value.trim()).to_owned();
+        // config keys are case insensitive
+        match key.trim().to_ascii_lowercase().as_str() {
+            "path" => section.path = Some(value),
+            "url" => section.url = Some(value),
+            "branch" => section.branch = Some(value),
+            _ => {}
+        }
+    }
+    if let Some(config) = current.and_then(Section::into_config) {
+        configs.push(config);
+    }
+    if let Some(config) = configs.iter().find(|x| !is_safe_url(&x.url)) {
+        return Err(GitError::UnsafeSubmoduleUrl(
+            config.name.clone(),
+            config.url.clone(),
+        ));
+    }
+    Ok(configs)
+}
+
+#[cfg(test)]
+mod test {
+    use venus::errors::GitError;
+
+    use super::{is_safe_url, parse, SubmoduleConfig};
+
+    #[test]
+    fn test_parse_gitmodules() {
+        let content = r#"
+# vendored dependencies
+[submodule "libgit2"]
+	path = third_party/libgit2
+	url = https://github.com/libgit2/libgit2.git
+[core]
+	bare = false
+[submodule "zlib"]
+	path = "third_party/deps/zlib"
+	URL = git@github.com:madler/zlib.git
+	branch = develop
+"#;
+        assert_eq!(
+            parse(content).unwrap(),
+            vec![
+                SubmoduleConfig {
+                    name: "libgit2".to_owned(),
+                    path: "third_party/libgit2".to_owned(),
+                    url: "https://github.com/libgit2/libgit2.git".to_owned(),
+                    branch: None,
+                },
+                SubmoduleConfig {
+                    name: "zlib".to_owned(),
+                    path: "third_party/deps/zlib".to_owned(),
+                    url: "git@github.com:madler/zlib.git".to_owned(),
+                    branch: Some("develop".to_owned()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reject_unsafe_url() {
+        let content = r#"
+[submodule "payload"]
+	path = payload
+	url = ext::sh -c touch% /tmp/pwned
+"#;
+        match parse(content) {
+            Err(GitError::UnsafeSubmoduleUrl(name, url)) => {
+                assert_eq!(name, "payload");
+                assert_eq!(url, "ext::sh -c touch% /tmp/pwned");
+            }
+            other => panic!("url wasn't rejected: {:?}", other),
+        }
+
+        assert!(is_safe_url("https://github.com/libgit2/libgit2.git"));
+        assert!(is_safe_url("ssh://git@github.com/madler/zlib.git"));
+        for url in [
+            "ext::sh -c 'touch /tmp/pwned'",
+            "file:///etc/passwd",
+            "/srv/git/zlib.git",
+            "ssh://-oProxyCommand=touch/zlib.git",
+            "-oProxyCommand=touch:zlib.git",
+            "https://github.com/zlib.git$(touch)",
+            "https://github.com/zlib.git\n",
+        ] {
+            assert!(!is_safe_url(url), "{} was accepted", url);
+        }
+    }
+}
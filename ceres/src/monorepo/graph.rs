@@ -0,0 +1,377 @@
+//! Exporting the object graph of the monorepo as newline delimited JSON.
+//!
+//! Each line is one object with its type, hash and the fields of that type, blobs carry their
+//! content hex encoded. Objects are written as they are read, so an export never holds more than
+//! a batch of them. Importing rebuilds each object from its fields and checks it still hashes to
+//! the recorded hash.
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use venus::errors::GitError;
+use venus::hash::SHA1;
+use venus::internal::object::blob::Blob;
+use venus::internal::object::commit::Commit;
+use venus::internal::object::signature::Signature;
+use venus::internal::object::tag::Tag;
+use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+use venus::internal::object::types::ObjectType;
+use venus::internal::object::GitObject;
+use venus::internal::pack::entry::Entry;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphTreeItem {
+    pub mode: String,
+    pub id: String,
+    pub name: String,
+}
+
+/// One line of an export.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum GraphRecord {
+    Commit {
+        hash: String,
+        tree: String,
+        parents: Vec<String>,
+        /// The signature lines as in the commit, e.g. `author dev <dev@mega.org> 1700000000 +0800`.
+        author: String,
+        committer: String,
+        message: String,
+    },
+    Tree {
+        hash: String,
+        items: Vec<GraphTreeItem>,
+    },
+    Blob {
+        hash: String,
+        data: String,
+    },
+    Tag {
+        hash: String,
+        object: String,
+        object_type: String,
+        tag_name: String,
+        tagger: String,
+        message: String,
+    },
+}
+
+fn signature_line(signature: &Signature) -> Result<String, GitError> {
+    Ok(String::from_utf8_lossy(&signature.to_data()?).into_owned())
+}
+
+fn parse_hash(hash: &str) -> Result<SHA1, GitError> {
+    SHA1::from_str(hash).map_err(GitError::InvalidHashValue)
+}
+
+impl GraphRecord {
+    pub fn from_entry(entry: &Entry) -> Result<Self, GitError> {
+        let hash = entry.hash.to_plain_str();
+        Ok(match entry.process_entry() {
+            GitObject::Commit(commit) => GraphRecord::Commit {
+                hash,
+                tree: commit.tree_id.to_plain_str(),
+                parents: commit
+                    .parent_commit_ids
+                    .iter()
+                    .map(|x| x.to_plain_str())
+                    .collect(),
+                author: signature_line(&commit.author)?,
+                committer: signature_line(&commit.committer)?,
+                message: commit.message,
+            },
+            GitObject::Tree(tree) => GraphRecord::Tree {
+                hash,
+                items: tree
+                    .tree_items
+                    .into_iter()
+                    .map(|x| GraphTreeItem {
+                        mode: String::from_utf8_lossy(x.mode.to_bytes()).into_owned(),
+                        id: x.id.to_plain_str(),
+                        name: x.name,
+                    })
+                    .collect(),
+            },
+            GitObject::Blob(blob) => GraphRecord::Blob {
+                hash,
+                data: hex::encode(blob.data),
+            },
+            GitObject::Tag(tag) => GraphRecord::Tag {
+                hash,
+                object: tag.object_hash.to_plain_str(),
+                object_type: tag.object_type.to_string(),
+                tag_name: tag.tag_name,
+                tagger: signature_line(&tag.tagger)?,
+                message: tag.message,
+            },
+        })
+    }
+
+    /// Rebuild the object of the record, failing if it doesn't hash to the recorded hash.
+    pub fn into_entry(self) -> Result<Entry, GitError> {
+        let entry: Entry = match self {
+            GraphRecord::Commit {
+                hash,
+                tree,
+                parents,
+                author,
+                committer,
+                message,
+            } => Commit {
+                id: parse_hash(&hash)?,
+                tree_id: parse_hash(&tree)?,
+                parent_commit_ids: parents
+                    .iter()
+                    .map(|x| parse_hash(x))
+                    .collect::<Result<_, _>>()?,
+                author: Signature::from_data(author.into_bytes())?,
+                committer: Signature::from_data(committer.into_bytes())?,
+                message,
+            }
+            .into(),
+            GraphRecord::Tree { hash, items } => Tree {
+                id: parse_hash(&hash)?,
+                tree_items: items
+                    .into_iter()
+                    .map(|x| {
+                        Ok(TreeItem {
+                            mode: TreeItemMode::tree_item_type_from_bytes(x.mode.as_bytes())?,
+                            id: parse_hash(&x.id)?,
+                            name: x.name,
+                        })
+                    })
+                    .collect::<Result<_, GitError>>()?,
+            }
+            .into(),
+            GraphRecord::Blob { hash, data } => Blob {
+                id: parse_hash(&hash)?,
+                data: hex::decode(&data)
+                    .map_err(|err| GitError::InvalidBlobObject(err.to_string()))?,
+            }
+            .into(),
+            GraphRecord::Tag {
+                hash,
+                object,
+                object_type,
+                tag_name,
+                tagger,
+                message,
+            } => Tag {
+                id: parse_hash(&hash)?,
+                object_hash: parse_hash(&object)?,
+                object_type: ObjectType::from_string(&object_type)?,
+                tag_name,
+                tagger: Signature::from_data(tagger.into_bytes())?,
+                message,
+            }
+            .into(),
+        };
+        if SHA1::from_type_and_data(entry.obj_type, &entry.data) != entry.hash {
+            return Err(GitError::ObjectHashMismatch(entry.hash.to_plain_str()));
+        }
+        Ok(entry)
+    }
+}
+
+/// Write `entry` as a line of the export.
+pub fn write_record(writer: &mut impl Write, entry: &Entry) -> Result<(), GitError> {
+    let record = GraphRecord::from_entry(entry)?;
+    serde_json::to_writer(&mut *writer, &record)
+        .map_err(|err| GitError::EncodeObjectError(err.to_string()))?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Read the objects of an export line by line, skipping empty lines.
+pub fn read_records(reader: impl BufRead) -> impl Iterator<Item = Result<Entry, GitError>> {
+    reader
+        .lines()
+        .filter(|x| !x.as_ref().is_ok_and(|x| x.trim().is_empty()))
+        .map(|line| {
+            let record: GraphRecord = serde_json::from_str(&line?)
+                .map_err(|err| GitError::InvalidObjectInfo(err.to_string()))?;
+            record.into_entry()
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use sea_orm::{DatabaseBackend, DatabaseConnection, MockDatabase, MockExecResult, Value};
+
+    use callisto::db_enums::StorageType;
+    use callisto::{mega_commit, mega_tag, mega_tree, raw_blob};
+    use jupiter::context::{Context, Service};
+    use jupiter::storage::mega_storage::MegaStorage;
+    use venus::errors::GitError;
+    use venus::hash::SHA1;
+    use venus::internal::object::blob::Blob;
+    use venus::internal::object::commit::Commit;
+    use venus::internal::object::signature::Signature;
+    use venus::internal::object::tag::Tag;
+    use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+    use venus::internal::object::types::ObjectType;
+    use venus::internal::pack::entry::Entry;
+
+    use super::{read_records, write_record};
+    use crate::pack::monorepo::MonoRepo;
+    use crate::test_util::transaction_log;
+
+    fn repo_with_db(db: DatabaseConnection) -> MonoRepo {
+        let mock = Context::mock();
+        let context = Context {
+            services: Arc::new(Service {
+                mega_storage: Arc::new(MegaStorage {
+                    connection: Arc::new(db),
+                    ..MegaStorage::mock()
+                }),
+                ..(*mock.services).clone()
+            }),
+            ..mock
+        };
+        MonoRepo::new(context, &PathBuf::from("/")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_export_and_import() {
+        let blob = Blob::from_content("fn main() {}\n");
+        let data = vec![0, 159, 146, 150, 255];
+        let binary = Blob {
+            id: SHA1::from_type_and_data(ObjectType::Blob, &data),
+            data,
+        };
+        let src = Tree::from_tree_items(vec![
+            TreeItem::new(TreeItemMode::Blob, blob.id, "main.rs".to_owned()),
+            TreeItem::new(TreeItemMode::BlobExecutable, binary.id, "run".to_owned()),
+        ])
+        .unwrap();
+        let root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Tree,
+            src.id,
+            "src".to_owned(),
+        )])
+        .unwrap();
+        let init = Commit::from_tree_id(root.id, vec![], "\ninit\n\nwith a body\n");
+        let next = Commit::from_tree_id(root.id, vec![init.id], "\nnext\n");
+        let tagger =
+            Signature::from_data(b"tagger dev <dev@mega.org> 1700000000 +0800".to_vec()).unwrap();
+        let tag = Tag::new(next.id, ObjectType::Commit, "v1.0.0", tagger, "release\n");
+
+        let now = chrono::Utc::now().naive_utc();
+        let raw = |blob: &Blob| raw_blob::Model {
+            id: 0,
+            sha1: blob.id.to_plain_str(),
+            content: None,
+            file_type: None,
+            storage_type: StorageType::Database,
+            data: Some(blob.data.clone()),
+            local_path: None,
+            remote_url: None,
+            created_at: now,
+        };
+        let rows = |column: &'static str, hashes: &[SHA1]| {
+            hashes
+                .iter()
+                .map(|x| BTreeMap::from([(column, Value::from(x.to_plain_str()))]))
+                .collect::<Vec<_>>()
+        };
+        // the hashes of each type followed by their objects
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([rows("commit_id", &[init.id, next.id])])
+            .append_query_results([vec![
+                mega_commit::Model::from(init.clone()),
+                mega_commit::Model::from(next.clone()),
+            ]])
+            .append_query_results([rows("tree_id", &[root.id, src.id])])
+            .append_query_results([vec![
+                mega_tree::Model::from(root.clone()),
+                mega_tree::Model::from(src.clone()),
+            ]])
+            .append_query_results([rows("blob_id", &[blob.id, binary.id])])
+            .append_query_results([vec![raw(&blob), raw(&binary)]])
+            .append_query_results([rows("tag_id", &[tag.id])])
+            .append_query_results([vec![mega_tag::Model::from(tag.clone())]])
+            .into_connection();
+        let source = repo_with_db(db);
+        let mut export = Vec::new();
+        source.export_graph(&mut export).await.unwrap();
+
+        let entries: Vec<Entry> = vec![
+            init.into(),
+            next.into(),
+            root.into(),
+            src.into(),
+            blob.into(),
+            binary.into(),
+            tag.into(),
+        ];
+        let mut expected = Vec::new();
+        for entry in &entries {
+            write_record(&mut expected, entry).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(export.clone()).unwrap(),
+            String::from_utf8(expected).unwrap()
+        );
+        let imported = read_records(export.as_slice())
+            .collect::<Result<Vec<Entry>, GitError>>()
+            .unwrap();
+        for (entry, original) in imported.iter().zip(&entries) {
+            assert_eq!(
+                (entry.obj_type, entry.hash),
+                (original.obj_type, original.hash)
+            );
+            assert_eq!(entry.data, original.data);
+        }
+
+        // the commits, trees, blobs, raw blobs and tags are each saved in one insert
+        let inserted = MockExecResult {
+            last_insert_id: 0,
+            rows_affected: 1,
+        };
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results(vec![inserted; 5])
+            .into_connection();
+        let target = repo_with_db(db);
+        let count = target.import_graph(export.as_slice()).await.unwrap();
+        assert_eq!(count, entries.len());
+
+        // a record changed after the export no longer matches its hash, nothing is saved
+        let tampered = String::from_utf8(export)
+            .unwrap()
+            .replacen("main.rs", "lib.rs", 1);
+        let result = target.import_graph(tampered.as_bytes()).await;
+        assert!(matches!(result, Err(GitError::ObjectHashMismatch(_))));
+
+        let storage = target.context.services.mega_storage.clone();
+        drop(target);
+        let log: Vec<String> = transaction_log(storage);
+        assert_eq!(log.len(), 5);
+        for (table, entry) in [
+            "mega_commit",
+            "mega_commit",
+            "mega_tree",
+            "mega_tree",
+            "mega_blob",
+            "mega_blob",
+            "mega_tag",
+        ]
+        .into_iter()
+        .zip(&entries)
+        {
+            let insert = format!("INSERT INTO \\\"{}\\\"", table);
+            let hash = entry.hash.to_plain_str();
+            assert!(
+                log.iter().any(|x| x.contains(&insert) && x.contains(&hash)),
+                "{} {}",
+                table,
+                hash
+            );
+        }
+    }
+}
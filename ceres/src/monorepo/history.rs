@@ -0,0 +1,670 @@
+//! History queries over the monorepo object graph.
+//!
+//! Everything in this module is written against [`ObjectStorage`], so it can be used with the
+//! database storage as well as with an in-memory storage in tests.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::str::FromStr;
+
+use jupiter::storage::object_storage::ObjectStorage;
+use mercury::internal::blob::unified_diff;
+use venus::errors::GitError;
+use venus::hash::SHA1;
+use venus::internal::object::commit::Commit;
+use venus::internal::object::tree::{Tree, TreeItemMode};
+use venus::internal::pack::reference::{CommandType, RefCommand};
+
+use crate::monorepo::attributes::{is_binary_for_diff, Attributes};
+
+pub(crate) async fn load_commit(
+    storage: &dyn ObjectStorage,
+    hash: &SHA1,
+) -> Result<Commit, GitError> {
+    storage
+        .get_commit(hash)
+        .await?
+        .ok_or_else(|| GitError::NotFountHashValue(hash.to_plain_str()))
+}
+
+/// Resolve a slash separated `path`, relative to the tree `tree_id`, to the id of its tree entry.
+/// An empty path resolves to `tree_id` itself, a missing path to `None`.
+pub async fn entry_id_at_path(
+    storage: &dyn ObjectStorage,
+    tree_id: SHA1,
+    path: &str,
+) -> Result<Option<SHA1>, GitError> {
+    let mut id = tree_id;
+    for name in path.split('/').filter(|x| !x.is_empty()) {
+        let tree = match storage.get_tree(&id).await? {
+            Some(tree) => tree,
+            // a blob on the way to path, so path can't exist
+            None => return Ok(None),
+        };
+        match tree.tree_items.iter().find(|x| x.name == name) {
+            Some(item) => id = item.id,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(id))
+}
+
+/// The tree at the slash separated `path` in the commit `commit`, the root tree for an empty
+/// path. Only the trees on the way to `path` are loaded, a missing path or one naming something
+/// else than a directory gives `None`.
+pub async fn tree_at(
+    storage: &dyn ObjectStorage,
+    commit: &str,
+    path: &str,
+) -> Result<Option<Tree>, GitError> {
+    let hash = SHA1::from_str(commit).map_err(GitError::InvalidHashValue)?;
+    let commit = load_commit(storage, &hash).await?;
+    let mut tree = storage
+        .get_tree(&commit.tree_id)
+        .await?
+        .ok_or_else(|| GitError::NotFountHashValue(commit.tree_id.to_plain_str()))?;
+    for name in path.split('/').filter(|x| !x.is_empty()) {
+        let id = match tree.tree_items.iter().find(|x| x.name == name) {
+            Some(item) if item.mode == TreeItemMode::Tree => item.id,
+            _ => return Ok(None),
+        };
+        tree = storage
+            .get_tree(&id)
+            .await?
+            .ok_or_else(|| GitError::NotFountHashValue(id.to_plain_str()))?;
+    }
+    Ok(Some(tree))
+}
+
+/// Walk the first parent history from `start_commit` and return the most recent commit in which
+/// the entry at `path` differs from its first parent's, like `git log -1 -- <path>`.
+pub async fn last_commit_for_path(
+    storage: &dyn ObjectStorage,
+    start_commit: &str,
+    path: &str,
+) -> Result<Option<Commit>, GitError> {
+    let hash = SHA1::from_str(start_commit).map_err(GitError::InvalidHashValue)?;
+    let mut commit = load_commit(storage, &hash).await?;
+    let mut entry_id = entry_id_at_path(storage, commit.tree_id, path).await?;
+
+    loop {
+        let Some(parent_id) = commit.parent_commit_ids.first() else {
+            // the root commit introduced the path, if it exists at all
+            return Ok(entry_id.map(|_| commit));
+        };
+        let parent = load_commit(storage, parent_id).await?;
+        let parent_entry_id = entry_id_at_path(storage, parent.tree_id, path).await?;
+        if parent_entry_id != entry_id {
+            return Ok(Some(commit));
+        }
+        commit = parent;
+        entry_id = parent_entry_id;
+    }
+}
+
+/// A page of at most `limit` commits which changed the entry at `path`, like
+/// `git log --first-parent -- <path>`, newest first. The walk starts at `start_commit`, or after
+/// `before`, the cursor returned with the previous page, which is the last commit of that page.
+/// The cursor of the next page is `None` once the root commit was reached, a full page may be
+/// followed by an empty one.
+pub async fn log_path(
+    storage: &dyn ObjectStorage,
+    start_commit: &str,
+    path: &str,
+    limit: usize,
+    before: Option<&str>,
+) -> Result<(Vec<Commit>, Option<String>), GitError> {
+    let hash =
+        SHA1::from_str(before.unwrap_or(start_commit)).map_err(GitError::InvalidHashValue)?;
+    let mut commit = load_commit(storage, &hash).await?;
+    if before.is_some() {
+        let Some(parent_id) = commit.parent_commit_ids.first() else {
+            return Ok((Vec::new(), None));
+        };
+        commit = load_commit(storage, parent_id).await?;
+    }
+    let mut entry_id = entry_id_at_path(storage, commit.tree_id, path).await?;
+
+    let mut page = Vec::new();
+    // an empty page would never advance
+    while page.len() < limit.max(1) {
+        let Some(parent_id) = commit.parent_commit_ids.first() else {
+            // the root commit introduced the path, if it exists at all
+            if entry_id.is_some() {
+                page.push(commit);
+            }
+            return Ok((page, None));
+        };
+        let parent = load_commit(storage, parent_id).await?;
+        // only the ids of the entry are compared, the trees below it are never loaded
+        let parent_entry_id = entry_id_at_path(storage, parent.tree_id, path).await?;
+        if parent_entry_id != entry_id {
+            page.push(commit);
+        }
+        commit = parent;
+        entry_id = parent_entry_id;
+    }
+    let next = page.last().map(|x| x.id.to_plain_str());
+    Ok((page, next))
+}
+
+/// The commits [`file_patch_series`] reads from [`log_path`] at once.
+const LOG_PAGE_SIZE: usize = 100;
+
+/// The lines of context around the changes of a [`FilePatch`], git's default.
+const CONTEXT_LINES: usize = 3;
+
+/// A commit which changed a file, with the change as a unified diff.
+#[derive(Debug, Clone)]
+pub struct FilePatch {
+    pub commit: Commit,
+    /// The change from the first parent, see [`unified_diff`]. A binary file gives the single
+    /// line `Binary files ... differ`.
+    pub diff: String,
+}
+
+/// Every commit from `start_commit` which changed the file at `path`, oldest first, each with the
+/// change to the file, like `git log --first-parent --reverse -p -- <path>`. The commit adding
+/// the file diffs against `/dev/null`. Whether the file is binary follows `attributes`.
+pub async fn file_patch_series(
+    storage: &dyn ObjectStorage,
+    start_commit: &str,
+    path: &str,
+    attributes: &Attributes,
+) -> Result<Vec<FilePatch>, GitError> {
+    let path = path.trim_matches('/');
+    let mut commits = Vec::new();
+    let mut before = None;
+    loop {
+        let (page, next) = log_path(
+            storage,
+            start_commit,
+            path,
+            LOG_PAGE_SIZE,
+            before.as_deref(),
+        )
+        .await?;
+        commits.extend(page);
+        match next {
+            Some(next) => before = Some(next),
+            None => break,
+        }
+    }
+
+    let mut patches = Vec::with_capacity(commits.len());
+    for commit in commits.into_iter().rev() {
+        let old = match commit.parent_commit_ids.first() {
+            Some(parent) => {
+                let parent = load_commit(storage, parent).await?;
+                file_at_path(storage, parent.tree_id, path).await?
+            }
+            None => None,
+        };
+        let new = file_at_path(storage, commit.tree_id, path).await?;
+        let binary = [&old, &new]
+            .into_iter()
+            .flatten()
+            .any(|x| is_binary_for_diff(path, x, attributes));
+        let text = (!binary)
+            .then(|| unified_diff(old.as_deref(), new.as_deref(), path, CONTEXT_LINES))
+            .flatten();
+        let diff = text.unwrap_or_else(|| {
+            let side = |prefix: &str, data: &Option<Vec<u8>>| match data {
+                Some(_) => format!("{}/{}", prefix, path),
+                None => "/dev/null".to_owned(),
+            };
+            format!(
+                "Binary files {} and {} differ\n",
+                side("a", &old),
+                side("b", &new)
+            )
+        });
+        patches.push(FilePatch { commit, diff });
+    }
+    Ok(patches)
+}
+
+/// The content of the file at `path` in the tree `tree_id`, `None` if there is no blob there.
+async fn file_at_path(
+    storage: &dyn ObjectStorage,
+    tree_id: SHA1,
+    path: &str,
+) -> Result<Option<Vec<u8>>, GitError> {
+    match entry_id_at_path(storage, tree_id, path).await? {
+        Some(id) => Ok(storage.get_blob(&id).await?.map(|x| x.data)),
+        None => Ok(None),
+    }
+}
+
+/// The commits reachable from `to` but not from `from`, each after its parents, like
+/// `git rev-list --topo-order --reverse from..to`. A zero `from`, as for a new ref, excludes
+/// nothing.
+///
+/// Both sides are walked together a generation at a time, with a single lookup per generation.
+/// Like git, the walk stops once no commit left to walk leads to a commit of `to` only, and
+/// the commits left to walk from `from` are all older than the commits found so far, so the
+/// history below the fork point is not loaded.
+pub async fn commits_between(
+    storage: &dyn ObjectStorage,
+    from: &SHA1,
+    to: &SHA1,
+) -> Result<Vec<Commit>, GitError> {
+    let mut loaded: HashMap<SHA1, Commit> = HashMap::new();
+    // the commits known to be reachable from `from`, loaded or not
+    let mut excluded = HashSet::new();
+    let mut level = vec![*to];
+    if *from != SHA1::default() {
+        excluded.insert(*from);
+        level.push(*from);
+    }
+
+    while !level.is_empty() {
+        let commits: HashMap<SHA1, Commit> = storage
+            .get_commits(&level)
+            .await?
+            .into_iter()
+            .map(|x| (x.id, x))
+            .collect();
+        let mut next = Vec::new();
+        for id in &level {
+            let commit = commits
+                .get(id)
+                .ok_or_else(|| GitError::NotFountHashValue(id.to_plain_str()))?;
+            for parent in &commit.parent_commit_ids {
+                if !loaded.contains_key(parent) && !commits.contains_key(parent) {
+                    next.push(*parent);
+                }
+            }
+        }
+        loaded.extend(commits);
+        for id in &level {
+            if excluded.contains(id) {
+                exclude_parents(&loaded, &mut excluded, id);
+            }
+        }
+        next.sort();
+        next.dedup();
+
+        if next.iter().all(|x| excluded.contains(x)) {
+            let oldest = loaded
+                .values()
+                .filter(|x| !excluded.contains(&x.id))
+                .map(|x| x.committer.timestamp)
+                .min();
+            // a parent is older than its child, so a commit left to walk can only lead to a
+            // commit found so far if the one it was reached from is newer than that commit
+            let done = match oldest {
+                Some(oldest) => level
+                    .iter()
+                    .filter(|x| excluded.contains(x))
+                    .all(|x| loaded[x].committer.timestamp < oldest),
+                None => true,
+            };
+            if done {
+                break;
+            }
+        }
+        level = next;
+    }
+
+    let mut commits = Vec::new();
+    let mut visited = HashSet::new();
+    // a commit is emitted when it is popped the second time, after all of its parents
+    let mut stack = vec![(*to, false)];
+    while let Some((id, parents_done)) = stack.pop() {
+        if parents_done {
+            commits.push(loaded[&id].clone());
+            continue;
+        }
+        if excluded.contains(&id) || !visited.insert(id) {
+            continue;
+        }
+        stack.push((id, true));
+        for parent in loaded[&id].parent_commit_ids.iter().rev() {
+            stack.push((*parent, false));
+        }
+    }
+    Ok(commits)
+}
+
+/// Add the parents of the commit `id` to `excluded`, and the parents of those which are
+/// `loaded` already, recursively.
+fn exclude_parents(loaded: &HashMap<SHA1, Commit>, excluded: &mut HashSet<SHA1>, id: &SHA1) {
+    let mut stack = vec![*id];
+    while let Some(id) = stack.pop() {
+        let Some(commit) = loaded.get(&id) else {
+            continue;
+        };
+        for parent in &commit.parent_commit_ids {
+            if excluded.insert(*parent) {
+                stack.push(*parent);
+            }
+        }
+    }
+}
+
+/// Whether `ancestor` is reachable from `descendant` through parents, a commit being its own
+/// ancestor.
+pub async fn is_ancestor(
+    storage: &dyn ObjectStorage,
+    ancestor: &SHA1,
+    descendant: &SHA1,
+) -> Result<bool, GitError> {
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::from([*descendant]);
+    while let Some(id) = queue.pop_front() {
+        if id == *ancestor {
+            return Ok(true);
+        }
+        if visited.insert(id) {
+            queue.extend(load_commit(storage, &id).await?.parent_commit_ids);
+        }
+    }
+    Ok(false)
+}
+
+/// Every commit reachable from the commits `tips` through parents, including the tips
+/// themselves, each once.
+pub async fn reachable_commits(
+    storage: &dyn ObjectStorage,
+    tips: &[SHA1],
+) -> Result<Vec<Commit>, GitError> {
+    let mut visited = HashSet::new();
+    let mut commits = Vec::new();
+    let mut queue: VecDeque<SHA1> = tips.iter().copied().collect();
+    while let Some(id) = queue.pop_front() {
+        if visited.insert(id) {
+            let commit = load_commit(storage, &id).await?;
+            queue.extend(commit.parent_commit_ids.iter().copied());
+            commits.push(commit);
+        }
+    }
+    Ok(commits)
+}
+
+/// Every commit, tree and blob reachable from the commits `tips`, including the tips themselves.
+/// Submodule commits live in other repositories and are left out. Trees are loaded a level at a
+/// time, and trees shared between commits only once.
+pub async fn reachable_objects(
+    storage: &dyn ObjectStorage,
+    tips: &[SHA1],
+) -> Result<HashSet<SHA1>, GitError> {
+    let mut reachable = HashSet::new();
+    let mut level = Vec::new();
+    for commit in reachable_commits(storage, tips).await? {
+        reachable.insert(commit.id);
+        if reachable.insert(commit.tree_id) {
+            level.push(commit.tree_id);
+        }
+    }
+
+    while !level.is_empty() {
+        let trees = storage.get_trees(&level).await?;
+        if let Some(missing) = level.iter().find(|x| !trees.iter().any(|t| t.id == **x)) {
+            return Err(GitError::NotFountHashValue(missing.to_plain_str()));
+        }
+        level = Vec::new();
+        for item in trees.into_iter().flat_map(|x| x.tree_items) {
+            if item.mode == TreeItemMode::Commit || !reachable.insert(item.id) {
+                continue;
+            }
+            if item.mode == TreeItemMode::Tree {
+                level.push(item.id);
+            }
+        }
+    }
+    Ok(reachable)
+}
+
+/// Reject an update of a ref which isn't a fast-forward, i.e. whose old value isn't an ancestor
+/// of the new one, unless the command is forced. Creating and deleting refs always passes.
+pub async fn check_fast_forward(
+    storage: &dyn ObjectStorage,
+    command: &RefCommand,
+) -> Result<(), GitError> {
+    if command.command_type != CommandType::Update || command.force {
+        return Ok(());
+    }
+    let old = SHA1::from_str(&command.old_id).map_err(GitError::InvalidHashValue)?;
+    let new = SHA1::from_str(&command.new_id).map_err(GitError::InvalidHashValue)?;
+    if !is_ancestor(storage, &old, &new).await? {
+        return Err(GitError::NonFastForward(command.ref_name.clone()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use jupiter::storage::object_storage::{MemoryStorage, ObjectStorage};
+    use venus::errors::GitError;
+    use venus::hash::SHA1;
+    use venus::internal::object::blob::Blob;
+    use venus::internal::object::commit::Commit;
+    use venus::internal::pack::reference::RefCommand;
+
+    use super::{
+        check_fast_forward, commits_between, file_patch_series, last_commit_for_path, log_path,
+        reachable_objects, tree_at,
+    };
+    use crate::monorepo::attributes::Attributes;
+    use crate::test_util::save_commit;
+
+    /// The files of a commit, `src/main.rs` with `content` and a readme saying `readme`.
+    fn files<'a>(content: &'a str, readme: &'a str) -> [(&'a str, &'a str); 2] {
+        [("README.md", readme), ("src/main.rs", content)]
+    }
+
+    #[tokio::test]
+    async fn test_last_commit_for_path() {
+        let storage = MemoryStorage::new();
+        let c1 = save_commit(&storage, &files("fn main() {}", "first"), &[]);
+        let c2 = save_commit(&storage, &files("fn main() { todo!() }", "second"), &[&c1]);
+        let c3 = save_commit(&storage, &files("fn main() { todo!() }", "third"), &[&c2]);
+        let head = c3.id.to_plain_str();
+
+        let found = last_commit_for_path(&storage, &head, "src/main.rs")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.id, c2.id);
+
+        // the readme changes in every commit
+        let found = last_commit_for_path(&storage, &head, "README.md")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.id, c3.id);
+
+        let found = last_commit_for_path(&storage, &c1.id.to_plain_str(), "src/main.rs")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.id, c1.id);
+
+        assert!(last_commit_for_path(&storage, &head, "src/lib.rs")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tree_at() {
+        let storage = MemoryStorage::new();
+        let commit = save_commit(&storage, &files("fn main() {}", "init"), &[]);
+        let hash = commit.id.to_plain_str();
+
+        let root = tree_at(&storage, &hash, "").await.unwrap().unwrap();
+        assert_eq!(root.id, commit.tree_id);
+        let src = tree_at(&storage, &hash, "/src/").await.unwrap().unwrap();
+        assert_eq!(src.tree_items.len(), 1);
+        assert_eq!(src.tree_items[0].name, "main.rs");
+        // a file and a missing path
+        assert!(tree_at(&storage, &hash, "src/main.rs")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(tree_at(&storage, &hash, "src/main.rs/x")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(tree_at(&storage, &hash, "docs").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_log_path_pages() {
+        let storage = MemoryStorage::new();
+        let c1 = save_commit(&storage, &files("fn main() {}", "first"), &[]);
+        let c2 = save_commit(&storage, &files("fn main() { run() }", "second"), &[&c1]);
+        let c3 = save_commit(&storage, &files("fn main() { run() }", "third"), &[&c2]);
+        let c4 = save_commit(&storage, &files("fn main() { exit() }", "fourth"), &[&c3]);
+        let head = c4.id.to_plain_str();
+
+        let (first, cursor) = log_path(&storage, &head, "src/main.rs", 2, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            first.iter().map(|x| x.id).collect::<Vec<_>>(),
+            vec![c4.id, c2.id]
+        );
+        assert_eq!(cursor, Some(c2.id.to_plain_str()));
+
+        let (second, cursor) = log_path(&storage, &head, "src/main.rs", 2, cursor.as_deref())
+            .await
+            .unwrap();
+        assert_eq!(second.iter().map(|x| x.id).collect::<Vec<_>>(), vec![c1.id]);
+        assert_eq!(cursor, None);
+
+        // the directory changes with the file
+        let (all, _) = log_path(&storage, &head, "src", 10, None).await.unwrap();
+        assert_eq!(all.len(), 3);
+        let (missing, cursor) = log_path(&storage, &head, "src/lib.rs", 10, None)
+            .await
+            .unwrap();
+        assert!(missing.is_empty());
+        assert_eq!(cursor, None);
+    }
+
+    #[tokio::test]
+    async fn test_file_patch_series() {
+        let storage = MemoryStorage::new();
+        let revisions = [
+            "fn main() {}\n",
+            "fn main() {\n    run();\n}\n",
+            "fn main() {\n    run();\n    exit();\n}\n",
+        ];
+        for content in revisions {
+            storage.insert_blob(Blob::from_content(content));
+        }
+        let c1 = save_commit(&storage, &files(revisions[0], "first"), &[]);
+        let c2 = save_commit(&storage, &files(revisions[1], "second"), &[&c1]);
+        // only the readme changes
+        let c3 = save_commit(&storage, &files(revisions[1], "third"), &[&c2]);
+        let c4 = save_commit(&storage, &files(revisions[2], "fourth"), &[&c3]);
+
+        let patches = file_patch_series(
+            &storage,
+            &c4.id.to_plain_str(),
+            "src/main.rs",
+            &Attributes::default(),
+        )
+        .await
+        .unwrap();
+        let ids: Vec<_> = patches.iter().map(|x| x.commit.id).collect();
+        assert_eq!(ids, vec![c1.id, c2.id, c4.id]);
+        assert_eq!(
+            patches[0].diff,
+            "--- /dev/null\n+++ b/src/main.rs\n@@ -0,0 +1 @@\n+fn main() {}\n"
+        );
+        assert_eq!(
+            patches[1].diff,
+            "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1 +1,3 @@\n\
+             -fn main() {}\n+fn main() {\n+    run();\n+}\n"
+        );
+        assert_eq!(
+            patches[2].diff,
+            "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,3 +1,4 @@\n\
+             \x20fn main() {\n     run();\n+    exit();\n }\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commits_between_with_merge() {
+        let storage = MemoryStorage::new();
+        let base = save_commit(&storage, &files("base", "base"), &[]);
+        let left = save_commit(&storage, &files("left", "left"), &[&base]);
+        let right = save_commit(&storage, &files("right", "right"), &[&base]);
+        let merge = save_commit(&storage, &files("merge", "merge"), &[&left, &right]);
+
+        let commits = commits_between(&storage, &base.id, &merge.id)
+            .await
+            .unwrap();
+        let ids: Vec<SHA1> = commits.iter().map(|x| x.id).collect();
+        assert_eq!(ids, vec![left.id, right.id, merge.id]);
+        assert!(commits_between(&storage, &merge.id, &merge.id)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // from a side branch, the commits it shares with the merge are left out too
+        let commits = commits_between(&storage, &right.id, &merge.id)
+            .await
+            .unwrap();
+        let ids: Vec<SHA1> = commits.iter().map(|x| x.id).collect();
+        assert_eq!(ids, vec![left.id, merge.id]);
+    }
+
+    #[tokio::test]
+    async fn test_check_fast_forward() {
+        let storage = MemoryStorage::default();
+        let base = save_commit(&storage, &files("fn main() {}", "base"), &[]);
+        let next = save_commit(&storage, &files("fn main() { run() }", "next"), &[&base]);
+        let other = save_commit(&storage, &files("fn main() { exit() }", "other"), &[&base]);
+        let update = |old: &Commit, new: &Commit| {
+            RefCommand::new(
+                old.id.to_plain_str(),
+                new.id.to_plain_str(),
+                "refs/heads/main".to_owned(),
+            )
+        };
+
+        check_fast_forward(&storage, &update(&base, &next))
+            .await
+            .unwrap();
+
+        let mut rewrite = update(&next, &other);
+        let result = check_fast_forward(&storage, &rewrite).await;
+        assert!(matches!(result, Err(GitError::NonFastForward(name)) if name == "refs/heads/main"));
+
+        rewrite.force = true;
+        check_fast_forward(&storage, &rewrite).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reachable_objects() {
+        let storage = MemoryStorage::new();
+        let base = save_commit(&storage, &files("fn main() {}", "base"), &[]);
+        let next = save_commit(&storage, &files("fn main() { run() }", "next"), &[&base]);
+        let unrelated = save_commit(&storage, &files("fn main() { exit() }", "unrelated"), &[]);
+
+        let reachable = reachable_objects(&storage, &[base.id]).await.unwrap();
+        let root = storage.get_tree(&base.tree_id).await.unwrap().unwrap();
+        let src = root.tree_items[1].id;
+        let src = storage.get_tree(&src).await.unwrap().unwrap();
+        let expected = [
+            base.id,
+            root.id,
+            root.tree_items[0].id,
+            src.id,
+            src.tree_items[0].id,
+        ];
+        assert_eq!(reachable.len(), expected.len());
+        assert!(expected.iter().all(|x| reachable.contains(x)));
+        assert!(!reachable.contains(&next.id));
+        assert!(!reachable.contains(&unrelated.tree_id));
+
+        // the objects next adds on top of base
+        let added = reachable_objects(&storage, &[next.id]).await.unwrap();
+        let added: Vec<&SHA1> = added.difference(&reachable).collect();
+        assert_eq!(added.len(), 5);
+        assert!(added.contains(&&next.id));
+        assert!(!added.contains(&&Blob::from_content("fn main() {}").id));
+    }
+}
@@ -0,0 +1,194 @@
+//! Hashing a directory on disk into monorepo objects.
+//!
+//! The directory is read into a [`FlatTree`] of blobs, [`build_tree`] then creates the trees.
+//! Git only knows a handful of file modes, which are taken from the file metadata where the
+//! platform provides them. Elsewhere, e.g. on Windows where there is no executable bit, files get
+//! [`ImportOptions::default_file_mode`].
+use std::fs;
+use std::path::Path;
+
+use jupiter::storage::object_storage::ObjectStorage;
+use venus::errors::GitError;
+use venus::hash::SHA1;
+use venus::internal::object::blob::Blob;
+use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+use venus::internal::object::types::ObjectType;
+
+use crate::monorepo::diff::{build_tree, diff_flat_trees, flatten_tree, ChangeKind, FlatTree};
+use crate::pack::sparse::SparsePatterns;
+
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    /// The mode of files whose permissions can't be read from the platform.
+    pub default_file_mode: TreeItemMode,
+    /// Gitignore style patterns of the files to skip, relative to the imported directory.
+    pub ignore: Option<SparsePatterns>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            default_file_mode: TreeItemMode::Blob,
+            ignore: None,
+        }
+    }
+}
+
+impl ImportOptions {
+    /// The default options, ignoring the files matched by the `.gitignore` directly in `path` if
+    /// there is one. Nested `.gitignore` files aren't read.
+    pub fn with_gitignore(path: &Path) -> Result<Self, GitError> {
+        let ignore = match fs::read_to_string(path.join(".gitignore")) {
+            Ok(content) => Some(SparsePatterns::glob(&content)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+        Ok(ImportOptions {
+            ignore,
+            ..Default::default()
+        })
+    }
+}
+
+/// Hash the files below `path`, returning the root tree id, every tree created with the root
+/// last, and the blobs. The `.git` directory and empty directories are skipped.
+pub fn hash_directory(
+    path: &Path,
+    options: &ImportOptions,
+) -> Result<(SHA1, Vec<Tree>, Vec<Blob>), GitError> {
+    let mut flat = FlatTree::new();
+    let mut blobs = Vec::new();
+    walk_files(path, options, |path, item, data| {
+        blobs.push(Blob { id: item.id, data });
+        flat.insert(path, item);
+    })?;
+    let (root, trees) = build_tree(&flat)?;
+    Ok((root, trees, blobs))
+}
+
+/// Call `visit` with the path, relative to `path`, the tree entry and the content of every file
+/// below `path` which isn't ignored, one file at a time. The `.git` directory is skipped.
+fn walk_files(
+    path: &Path,
+    options: &ImportOptions,
+    mut visit: impl FnMut(String, TreeItem, Vec<u8>),
+) -> Result<(), GitError> {
+    let mut dirs = vec![(path.to_path_buf(), String::new())];
+    while let Some((dir, prefix)) = dirs.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let file_type = entry.file_type()?;
+            let path = format!("{}{}", prefix, name);
+            if file_type.is_dir() {
+                if name != ".git" {
+                    dirs.push((entry.path(), format!("{}/", path)));
+                }
+                continue;
+            }
+            if options.ignore.as_ref().is_some_and(|x| x.includes(&path)) {
+                continue;
+            }
+            let (mode, data) = if file_type.is_symlink() {
+                let target = fs::read_link(entry.path())?;
+                let target = target.to_string_lossy().into_owned().into_bytes();
+                (TreeItemMode::Link, target)
+            } else {
+                let mode = TreeItemMode::from_metadata(&entry.metadata()?);
+                (
+                    mode.unwrap_or(options.default_file_mode),
+                    fs::read(entry.path())?,
+                )
+            };
+            let id = SHA1::from_type_and_data(ObjectType::Blob, &data);
+            visit(path, TreeItem::new(mode, id, name), data);
+        }
+    }
+    Ok(())
+}
+
+/// The files of `working_dir` added, modified or deleted compared to the tree `head`, ordered by
+/// path, like `git status`. Files matched by the `.gitignore` of `working_dir` are left out.
+///
+/// Files are compared by their blob ids, the content of a file is dropped once it is hashed.
+pub async fn working_dir_status(
+    storage: &dyn ObjectStorage,
+    working_dir: &Path,
+    head: SHA1,
+) -> Result<Vec<(String, ChangeKind)>, GitError> {
+    let options = ImportOptions::with_gitignore(working_dir)?;
+    let mut new = FlatTree::new();
+    walk_files(working_dir, &options, |path, item, _| {
+        new.insert(path, item);
+    })?;
+    let old = flatten_tree(storage, head).await?;
+    Ok(diff_flat_trees(&old, &new)
+        .into_iter()
+        .map(|x| {
+            let kind = match (&x.old, &x.new) {
+                (None, _) => ChangeKind::Added,
+                (_, None) => ChangeKind::Deleted,
+                _ => ChangeKind::Modified,
+            };
+            (x.path, kind)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+
+    use jupiter::storage::object_storage::{MemoryStorage, ObjectStorage};
+    use venus::internal::object::tree::TreeItemMode;
+
+    use super::{hash_directory, working_dir_status, ImportOptions};
+    use crate::monorepo::diff::ChangeKind;
+
+    #[test]
+    fn test_plain_file_gets_blob_mode() {
+        let dir = env::temp_dir().join("mega_hash_directory");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join("README.md"), "# readme\n").unwrap();
+        fs::write(dir.join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::write(dir.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        let (root, trees, blobs) = hash_directory(&dir, &ImportOptions::default()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(blobs.len(), 2);
+        let root = trees.iter().find(|x| x.id == root).unwrap();
+        let names: Vec<&str> = root.tree_items.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, vec!["README.md", "src"]);
+        assert_eq!(root.tree_items[0].mode, TreeItemMode::Blob);
+        assert_eq!(root.tree_items[1].mode, TreeItemMode::Tree);
+    }
+
+    #[tokio::test]
+    async fn test_working_dir_status() {
+        let dir = env::temp_dir().join("mega_working_dir_status");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join(".gitignore"), "target/\n*.log\n").unwrap();
+        fs::write(dir.join("README.md"), "# readme\n").unwrap();
+        fs::write(dir.join("src/main.rs"), "fn main() {}\n").unwrap();
+        let (head, trees, _) = hash_directory(&dir, &ImportOptions::default()).unwrap();
+        let storage = MemoryStorage::new();
+        storage.save_trees(trees).await.unwrap();
+
+        fs::write(dir.join("src/main.rs"), "fn main() { run() }\n").unwrap();
+        fs::create_dir_all(dir.join("src/target")).unwrap();
+        fs::write(dir.join("src/target/out.o"), "obj").unwrap();
+        fs::write(dir.join("build.log"), "done\n").unwrap();
+        let status = working_dir_status(&storage, &dir, head).await;
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            status.unwrap(),
+            vec![("src/main.rs".to_owned(), ChangeKind::Modified)]
+        );
+    }
+}
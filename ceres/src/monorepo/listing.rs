@@ -0,0 +1,161 @@
+//! Listing directories a page at a time.
+//!
+//! A directory can hold tens of thousands of entries, which a web client should get in pages.
+//! Pages follow the canonical order of tree entries, and the cursor of the next page is the name
+//! of the last entry of the previous one. Since names are unique within a tree, a cursor stays
+//! valid when entries are added or removed between two calls.
+//!
+//! For directory stats, [`recursive_counts`] counts the entries of a whole directory tree.
+use jupiter::storage::object_storage::ObjectStorage;
+use venus::errors::GitError;
+use venus::hash::SHA1;
+use venus::internal::object::tree::{TreeCounts, TreeItem};
+
+use crate::monorepo::diff::{sort_tree_items, tree_sort_key};
+use crate::monorepo::history::entry_id_at_path;
+use crate::monorepo::walk::walk_batched;
+
+/// List at most `limit` entries of the directory at `path` below the tree `root`, starting after
+/// the entry named `cursor`. Returns the entries and the cursor of the next page, `None` once the
+/// directory is exhausted.
+pub async fn list_dir_page(
+    storage: &dyn ObjectStorage,
+    root: SHA1,
+    path: &str,
+    cursor: Option<&str>,
+    limit: usize,
+) -> Result<(Vec<TreeItem>, Option<String>), GitError> {
+    let tree = match entry_id_at_path(storage, root, path).await? {
+        Some(id) => storage.get_tree(&id).await?,
+        None => None,
+    };
+    let mut items = tree
+        .ok_or_else(|| GitError::InvalidPathError(path.to_owned()))?
+        .tree_items;
+    sort_tree_items(&mut items);
+
+    let start = match cursor {
+        Some(cursor) => {
+            // the entry may have been removed since, then its name still tells where it was
+            let key = items
+                .iter()
+                .find(|x| x.name == cursor)
+                .map(tree_sort_key)
+                .unwrap_or_else(|| cursor.to_owned());
+            items.partition_point(|x| tree_sort_key(x) <= key)
+        }
+        None => 0,
+    };
+    // an empty page would never advance
+    let end = (start + limit.max(1)).min(items.len());
+    let next = (end < items.len()).then(|| items[end - 1].name.clone());
+    let page = items.drain(start..end).collect();
+    Ok((page, next))
+}
+
+/// Count the entries of the tree `root` and of every tree below it. A subtree stored once but
+/// found at several paths is counted at each of them.
+pub async fn recursive_counts(
+    storage: &dyn ObjectStorage,
+    root: SHA1,
+) -> Result<TreeCounts, GitError> {
+    let mut counts = TreeCounts::default();
+    for (_, tree) in walk_batched(storage, root).await? {
+        counts += tree.counts();
+    }
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod test {
+    use jupiter::storage::object_storage::MemoryStorage;
+    use venus::hash::SHA1;
+    use venus::internal::object::blob::Blob;
+    use venus::internal::object::tree::{Tree, TreeCounts, TreeItem, TreeItemMode};
+
+    use super::{list_dir_page, recursive_counts};
+
+    #[tokio::test]
+    async fn test_page_through_large_directory() {
+        let storage = MemoryStorage::default();
+        let blob = Blob::from_content("generated");
+        let mut items: Vec<TreeItem> = (0..3000)
+            .map(|i| TreeItem::new(TreeItemMode::Blob, blob.id, format!("file{:04}.rs", i)))
+            .collect();
+        // sorts after `file0000.rs` as a tree, it would come before it as a blob
+        let dir = Tree::from_tree_items(items.clone()).unwrap();
+        items.push(TreeItem::new(
+            TreeItemMode::Tree,
+            dir.id,
+            "file0000".to_owned(),
+        ));
+        let generated = Tree::from_tree_items(items).unwrap();
+        let root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Tree,
+            generated.id,
+            "generated".to_owned(),
+        )])
+        .unwrap();
+        let root_id = root.id;
+        storage.insert_tree(dir);
+        storage.insert_tree(generated);
+        storage.insert_tree(root);
+
+        let (first, cursor) = list_dir_page(&storage, root_id, "generated", None, 2000)
+            .await
+            .unwrap();
+        assert_eq!(first.len(), 2000);
+        assert_eq!(first[0].name, "file0000.rs");
+        assert_eq!(first[1].name, "file0000");
+        assert_eq!(cursor.as_deref(), Some("file1998.rs"));
+
+        let (second, cursor) =
+            list_dir_page(&storage, root_id, "generated", cursor.as_deref(), 2000)
+                .await
+                .unwrap();
+        assert_eq!(second.len(), 1001);
+        assert_eq!(second[0].name, "file1999.rs");
+        assert_eq!(second[1000].name, "file2999.rs");
+        assert_eq!(cursor, None);
+
+        assert!(list_dir_page(&storage, root_id, "missing", None, 10)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_recursive_counts() {
+        let storage = MemoryStorage::default();
+        let blob = Blob::from_content("fn main() {}");
+        let src = Tree::from_tree_items(vec![
+            TreeItem::new(TreeItemMode::Blob, blob.id, "main.rs".to_owned()),
+            TreeItem::new(TreeItemMode::Link, blob.id, "lib.rs".to_owned()),
+        ])
+        .unwrap();
+        let root = Tree::from_tree_items(vec![
+            TreeItem::new(TreeItemMode::Blob, blob.id, "build.rs".to_owned()),
+            TreeItem::new(TreeItemMode::Tree, src.id, "examples".to_owned()),
+            TreeItem::new(TreeItemMode::Tree, src.id, "src".to_owned()),
+            TreeItem::new(
+                TreeItemMode::Commit,
+                SHA1::new(&b"zlib".to_vec()),
+                "zlib".to_owned(),
+            ),
+        ])
+        .unwrap();
+        let root_id = root.id;
+        storage.insert_tree(src);
+        storage.insert_tree(root);
+
+        let counts = recursive_counts(&storage, root_id).await.unwrap();
+        assert_eq!(
+            counts,
+            TreeCounts {
+                files: 3,
+                dirs: 2,
+                symlinks: 2,
+                submodules: 1,
+            }
+        );
+    }
+}
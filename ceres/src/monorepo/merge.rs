@@ -0,0 +1,594 @@
+//! Merging two lines of history into a merge commit.
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use futures::future::BoxFuture;
+use jupiter::storage::object_storage::ObjectStorage;
+use venus::errors::GitError;
+use venus::hash::SHA1;
+use venus::internal::object::commit::Commit;
+use venus::internal::object::signature::{Signature, SignatureType};
+use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+
+use crate::monorepo::diff::sort_tree_items;
+use crate::monorepo::history::load_commit;
+
+/// The outcome of a [`three_way_merge`].
+#[derive(Debug)]
+pub enum MergeResult {
+    /// The id of the merged tree, with every tree object created for it, the root last.
+    Clean { tree_id: SHA1, trees: Vec<Tree> },
+    /// The paths changed on both sides in different ways.
+    Conflict(Vec<String>),
+}
+
+/// Merge the changes `ours` and `theirs` made to `base`, entry by entry. An entry changed on one
+/// side only takes that side, an entry changed identically on both sides is kept, and an entry
+/// changed differently on both sides is a conflict unless it is a directory on both sides, then
+/// the merge recurses into it. Directories left without entries are dropped.
+pub async fn three_way_merge(
+    storage: &dyn ObjectStorage,
+    base: &Tree,
+    ours: &Tree,
+    theirs: &Tree,
+) -> Result<MergeResult, GitError> {
+    merge_trees(storage, Some(base.clone()), ours.clone(), theirs.clone()).await
+}
+
+async fn merge_trees(
+    storage: &dyn ObjectStorage,
+    base: Option<Tree>,
+    ours: Tree,
+    theirs: Tree,
+) -> Result<MergeResult, GitError> {
+    let mut trees = Vec::new();
+    let mut conflicts = Vec::new();
+    let dirs = (base, Some(ours), Some(theirs));
+    let root = merge_dir(storage, dirs, String::new(), &mut trees, &mut conflicts).await?;
+    if !conflicts.is_empty() {
+        return Ok(MergeResult::Conflict(conflicts));
+    }
+    match root {
+        Some(tree_id) => Ok(MergeResult::Clean { tree_id, trees }),
+        None => Err(GitError::EmptyTreeItems(
+            "Can't build a tree without entries".to_owned(),
+        )),
+    }
+}
+
+fn entries(tree: &Option<Tree>) -> BTreeMap<String, TreeItem> {
+    tree.iter()
+        .flat_map(|x| x.tree_items.iter())
+        .map(|x| (x.name.clone(), x.clone()))
+        .collect()
+}
+
+async fn load_tree(storage: &dyn ObjectStorage, id: &SHA1) -> Result<Tree, GitError> {
+    storage
+        .get_tree(id)
+        .await?
+        .ok_or_else(|| GitError::NotFountHashValue(id.to_plain_str()))
+}
+
+/// Load the tree of a directory entry, `None` if there is no entry or it isn't a directory.
+async fn load_dir(
+    storage: &dyn ObjectStorage,
+    item: Option<&TreeItem>,
+) -> Result<Option<Tree>, GitError> {
+    match item {
+        Some(item) if item.mode == TreeItemMode::Tree => {
+            load_tree(storage, &item.id).await.map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Merge the (base, ours, theirs) versions of the directory at `path`. Returns the id of the
+/// merged directory, `None` if it ended up empty.
+fn merge_dir<'a>(
+    storage: &'a dyn ObjectStorage,
+    (base, ours, theirs): (Option<Tree>, Option<Tree>, Option<Tree>),
+    path: String,
+    trees: &'a mut Vec<Tree>,
+    conflicts: &'a mut Vec<String>,
+) -> BoxFuture<'a, Result<Option<SHA1>, GitError>> {
+    Box::pin(async move {
+        let (base, ours, theirs) = (entries(&base), entries(&ours), entries(&theirs));
+        let names: BTreeSet<&String> = base
+            .keys()
+            .chain(ours.keys())
+            .chain(theirs.keys())
+            .collect();
+
+        let mut items = Vec::new();
+        for name in names {
+            let (b, o, t) = (base.get(name), ours.get(name), theirs.get(name));
+            if o == t || b == t {
+                items.extend(o.cloned());
+                continue;
+            }
+            if b == o {
+                items.extend(t.cloned());
+                continue;
+            }
+            let child_path = if path.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", path, name)
+            };
+            let is_dir = |x: Option<&TreeItem>| x.is_some_and(|x| x.mode == TreeItemMode::Tree);
+            if !(is_dir(o) && is_dir(t)) {
+                conflicts.push(child_path);
+                continue;
+            }
+            let dirs = (
+                load_dir(storage, b).await?,
+                load_dir(storage, o).await?,
+                load_dir(storage, t).await?,
+            );
+            if let Some(id) = merge_dir(storage, dirs, child_path, trees, conflicts).await? {
+                items.push(TreeItem::new(TreeItemMode::Tree, id, name.clone()));
+            }
+        }
+
+        if items.is_empty() {
+            return Ok(None);
+        }
+        sort_tree_items(&mut items);
+        let tree = Tree::from_tree_items(items)?;
+        let id = tree.id;
+        trees.push(tree);
+        Ok(Some(id))
+    })
+}
+
+/// Find a common ancestor of `ours` and `theirs`, the first ancestor of `theirs` in breadth
+/// first order which is also reachable from `ours`. Both histories are walked a generation at a
+/// time, with one lookup for the commits of each generation. `None` if the histories are
+/// unrelated.
+pub async fn merge_base(
+    storage: &dyn ObjectStorage,
+    ours: &SHA1,
+    theirs: &SHA1,
+) -> Result<Option<SHA1>, GitError> {
+    let mut ancestors = HashSet::new();
+    let mut level = vec![*ours];
+    while !level.is_empty() {
+        level.retain(|x| ancestors.insert(*x));
+        level = load_parents(storage, &level).await?;
+    }
+
+    let mut visited = HashSet::new();
+    let mut level = vec![*theirs];
+    while !level.is_empty() {
+        if let Some(id) = level.iter().find(|x| ancestors.contains(x)) {
+            return Ok(Some(*id));
+        }
+        level.retain(|x| visited.insert(*x));
+        level = load_parents(storage, &level).await?;
+    }
+    Ok(None)
+}
+
+/// The parents of the commits `ids` in order, with a single lookup for all of them.
+async fn load_parents(storage: &dyn ObjectStorage, ids: &[SHA1]) -> Result<Vec<SHA1>, GitError> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let commits: HashMap<SHA1, Commit> = storage
+        .get_commits(ids)
+        .await?
+        .into_iter()
+        .map(|x| (x.id, x))
+        .collect();
+    let mut parents = Vec::new();
+    for id in ids {
+        let commit = commits
+            .get(id)
+            .ok_or_else(|| GitError::NotFountHashValue(id.to_plain_str()))?;
+        parents.extend(commit.parent_commit_ids.iter().copied());
+    }
+    Ok(parents)
+}
+
+/// Merge `theirs` into `ours` and save the resulting merge commit, whose first parent is `ours`
+/// and second parent `theirs`, together with its new trees. No ref is updated. A path changed on
+/// both sides since the merge base in different ways is a conflict, in that case nothing is saved
+/// and [`GitError::ConflictError`] lists the conflicting paths.
+pub async fn merge_commits(
+    storage: &dyn ObjectStorage,
+    ours: &SHA1,
+    theirs: &SHA1,
+    message: &str,
+) -> Result<Commit, GitError> {
+    let ours_commit = load_commit(storage, ours).await?;
+    let theirs_commit = load_commit(storage, theirs).await?;
+    // unrelated histories are merged as if both sides were added to an empty tree
+    let base = match merge_base(storage, ours, theirs).await? {
+        Some(id) => Some(load_tree(storage, &load_commit(storage, &id).await?.tree_id).await?),
+        None => None,
+    };
+
+    let ours_tree = load_tree(storage, &ours_commit.tree_id).await?;
+    let theirs_tree = load_tree(storage, &theirs_commit.tree_id).await?;
+    let (tree_id, trees) = match merge_trees(storage, base, ours_tree, theirs_tree).await? {
+        MergeResult::Clean { tree_id, trees } => (tree_id, trees),
+        MergeResult::Conflict(paths) => return Err(GitError::ConflictError(paths)),
+    };
+
+    let commit = Commit::from_tree_id(tree_id, vec![ours_commit.id, theirs_commit.id], message);
+    storage.save_trees(trees).await?;
+    storage.save_commits(vec![commit.clone()]).await?;
+    Ok(commit)
+}
+
+/// Apply the changes `commit` made to its first parent on top of `onto`, with a three-way merge
+/// of the trees, and save the result as a new commit on `onto` with the author and message of
+/// `commit`. No ref is updated. If the changes don't apply cleanly nothing is saved and
+/// [`GitError::ConflictError`] lists the conflicting paths.
+pub async fn cherry_pick_commit(
+    storage: &dyn ObjectStorage,
+    commit: &SHA1,
+    onto: &SHA1,
+) -> Result<Commit, GitError> {
+    let picked = load_commit(storage, commit).await?;
+    let onto_commit = load_commit(storage, onto).await?;
+    // a root commit is picked as if it added everything to an empty tree
+    let base = match picked.parent_commit_ids.first() {
+        Some(parent) => {
+            Some(load_tree(storage, &load_commit(storage, parent).await?.tree_id).await?)
+        }
+        None => None,
+    };
+
+    let onto_tree = load_tree(storage, &onto_commit.tree_id).await?;
+    let picked_tree = load_tree(storage, &picked.tree_id).await?;
+    let (tree_id, trees) = match merge_trees(storage, base, onto_tree, picked_tree).await? {
+        MergeResult::Clean { tree_id, trees } => (tree_id, trees),
+        MergeResult::Conflict(paths) => return Err(GitError::ConflictError(paths)),
+    };
+
+    let commit = Commit::new(
+        picked.author,
+        picked.committer,
+        tree_id,
+        vec![onto_commit.id],
+        &picked.message,
+    );
+    storage.save_trees(trees).await?;
+    storage.save_commits(vec![commit.clone()]).await?;
+    Ok(commit)
+}
+
+/// Replay the commits of the branch `tip` since its merge base with `onto`, following first
+/// parents, on top of `onto` and return the new tip. The replayed commits are saved, no ref is
+/// updated. Replaying stops at the first commit which doesn't apply cleanly with
+/// [`GitError::ConflictError`], the commits replayed before it stay saved.
+pub async fn rebase_commits(
+    storage: &dyn ObjectStorage,
+    tip: &SHA1,
+    onto: &SHA1,
+) -> Result<Commit, GitError> {
+    let base = merge_base(storage, onto, tip).await?;
+    let mut commits = Vec::new();
+    let mut id = Some(*tip);
+    while let Some(current) = id.filter(|x| Some(*x) != base) {
+        let commit = load_commit(storage, &current).await?;
+        id = commit.parent_commit_ids.first().copied();
+        commits.push(commit.id);
+    }
+
+    let mut new_tip = load_commit(storage, onto).await?;
+    for commit in commits.iter().rev() {
+        new_tip = cherry_pick_commit(storage, commit, &new_tip.id).await?;
+    }
+    Ok(new_tip)
+}
+
+/// Squash the commits after `base` up to `tip` into a single commit on top of `base` with the
+/// tree of `tip`, authored and committed by `author`. The commit is saved, no ref is updated.
+pub async fn squash_commits(
+    storage: &dyn ObjectStorage,
+    tip: &SHA1,
+    base: &SHA1,
+    message: &str,
+    author: Signature,
+) -> Result<Commit, GitError> {
+    let tip_commit = load_commit(storage, tip).await?;
+    let base_commit = load_commit(storage, base).await?;
+
+    let committer = Signature {
+        signature_type: SignatureType::Committer,
+        ..author.clone()
+    };
+    let commit = Commit::new(
+        author,
+        committer,
+        tip_commit.tree_id,
+        vec![base_commit.id],
+        message,
+    );
+    storage.save_commits(vec![commit.clone()]).await?;
+    Ok(commit)
+}
+
+#[cfg(test)]
+mod test {
+    use jupiter::storage::object_storage::{MemoryStorage, ObjectStorage};
+    use venus::errors::GitError;
+    use venus::internal::object::blob::Blob;
+    use venus::internal::object::signature::{Signature, SignatureType};
+
+    use super::{
+        cherry_pick_commit, merge_base, merge_commits, rebase_commits, squash_commits,
+        three_way_merge, MergeResult,
+    };
+    use crate::monorepo::diff::flatten_tree;
+    use crate::test_util::{save_commit, save_tree};
+
+    #[tokio::test]
+    async fn test_three_way_merge_clean() {
+        let storage = MemoryStorage::new();
+        let base = save_tree(&storage, &[("README.md", "readme"), ("src/lib.rs", "lib")]);
+        // ours modifies the readme and adds a file in src
+        let ours = save_tree(
+            &storage,
+            &[
+                ("README.md", "readme v2"),
+                ("src/lib.rs", "lib"),
+                ("src/ours.rs", "ours"),
+            ],
+        );
+        // theirs adds a file in src and a new directory
+        let theirs = save_tree(
+            &storage,
+            &[
+                ("README.md", "readme"),
+                ("src/lib.rs", "lib"),
+                ("src/theirs.rs", "theirs"),
+                ("docs/guide.md", "guide"),
+            ],
+        );
+
+        let (tree_id, trees) = match three_way_merge(&storage, &base, &ours, &theirs).await {
+            Ok(MergeResult::Clean { tree_id, trees }) => (tree_id, trees),
+            other => panic!("unexpected result: {:?}", other),
+        };
+        assert_eq!(trees.last().unwrap().id, tree_id);
+        for tree in trees {
+            storage.insert_tree(tree);
+        }
+        let files = flatten_tree(&storage, tree_id).await.unwrap();
+        let expected = save_tree(
+            &storage,
+            &[
+                ("README.md", "readme v2"),
+                ("docs/guide.md", "guide"),
+                ("src/lib.rs", "lib"),
+                ("src/ours.rs", "ours"),
+                ("src/theirs.rs", "theirs"),
+            ],
+        );
+        assert_eq!(tree_id, expected.id);
+        assert_eq!(files.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_three_way_merge_conflict() {
+        let storage = MemoryStorage::new();
+        let base = save_tree(&storage, &[("README.md", "readme"), ("src/lib.rs", "lib")]);
+        let ours = save_tree(&storage, &[("README.md", "readme"), ("src/lib.rs", "ours")]);
+        let theirs = save_tree(&storage, &[("README.md", "v2"), ("src/lib.rs", "theirs")]);
+
+        match three_way_merge(&storage, &base, &ours, &theirs).await {
+            Ok(MergeResult::Conflict(paths)) => assert_eq!(paths, vec!["src/lib.rs"]),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_merge_branches() {
+        let storage = MemoryStorage::new();
+        let base = save_commit(
+            &storage,
+            &[("README.md", "readme"), ("src/lib.rs", "")],
+            &[],
+        );
+        let ours = save_commit(
+            &storage,
+            &[("README.md", "readme v2"), ("src/lib.rs", "")],
+            &[&base],
+        );
+        let theirs = save_commit(
+            &storage,
+            &[
+                ("README.md", "readme"),
+                ("src/lib.rs", ""),
+                ("src/main.rs", ""),
+            ],
+            &[&base],
+        );
+        assert_eq!(
+            merge_base(&storage, &ours.id, &theirs.id).await.unwrap(),
+            Some(base.id)
+        );
+
+        let merge = merge_commits(&storage, &ours.id, &theirs.id, "merge")
+            .await
+            .unwrap();
+        assert_eq!(merge.parent_commit_ids, vec![ours.id, theirs.id]);
+        assert!(storage.get_commit(&merge.id).await.unwrap().is_some());
+        let files = flatten_tree(&storage, merge.tree_id).await.unwrap();
+        let paths: Vec<&str> = files.keys().map(|x| x.as_str()).collect();
+        assert_eq!(paths, vec!["README.md", "src/lib.rs", "src/main.rs"]);
+        assert_eq!(files["README.md"].id, Blob::from_content("readme v2").id);
+    }
+
+    #[tokio::test]
+    async fn test_merge_base_unrelated() {
+        let storage = MemoryStorage::new();
+        let ours_root = save_commit(&storage, &[("README.md", "ours")], &[]);
+        let ours = save_commit(&storage, &[("README.md", "ours v2")], &[&ours_root]);
+        let theirs_root = save_commit(&storage, &[("LICENSE", "theirs")], &[]);
+        let theirs = save_commit(&storage, &[("LICENSE", "theirs v2")], &[&theirs_root]);
+
+        assert_eq!(
+            merge_base(&storage, &ours.id, &theirs.id).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            merge_base(&storage, &ours.id, &ours_root.id).await.unwrap(),
+            Some(ours_root.id)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_merge_conflict() {
+        let storage = MemoryStorage::new();
+        let base = save_commit(&storage, &[("README.md", "readme")], &[]);
+        let ours = save_commit(&storage, &[("README.md", "ours")], &[&base]);
+        let theirs = save_commit(&storage, &[("README.md", "theirs")], &[&base]);
+
+        match merge_commits(&storage, &ours.id, &theirs.id, "merge").await {
+            Err(GitError::ConflictError(paths)) => assert_eq!(paths, vec!["README.md"]),
+            other => panic!("unexpected result: {:?}", other.map(|x| x.id)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_squash_commits() {
+        let storage = MemoryStorage::new();
+        let base = save_commit(&storage, &[("README.md", "readme")], &[]);
+        let c1 = save_commit(&storage, &[("README.md", "readme v2")], &[&base]);
+        let c2 = save_commit(
+            &storage,
+            &[("README.md", "readme v2"), ("src/lib.rs", "lib")],
+            &[&c1],
+        );
+        let tip = save_commit(
+            &storage,
+            &[("README.md", "readme v3"), ("src/lib.rs", "lib")],
+            &[&c2],
+        );
+
+        let author =
+            Signature::from_data(b"author dev <dev@mega.org> 1700000000 +0800".to_vec()).unwrap();
+        let squashed = squash_commits(&storage, &tip.id, &base.id, "squash", author.clone())
+            .await
+            .unwrap();
+        assert_eq!(squashed.tree_id, tip.tree_id);
+        assert_eq!(squashed.parent_commit_ids, vec![base.id]);
+        assert_eq!(squashed.author, author);
+        assert_eq!(squashed.committer.signature_type, SignatureType::Committer);
+        assert_eq!(squashed.committer.email, "dev@mega.org");
+        assert_eq!(squashed.message, "squash");
+
+        let saved = storage.get_commit(&squashed.id).await.unwrap().unwrap();
+        assert_eq!(saved.parent_commit_ids, vec![base.id]);
+    }
+
+    #[tokio::test]
+    async fn test_cherry_pick() {
+        let storage = MemoryStorage::new();
+        let base = save_commit(
+            &storage,
+            &[("README.md", "readme"), ("src/lib.rs", "lib")],
+            &[],
+        );
+        // a fix on a feature branch, which also carries an unrelated change
+        let feature = save_commit(
+            &storage,
+            &[
+                ("README.md", "readme"),
+                ("src/lib.rs", "lib"),
+                ("wip.rs", "wip"),
+            ],
+            &[&base],
+        );
+        let fix = save_commit(
+            &storage,
+            &[
+                ("README.md", "readme"),
+                ("src/lib.rs", "lib fixed"),
+                ("wip.rs", "wip"),
+            ],
+            &[&feature],
+        );
+        let main = save_commit(
+            &storage,
+            &[("README.md", "readme v2"), ("src/lib.rs", "lib")],
+            &[&base],
+        );
+
+        let picked = cherry_pick_commit(&storage, &fix.id, &main.id)
+            .await
+            .unwrap();
+        assert_eq!(picked.parent_commit_ids, vec![main.id]);
+        assert_eq!(picked.message, fix.message);
+        let files = flatten_tree(&storage, picked.tree_id).await.unwrap();
+        let expected = save_tree(
+            &storage,
+            &[("README.md", "readme v2"), ("src/lib.rs", "lib fixed")],
+        );
+        assert_eq!(picked.tree_id, expected.id);
+        assert_eq!(files.len(), 2);
+        assert!(storage.get_commit(&picked.id).await.unwrap().is_some());
+
+        // the same file changed differently on main
+        let conflicting = save_commit(
+            &storage,
+            &[
+                ("README.md", "readme"),
+                ("src/lib.rs", "lib changed on main"),
+            ],
+            &[&base],
+        );
+        let result = cherry_pick_commit(&storage, &fix.id, &conflicting.id).await;
+        assert!(matches!(result, Err(GitError::ConflictError(paths)) if paths == ["src/lib.rs"]));
+    }
+
+    #[tokio::test]
+    async fn test_rebase_branch() {
+        let storage = MemoryStorage::new();
+        let base = save_commit(&storage, &[("README.md", "readme")], &[]);
+        let b1 = save_commit(
+            &storage,
+            &[("README.md", "readme"), ("src/a.rs", "a")],
+            &[&base],
+        );
+        let b2 = save_commit(
+            &storage,
+            &[
+                ("README.md", "readme"),
+                ("src/a.rs", "a v2"),
+                ("src/b.rs", "b"),
+            ],
+            &[&b1],
+        );
+        let main = save_commit(&storage, &[("README.md", "readme v2")], &[&base]);
+
+        let tip = rebase_commits(&storage, &b2.id, &main.id).await.unwrap();
+        let expected = save_tree(
+            &storage,
+            &[
+                ("README.md", "readme v2"),
+                ("src/a.rs", "a v2"),
+                ("src/b.rs", "b"),
+            ],
+        );
+        assert_eq!(tip.tree_id, expected.id);
+
+        // both branch commits were replayed in order on top of main
+        let replayed_b1 = storage
+            .get_commit(&tip.parent_commit_ids[0])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(replayed_b1.parent_commit_ids, vec![main.id]);
+        let b1_tree = save_tree(&storage, &[("README.md", "readme v2"), ("src/a.rs", "a")]);
+        assert_eq!(replayed_b1.tree_id, b1_tree.id);
+
+        // rebasing onto a commit the branch already contains changes nothing
+        let same = rebase_commits(&storage, &b2.id, &base.id).await.unwrap();
+        assert_eq!(same.tree_id, b2.tree_id);
+    }
+}
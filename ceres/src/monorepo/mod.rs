@@ -1 +1,19 @@
+pub mod attributes;
+pub mod build_hook;
+pub mod checkout;
+pub mod connectivity;
+pub mod dedup;
+pub mod diff;
+pub mod fsck;
+pub mod gitmodules;
+pub mod graph;
+pub mod history;
+pub mod import;
+pub mod listing;
+pub mod merge;
+pub mod rename;
+pub mod revert;
+pub mod submodule;
+pub mod tags;
+pub mod walk;
 pub mod service;
@@ -0,0 +1,343 @@
+//! Rename detection for tree diffs.
+//!
+//! A plain diff reports a moved file as a deletion and an addition. Each deleted blob is paired
+//! with the added blob most similar to it, as long as the similarity reaches a threshold, the way
+//! `git diff -M` does.
+use std::collections::{HashMap, HashSet};
+
+use jupiter::storage::object_storage::ObjectStorage;
+use venus::errors::GitError;
+use venus::hash::SHA1;
+use venus::internal::object::tree::TreeItem;
+
+use crate::monorepo::diff::FileChange;
+
+/// The similarity, in percent, from which a deleted and an added file count as a rename, the
+/// same default as git.
+pub const DEFAULT_RENAME_THRESHOLD: u8 = 50;
+
+/// How many deleted times added files are compared at most, as a square, the same default as
+/// git's `diff.renameLimit`.
+pub const DEFAULT_RENAME_LIMIT: usize = 1000;
+
+/// How many lines of a file are looked up in the other one before comparing them completely.
+const SAMPLE_LINES: usize = 16;
+
+/// An entry of a diff with rename detection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffEntry {
+    Changed(FileChange),
+    /// The file at `from` was moved to `to`, `similarity` percent of its content are unchanged.
+    Renamed {
+        from: String,
+        to: String,
+        similarity: u8,
+    },
+}
+
+impl DiffEntry {
+    /// The path the entry is ordered by, the new path of a rename.
+    pub fn path(&self) -> &str {
+        match self {
+            DiffEntry::Changed(change) => &change.path,
+            DiffEntry::Renamed { to, .. } => to,
+        }
+    }
+}
+
+/// Pair up the deletions and additions in `changes` whose blobs are at least `threshold` percent
+/// similar into renames, the most similar pairs first. Files moved unchanged are paired up before
+/// any content is compared. If more than `limit` deleted files times `limit` added ones are left
+/// after that, only those unchanged moves are detected, like with git's `diff.renameLimit`. The
+/// result is ordered by path.
+pub async fn detect_renames(
+    storage: &dyn ObjectStorage,
+    changes: Vec<FileChange>,
+    threshold: u8,
+    limit: usize,
+) -> Result<Vec<DiffEntry>, GitError> {
+    let (mut deleted, mut added) = (Vec::new(), Vec::new());
+    let mut entries = Vec::new();
+    for change in changes {
+        match (&change.old, &change.new) {
+            (Some(old), None) if is_file(old) => deleted.push((old.id, change)),
+            (None, Some(new)) if is_file(new) => added.push((new.id, change)),
+            _ => entries.push(DiffEntry::Changed(change)),
+        }
+    }
+
+    let (mut renamed_from, mut renamed_to) = (HashSet::new(), HashSet::new());
+    let mut renames = Vec::new();
+
+    // exact renames, each deleted file paired with the first unpaired added file of its blob
+    let mut added_by_id: HashMap<SHA1, Vec<usize>> = HashMap::new();
+    for (j, (new_id, _)) in added.iter().enumerate().rev() {
+        added_by_id.entry(*new_id).or_default().push(j);
+    }
+    for (i, (old_id, _)) in deleted.iter().enumerate() {
+        if let Some(j) = added_by_id.get_mut(old_id).and_then(|x| x.pop()) {
+            renamed_from.insert(i);
+            renamed_to.insert(j);
+            renames.push((i, j, 100));
+        }
+    }
+
+    let sources: Vec<usize> = (0..deleted.len())
+        .filter(|i| !renamed_from.contains(i))
+        .collect();
+    let destinations: Vec<usize> = (0..added.len())
+        .filter(|j| !renamed_to.contains(j))
+        .collect();
+    let inexact = !sources.is_empty()
+        && !destinations.is_empty()
+        && sources.len().saturating_mul(destinations.len()) <= limit.saturating_mul(limit);
+    if inexact {
+        let ids = sources
+            .iter()
+            .map(|i| deleted[*i].0)
+            .chain(destinations.iter().map(|j| added[*j].0));
+        let contents = load_blobs(storage, ids.collect()).await?;
+        let mut candidates = Vec::new();
+        for &i in &sources {
+            for &j in &destinations {
+                let old_data = &contents[&deleted[i].0];
+                let new_data = &contents[&added[j].0];
+                match similarity(old_data, new_data, threshold) {
+                    Some(similarity) if similarity >= threshold => {
+                        candidates.push((similarity, i, j))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        // the best matches first, ties broken by path order
+        candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+        for (similarity, i, j) in candidates {
+            if renamed_from.contains(&i) || renamed_to.contains(&j) {
+                continue;
+            }
+            renamed_from.insert(i);
+            renamed_to.insert(j);
+            renames.push((i, j, similarity));
+        }
+    }
+
+    for (i, j, similarity) in renames {
+        entries.push(DiffEntry::Renamed {
+            from: deleted[i].1.path.clone(),
+            to: added[j].1.path.clone(),
+            similarity,
+        });
+    }
+    let unpaired = deleted
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !renamed_from.contains(i))
+        .chain(
+            added
+                .into_iter()
+                .enumerate()
+                .filter(|(j, _)| !renamed_to.contains(j)),
+        );
+    entries.extend(unpaired.map(|(_, (_, change))| DiffEntry::Changed(change)));
+    entries.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(entries)
+}
+
+/// Only regular files are considered, not links or submodules.
+fn is_file(item: &TreeItem) -> bool {
+    item.mode.is_blob()
+}
+
+/// The content of the blobs `ids`, each loaded once.
+async fn load_blobs(
+    storage: &dyn ObjectStorage,
+    mut ids: Vec<SHA1>,
+) -> Result<HashMap<SHA1, Vec<u8>>, GitError> {
+    ids.sort();
+    ids.dedup();
+    let contents: HashMap<SHA1, Vec<u8>> = storage
+        .get_blobs(&ids)
+        .await?
+        .into_iter()
+        .map(|x| (x.id, x.data))
+        .collect();
+    if let Some(missing) = ids.iter().find(|x| !contents.contains_key(x)) {
+        return Err(GitError::NotFountHashValue(missing.to_plain_str()));
+    }
+    Ok(contents)
+}
+
+/// The share of `old`, in percent, which is kept in `new`, counted in bytes of identical lines
+/// relative to the larger file. Returns `None` without a full comparison if the sizes or a sample
+/// of the lines already show the files can't reach `threshold`.
+fn similarity(old: &[u8], new: &[u8], threshold: u8) -> Option<u8> {
+    let max = old.len().max(new.len());
+    if max == 0 {
+        return Some(100);
+    }
+    // even if the smaller file were contained in the larger one completely
+    if old.len().min(new.len()) * 100 < max * threshold as usize {
+        return None;
+    }
+
+    let mut new_lines: HashMap<&[u8], usize> = HashMap::new();
+    for line in new.split_inclusive(|x| *x == b'\n') {
+        *new_lines.entry(line).or_default() += 1;
+    }
+
+    let old_lines: Vec<&[u8]> = old.split_inclusive(|x| *x == b'\n').collect();
+    let step = (old_lines.len() / SAMPLE_LINES).max(1);
+    let sampled = old_lines.iter().step_by(step).take(SAMPLE_LINES);
+    let (total, found) = sampled.fold((0, 0), |(total, found), line| {
+        (total + 1, found + new_lines.contains_key(line) as usize)
+    });
+    // a sample is only an estimate, so it just rules out files with hardly anything in common
+    if found * 200 < total * threshold as usize {
+        return None;
+    }
+
+    let mut common = 0;
+    for line in old_lines {
+        if let Some(count) = new_lines.get_mut(line).filter(|x| **x > 0) {
+            *count -= 1;
+            common += line.len();
+        }
+    }
+    Some((common * 100 / max) as u8)
+}
+
+#[cfg(test)]
+mod test {
+    use jupiter::storage::object_storage::MemoryStorage;
+    use venus::internal::object::blob::Blob;
+    use venus::internal::object::tree::{TreeItem, TreeItemMode};
+
+    use super::{detect_renames, DiffEntry, DEFAULT_RENAME_LIMIT, DEFAULT_RENAME_THRESHOLD};
+    use crate::monorepo::diff::{diff_flat_trees, FlatTree};
+
+    fn flat_tree(storage: &MemoryStorage, files: &[(&str, &str)]) -> FlatTree {
+        files
+            .iter()
+            .map(|(path, content)| {
+                let blob = Blob::from_content(content);
+                let item = TreeItem::new(TreeItemMode::Blob, blob.id, String::new());
+                storage.insert_blob(blob);
+                (path.to_string(), item)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_detect_rename_with_edit() {
+        let storage = MemoryStorage::new();
+        let source: String = (0..20)
+            .map(|i| format!("let line_{} = {};\n", i, i))
+            .collect();
+        let edited = source.replace("let line_7 = 7;", "let line_7 = 70;");
+        let old = flat_tree(
+            &storage,
+            &[("src/util.rs", &source), ("README.md", "readme")],
+        );
+        let new = flat_tree(
+            &storage,
+            &[
+                ("src/helpers.rs", &edited),
+                ("README.md", "readme"),
+                ("LICENSE", "MIT"),
+            ],
+        );
+
+        let changes = diff_flat_trees(&old, &new);
+        assert_eq!(changes.len(), 3);
+        let entries = detect_renames(
+            &storage,
+            changes,
+            DEFAULT_RENAME_THRESHOLD,
+            DEFAULT_RENAME_LIMIT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path(), "LICENSE");
+        assert!(matches!(&entries[0], DiffEntry::Changed(x) if x.old.is_none()));
+        match &entries[1] {
+            DiffEntry::Renamed {
+                from,
+                to,
+                similarity,
+            } => {
+                assert_eq!(
+                    (from.as_str(), to.as_str()),
+                    ("src/util.rs", "src/helpers.rs")
+                );
+                assert!(*similarity >= 90 && *similarity < 100);
+            }
+            entry => panic!("expected a rename, got {:?}", entry),
+        }
+
+        // nothing is similar enough at a threshold of 100%
+        let entries = detect_renames(
+            &storage,
+            diff_flat_trees(&old, &new),
+            100,
+            DEFAULT_RENAME_LIMIT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(entries.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_detect_exact_renames_beyond_limit() {
+        let storage = MemoryStorage::new();
+        let source: String = (0..20)
+            .map(|i| format!("let line_{} = {};\n", i, i))
+            .collect();
+        let edited = source.replace("let line_7 = 7;", "let line_7 = 70;");
+        let mut old = flat_tree(&storage, &[("src/util.rs", &source)]);
+        let mut new = flat_tree(&storage, &[("src/helpers.rs", &edited), ("LICENSE", "MIT")]);
+        // an unchanged move is detected without loading the blob, it isn't even stored
+        let moved = Blob::from_content("moved");
+        let item = TreeItem::new(TreeItemMode::Blob, moved.id, String::new());
+        old.insert("docs/guide.md".to_owned(), item.clone());
+        new.insert("guide.md".to_owned(), item);
+
+        // one deleted and two added files are left, more than 1 x 1
+        let entries = detect_renames(
+            &storage,
+            diff_flat_trees(&old, &new),
+            DEFAULT_RENAME_THRESHOLD,
+            1,
+        )
+        .await
+        .unwrap();
+        let renames: Vec<(&str, &str, u8)> = entries
+            .iter()
+            .filter_map(|x| match x {
+                DiffEntry::Renamed {
+                    from,
+                    to,
+                    similarity,
+                } => Some((from.as_str(), to.as_str(), *similarity)),
+                DiffEntry::Changed(_) => None,
+            })
+            .collect();
+        assert_eq!(renames, vec![("docs/guide.md", "guide.md", 100)]);
+        assert_eq!(entries.len(), 4);
+
+        // within the limit the edited file is paired up too
+        let entries = detect_renames(
+            &storage,
+            diff_flat_trees(&old, &new),
+            DEFAULT_RENAME_THRESHOLD,
+            2,
+        )
+        .await
+        .unwrap();
+        assert_eq!(entries.len(), 3);
+        assert!(entries
+            .iter()
+            .any(|x| matches!(x, DiffEntry::Renamed { from, .. } if from == "src/util.rs")));
+    }
+}
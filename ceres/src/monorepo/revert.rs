@@ -0,0 +1,112 @@
+//! Reverting the changes between two commits on top of another commit.
+use jupiter::storage::object_storage::ObjectStorage;
+use venus::errors::GitError;
+use venus::hash::SHA1;
+use venus::internal::object::commit::Commit;
+
+use crate::monorepo::diff::{apply_changes, build_tree, diff_flat_trees, flatten_tree};
+use crate::monorepo::history::load_commit;
+
+/// Build a commit on top of `base` which undoes the changes made from `from` to `to`. The new
+/// trees and the commit are saved, but no ref is updated. If `base` has since changed any of the
+/// affected paths in another way, nothing is saved and [`GitError::ConflictError`] lists them.
+pub async fn revert_changes(
+    storage: &dyn ObjectStorage,
+    from: &SHA1,
+    to: &SHA1,
+    base: &SHA1,
+    message: &str,
+) -> Result<Commit, GitError> {
+    let from_commit = load_commit(storage, from).await?;
+    let to_commit = load_commit(storage, to).await?;
+    let base_commit = load_commit(storage, base).await?;
+
+    let old = flatten_tree(storage, from_commit.tree_id).await?;
+    let new = flatten_tree(storage, to_commit.tree_id).await?;
+    let changes = diff_flat_trees(&old, &new)
+        .into_iter()
+        .map(|x| x.reverse())
+        .collect();
+
+    let base_tree = flatten_tree(storage, base_commit.tree_id).await?;
+    let reverted = apply_changes(base_tree, changes).map_err(GitError::ConflictError)?;
+    let (tree_id, trees) = build_tree(&reverted)?;
+
+    let commit = Commit::from_tree_id(tree_id, vec![base_commit.id], message);
+    storage.save_trees(trees).await?;
+    storage.save_commits(vec![commit.clone()]).await?;
+    Ok(commit)
+}
+
+#[cfg(test)]
+mod test {
+    use jupiter::storage::object_storage::{MemoryStorage, ObjectStorage};
+    use venus::errors::GitError;
+
+    use super::revert_changes;
+    use crate::monorepo::diff::flatten_tree;
+    use crate::test_util::save_commit;
+
+    #[tokio::test]
+    async fn test_revert_file_addition() {
+        let storage = MemoryStorage::new();
+        let c1 = save_commit(&storage, &[("README.md", "readme")], &[]);
+        // the merge request adds src/main.rs
+        let c2 = save_commit(
+            &storage,
+            &[("README.md", "readme"), ("src/main.rs", "fn main() {}")],
+            &[&c1],
+        );
+        // an unrelated change merged after it
+        let c3 = save_commit(
+            &storage,
+            &[
+                ("README.md", "readme"),
+                ("src/main.rs", "fn main() {}"),
+                ("docs/guide.md", "guide"),
+            ],
+            &[&c2],
+        );
+
+        let revert = revert_changes(&storage, &c1.id, &c2.id, &c3.id, "revert")
+            .await
+            .unwrap();
+        assert_eq!(revert.parent_commit_ids, vec![c3.id]);
+        assert!(storage.get_commit(&revert.id).await.unwrap().is_some());
+
+        let files = flatten_tree(&storage, revert.tree_id).await.unwrap();
+        let paths: Vec<&str> = files.keys().map(|x| x.as_str()).collect();
+        assert_eq!(paths, vec!["README.md", "docs/guide.md"]);
+        // src only contained main.rs, so the whole directory is gone
+        let root = storage.get_tree(&revert.tree_id).await.unwrap().unwrap();
+        assert!(root.tree_items.iter().all(|x| x.name != "src"));
+    }
+
+    #[tokio::test]
+    async fn test_revert_conflict() {
+        let storage = MemoryStorage::new();
+        let c1 = save_commit(&storage, &[("README.md", "readme")], &[]);
+        let c2 = save_commit(
+            &storage,
+            &[("README.md", "readme"), ("src/main.rs", "fn main() {}")],
+            &[&c1],
+        );
+        // src/main.rs was modified after the merge request
+        let c3 = save_commit(
+            &storage,
+            &[
+                ("README.md", "readme"),
+                ("src/main.rs", "fn main() { todo!() }"),
+            ],
+            &[&c2],
+        );
+
+        let err = revert_changes(&storage, &c1.id, &c2.id, &c3.id, "revert")
+            .await
+            .unwrap_err();
+        match err {
+            GitError::ConflictError(paths) => assert_eq!(paths, vec!["src/main.rs"]),
+            err => panic!("unexpected error: {}", err),
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use std::env;
 use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
@@ -13,17 +14,47 @@ use venus::errors::GitError;
 use venus::hash::SHA1;
 use venus::internal::object::blob::Blob;
 use venus::internal::object::commit::Commit;
+use venus::internal::object::signature::{Signature, SignatureType};
 use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
-use venus::monorepo::mr::{MergeOperation, MergeResult};
+use venus::monorepo::mr::{BuildStatus, MergeOperation, MergeRequest, MergeResult};
+
+use crate::monorepo::build_hook::BuildQueue;
 
 #[derive(Clone)]
 pub struct MonorepoService {
     pub storage: Arc<MegaStorage>,
+    /// Builds the project of each merged MR, see [`MonorepoService::post_merge_hook`].
+    pub build_queue: Option<Arc<dyn BuildQueue>>,
+}
+
+/// The identity committing the first commit of the monorepo, from `MEGA_INIT_NAME` and
+/// `MEGA_INIT_EMAIL`, `mega <admin@mega.org>` by default, at the current time in UTC.
+pub fn init_identity() -> Signature {
+    let var = |key: &str, default: &str| {
+        env::var(key)
+            .ok()
+            .map(|x| x.trim().to_owned())
+            .filter(|x| !x.is_empty())
+            .unwrap_or_else(|| default.to_owned())
+    };
+    Signature {
+        signature_type: SignatureType::Committer,
+        name: var("MEGA_INIT_NAME", "mega"),
+        email: var("MEGA_INIT_EMAIL", "admin@mega.org"),
+        timestamp: chrono::Utc::now().timestamp() as usize,
+        timezone: "+0000".to_owned(),
+    }
 }
 
 impl MonorepoService {
-    pub async fn init_monorepo(&self) {
-        self.storage.init_monorepo().await
+    /// Create the first commit of the monorepo with its top level directories, committed by
+    /// [`init_identity`]. Like `MonoRepo::init_root`, an existing root ref is refused.
+    pub async fn init_monorepo(&self) -> Result<(), GitError> {
+        if self.storage.get_ref("/").await?.is_some() {
+            return Err(GitError::RefAlreadyExists("/".to_owned()));
+        }
+        self.storage.init_monorepo(init_identity()).await;
+        Ok(())
     }
 
     pub async fn create_mega_file(&self, file_info: CreateFileInfo) -> Result<(), GitError> {
@@ -98,8 +129,11 @@ impl MonorepoService {
 
             if mr.from_hash == refs.ref_commit_hash {
                 // update mr
-                mr.merge(op.message);
-                self.storage.update_mr(mr.clone()).await.unwrap();
+                if let Err(err) = mr.merge(op.message) {
+                    res.result = false;
+                    res.err_message = err.to_string();
+                    return Ok(res);
+                }
 
                 let commit: Commit = self
                     .storage
@@ -109,22 +143,30 @@ impl MonorepoService {
                     .unwrap()
                     .into();
 
-                // add conversation
-                self.storage
-                    .add_mr_conversation(mr.id, 0, ConvType::Merged)
-                    .await
-                    .unwrap();
                 if mr.path != "/" {
                     let path = PathBuf::from(mr.path.clone());
 
                     let (tree_vec, _) = self.search_tree_by_path(&path).await.unwrap();
-                    self.update_parent_tree(path, tree_vec, commit)
-                        .await
-                        .unwrap();
+                    if let Err(err) = self.update_parent_tree(path, tree_vec, commit).await {
+                        res.result = false;
+                        res.err_message = err.to_string();
+                        return Ok(res);
+                    }
                     // remove refs start with path
                     self.storage.remove_refs(&mr.path).await.unwrap();
                     // todo: self.clean_dangling_commits().await;
                 }
+                // the MR is only recorded as merged once the refs were moved, a merge losing the
+                // race for the root ref leaves it open
+                self.storage.update_mr(mr.clone()).await.unwrap();
+                self.storage
+                    .add_mr_conversation(mr.id, 0, ConvType::Merged)
+                    .await
+                    .unwrap();
+                // the merge is already saved, a failing hook mustn't report it as failed
+                if let Err(err) = self.post_merge_hook(&mut mr).await {
+                    tracing::error!("post merge hook of MR {} failed: {}", mr.id, err);
+                }
             } else {
                 res.result = false;
                 res.err_message = "ref hash conflict".to_owned();
@@ -136,6 +178,21 @@ impl MonorepoService {
         Ok(res)
     }
 
+    /// Start a build of the project of the merged `mr` if a build queue is configured, and
+    /// record the build id on the MR.
+    pub async fn post_merge_hook(&self, mr: &mut MergeRequest) -> Result<(), MegaError> {
+        let Some(queue) = &self.build_queue else {
+            return Ok(());
+        };
+        mr.build_id = Some(queue.enqueue(mr.id, PathBuf::from(&mr.path)));
+        self.storage.update_mr(mr.clone()).await
+    }
+
+    /// The status of the build of the merged MR `mr_id`, `None` if no build was started.
+    pub async fn get_build_status(&self, mr_id: i64) -> Result<Option<BuildStatus>, MegaError> {
+        self.storage.get_build_status(mr_id).await
+    }
+
     async fn search_tree_by_path(&self, path: &Path) -> Result<(Vec<Tree>, Tree), GitError> {
         let refs = self.storage.get_ref("/").await.unwrap().unwrap();
 
@@ -185,7 +242,9 @@ impl MonorepoService {
         commit: Commit,
     ) -> Result<(), GitError> {
         let mut save_trees = Vec::new();
-        let mut p_commit_id = String::new();
+        let mut root_ref = None;
+        // refs of the directories on the way to the root, removed once the root ref was moved
+        let mut inner_refs = Vec::new();
 
         let mut target_hash = commit.tree_id;
 
@@ -203,29 +262,30 @@ impl MonorepoService {
             save_trees.push(model);
 
             let p_ref = self.storage.get_ref(path.to_str().unwrap()).await.unwrap();
-            if let Some(mut p_ref) = p_ref {
+            if let Some(p_ref) = p_ref {
                 if path == Path::new("/") {
-                    let p_commit = Commit::new(
-                        commit.author.clone(),
-                        commit.committer.clone(),
-                        target_hash,
-                        vec![SHA1::from_str(&p_ref.ref_commit_hash).unwrap()],
-                        &commit.message,
-                    );
-                    p_commit_id = p_commit.id.to_plain_str();
-                    // update p_ref
-                    p_ref.ref_commit_hash = p_commit.id.to_plain_str();
-                    p_ref.ref_tree_hash = target_hash.to_plain_str();
-                    self.storage.update_ref(p_ref).await.unwrap();
-                    self.storage
-                        .save_mega_commits(vec![p_commit])
-                        .await
-                        .unwrap();
+                    root_ref = Some(p_ref);
                 } else {
-                    self.storage.remove_ref(p_ref).await.unwrap();
+                    inner_refs.push(p_ref);
                 }
             }
         }
+
+        let mut p_commit_id = String::new();
+        if let Some(p_ref) = &root_ref {
+            let p_commit = Commit::new(
+                commit.author.clone(),
+                commit.committer.clone(),
+                target_hash,
+                vec![SHA1::from_str(&p_ref.ref_commit_hash).unwrap()],
+                &commit.message,
+            );
+            p_commit_id = p_commit.id.to_plain_str();
+            self.storage
+                .save_mega_commits(vec![p_commit])
+                .await
+                .unwrap();
+        }
         let save_trees: Vec<mega_tree::ActiveModel> = save_trees
             .into_iter()
             .map(|mut x| {
@@ -237,6 +297,25 @@ impl MonorepoService {
         batch_save_model(self.storage.get_connection(), save_trees)
             .await
             .unwrap();
+
+        if let Some(p_ref) = root_ref {
+            // update p_ref, unless another merge moved it since it was read
+            let updated = self
+                .storage
+                .save_ref_cas(
+                    &p_ref.path,
+                    &p_ref.ref_commit_hash,
+                    &p_commit_id,
+                    &target_hash.to_plain_str(),
+                )
+                .await?;
+            if !updated {
+                return Err(GitError::StaleRef(p_ref.path));
+            }
+        }
+        for p_ref in inner_refs {
+            self.storage.remove_ref(p_ref).await.unwrap();
+        }
         Ok(())
     }
 }
@@ -244,6 +323,37 @@ impl MonorepoService {
 #[cfg(test)]
 mod test {
     use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult};
+
+    use callisto::db_enums::ConvType;
+    use callisto::{mega_commit, mega_mr, mega_mr_conv, mega_refs, mega_tree};
+    use jupiter::storage::mega_storage::MegaStorage;
+    use venus::errors::GitError;
+    use venus::hash::SHA1;
+    use venus::internal::object::blob::Blob;
+    use venus::internal::object::commit::Commit;
+    use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+    use venus::internal::object::types::ObjectType;
+    use venus::internal::object::ObjectTrait;
+    use venus::monorepo::mr::{MergeOperation, MergeRequest};
+
+    use super::MonorepoService;
+    use crate::monorepo::build_hook::BuildQueue;
+    use crate::test_util::transaction_log;
+
+    #[derive(Default)]
+    struct RecordingQueue {
+        paths: Mutex<Vec<PathBuf>>,
+    }
+
+    impl BuildQueue for RecordingQueue {
+        fn enqueue(&self, _: i64, repo_path: PathBuf) -> String {
+            self.paths.lock().unwrap().push(repo_path);
+            "build-1".to_owned()
+        }
+    }
 
     #[test]
     pub fn test() {
@@ -255,4 +365,238 @@ mod test {
             println!("name: {}, path: {:?}", name, full_path);
         }
     }
+
+    /// A root tree with `project/mega` below it and an open MR on `/project/mega`, whose head
+    /// commit replaces that directory.
+    struct MergeFixture {
+        root: Tree,
+        project: Tree,
+        base: Commit,
+        head: Commit,
+        now: chrono::NaiveDateTime,
+    }
+
+    impl MergeFixture {
+        fn new() -> Self {
+            let main = Blob::from_content("fn main() {}");
+            let mega = Tree::from_tree_items(vec![TreeItem::new(
+                TreeItemMode::Blob,
+                main.id,
+                "main.rs".to_owned(),
+            )])
+            .unwrap();
+            let project = Tree::from_tree_items(vec![TreeItem::new(
+                TreeItemMode::Tree,
+                mega.id,
+                "mega".to_owned(),
+            )])
+            .unwrap();
+            Self::with_project(project, mega.id)
+        }
+
+        fn with_project(project: Tree, head_tree: SHA1) -> Self {
+            let root = Tree::from_tree_items(vec![TreeItem::new(
+                TreeItemMode::Tree,
+                project.id,
+                "project".to_owned(),
+            )])
+            .unwrap();
+            let base = Commit::from_tree_id(root.id, vec![], "base");
+            let head = Commit::from_tree_id(head_tree, vec![], "change mega");
+            MergeFixture {
+                root,
+                project,
+                base,
+                head,
+                now: chrono::Utc::now().naive_utc(),
+            }
+        }
+
+        fn refs(&self, path: &str) -> mega_refs::Model {
+            mega_refs::Model {
+                id: 1,
+                path: path.to_owned(),
+                ref_commit_hash: self.base.id.to_plain_str(),
+                ref_tree_hash: self.base.tree_id.to_plain_str(),
+                created_at: self.now,
+                updated_at: self.now,
+            }
+        }
+
+        fn mr(&self) -> mega_mr::Model {
+            mega_mr::Model::from(MergeRequest {
+                id: 1,
+                path: "/project/mega".to_owned(),
+                from_hash: self.base.id.to_plain_str(),
+                to_hash: self.head.id.to_plain_str(),
+                ..Default::default()
+            })
+        }
+
+        fn conv(&self) -> mega_mr_conv::Model {
+            mega_mr_conv::Model {
+                id: 2,
+                mr_id: 1,
+                user_id: 0,
+                conv_type: ConvType::Merged,
+                created_at: self.now,
+                updated_at: self.now,
+            }
+        }
+
+        /// The MR and its ref, its commit, then the trees from the root down to the parent of the
+        /// MR path and the refs on the way back up, the ref update being statements of their own
+        /// with the results `exec`.
+        fn database(&self, exec: Vec<MockExecResult>) -> MockDatabase {
+            MockDatabase::new(DatabaseBackend::Postgres)
+                .append_query_results([vec![self.mr()]])
+                .append_query_results([vec![self.refs("/project/mega")]])
+                .append_query_results([vec![mega_commit::Model::from(self.head.clone())]])
+                .append_query_results([vec![self.refs("/")]])
+                .append_query_results([vec![mega_tree::Model::from(self.root.clone())]])
+                .append_query_results([vec![mega_tree::Model::from(self.project.clone())]])
+                .append_query_results([Vec::<mega_refs::Model>::new()])
+                .append_query_results([vec![self.refs("/")]])
+                .append_exec_results(exec)
+        }
+
+        async fn merge(
+            db: MockDatabase,
+            build_queue: Option<Arc<dyn BuildQueue>>,
+        ) -> (bool, Arc<MegaStorage>) {
+            let service = MonorepoService {
+                storage: Arc::new(MegaStorage {
+                    connection: Arc::new(db.into_connection()),
+                    ..MegaStorage::mock()
+                }),
+                build_queue,
+            };
+            let result = service
+                .merge_mr(MergeOperation {
+                    message: None,
+                    mr_id: 1,
+                })
+                .await
+                .unwrap();
+            (result.result, service.storage)
+        }
+    }
+
+    fn exec(rows_affected: u64) -> MockExecResult {
+        MockExecResult {
+            last_insert_id: 0,
+            rows_affected,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_merge_enqueues_build() {
+        let fixture = MergeFixture::new();
+        // the merged MR and its conversation once the ref moved, the MR with its build last
+        let db = fixture
+            .database(vec![exec(1); 5])
+            .append_query_results([vec![fixture.mr()]])
+            .append_query_results([vec![fixture.conv()]])
+            .append_query_results([vec![fixture.mr()]]);
+        let queue = Arc::new(RecordingQueue::default());
+
+        let (merged, storage) = MergeFixture::merge(db, Some(queue.clone())).await;
+        assert!(merged);
+        assert_eq!(
+            *queue.paths.lock().unwrap(),
+            vec![PathBuf::from("/project/mega")]
+        );
+
+        let log = transaction_log(storage);
+        let last = log.last().unwrap();
+        assert!(last.contains("UPDATE") && last.contains("mega_mr") && last.contains("build-1"));
+    }
+
+    #[tokio::test]
+    async fn test_merge_keeps_group_writeable_mode() {
+        let main = Blob::from_content("fn main() {}");
+        let notes = Blob::from_content("notes");
+        let mega = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            main.id,
+            "main.rs".to_owned(),
+        )])
+        .unwrap();
+        // the project tree as a client would write it, with a group-writeable file next to the MR
+        let project_data = |mega: SHA1| {
+            let mut data = Vec::new();
+            for (mode, name, id) in [("40000", "mega", mega), ("100664", "notes", notes.id)] {
+                data.extend_from_slice(format!("{} {}\0", mode, name).as_bytes());
+                data.extend_from_slice(&id.0);
+            }
+            data
+        };
+        let data = project_data(mega.id);
+        let id = SHA1::from_type_and_data(ObjectType::Tree, &data);
+        let project = Tree::from_bytes(data.clone(), id).unwrap();
+        assert_eq!(project.to_data().unwrap(), data);
+        let new_mega = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            notes.id,
+            "main.rs".to_owned(),
+        )])
+        .unwrap();
+        let fixture = MergeFixture::with_project(project, new_mega.id);
+        let db = fixture
+            .database(vec![exec(1); 5])
+            .append_query_results([vec![fixture.mr()]])
+            .append_query_results([vec![fixture.conv()]]);
+
+        let (merged, storage) = MergeFixture::merge(db, None).await;
+        assert!(merged);
+
+        // the merged project tree only differs from the pushed one in the id of the MR tree
+        let merged =
+            SHA1::from_type_and_data(ObjectType::Tree, &project_data(new_mega.id)).to_plain_str();
+        let log = transaction_log(storage);
+        assert!(log
+            .iter()
+            .any(|x| x.contains("mega_tree") && x.contains(&merged)));
+    }
+
+    #[tokio::test]
+    async fn test_merge_with_stale_root_ref_leaves_mr_open() {
+        let fixture = MergeFixture::new();
+        // the root ref was moved by another merge, so the compare and swap updates no row
+        let db = fixture.database(vec![exec(1), exec(1), exec(0)]);
+
+        let (merged, storage) = MergeFixture::merge(db, None).await;
+        assert!(!merged);
+
+        let log = transaction_log(storage);
+        assert!(!log.iter().any(|x| x.contains("UPDATE \\\"mega_mr\\\"")));
+        assert!(!log.iter().any(|x| x.contains("mega_mr_conv")));
+        assert!(!log.iter().any(|x| x.contains("DELETE")));
+    }
+
+    #[tokio::test]
+    async fn test_init_existing_monorepo() {
+        let now = chrono::Utc::now().naive_utc();
+        let root = mega_refs::Model {
+            id: 1,
+            path: "/".to_owned(),
+            ref_commit_hash: "1".repeat(40),
+            ref_tree_hash: "2".repeat(40),
+            created_at: now,
+            updated_at: now,
+        };
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![root]])
+            .into_connection();
+        let service = MonorepoService {
+            storage: Arc::new(MegaStorage {
+                connection: Arc::new(db),
+                ..MegaStorage::mock()
+            }),
+            build_queue: None,
+        };
+
+        let result = service.init_monorepo().await;
+        assert!(matches!(result, Err(GitError::RefAlreadyExists(path)) if path == "/"));
+    }
 }
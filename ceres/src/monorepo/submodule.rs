@@ -0,0 +1,93 @@
+//! Listing the submodules (gitlink entries) of a monorepo tree.
+use jupiter::storage::object_storage::ObjectStorage;
+use venus::errors::GitError;
+use venus::hash::SHA1;
+use venus::internal::object::tree::TreeItemMode;
+
+use crate::monorepo::walk::{join_path, walk_batched};
+
+/// A gitlink entry, pinning a commit of another repository at `path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Submodule {
+    /// Slash separated path relative to the scanned tree.
+    pub path: String,
+    pub commit_hash: String,
+}
+
+/// Recursively find every gitlink entry below the tree `tree_id`, ordered by path.
+pub async fn list_submodules(
+    storage: &dyn ObjectStorage,
+    tree_id: SHA1,
+) -> Result<Vec<Submodule>, GitError> {
+    let mut submodules = Vec::new();
+    for (prefix, tree) in walk_batched(storage, tree_id).await? {
+        for item in tree.tree_items {
+            if item.mode == TreeItemMode::Commit {
+                submodules.push(Submodule {
+                    path: join_path(&prefix, &item.name),
+                    commit_hash: item.id.to_plain_str(),
+                });
+            }
+        }
+    }
+    submodules.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(submodules)
+}
+
+#[cfg(test)]
+mod test {
+    use jupiter::storage::object_storage::MemoryStorage;
+    use venus::hash::SHA1;
+    use venus::internal::object::blob::Blob;
+    use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+
+    use super::{list_submodules, Submodule};
+
+    #[tokio::test]
+    async fn test_list_nested_submodules() {
+        let storage = MemoryStorage::new();
+        // commits of other repositories, they don't exist in the storage
+        let libgit2 = SHA1::new(&b"libgit2".to_vec());
+        let zlib = SHA1::new(&b"zlib".to_vec());
+        let readme = Blob::from_content("vendored libraries");
+        let deps = Tree::from_tree_items(vec![
+            TreeItem::new(TreeItemMode::Blob, readme.id, "README.md".to_owned()),
+            TreeItem::new(TreeItemMode::Commit, zlib, "zlib".to_owned()),
+        ])
+        .unwrap();
+        let third_party = Tree::from_tree_items(vec![
+            TreeItem::new(TreeItemMode::Tree, deps.id, "deps".to_owned()),
+            TreeItem::new(TreeItemMode::Commit, libgit2, "libgit2".to_owned()),
+        ])
+        .unwrap();
+        let root = Tree::from_tree_items(vec![
+            TreeItem::new(TreeItemMode::Blob, readme.id, "README.md".to_owned()),
+            TreeItem::new(TreeItemMode::Tree, third_party.id, "third_party".to_owned()),
+        ])
+        .unwrap();
+        let root_id = root.id;
+        let third_party_id = third_party.id;
+        storage.insert_tree(deps);
+        storage.insert_tree(third_party);
+        storage.insert_tree(root);
+
+        let submodules = list_submodules(&storage, root_id).await.unwrap();
+        assert_eq!(
+            submodules,
+            vec![
+                Submodule {
+                    path: "third_party/deps/zlib".to_owned(),
+                    commit_hash: zlib.to_plain_str(),
+                },
+                Submodule {
+                    path: "third_party/libgit2".to_owned(),
+                    commit_hash: libgit2.to_plain_str(),
+                },
+            ]
+        );
+
+        // paths are relative to the scanned tree
+        let submodules = list_submodules(&storage, third_party_id).await.unwrap();
+        assert_eq!(submodules[0].path, "deps/zlib");
+    }
+}
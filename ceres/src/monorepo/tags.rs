@@ -0,0 +1,136 @@
+//! Resolving tags to the commits they point at.
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+
+use venus::errors::GitError;
+use venus::hash::SHA1;
+use venus::internal::object::tag::Tag;
+use venus::internal::pack::reference::Refs;
+
+/// The names of the tags which resolve to `commit`, sorted.
+///
+/// `tag_refs` are `refs/tags/*` refs, pointing at the commit itself for lightweight tags or at a
+/// tag object for annotated ones. `tags` are the annotated tag objects by id, which are matched
+/// by their own name even without a ref. Tags of tags are followed to their final target.
+pub fn tags_for_commit(commit: &SHA1, tag_refs: &[Refs], tags: &HashMap<SHA1, Tag>) -> Vec<String> {
+    let mut names = BTreeSet::new();
+    for tag_ref in tag_refs {
+        let Ok(hash) = SHA1::from_str(&tag_ref.ref_hash) else {
+            continue;
+        };
+        if peel(hash, tags) == *commit {
+            let name = tag_ref.ref_name.strip_prefix("refs/tags/");
+            names.insert(name.unwrap_or(&tag_ref.ref_name).to_owned());
+        }
+    }
+    for tag in tags.values() {
+        if peel(tag.id, tags) == *commit {
+            names.insert(tag.tag_name.clone());
+        }
+    }
+    names.into_iter().collect()
+}
+
+/// Check `name` against the rules of `git check-ref-format` for a tag name, as it becomes part of
+/// the tag object and of its ref.
+pub fn check_tag_name(name: &str) -> Result<(), GitError> {
+    let invalid = name.is_empty()
+        || name.starts_with('-')
+        || name.ends_with('/')
+        || name.ends_with('.')
+        || name.contains("..")
+        || name.contains("@{")
+        || name.contains("//")
+        || name == "@"
+        || name
+            .chars()
+            .any(|x| x.is_ascii_control() || " ~^:?*[\\".contains(x))
+        || name
+            .split('/')
+            .any(|x| x.starts_with('.') || x.ends_with(".lock"));
+    if invalid {
+        return Err(GitError::InvalidRefName(name.to_owned()));
+    }
+    Ok(())
+}
+
+/// Follow `hash` through tag objects to the object it finally points at.
+fn peel(mut hash: SHA1, tags: &HashMap<SHA1, Tag>) -> SHA1 {
+    // a tag can't point at itself through a chain, but a crafted one could
+    for _ in 0..=tags.len() {
+        match tags.get(&hash) {
+            Some(tag) => hash = tag.object_hash,
+            None => break,
+        }
+    }
+    hash
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use venus::hash::SHA1;
+    use venus::internal::object::signature::Signature;
+    use venus::internal::object::tag::Tag;
+    use venus::internal::object::types::ObjectType;
+    use venus::internal::pack::reference::Refs;
+
+    use super::{check_tag_name, tags_for_commit};
+
+    fn tag_ref(name: &str, hash: SHA1) -> Refs {
+        Refs {
+            ref_name: format!("refs/tags/{}", name),
+            ref_hash: hash.to_plain_str(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_lightweight_and_annotated_tags() {
+        let commit = SHA1::new(&b"commit".to_vec());
+        let other = SHA1::new(&b"other commit".to_vec());
+        let annotated = Tag {
+            id: SHA1::new(&b"tag v1.0.0".to_vec()),
+            object_hash: commit,
+            object_type: ObjectType::Commit,
+            tag_name: "v1.0.0".to_owned(),
+            tagger: Signature::from_data(b"tagger mega <admin@mega.org> 1700000000 +0800".to_vec())
+                .unwrap(),
+            message: "\nrelease 1.0.0\n".to_owned(),
+        };
+        let tags = HashMap::from([(annotated.id, annotated.clone())]);
+        let tag_refs = vec![
+            tag_ref("latest", commit),
+            tag_ref("v1.0.0", annotated.id),
+            tag_ref("v0.9.0", other),
+        ];
+
+        assert_eq!(
+            tags_for_commit(&commit, &tag_refs, &tags),
+            vec!["latest", "v1.0.0"]
+        );
+        // the annotated tag is found without its ref too
+        assert_eq!(tags_for_commit(&commit, &[], &tags), vec!["v1.0.0"]);
+        assert_eq!(tags_for_commit(&other, &tag_refs, &tags), vec!["v0.9.0"]);
+    }
+
+    #[test]
+    fn test_check_tag_name() {
+        for name in ["v1.0.0", "release/2024-01", "v1.0.0-rc.1"] {
+            assert!(check_tag_name(name).is_ok(), "{}", name);
+        }
+        for name in [
+            "",
+            "v1..0",
+            "v1 0",
+            "v1.lock",
+            "release/.hidden",
+            "-v1",
+            "v1/",
+            "a\nb",
+        ] {
+            assert!(check_tag_name(name).is_err(), "{}", name);
+        }
+    }
+}
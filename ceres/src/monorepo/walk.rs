@@ -0,0 +1,139 @@
+//! Loading whole trees level by level.
+//!
+//! Walking a tree one subtree at a time costs a storage round trip per tree, which adds up for
+//! wide trees like the monorepo root. [`walk_batched`] loads all trees of a level at once, so the
+//! number of round trips is the depth of the tree instead.
+use std::collections::HashMap;
+
+use jupiter::storage::object_storage::ObjectStorage;
+use venus::errors::GitError;
+use venus::hash::SHA1;
+use venus::internal::object::tree::{Tree, TreeItemMode};
+
+/// Load the tree `root` and every tree below it, breadth first, each with its slash separated
+/// path relative to `root`. The path of `root` itself is empty.
+pub async fn walk_batched(
+    storage: &dyn ObjectStorage,
+    root: SHA1,
+) -> Result<Vec<(String, Tree)>, GitError> {
+    let mut walked = Vec::new();
+    let mut level = vec![(String::new(), root)];
+    while !level.is_empty() {
+        let mut hashes: Vec<SHA1> = level.iter().map(|(_, id)| *id).collect();
+        hashes.sort();
+        hashes.dedup();
+        let trees: HashMap<SHA1, Tree> = storage
+            .get_trees(&hashes)
+            .await?
+            .into_iter()
+            .map(|x| (x.id, x))
+            .collect();
+
+        let mut next = Vec::new();
+        for (prefix, id) in level {
+            let tree = trees
+                .get(&id)
+                .ok_or_else(|| GitError::NotFountHashValue(id.to_plain_str()))?;
+            for item in &tree.tree_items {
+                if item.mode == TreeItemMode::Tree {
+                    next.push((join_path(&prefix, &item.name), item.id));
+                }
+            }
+            walked.push((prefix, tree.clone()));
+        }
+        level = next;
+    }
+    Ok(walked)
+}
+
+/// `name` below the directory `prefix`, an empty prefix being the root.
+pub fn join_path(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+
+    use common::errors::MegaError;
+    use jupiter::storage::object_storage::{MemoryStorage, ObjectStorage};
+    use venus::hash::SHA1;
+    use venus::internal::object::blob::Blob;
+    use venus::internal::object::commit::Commit;
+    use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+
+    use super::walk_batched;
+    use crate::monorepo::diff::{build_tree, flatten_tree, FlatTree};
+
+    /// Counts the round trips for trees.
+    #[derive(Default)]
+    struct CountingStorage {
+        inner: MemoryStorage,
+        tree_calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ObjectStorage for CountingStorage {
+        async fn get_commit(&self, hash: &SHA1) -> Result<Option<Commit>, MegaError> {
+            self.inner.get_commit(hash).await
+        }
+
+        async fn get_tree(&self, hash: &SHA1) -> Result<Option<Tree>, MegaError> {
+            self.tree_calls.fetch_add(1, Ordering::SeqCst);
+            self.inner.get_tree(hash).await
+        }
+
+        async fn get_blob(&self, hash: &SHA1) -> Result<Option<Blob>, MegaError> {
+            self.inner.get_blob(hash).await
+        }
+
+        async fn get_trees(&self, hashes: &[SHA1]) -> Result<Vec<Tree>, MegaError> {
+            self.tree_calls.fetch_add(1, Ordering::SeqCst);
+            self.inner.get_trees(hashes).await
+        }
+
+        async fn save_commits(&self, commits: Vec<Commit>) -> Result<(), MegaError> {
+            self.inner.save_commits(commits).await
+        }
+
+        async fn save_trees(&self, trees: Vec<Tree>) -> Result<(), MegaError> {
+            self.inner.save_trees(trees).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_walk_wide_tree() {
+        // 20 projects with a src and a docs directory each, 61 trees in 3 levels
+        let mut flat = FlatTree::new();
+        for i in 0..20 {
+            for dir in ["src", "docs"] {
+                let blob = Blob::from_content(&format!("{} of project {}", dir, i));
+                let item = TreeItem::new(TreeItemMode::Blob, blob.id, String::new());
+                flat.insert(format!("project{}/{}/file", i, dir), item);
+            }
+        }
+        let (root, trees) = build_tree(&flat).unwrap();
+        assert_eq!(trees.len(), 61);
+        let storage = CountingStorage::default();
+        storage.save_trees(trees).await.unwrap();
+
+        let walked = walk_batched(&storage, root).await.unwrap();
+        assert_eq!(walked.len(), 61);
+        assert_eq!(walked[0].0, "");
+        assert!(walked.iter().any(|(path, _)| path == "project7/docs"));
+        assert_eq!(storage.tree_calls.load(Ordering::SeqCst), 3);
+
+        let flattened = flatten_tree(&storage, root).await.unwrap();
+        assert_eq!(
+            flattened.keys().collect::<Vec<_>>(),
+            flat.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(storage.tree_calls.load(Ordering::SeqCst), 6);
+    }
+}
@@ -1,12 +1,14 @@
 use std::{
-    collections::HashSet,
+    collections::{hash_map, HashMap, HashSet},
     env,
     io::Cursor,
     path::PathBuf,
+    str::FromStr,
     sync::{
         atomic::{AtomicUsize, Ordering},
         mpsc::{self, Receiver, Sender},
     },
+    thread::JoinHandle,
 };
 
 use async_trait::async_trait;
@@ -29,10 +31,75 @@ use venus::{
     },
 };
 
+use crate::pack::sparse::SparsePatterns;
+use crate::pack::upload::PackUploads;
+use crate::protocol::smart::{self, ServerCapabilities};
+use crate::protocol::ServiceType;
+
+/// Object filter sent by partial clone clients in the `filter <filter-spec>` line of an
+/// upload-pack request, see `git rev-list --filter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectFilter {
+    /// `blob:none`, omit all blobs from the pack
+    BlobNone,
+    /// `blob:limit=<n>[kmg]`, omit blobs of at least `n` bytes
+    BlobLimit(usize),
+}
+
+impl FromStr for ObjectFilter {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "blob:none" {
+            return Ok(ObjectFilter::BlobNone);
+        }
+        let limit = s.strip_prefix("blob:limit=").ok_or(())?;
+        let (digits, unit) = match limit.char_indices().last() {
+            Some((i, c)) if c.is_ascii_alphabetic() => (&limit[..i], c.to_ascii_lowercase()),
+            _ => (limit, 'b'),
+        };
+        let scale = match unit {
+            'b' => 1,
+            'k' => 1024,
+            'm' => 1024 * 1024,
+            'g' => 1024 * 1024 * 1024,
+            _ => return Err(()),
+        };
+        let n = digits.parse::<usize>().map_err(|_| ())?;
+        Ok(ObjectFilter::BlobLimit(n.saturating_mul(scale)))
+    }
+}
+
+/// The prefix of the `want` entries naming a ref instead of a hash, see
+/// [`PackHandler::resolve_wants`].
+pub const WANT_REF: &str = "want-ref ";
+
+fn join_path(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
+
+/// The key recording in `exist_objs` that the tree `hash` at `path` was walked with a sparse
+/// checkout, next to the hash recording the tree was sent.
+fn sparse_tree_key(path: &str, hash: &str) -> String {
+    format!("{}:{}", path, hash)
+}
+
 #[async_trait]
 pub trait PackHandler: Send + Sync {
+    /// The hash of the default branch and all refs, `ZERO_ID` if there are none. Only reads,
+    /// refs which don't exist yet are created by [`PackHandler::ensure_ref`].
     async fn head_hash(&self) -> (String, Vec<Refs>);
 
+    /// Create the refs of the repository if it has none yet. Transports call this before
+    /// advertising the refs, so a clone of a new repository finds them.
+    async fn ensure_ref(&self) -> Result<(), GitError> {
+        Ok(())
+    }
+
     fn find_head_hash(&self, refs: Vec<Refs>) -> (String, Vec<Refs>) {
         let mut head_hash = ZERO_ID.to_string();
         for git_ref in refs.iter() {
@@ -43,89 +110,284 @@ pub trait PackHandler: Send + Sync {
         (head_hash, refs)
     }
 
+    /// The commit hash the ref `name`, like `refs/heads/main`, points at among the refs of
+    /// [`PackHandler::head_hash`], `HEAD` standing for the default branch.
+    async fn resolve_ref(&self, name: &str) -> Result<String, GitError> {
+        let (head_hash, refs) = self.head_hash().await;
+        if name == "HEAD" && head_hash != ZERO_ID {
+            return Ok(head_hash);
+        }
+        refs.into_iter()
+            .find(|x| x.ref_name == name)
+            .map(|x| x.ref_hash)
+            .ok_or_else(|| GitError::RefNotFound(name.to_owned()))
+    }
+
+    /// Replace the `want-ref <name>` entries of `want`, refs a client asked for by name instead
+    /// of by hash, with the hashes [`PackHandler::resolve_ref`] resolves them to.
+    async fn resolve_wants(&self, want: Vec<String>) -> Result<Vec<String>, GitError> {
+        let mut resolved = Vec::with_capacity(want.len());
+        for hash in want {
+            match hash.strip_prefix(WANT_REF) {
+                Some(name) => resolved.push(self.resolve_ref(name).await?),
+                None => resolved.push(hash),
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// The capabilities advertised to clients along with the refs.
+    fn capabilities(&self) -> ServerCapabilities {
+        ServerCapabilities::default()
+    }
+
+    /// Whether pushes may update branches to commits which don't descend from their old value,
+    /// discarding commits.
+    fn force_push_allowed(&self) -> bool {
+        false
+    }
+
+    /// The body of a smart http `info/refs?service=<service>` response: the service header, then
+    /// the refs as pkt-lines with the capabilities on the first one, each part ended by a flush.
+    async fn info_refs(&self, service: &str) -> Result<Bytes, GitError> {
+        let service_type = ServiceType::from_str(service)
+            .map_err(|_| GitError::UnsupportedService(service.to_owned()))?;
+        self.ensure_ref().await?;
+        let (head_hash, refs) = self.head_hash().await;
+        let ref_list = smart::advertised_refs(service_type, &self.capabilities(), head_hash, refs);
+        Ok(smart::build_pkt_line_stream(&ref_list, Some(service)).freeze())
+    }
+
     async fn unpack(&self, pack_file: Bytes) -> Result<(), GitError>;
 
+    /// Receive the chunk of an upload starting at `offset` of its `total` bytes. The pack is only
+    /// unpacked once all of its chunks arrived and its checksum matches, returns whether it was.
+    async fn unpack_chunk(
+        &self,
+        uploads: &PackUploads,
+        upload_id: &str,
+        offset: usize,
+        total: usize,
+        chunk: Bytes,
+    ) -> Result<bool, GitError> {
+        uploads.put_chunk(upload_id, offset, &chunk).await?;
+        let Some(pack_file) = uploads.assemble(upload_id, total).await? else {
+            return Ok(false);
+        };
+        // the chunks are kept until the pack is stored, a failed unpack can be retried by sending
+        // any chunk again
+        if let Err(err) = self.unpack(pack_file).await {
+            let _ = uploads.release(upload_id).await;
+            return Err(err);
+        }
+        uploads.finish(upload_id).await?;
+        Ok(true)
+    }
+
     /// Asynchronously retrieves the full pack data for the specified repository path.
     /// This function collects commits and nodes from the storage and packs them into
     /// a single binary vector. There is no need to build the entire tree; the function
     /// only sends all the data related to this repository.
     ///
+    /// # Arguments
+    /// * `want` - The hashes the client asked for, all of the repository if empty. Entries may
+    ///   name refs, see [`PackHandler::resolve_wants`].
+    /// * `filter` - The object filter requested by a partial clone client, if any.
+    ///
     /// # Returns
     /// * `Result<Vec<u8>, GitError>` - The packed binary data as a vector of bytes.
     ///
-    async fn full_pack(&self) -> Result<Vec<u8>, GitError>;
+    async fn full_pack(
+        &self,
+        want: Vec<String>,
+        filter: Option<ObjectFilter>,
+    ) -> Result<Vec<u8>, GitError>;
 
+    /// Pack the objects reachable from `want` which aren't reachable from `have`. Entries of
+    /// `want` may name refs, see [`PackHandler::resolve_wants`].
     async fn incremental_pack(
         &self,
         want: Vec<String>,
         have: Vec<String>,
+        filter: Option<ObjectFilter>,
     ) -> Result<Vec<u8>, GitError>;
 
+    /// The sparse checkout patterns limiting which blobs are packed, `None` packs all of them.
+    fn sparse_patterns(&self) -> Option<&SparsePatterns> {
+        None
+    }
+
+    /// Count the objects [`PackHandler::traverse`] would send for `tree`, whose path relative to
+    /// the repository root is `path`. Counted objects are recorded in `exist_objs` like sent ones
+    /// are, so objects shared by several trees counted one after the other are counted once.
     async fn traverse_for_count(
         &self,
         tree: Tree,
-        exist_objs: &HashSet<String>,
+        path: &str,
+        exist_objs: &mut HashSet<String>,
         obj_num: &AtomicUsize,
-    ) {
+        filter: Option<ObjectFilter>,
+    ) -> Result<(), GitError> {
+        let (walk, count) = self.visit_tree(exist_objs, path, tree.id.to_plain_str());
+        if !walk {
+            return Ok(());
+        }
         let mut search_tree_ids = vec![];
         let mut search_blob_ids = vec![];
+        let mut tree_paths = HashMap::new();
         for item in &tree.tree_items {
             let hash = item.id.to_plain_str();
-            if !exist_objs.contains(&hash) {
-                if item.mode == TreeItemMode::Tree {
-                    search_tree_ids.push(hash.clone())
-                } else {
-                    search_blob_ids.push(hash.clone());
+            let item_path = join_path(path, &item.name);
+            if item.mode == TreeItemMode::Tree {
+                if !self.tree_visited(exist_objs, &item_path, &hash) {
+                    match tree_paths.entry(hash.clone()) {
+                        hash_map::Entry::Vacant(e) => {
+                            e.insert(vec![item_path]);
+                            search_tree_ids.push(hash);
+                        }
+                        hash_map::Entry::Occupied(mut e) => e.get_mut().push(item_path),
+                    }
                 }
+            } else if self.in_sparse_checkout(&item_path) && exist_objs.insert(hash.clone()) {
+                search_blob_ids.push(hash);
             }
         }
+        let search_blob_ids = self.filter_blobs(search_blob_ids, filter).await?;
         obj_num.fetch_add(search_blob_ids.len(), Ordering::SeqCst);
-        let trees = self.get_trees_by_hashes(search_tree_ids).await.unwrap();
+        let trees = self.get_trees_by_hashes(search_tree_ids).await?;
         for t in trees {
-            self.traverse_for_count(t, exist_objs, obj_num).await;
+            for t_path in &tree_paths[&t.id.to_plain_str()] {
+                self.traverse_for_count(t.clone(), t_path, exist_objs, obj_num, filter)
+                    .await?;
+            }
+        }
+        if count {
+            obj_num.fetch_add(1, Ordering::SeqCst);
         }
-        obj_num.fetch_add(1, Ordering::SeqCst);
+        Ok(())
     }
 
+    /// Send `tree`, whose path relative to the repository root is `path`, and all objects
+    /// reachable from it which are not in `exist_objs` to `sender`, children first. Blobs
+    /// rejected by `filter` are skipped, but still recorded in `exist_objs` as promised objects
+    /// so they are not considered again. Blobs outside the sparse checkout are skipped without
+    /// being recorded, the same blob may still be needed at another path.
+    ///
+    /// If `tree` itself is already in `exist_objs`, e.g. a want commit with the same tree as a
+    /// have commit, nothing is sent at all. With a sparse checkout that only holds for the same
+    /// tree at the same path, see [`PackHandler::visit_tree`].
     async fn traverse(
         &self,
         tree: Tree,
+        path: &str,
         exist_objs: &mut HashSet<String>,
         sender: Option<&Sender<Entry>>,
-    ) {
-        exist_objs.insert(tree.id.to_plain_str());
+        filter: Option<ObjectFilter>,
+    ) -> Result<(), GitError> {
+        let (walk, send) = self.visit_tree(exist_objs, path, tree.id.to_plain_str());
+        if !walk {
+            return Ok(());
+        }
         let mut search_tree_ids = vec![];
         let mut search_blob_ids = vec![];
+        let mut tree_paths = HashMap::new();
         for item in &tree.tree_items {
             let hash = item.id.to_plain_str();
-            if !exist_objs.contains(&hash) {
-                if item.mode == TreeItemMode::Tree {
-                    search_tree_ids.push(hash.clone())
-                } else {
-                    search_blob_ids.push(hash.clone());
+            let item_path = join_path(path, &item.name);
+            if item.mode == TreeItemMode::Tree {
+                // subtrees are recorded by their own traversal
+                if !self.tree_visited(exist_objs, &item_path, &hash) {
+                    match tree_paths.entry(hash.clone()) {
+                        hash_map::Entry::Vacant(e) => {
+                            e.insert(vec![item_path]);
+                            search_tree_ids.push(hash);
+                        }
+                        hash_map::Entry::Occupied(mut e) => e.get_mut().push(item_path),
+                    }
                 }
-                exist_objs.insert(hash);
+            } else if self.in_sparse_checkout(&item_path) && exist_objs.insert(hash.clone()) {
+                search_blob_ids.push(hash);
             }
         }
 
         if let Some(sender) = sender {
-            let blobs = self.get_blobs_by_hashes(search_blob_ids).await.unwrap();
+            let search_blob_ids = self.filter_blobs(search_blob_ids, filter).await?;
+            let blobs = self.get_blobs_by_hashes(search_blob_ids).await?;
             for b in blobs {
                 let blob: Blob = b.into();
                 sender.send(blob.into()).unwrap();
             }
         }
-        let trees = self.get_trees_by_hashes(search_tree_ids).await.unwrap();
+        let trees = self.get_trees_by_hashes(search_tree_ids).await?;
         for t in trees {
-            self.traverse(t, exist_objs, sender).await;
+            for t_path in &tree_paths[&t.id.to_plain_str()] {
+                self.traverse(t.clone(), t_path, exist_objs, sender, filter)
+                    .await?;
+            }
         }
-        if let Some(sender) = sender {
+        if let Some(sender) = sender.filter(|_| send) {
             sender.send(tree.into()).unwrap();
         }
+        Ok(())
+    }
+
+    fn in_sparse_checkout(&self, path: &str) -> bool {
+        self.sparse_patterns().is_none_or(|x| x.includes(path))
+    }
+
+    /// Record the tree `hash` at `path` in `exist_objs`, returning whether it has to be walked
+    /// and whether the tree itself has to be sent. With a sparse checkout the blobs sent below a
+    /// tree depend on its path, so the same tree is walked once per path, but sent once.
+    fn visit_tree(
+        &self,
+        exist_objs: &mut HashSet<String>,
+        path: &str,
+        hash: String,
+    ) -> (bool, bool) {
+        let send = exist_objs.insert(hash.clone());
+        match self.sparse_patterns() {
+            None => (send, send),
+            Some(_) => (exist_objs.insert(sparse_tree_key(path, &hash)), send),
+        }
+    }
+
+    /// Whether the tree `hash` at `path` has been recorded by [`PackHandler::visit_tree`].
+    fn tree_visited(&self, exist_objs: &HashSet<String>, path: &str, hash: &str) -> bool {
+        match self.sparse_patterns() {
+            None => exist_objs.contains(hash),
+            Some(_) => exist_objs.contains(&sparse_tree_key(path, hash)),
+        }
+    }
+
+    /// Keep the blobs in `hashes` which pass `filter`. The omitted blobs are still referenced
+    /// by their trees, the client treats them as promised objects and fetches them on demand.
+    async fn filter_blobs(
+        &self,
+        hashes: Vec<String>,
+        filter: Option<ObjectFilter>,
+    ) -> Result<Vec<String>, GitError> {
+        match filter {
+            None => Ok(hashes),
+            Some(ObjectFilter::BlobNone) => Ok(vec![]),
+            Some(ObjectFilter::BlobLimit(limit)) => {
+                let sizes = self.get_blob_sizes(hashes.clone()).await?;
+                // blobs without a recorded size are kept, a client can't ask for them otherwise
+                Ok(hashes
+                    .into_iter()
+                    .filter(|x| sizes.get(x).is_none_or(|size| *size < limit))
+                    .collect())
+            }
+        }
     }
 
     async fn get_trees_by_hashes(&self, hashes: Vec<String>) -> Result<Vec<Tree>, MegaError>;
 
+    /// Returns the size in bytes of each blob in `hashes`, keyed by blob hash.
+    async fn get_blob_sizes(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<HashMap<String, usize>, MegaError>;
+
     async fn get_blobs_by_hashes(
         &self,
         hashes: Vec<String>,
@@ -137,7 +399,8 @@ pub trait PackHandler: Send + Sync {
 
     async fn check_default_branch(&self) -> bool;
 
-    fn pack_decoder(&self, pack_file: Bytes) -> Result<Receiver<Entry>, GitError> {
+    /// Start decoding `pack_file` in the background, see [`DecodingPack`].
+    fn pack_decoder(&self, pack_file: Bytes) -> Result<DecodingPack, GitError> {
         // #[cfg(debug_assertions)]
         // {
         //     let datetime = chrono::Utc::now().naive_utc();
@@ -163,7 +426,517 @@ pub trait PackHandler: Send + Sync {
             Some(tmp.clone()),
             clean_tmp,
         );
-        p.decode_async(Cursor::new(pack_file), sender); //Pack moved here
-        Ok(receiver)
+        let handle = p.decode_async(Cursor::new(pack_file), sender); //Pack moved here
+        Ok(DecodingPack {
+            entries: receiver,
+            handle,
+        })
+    }
+}
+
+/// A pack being decoded on a background thread. The decoded entries arrive on `entries`, but
+/// the pack is only known to be valid once [`DecodingPack::finish`] returned `Ok`, so nothing
+/// depending on the whole push may be saved before.
+pub struct DecodingPack {
+    pub entries: Receiver<Entry>,
+    handle: JoinHandle<Result<Pack, GitError>>,
+}
+
+impl DecodingPack {
+    /// Wait for the decoding thread and return its error, e.g. for a wrong object count.
+    pub fn finish(self) -> Result<(), GitError> {
+        match self.handle.join() {
+            Ok(result) => result.map(|_| ()),
+            Err(err) => std::panic::resume_unwind(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
+
+    use async_trait::async_trait;
+    use bytes::Bytes;
+
+    use callisto::{db_enums::StorageType, raw_blob};
+    use common::errors::MegaError;
+    use mercury::internal::pack::{encode::PackEncoder, Pack};
+    use venus::errors::GitError;
+    use venus::internal::object::blob::Blob;
+    use venus::internal::object::commit::Commit;
+    use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+    use venus::internal::object::types::ObjectType;
+    use venus::internal::pack::entry::Entry;
+    use venus::internal::pack::reference::{RefCommand, Refs};
+
+    use super::{ObjectFilter, PackHandler};
+    use crate::pack::sparse::SparsePatterns;
+    use crate::pack::upload::PackUploads;
+    use crate::protocol::pktline::Packet;
+    use crate::protocol::smart::ServerCapabilities;
+    use crate::test_util::packets;
+
+    /// A handler serving trees and blobs from memory, only the traversal is exercised.
+    #[derive(Default)]
+    struct MemoryHandler {
+        trees: HashMap<String, Tree>,
+        blobs: HashMap<String, Blob>,
+        sparse: Option<SparsePatterns>,
+        refs: Vec<Refs>,
+        capabilities: ServerCapabilities,
+        unpacked: Mutex<Vec<Bytes>>,
+        /// Fail the next unpack, like a storage error would.
+        fail_unpack: AtomicBool,
+    }
+
+    impl MemoryHandler {
+        fn add_tree(&mut self, items: Vec<TreeItem>) -> Tree {
+            let tree = Tree::from_tree_items(items).unwrap();
+            self.trees.insert(tree.id.to_plain_str(), tree.clone());
+            tree
+        }
+
+        fn add_blob(&mut self, content: &str, name: &str) -> TreeItem {
+            let blob = Blob::from_content(content);
+            let item = TreeItem::new(TreeItemMode::Blob, blob.id, name.to_owned());
+            self.blobs.insert(blob.id.to_plain_str(), blob);
+            item
+        }
+
+        /// Pack `commit` and its tree the way `MonoRepo::full_pack` does.
+        async fn pack(&self, commit: Commit, tree: Tree, filter: Option<ObjectFilter>) -> Vec<u8> {
+            let obj_num = AtomicUsize::new(1);
+            self.traverse_for_count(tree.clone(), "", &mut HashSet::new(), &obj_num, filter)
+                .await
+                .unwrap();
+            let (sender, receiver) = mpsc::channel();
+            let encoder = PackEncoder::new(obj_num.into_inner(), 0);
+            let data = encoder.encode_async(receiver).unwrap();
+            self.traverse(tree, "", &mut HashSet::new(), Some(&sender), filter)
+                .await
+                .unwrap();
+            sender.send(commit.into()).unwrap();
+            drop(sender);
+            data.join().unwrap()
+        }
+    }
+
+    #[async_trait]
+    impl PackHandler for MemoryHandler {
+        async fn head_hash(&self) -> (String, Vec<Refs>) {
+            self.find_head_hash(self.refs.clone())
+        }
+
+        async fn unpack(&self, pack_file: Bytes) -> Result<(), GitError> {
+            if self.fail_unpack.swap(false, Ordering::SeqCst) {
+                return Err(GitError::StorageError("storage unavailable".to_owned()));
+            }
+            self.unpacked.lock().unwrap().push(pack_file);
+            Ok(())
+        }
+
+        async fn full_pack(
+            &self,
+            _: Vec<String>,
+            _: Option<ObjectFilter>,
+        ) -> Result<Vec<u8>, GitError> {
+            unimplemented!()
+        }
+
+        async fn incremental_pack(
+            &self,
+            _: Vec<String>,
+            _: Vec<String>,
+            _: Option<ObjectFilter>,
+        ) -> Result<Vec<u8>, GitError> {
+            unimplemented!()
+        }
+
+        fn sparse_patterns(&self) -> Option<&SparsePatterns> {
+            self.sparse.as_ref()
+        }
+
+        fn capabilities(&self) -> ServerCapabilities {
+            self.capabilities.clone()
+        }
+
+        async fn get_trees_by_hashes(&self, hashes: Vec<String>) -> Result<Vec<Tree>, MegaError> {
+            Ok(hashes
+                .iter()
+                .filter_map(|x| self.trees.get(x).cloned())
+                .collect())
+        }
+
+        async fn get_blob_sizes(
+            &self,
+            hashes: Vec<String>,
+        ) -> Result<HashMap<String, usize>, MegaError> {
+            Ok(hashes
+                .iter()
+                .filter_map(|x| self.blobs.get(x))
+                .map(|blob| (blob.id.to_plain_str(), blob.data.len()))
+                .collect())
+        }
+
+        async fn get_blobs_by_hashes(
+            &self,
+            hashes: Vec<String>,
+        ) -> Result<Vec<raw_blob::Model>, MegaError> {
+            Ok(hashes
+                .iter()
+                .filter_map(|x| self.blobs.get(x))
+                .map(|blob| raw_blob::Model {
+                    id: 0,
+                    sha1: blob.id.to_plain_str(),
+                    content: None,
+                    file_type: None,
+                    storage_type: StorageType::Database,
+                    data: Some(blob.data.clone()),
+                    local_path: None,
+                    remote_url: None,
+                    created_at: chrono::Utc::now().naive_utc(),
+                })
+                .collect())
+        }
+
+        async fn update_refs(&self, _: &RefCommand) -> Result<(), GitError> {
+            unimplemented!()
+        }
+
+        async fn check_commit_exist(&self, _: &str) -> bool {
+            unimplemented!()
+        }
+
+        async fn check_default_branch(&self) -> bool {
+            unimplemented!()
+        }
+    }
+
+    fn decode(data: Vec<u8>) -> Vec<Entry> {
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let mut p = Pack::new(
+            None,
+            Some(1024 * 20),
+            Some(PathBuf::from("/tmp/.cache_temp")),
+            true,
+        );
+        let result = entries.clone();
+        p.decode(&mut Cursor::new(data), move |entry| {
+            result.lock().unwrap().push(entry)
+        })
+        .unwrap();
+        let entries = entries.lock().unwrap().clone();
+        entries
+    }
+
+    fn decode_types(data: Vec<u8>) -> Vec<ObjectType> {
+        decode(data).into_iter().map(|x| x.obj_type).collect()
+    }
+
+    fn count(types: &[ObjectType], obj_type: ObjectType) -> usize {
+        types.iter().filter(|x| **x == obj_type).count()
+    }
+
+    #[tokio::test]
+    async fn test_blob_none_filter() {
+        let mut handler = MemoryHandler::default();
+        let main = handler.add_blob("fn main() {}", "main.rs");
+        let src = handler.add_tree(vec![main]);
+        let readme = handler.add_blob("# mega", "README.md");
+        let root = handler.add_tree(vec![
+            readme,
+            TreeItem::new(TreeItemMode::Tree, src.id, "src".to_owned()),
+        ]);
+        let commit = Commit::from_tree_id(root.id, vec![], "init");
+
+        let types = decode_types(handler.pack(commit.clone(), root.clone(), None).await);
+        assert_eq!(count(&types, ObjectType::Blob), 2);
+
+        let types = decode_types(
+            handler
+                .pack(commit, root, Some(ObjectFilter::BlobNone))
+                .await,
+        );
+        assert_eq!(count(&types, ObjectType::Commit), 1);
+        assert_eq!(count(&types, ObjectType::Tree), 2);
+        assert_eq!(count(&types, ObjectType::Blob), 0);
+    }
+
+    #[tokio::test]
+    async fn test_blob_limit_filter() {
+        let mut handler = MemoryHandler::default();
+        let small = handler.add_blob("small", "small.txt");
+        let large = handler.add_blob(&"large".repeat(1000), "large.txt");
+        let limit = handler.add_blob(&"x".repeat(100), "limit.txt");
+        let nested = handler.add_blob("tiny", "tiny.txt");
+        let sub = handler.add_tree(vec![nested]);
+        let root = handler.add_tree(vec![
+            large,
+            limit,
+            small,
+            TreeItem::new(TreeItemMode::Tree, sub.id, "sub".to_owned()),
+        ]);
+        let commit = Commit::from_tree_id(root.id, vec![], "init");
+
+        let types = decode_types(
+            handler
+                .pack(commit, root, Some(ObjectFilter::BlobLimit(100)))
+                .await,
+        );
+        // small.txt and tiny.txt, blobs of exactly the limit are omitted too
+        assert_eq!(count(&types, ObjectType::Blob), 2);
+        assert_eq!(count(&types, ObjectType::Tree), 2);
+        assert_eq!(count(&types, ObjectType::Commit), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sparse_cone_pattern() {
+        let mut handler = MemoryHandler {
+            sparse: Some(SparsePatterns::cone(&["src/"])),
+            ..Default::default()
+        };
+        let main = handler.add_blob("fn main() {}", "main.rs");
+        let src = handler.add_tree(vec![main.clone()]);
+        let guide = handler.add_blob("# guide", "guide.md");
+        let docs = handler.add_tree(vec![guide.clone()]);
+        let readme = handler.add_blob("# mega", "README.md");
+        let root = handler.add_tree(vec![
+            readme.clone(),
+            TreeItem::new(TreeItemMode::Tree, docs.id, "docs".to_owned()),
+            TreeItem::new(TreeItemMode::Tree, src.id, "src".to_owned()),
+        ]);
+        let commit = Commit::from_tree_id(root.id, vec![], "init");
+
+        let entries = decode(handler.pack(commit, root, None).await);
+        let hashes: Vec<_> = entries.iter().map(|x| x.hash).collect();
+        assert!(hashes.contains(&main.id));
+        // files in the root are always part of a cone checkout
+        assert!(hashes.contains(&readme.id));
+        assert!(!hashes.contains(&guide.id));
+        // the docs tree is still sent, only its blobs are left out
+        assert!(hashes.contains(&docs.id));
+        let types: Vec<_> = entries.into_iter().map(|x| x.obj_type).collect();
+        assert_eq!(count(&types, ObjectType::Tree), 3);
+    }
+
+    #[tokio::test]
+    async fn test_sparse_tree_at_two_paths() {
+        let mut handler = MemoryHandler {
+            sparse: Some(SparsePatterns::cone(&["src/"])),
+            ..Default::default()
+        };
+        let lib = handler.add_blob("pub fn f() {}", "lib.rs");
+        let shared = handler.add_tree(vec![lib.clone()]);
+        let item = |name: &str| TreeItem::new(TreeItemMode::Tree, shared.id, name.to_owned());
+        // the same tree outside of the checkout first, then inside of it
+        let guide = handler.add_blob("# guide", "guide.md");
+        let docs = handler.add_tree(vec![guide, item("lib")]);
+        let src = handler.add_tree(vec![item("lib")]);
+        let root = handler.add_tree(vec![
+            TreeItem::new(TreeItemMode::Tree, docs.id, "docs".to_owned()),
+            TreeItem::new(TreeItemMode::Tree, src.id, "src".to_owned()),
+        ]);
+        let commit = Commit::from_tree_id(root.id, vec![], "init");
+
+        let entries = decode(handler.pack(commit, root, None).await);
+        let hashes: Vec<_> = entries.iter().map(|x| x.hash).collect();
+        assert!(hashes.contains(&lib.id));
+        let types: Vec<_> = entries.into_iter().map(|x| x.obj_type).collect();
+        // root, docs, src and the shared tree once
+        assert_eq!(count(&types, ObjectType::Tree), 4);
+        assert_eq!(count(&types, ObjectType::Blob), 1);
+    }
+
+    #[tokio::test]
+    async fn test_skip_existing_tree() {
+        let mut handler = MemoryHandler::default();
+        let main = handler.add_blob("fn main() {}", "main.rs");
+        let src = handler.add_tree(vec![main]);
+        let root = handler.add_tree(vec![TreeItem::new(
+            TreeItemMode::Tree,
+            src.id,
+            "src".to_owned(),
+        )]);
+
+        // the have commit's tree, as incremental_pack collects it
+        let mut exist_objs = HashSet::new();
+        handler
+            .traverse(root.clone(), "", &mut exist_objs, None, None)
+            .await
+            .unwrap();
+
+        // a want commit without changes has the very same tree
+        let obj_num = AtomicUsize::new(0);
+        handler
+            .traverse_for_count(root.clone(), "", &mut exist_objs.clone(), &obj_num, None)
+            .await
+            .unwrap();
+        assert_eq!(obj_num.into_inner(), 0);
+
+        let (sender, receiver) = mpsc::channel();
+        handler
+            .traverse(root, "", &mut exist_objs, Some(&sender), None)
+            .await
+            .unwrap();
+        drop(sender);
+        assert_eq!(receiver.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_parse_object_filter() {
+        assert_eq!("blob:none".parse(), Ok(ObjectFilter::BlobNone));
+        assert_eq!("blob:limit=512".parse(), Ok(ObjectFilter::BlobLimit(512)));
+        assert_eq!("blob:limit=1k".parse(), Ok(ObjectFilter::BlobLimit(1024)));
+        assert_eq!(
+            "blob:limit=2m".parse(),
+            Ok(ObjectFilter::BlobLimit(2 * 1024 * 1024))
+        );
+        assert!("blob:limit=".parse::<ObjectFilter>().is_err());
+        assert!("tree:0".parse::<ObjectFilter>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_info_refs() {
+        let hash = "7bdc783132575d5b3e78400ace9971970ff43a18";
+        let handler = MemoryHandler {
+            refs: vec![Refs {
+                id: 0,
+                ref_name: "refs/heads/main".to_owned(),
+                ref_hash: hash.to_owned(),
+                default_branch: true,
+            }],
+            ..Default::default()
+        };
+        let body = handler.info_refs("git-upload-pack").await.unwrap();
+
+        let lines = packets(&body);
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[0],
+            Packet::Data(b"# service=git-upload-pack\n".to_vec())
+        );
+        assert_eq!(lines[1], Packet::Flush);
+        let Packet::Data(first_ref) = &lines[2] else {
+            panic!("the first ref is missing");
+        };
+        let (first, caps) = first_ref.split_at(first_ref.iter().position(|&x| x == 0).unwrap());
+        assert_eq!(first, format!("{} HEAD", hash).as_bytes());
+        assert!(caps.ends_with(b"\n") && caps.len() > 2);
+        assert_eq!(
+            lines[3],
+            Packet::Data(format!("{} refs/heads/main\n", hash).into_bytes())
+        );
+        assert_eq!(lines[4], Packet::Flush);
+
+        assert!(matches!(
+            handler.info_refs("git-archive").await,
+            Err(GitError::UnsupportedService(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_advertised_capabilities() {
+        let handler = MemoryHandler {
+            capabilities: ServerCapabilities::default().with_agent("mega/test"),
+            ..Default::default()
+        };
+        for service in ["git-upload-pack", "git-receive-pack"] {
+            let body = handler.info_refs(service).await.unwrap();
+            let Packet::Data(first_ref) = &packets(&body)[2] else {
+                panic!("the first ref is missing");
+            };
+            let nul = first_ref.iter().position(|&x| x == 0).unwrap();
+            let caps = String::from_utf8(first_ref[nul + 1..].to_vec()).unwrap();
+            let caps: Vec<&str> = caps.split_whitespace().collect();
+            assert!(caps.contains(&"ofs-delta"));
+            assert!(caps.contains(&"side-band-64k"));
+            assert!(caps.contains(&"agent=mega/test"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unpack_chunks_out_of_order() {
+        let mut handler = MemoryHandler::default();
+        let readme = handler.add_blob("# mega", "README.md");
+        let root = handler.add_tree(vec![readme]);
+        let commit = Commit::from_tree_id(root.id, vec![], "init");
+        let pack = Bytes::from(handler.pack(commit, root, None).await);
+        let (first, second) = (pack.slice(..pack.len() / 2), pack.slice(pack.len() / 2..));
+
+        let dir = PathBuf::from("/tmp/.cache_temp/test_unpack_chunks");
+        let _ = std::fs::remove_dir_all(&dir);
+        let uploads = PackUploads::new(&dir);
+        let unpacked = handler
+            .unpack_chunk(&uploads, "push-1", first.len(), pack.len(), second)
+            .await
+            .unwrap();
+        assert!(!unpacked);
+        assert!(handler.unpacked.lock().unwrap().is_empty());
+
+        let unpacked = handler
+            .unpack_chunk(&uploads, "push-1", 0, pack.len(), first.clone())
+            .await
+            .unwrap();
+        assert!(unpacked);
+        assert_eq!(*handler.unpacked.lock().unwrap(), vec![pack.clone()]);
+        assert!(!dir.join("push-1").exists());
+
+        // a corrupted chunk fails the checksum and isn't unpacked
+        let mut corrupted = pack.to_vec();
+        corrupted[first.len()] ^= 0xff;
+        handler
+            .unpack_chunk(&uploads, "push-2", 0, pack.len(), first)
+            .await
+            .unwrap();
+        let result = handler
+            .unpack_chunk(
+                &uploads,
+                "push-2",
+                pack.len() / 2,
+                pack.len(),
+                Bytes::from(corrupted).slice(pack.len() / 2..),
+            )
+            .await;
+        assert!(matches!(result, Err(GitError::InvalidPackFile(_))));
+        assert_eq!(handler.unpacked.lock().unwrap().len(), 1);
+        assert!(dir.join("push-2").exists());
+
+        // of two requests completing an upload at once only one gets the pack
+        uploads.put_chunk("push-3", 0, &pack).await.unwrap();
+        let (a, b) = tokio::join!(
+            uploads.assemble("push-3", pack.len()),
+            uploads.assemble("push-3", pack.len())
+        );
+        let assembled: Vec<Bytes> = [a.unwrap(), b.unwrap()].into_iter().flatten().collect();
+        assert_eq!(assembled, vec![pack.clone()]);
+
+        // a chunk whose end overflows is rejected
+        uploads
+            .put_chunk("push-4", usize::MAX - 1, b"PACK")
+            .await
+            .unwrap();
+        let result = uploads.assemble("push-4", pack.len()).await;
+        assert!(matches!(result, Err(GitError::InvalidUpload(_))));
+
+        // a failed unpack keeps the chunks, sending the last one again retries it
+        handler.fail_unpack.store(true, Ordering::SeqCst);
+        let result = handler
+            .unpack_chunk(&uploads, "push-5", 0, pack.len(), pack.clone())
+            .await;
+        assert!(matches!(result, Err(GitError::StorageError(_))));
+        assert!(dir.join("push-5").exists());
+        let unpacked = handler
+            .unpack_chunk(&uploads, "push-5", 0, pack.len(), pack.clone())
+            .await
+            .unwrap();
+        assert!(unpacked);
+        assert!(!dir.join("push-5").exists());
+        assert!(!dir.join("push-5.assembling").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -1,17 +1,23 @@
-use std::sync::mpsc;
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    str::FromStr,
+    sync::mpsc,
+};
 
 use async_trait::async_trait;
 use bytes::Bytes;
 
-use callisto::raw_blob;
+use callisto::{db_enums::RefType, git_tag, raw_blob};
 use common::errors::MegaError;
 use jupiter::{
     context::Context,
-    storage::{batch_query_by_columns, GitStorageProvider},
+    storage::{batch_query_by_columns, object_storage::GitRepoStorage, GitStorageProvider},
 };
 use mercury::internal::pack::encode::PackEncoder;
 use venus::{
     errors::GitError,
+    hash::SHA1,
     internal::{
         object::{blob::Blob, commit::Commit, tag::Tag, tree::Tree},
         pack::{
@@ -22,13 +28,60 @@ use venus::{
     repo::Repo,
 };
 
-use crate::pack::handler::PackHandler;
+use crate::monorepo::history;
+use crate::pack::handler::{ObjectFilter, PackHandler};
 
 pub struct ImportRepo {
     pub context: Context,
     pub repo: Repo,
 }
 
+/// Whether pushes to the repo at `path` may rewrite its branches, from the comma separated repo
+/// paths in `MEGA_FORCE_PUSH_REPOS`, where `*` allows it for every repo. Unset allows none.
+pub fn force_push_allowed(path: &str) -> bool {
+    env::var("MEGA_FORCE_PUSH_REPOS").is_ok_and(|repos| repo_listed(&repos, path))
+}
+
+fn repo_listed(repos: &str, path: &str) -> bool {
+    repos
+        .split(',')
+        .map(str::trim)
+        .any(|x| x == "*" || x.trim_end_matches('/') == path)
+}
+
+impl ImportRepo {
+    /// The objects reachable from the hashes in `want`, `None` for all of the repository if it
+    /// is empty. A wanted annotated tag of `tags` takes the object it points at along.
+    async fn reachable_from(
+        &self,
+        want: &[String],
+        tags: &[git_tag::Model],
+    ) -> Result<Option<HashSet<String>>, GitError> {
+        if want.is_empty() {
+            return Ok(None);
+        }
+        let mut reachable = HashSet::new();
+        let mut tips = Vec::with_capacity(want.len());
+        for hash in want {
+            let target = match tags.iter().find(|x| x.tag_id == *hash) {
+                Some(tag) => {
+                    reachable.insert(tag.tag_id.clone());
+                    &tag.object_id
+                }
+                None => hash,
+            };
+            tips.push(SHA1::from_str(target).map_err(GitError::InvalidHashValue)?);
+        }
+        let objects = GitRepoStorage::new(
+            self.context.services.git_db_storage.clone(),
+            self.repo.clone(),
+        );
+        let objects = history::reachable_objects(&objects, &tips).await?;
+        reachable.extend(objects.into_iter().map(|x| x.to_plain_str()));
+        Ok(Some(reachable))
+    }
+}
+
 #[async_trait]
 impl PackHandler for ImportRepo {
     async fn head_hash(&self) -> (String, Vec<Refs>) {
@@ -43,38 +96,49 @@ impl PackHandler for ImportRepo {
         self.find_head_hash(refs)
     }
 
+    fn force_push_allowed(&self) -> bool {
+        force_push_allowed(&self.repo.repo_path)
+    }
+
     async fn unpack(&self, pack_file: Bytes) -> Result<(), GitError> {
-        let receiver = self.pack_decoder(pack_file).unwrap();
+        let decoding = self.pack_decoder(pack_file)?;
 
         let storage = self.context.services.git_db_storage.clone();
         let mut entry_list = Vec::new();
-        for entry in receiver {
+        while let Ok(entry) = decoding.entries.recv() {
             entry_list.push(entry);
             if entry_list.len() >= 1000 {
                 storage.save_entry(&self.repo, entry_list).await.unwrap();
                 entry_list = Vec::new();
             }
         }
+        // the refs of an invalid pack are never updated, the objects saved so far stay unreferenced
+        decoding.finish()?;
         storage.save_entry(&self.repo, entry_list).await.unwrap();
         Ok(())
     }
 
-    async fn full_pack(&self) -> Result<Vec<u8>, GitError> {
-        let (sender, receiver) = mpsc::channel();
-
+    async fn full_pack(
+        &self,
+        want: Vec<String>,
+        filter: Option<ObjectFilter>,
+    ) -> Result<Vec<u8>, GitError> {
+        let want = self.resolve_wants(want).await?;
         let storage = self.context.services.git_db_storage.clone();
-        let total = storage.get_obj_count_by_repo_id(&self.repo).await;
-        let mut encoder = PackEncoder::new(total, 0);
+        let tags = storage.get_tags_by_repo_id(&self.repo).await.unwrap();
+        let reachable = self.reachable_from(&want, &tags).await?;
+        let wanted = |hash: &str| reachable.as_ref().is_none_or(|x| x.contains(hash));
+        let mut entries: Vec<Entry> = Vec::new();
 
         for m in storage
             .get_commits_by_repo_id(&self.repo)
             .await
             .unwrap()
             .into_iter()
+            .filter(|x| wanted(&x.commit_id))
         {
             let c: Commit = m.into();
-            let entry: Entry = c.into();
-            sender.send(entry).unwrap();
+            entries.push(c.into());
         }
 
         for m in storage
@@ -82,10 +146,10 @@ impl PackHandler for ImportRepo {
             .await
             .unwrap()
             .into_iter()
+            .filter(|x| wanted(&x.tree_id))
         {
             let c: Tree = m.into();
-            let entry: Entry = c.into();
-            sender.send(entry).unwrap();
+            entries.push(c.into());
         }
 
         let bids: Vec<String> = storage
@@ -94,8 +158,12 @@ impl PackHandler for ImportRepo {
             .unwrap()
             .into_iter()
             .map(|b| b.blob_id)
+            .filter(|x| wanted(x))
             .collect();
 
+        // blobs rejected by the filter are promised to the client rather than packed
+        let bids = self.filter_blobs(bids, filter).await?;
+
         let raw_blobs = batch_query_by_columns::<raw_blob::Entity, raw_blob::Column>(
             storage.get_connection(),
             raw_blob::Column::Sha1,
@@ -105,22 +173,27 @@ impl PackHandler for ImportRepo {
         )
         .await
         .unwrap();
+        let raw_blobs = self
+            .context
+            .services
+            .mega_storage
+            .load_cold_blobs(raw_blobs)
+            .await
+            .unwrap();
 
         for m in raw_blobs {
-            // todo handle storage type
             let c: Blob = m.into();
-            let entry: Entry = c.into();
-            sender.send(entry).unwrap();
+            entries.push(c.into());
         }
 
-        for m in storage
-            .get_tags_by_repo_id(&self.repo)
-            .await
-            .unwrap()
-            .into_iter()
-        {
+        for m in tags.into_iter().filter(|x| wanted(&x.tag_id)) {
             let c: Tag = m.into();
-            let entry: Entry = c.into();
+            entries.push(c.into());
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let mut encoder = PackEncoder::new(entries.len(), 0);
+        for entry in entries {
             sender.send(entry).unwrap();
         }
         drop(sender);
@@ -133,6 +206,7 @@ impl PackHandler for ImportRepo {
         &self,
         _want: Vec<String>,
         _have: Vec<String>,
+        _filter: Option<ObjectFilter>,
     ) -> Result<Vec<u8>, GitError> {
         unimplemented!()
     }
@@ -150,6 +224,23 @@ impl PackHandler for ImportRepo {
             .collect())
     }
 
+    async fn get_blob_sizes(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<HashMap<String, usize>, MegaError> {
+        let blobs = self
+            .context
+            .services
+            .git_db_storage
+            .get_blobs_by_hashes(&self.repo, hashes)
+            .await?;
+        self.context
+            .services
+            .mega_storage
+            .complete_blob_sizes(blobs.into_iter().map(|x| (x.blob_id, x.size)))
+            .await
+    }
+
     async fn get_blobs_by_hashes(
         &self,
         hashes: Vec<String>,
@@ -167,8 +258,22 @@ impl PackHandler for ImportRepo {
             CommandType::Create => {
                 storage.save_ref(&self.repo, refs).await.unwrap();
             }
-            CommandType::Delete => storage.remove_ref(&self.repo, refs).await.unwrap(),
+            CommandType::Delete => {
+                let refs_of_repo = storage.get_ref(&self.repo).await?;
+                if refs_of_repo
+                    .iter()
+                    .any(|x| x.ref_name == refs.ref_name && x.default_branch)
+                {
+                    return Err(GitError::DefaultBranchDeletion(refs.ref_name.clone()));
+                }
+                storage.delete_ref(&self.repo, &refs.ref_name).await?;
+            }
             CommandType::Update => {
+                // tags may point at tag objects, only branches have to move forward
+                if refs.ref_type == RefType::Branch {
+                    let objects = GitRepoStorage::new(storage.clone(), self.repo.clone());
+                    history::check_fast_forward(&objects, refs).await?;
+                }
                 storage
                     .update_ref(&self.repo, &refs.ref_name, &refs.new_id)
                     .await
@@ -193,3 +298,116 @@ impl PackHandler for ImportRepo {
         storage.default_branch_exist(&self.repo).await.unwrap()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult};
+
+    use callisto::db_enums::RefType;
+    use callisto::import_refs;
+    use common::utils::ZERO_ID;
+    use jupiter::context::{Context, Service};
+    use jupiter::storage::git_db_storage::GitDbStorage;
+    use venus::errors::GitError;
+    use venus::internal::pack::reference::RefCommand;
+    use venus::repo::Repo;
+
+    use super::{repo_listed, ImportRepo};
+    use crate::pack::handler::PackHandler;
+
+    fn repo_with_refs(refs: Vec<import_refs::Model>) -> ImportRepo {
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([refs])
+            .append_exec_results([MockExecResult {
+                last_insert_id: 0,
+                rows_affected: 1,
+            }])
+            .into_connection();
+        let mock = Context::mock();
+        let context = Context {
+            services: Arc::new(Service {
+                git_db_storage: Arc::new(GitDbStorage {
+                    connection: Arc::new(db),
+                    ..GitDbStorage::mock()
+                }),
+                ..(*mock.services).clone()
+            }),
+            ..mock
+        };
+        ImportRepo {
+            context,
+            repo: Repo {
+                repo_id: 1,
+                repo_path: "/third-part/mega".to_owned(),
+                repo_name: "mega".to_owned(),
+            },
+        }
+    }
+
+    fn branch(name: &str, default_branch: bool) -> import_refs::Model {
+        let now = chrono::Utc::now().naive_utc();
+        import_refs::Model {
+            id: 1,
+            repo_id: 1,
+            ref_name: name.to_owned(),
+            ref_git_id: "1".repeat(40),
+            ref_type: RefType::Branch,
+            default_branch,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_branch() {
+        let repo = repo_with_refs(vec![
+            branch("refs/heads/main", true),
+            branch("refs/heads/feature", false),
+        ]);
+        let delete = RefCommand::new(
+            "1".repeat(40),
+            ZERO_ID.to_owned(),
+            "refs/heads/feature".to_owned(),
+        );
+        repo.update_refs(&delete).await.unwrap();
+
+        let storage = Arc::try_unwrap(repo.context.services)
+            .ok()
+            .and_then(|x| Arc::try_unwrap(x.git_db_storage).ok())
+            .unwrap();
+        let log = format!(
+            "{:?}",
+            Arc::try_unwrap(storage.connection)
+                .unwrap()
+                .into_transaction_log()
+        );
+        assert!(log.contains("DELETE FROM") && log.contains("refs/heads/feature"));
+    }
+
+    #[tokio::test]
+    async fn test_reject_default_branch_deletion() {
+        let repo = repo_with_refs(vec![branch("refs/heads/main", true)]);
+        let delete = RefCommand::new(
+            "1".repeat(40),
+            ZERO_ID.to_owned(),
+            "refs/heads/main".to_owned(),
+        );
+        assert!(matches!(
+            repo.update_refs(&delete).await,
+            Err(GitError::DefaultBranchDeletion(name)) if name == "refs/heads/main"
+        ));
+    }
+
+    #[test]
+    fn test_repo_listed() {
+        assert!(repo_listed(
+            "/third-part/mega, /third-part/libra/",
+            "/third-part/libra"
+        ));
+        assert!(repo_listed("*", "/third-part/mega"));
+        assert!(!repo_listed("/third-part/mega", "/third-part/megaphone"));
+        assert!(!repo_listed("", "/third-part/mega"));
+    }
+}
@@ -0,0 +1,630 @@
+//! Serving a repository mirrored from another mega node.
+//!
+//! A secondary node, e.g. in another region, serves clones from its own storage. Before the refs
+//! are advertised, those of the primary are compared with the local ones. Commits the mirror
+//! doesn't have yet are fetched from the primary in a single pack and stored, and the local refs
+//! are moved to the upstream ones. A warm mirror only asks the primary for its refs, packing asks
+//! again only for wanted commits missing locally. While the primary is unreachable the mirror
+//! serves the refs it has.
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+
+use callisto::raw_blob;
+use common::errors::MegaError;
+use common::utils::ZERO_ID;
+use venus::errors::GitError;
+use venus::internal::object::tree::Tree;
+use venus::internal::pack::reference::{RefCommand, Refs};
+
+use crate::pack::handler::{ObjectFilter, PackHandler, WANT_REF};
+use crate::pack::sparse::SparsePatterns;
+use crate::protocol::pktline::{self, Packet};
+use crate::protocol::smart::ServerCapabilities;
+
+/// The repository a mirror fetches from.
+#[async_trait]
+pub trait PackUpstream: Send + Sync {
+    /// The refs of the upstream repository.
+    async fn refs(&self) -> Result<Vec<Refs>, GitError>;
+
+    /// A pack of the objects reachable from `want` but not from `have`.
+    async fn fetch_pack(&self, want: Vec<String>, have: Vec<String>) -> Result<Bytes, GitError>;
+}
+
+/// An upstream repository served over smart http, like `https://mega.org/third-part/mega`.
+pub struct HttpUpstream {
+    pub url: String,
+    client: reqwest::Client,
+}
+
+impl HttpUpstream {
+    pub fn new(url: impl Into<String>) -> Self {
+        HttpUpstream {
+            url: url.into().trim_end_matches('/').to_owned(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+fn upstream_error(err: impl ToString) -> GitError {
+    GitError::UpstreamError(err.to_string())
+}
+
+/// Split `body` into its pkt-lines, flush and delim packets are skipped.
+fn pkt_lines(mut body: &[u8]) -> Result<Vec<Vec<u8>>, GitError> {
+    let mut lines = Vec::new();
+    while !body.is_empty() {
+        let Some((packet, len)) = pktline::decode(body).map_err(upstream_error)? else {
+            return Err(upstream_error("truncated pkt-line"));
+        };
+        if let Packet::Data(line) = packet {
+            lines.push(line);
+        }
+        body = &body[len..];
+    }
+    Ok(lines)
+}
+
+/// The refs in an `info/refs` advertisement. The ref at the hash of `HEAD` is the default branch.
+fn parse_advertisement(body: Bytes) -> Result<Vec<Refs>, GitError> {
+    let mut head = None;
+    let mut refs = Vec::new();
+    for line in pkt_lines(&body)? {
+        let line = String::from_utf8_lossy(&line);
+        let line = line.split('\0').next().unwrap().trim_end();
+        if line.starts_with('#') {
+            continue;
+        }
+        let Some((hash, name)) = line.split_once(' ') else {
+            return Err(upstream_error(format!("invalid ref line `{}`", line)));
+        };
+        match name {
+            "HEAD" => head = Some(hash.to_owned()),
+            "capabilities^{}" => {}
+            _ => refs.push(Refs {
+                id: 0,
+                ref_name: name.to_owned(),
+                ref_hash: hash.to_owned(),
+                default_branch: false,
+            }),
+        }
+    }
+    if let Some(default) = refs
+        .iter_mut()
+        .find(|x| Some(&x.ref_hash) == head.as_ref() && x.ref_name.starts_with("refs/heads/"))
+    {
+        default.default_branch = true;
+    }
+    Ok(refs)
+}
+
+/// The pack in an `upload-pack` response multiplexed with `side-band-64k`.
+fn demux_pack(body: Bytes) -> Result<Bytes, GitError> {
+    let mut pack = BytesMut::new();
+    for line in pkt_lines(&body)? {
+        match line.first() {
+            Some(1) => pack.extend_from_slice(&line[1..]),
+            Some(3) => return Err(upstream_error(String::from_utf8_lossy(&line[1..]))),
+            // progress messages and the ACK/NAK lines
+            _ => {}
+        }
+    }
+    Ok(pack.freeze())
+}
+
+#[async_trait]
+impl PackUpstream for HttpUpstream {
+    async fn refs(&self) -> Result<Vec<Refs>, GitError> {
+        let body = self
+            .client
+            .get(format!("{}/info/refs?service=git-upload-pack", self.url))
+            .send()
+            .await
+            .and_then(|x| x.error_for_status())
+            .map_err(upstream_error)?
+            .bytes()
+            .await
+            .map_err(upstream_error)?;
+        parse_advertisement(body)
+    }
+
+    async fn fetch_pack(&self, want: Vec<String>, have: Vec<String>) -> Result<Bytes, GitError> {
+        let mut request = Vec::new();
+        for (i, hash) in want.iter().enumerate() {
+            if i == 0 {
+                let capabilities = "side-band-64k multi_ack_detailed no-done";
+                request.extend(pktline::encode(
+                    format!("want {} {}\n", hash, capabilities).as_bytes(),
+                ));
+            } else {
+                request.extend(pktline::encode(format!("want {}\n", hash).as_bytes()));
+            }
+        }
+        request.extend(pktline::encode_flush());
+        for hash in &have {
+            request.extend(pktline::encode(format!("have {}\n", hash).as_bytes()));
+        }
+        request.extend(pktline::encode(b"done\n"));
+
+        let body = self
+            .client
+            .post(format!("{}/git-upload-pack", self.url))
+            .header("Content-Type", "application/x-git-upload-pack-request")
+            .body(request)
+            .send()
+            .await
+            .and_then(|x| x.error_for_status())
+            .map_err(upstream_error)?
+            .bytes()
+            .await
+            .map_err(upstream_error)?;
+        demux_pack(body)
+    }
+}
+
+/// A read-only repository filled from `upstream` on demand and served from `local`, which stores
+/// pushed packs and refs directly like an imported repository does.
+pub struct MirrorPackHandler {
+    pub local: Box<dyn PackHandler>,
+    pub upstream: Arc<dyn PackUpstream>,
+}
+
+impl MirrorPackHandler {
+    /// Fetch the commits of the upstream refs missing locally and move the local refs to the
+    /// upstream ones. Refs only existing locally are kept.
+    pub async fn sync(&self) -> Result<(), GitError> {
+        let upstream_refs = self.upstream.refs().await?;
+        let (_, local_refs) = self.local.head_hash().await;
+        let local_refs: HashMap<String, String> = local_refs
+            .into_iter()
+            .map(|x| (x.ref_name, x.ref_hash))
+            .collect();
+
+        let mut want = Vec::new();
+        let mut commands = Vec::new();
+        for upstream_ref in upstream_refs {
+            let old = local_refs.get(&upstream_ref.ref_name);
+            if old == Some(&upstream_ref.ref_hash) {
+                continue;
+            }
+            if !self.local.check_commit_exist(&upstream_ref.ref_hash).await
+                && !want.contains(&upstream_ref.ref_hash)
+            {
+                want.push(upstream_ref.ref_hash.clone());
+            }
+            let old = old.cloned().unwrap_or_else(|| ZERO_ID.to_owned());
+            let mut command = RefCommand::new(old, upstream_ref.ref_hash, upstream_ref.ref_name);
+            command.default_branch = upstream_ref.default_branch;
+            // the upstream already accepted the update
+            command.force = true;
+            commands.push(command);
+        }
+
+        if !want.is_empty() {
+            let mut have: Vec<String> = local_refs.into_values().collect();
+            have.sort();
+            have.dedup();
+            tracing::info!("fetching {} refs from the upstream", want.len());
+            let pack = self.upstream.fetch_pack(want, have).await?;
+            self.local.unpack(pack).await?;
+        }
+        for command in &commands {
+            self.local.update_refs(command).await?;
+        }
+        Ok(())
+    }
+
+    /// [`MirrorPackHandler::sync`] if a commit of `want` is missing locally, e.g. one the
+    /// upstream gained after the refs were advertised. The refs named by `want-ref` entries
+    /// are the local ones, synced when they were advertised.
+    async fn sync_missing(&self, want: &[String]) -> Result<(), GitError> {
+        for hash in want.iter().filter(|x| !x.starts_with(WANT_REF)) {
+            if !self.local.check_commit_exist(hash).await {
+                return self.sync().await;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl PackHandler for MirrorPackHandler {
+    async fn head_hash(&self) -> (String, Vec<Refs>) {
+        self.local.head_hash().await
+    }
+
+    async fn ensure_ref(&self) -> Result<(), GitError> {
+        match self.sync().await {
+            Err(GitError::UpstreamError(err)) => {
+                tracing::warn!("advertising the local refs, the upstream failed: {}", err);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn capabilities(&self) -> ServerCapabilities {
+        self.local.capabilities()
+    }
+
+    async fn unpack(&self, _: Bytes) -> Result<(), GitError> {
+        Err(GitError::UnsupportedService(
+            "git-receive-pack on a mirror".to_owned(),
+        ))
+    }
+
+    async fn full_pack(
+        &self,
+        want: Vec<String>,
+        filter: Option<ObjectFilter>,
+    ) -> Result<Vec<u8>, GitError> {
+        self.sync_missing(&want).await?;
+        self.local.full_pack(want, filter).await
+    }
+
+    async fn incremental_pack(
+        &self,
+        want: Vec<String>,
+        have: Vec<String>,
+        filter: Option<ObjectFilter>,
+    ) -> Result<Vec<u8>, GitError> {
+        self.sync_missing(&want).await?;
+        self.local.incremental_pack(want, have, filter).await
+    }
+
+    fn sparse_patterns(&self) -> Option<&SparsePatterns> {
+        self.local.sparse_patterns()
+    }
+
+    async fn get_trees_by_hashes(&self, hashes: Vec<String>) -> Result<Vec<Tree>, MegaError> {
+        self.local.get_trees_by_hashes(hashes).await
+    }
+
+    async fn get_blob_sizes(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<HashMap<String, usize>, MegaError> {
+        self.local.get_blob_sizes(hashes).await
+    }
+
+    async fn get_blobs_by_hashes(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<Vec<raw_blob::Model>, MegaError> {
+        self.local.get_blobs_by_hashes(hashes).await
+    }
+
+    async fn update_refs(&self, _: &RefCommand) -> Result<(), GitError> {
+        Err(GitError::UnsupportedService(
+            "git-receive-pack on a mirror".to_owned(),
+        ))
+    }
+
+    async fn check_commit_exist(&self, hash: &str) -> bool {
+        self.local.check_commit_exist(hash).await
+    }
+
+    async fn check_default_branch(&self) -> bool {
+        self.local.check_default_branch().await
+    }
+}
+
+/// The upstream of the repository at `path` if this node mirrors the one at
+/// `MEGA_MIRROR_UPSTREAM`, e.g. `https://mega.org`. Unset means no mirroring.
+pub fn mirror_upstream(path: &Path) -> Option<Arc<dyn PackUpstream>> {
+    let base = env::var("MEGA_MIRROR_UPSTREAM").ok()?;
+    let base = base.trim().trim_end_matches('/');
+    if base.is_empty() {
+        return None;
+    }
+    Some(Arc::new(HttpUpstream::new(format!(
+        "{}{}",
+        base,
+        path.to_string_lossy()
+    ))))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::env;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
+
+    use async_trait::async_trait;
+    use bytes::Bytes;
+
+    use callisto::raw_blob;
+    use common::errors::MegaError;
+    use common::utils::ZERO_ID;
+    use mercury::internal::pack::encode::PackEncoder;
+    use mercury::internal::pack::Pack;
+    use venus::errors::GitError;
+    use venus::internal::object::blob::Blob;
+    use venus::internal::object::commit::Commit;
+    use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+    use venus::internal::pack::entry::Entry;
+    use venus::internal::pack::reference::{RefCommand, Refs};
+
+    use super::{demux_pack, parse_advertisement, upstream_error, MirrorPackHandler, PackUpstream};
+    use crate::pack::handler::{ObjectFilter, PackHandler};
+
+    fn encode(entries: Vec<Entry>) -> Vec<u8> {
+        let (sender, receiver) = mpsc::channel();
+        let data = PackEncoder::new(entries.len(), 0)
+            .encode_async(receiver)
+            .unwrap();
+        for entry in entries {
+            sender.send(entry).unwrap();
+        }
+        drop(sender);
+        data.join().unwrap()
+    }
+
+    struct MockUpstream {
+        refs: Vec<Refs>,
+        entries: Vec<Entry>,
+        fetches: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl PackUpstream for MockUpstream {
+        async fn refs(&self) -> Result<Vec<Refs>, GitError> {
+            Ok(self.refs.clone())
+        }
+
+        async fn fetch_pack(&self, _: Vec<String>, _: Vec<String>) -> Result<Bytes, GitError> {
+            self.fetches.fetch_add(1, Ordering::SeqCst);
+            Ok(Bytes::from(encode(self.entries.clone())))
+        }
+    }
+
+    /// An upstream which can't be reached.
+    struct DownUpstream;
+
+    #[async_trait]
+    impl PackUpstream for DownUpstream {
+        async fn refs(&self) -> Result<Vec<Refs>, GitError> {
+            Err(upstream_error("connection refused"))
+        }
+
+        async fn fetch_pack(&self, _: Vec<String>, _: Vec<String>) -> Result<Bytes, GitError> {
+            Err(upstream_error("connection refused"))
+        }
+    }
+
+    /// A local store keeping pushed objects and refs in memory, packing all of them.
+    #[derive(Default)]
+    struct MemoryRepo {
+        entries: Mutex<HashMap<String, Entry>>,
+        refs: Mutex<Vec<Refs>>,
+    }
+
+    #[async_trait]
+    impl PackHandler for MemoryRepo {
+        async fn head_hash(&self) -> (String, Vec<Refs>) {
+            self.find_head_hash(self.refs.lock().unwrap().clone())
+        }
+
+        async fn unpack(&self, pack_file: Bytes) -> Result<(), GitError> {
+            let decoding = self.pack_decoder(pack_file)?;
+            let received: Vec<Entry> = decoding.entries.iter().collect();
+            decoding.finish()?;
+            let mut entries = self.entries.lock().unwrap();
+            for entry in received {
+                entries.insert(entry.hash.to_plain_str(), entry);
+            }
+            Ok(())
+        }
+
+        async fn full_pack(
+            &self,
+            _: Vec<String>,
+            _: Option<ObjectFilter>,
+        ) -> Result<Vec<u8>, GitError> {
+            let mut entries: Vec<Entry> = self.entries.lock().unwrap().values().cloned().collect();
+            entries.sort_by_key(|x| x.hash);
+            Ok(encode(entries))
+        }
+
+        async fn incremental_pack(
+            &self,
+            _: Vec<String>,
+            _: Vec<String>,
+            _: Option<ObjectFilter>,
+        ) -> Result<Vec<u8>, GitError> {
+            unimplemented!()
+        }
+
+        async fn get_trees_by_hashes(&self, _: Vec<String>) -> Result<Vec<Tree>, MegaError> {
+            unimplemented!()
+        }
+
+        async fn get_blob_sizes(
+            &self,
+            _: Vec<String>,
+        ) -> Result<HashMap<String, usize>, MegaError> {
+            unimplemented!()
+        }
+
+        async fn get_blobs_by_hashes(
+            &self,
+            _: Vec<String>,
+        ) -> Result<Vec<raw_blob::Model>, MegaError> {
+            unimplemented!()
+        }
+
+        async fn update_refs(&self, command: &RefCommand) -> Result<(), GitError> {
+            let mut refs = self.refs.lock().unwrap();
+            refs.retain(|x| x.ref_name != command.ref_name);
+            refs.push(Refs {
+                id: 0,
+                ref_name: command.ref_name.clone(),
+                ref_hash: command.new_id.clone(),
+                default_branch: command.default_branch,
+            });
+            Ok(())
+        }
+
+        async fn check_commit_exist(&self, hash: &str) -> bool {
+            self.entries.lock().unwrap().contains_key(hash)
+        }
+
+        async fn check_default_branch(&self) -> bool {
+            unimplemented!()
+        }
+    }
+
+    fn hashes(pack: Vec<u8>) -> Vec<String> {
+        let hashes = Arc::new(Mutex::new(Vec::new()));
+        let result = hashes.clone();
+        Pack::new(
+            None,
+            Some(1024 * 20),
+            Some(PathBuf::from("/tmp/.cache_temp")),
+            true,
+        )
+        .decode(&mut Cursor::new(pack), move |entry| {
+            result.lock().unwrap().push(entry.hash.to_plain_str())
+        })
+        .unwrap();
+        let mut hashes = hashes.lock().unwrap().clone();
+        hashes.sort();
+        hashes
+    }
+
+    #[tokio::test]
+    async fn test_mirror_fetches_once() {
+        env::set_var("MEGA_PACK_DECODE_MEM_SIZE", "1");
+        env::set_var("MEGA_PACK_DECODE_CACHE_PATH", "/tmp/.cache_temp");
+        env::set_var("CLEAN_CACHE_AFTER_DECODE", "true");
+
+        let blob = Blob::from_content("fn main() {}");
+        let tree = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            blob.id,
+            "main.rs".to_owned(),
+        )])
+        .unwrap();
+        let commit = Commit::from_tree_id(tree.id, vec![], "init");
+        let upstream = Arc::new(MockUpstream {
+            refs: vec![Refs {
+                id: 0,
+                ref_name: "refs/heads/main".to_owned(),
+                ref_hash: commit.id.to_plain_str(),
+                default_branch: true,
+            }],
+            entries: vec![
+                commit.clone().into(),
+                tree.clone().into(),
+                blob.clone().into(),
+            ],
+            fetches: AtomicUsize::new(0),
+        });
+        let mirror = MirrorPackHandler {
+            local: Box::new(MemoryRepo::default()),
+            upstream: upstream.clone(),
+        };
+
+        let mut expected = vec![
+            commit.id.to_plain_str(),
+            tree.id.to_plain_str(),
+            blob.id.to_plain_str(),
+        ];
+        expected.sort();
+        // a cold mirror fetches the objects, a warm one serves them from its own storage
+        for _ in 0..2 {
+            mirror.ensure_ref().await.unwrap();
+            let pack = mirror.full_pack(vec![], None).await.unwrap();
+            assert_eq!(hashes(pack), expected);
+            assert_eq!(upstream.fetches.load(Ordering::SeqCst), 1);
+        }
+        assert_eq!(mirror.head_hash().await.0, commit.id.to_plain_str());
+    }
+
+    #[tokio::test]
+    async fn test_mirror_fetches_missing_wants() {
+        env::set_var("MEGA_PACK_DECODE_MEM_SIZE", "1");
+        env::set_var("MEGA_PACK_DECODE_CACHE_PATH", "/tmp/.cache_temp");
+        env::set_var("CLEAN_CACHE_AFTER_DECODE", "true");
+
+        let blob = Blob::from_content("fn main() {}");
+        let tree = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            blob.id,
+            "main.rs".to_owned(),
+        )])
+        .unwrap();
+        let commit = Commit::from_tree_id(tree.id, vec![], "init");
+        let upstream = Arc::new(MockUpstream {
+            refs: vec![Refs {
+                id: 0,
+                ref_name: "refs/heads/main".to_owned(),
+                ref_hash: commit.id.to_plain_str(),
+                default_branch: true,
+            }],
+            entries: vec![commit.clone().into(), tree.into(), blob.into()],
+            fetches: AtomicUsize::new(0),
+        });
+        let mirror = MirrorPackHandler {
+            local: Box::new(MemoryRepo::default()),
+            upstream: upstream.clone(),
+        };
+
+        // the want of a client which read the refs elsewhere, only packing asks the upstream
+        let want = vec![commit.id.to_plain_str()];
+        for _ in 0..2 {
+            let pack = mirror.full_pack(want.clone(), None).await.unwrap();
+            assert_eq!(hashes(pack).len(), 3);
+            assert_eq!(upstream.fetches.load(Ordering::SeqCst), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mirror_without_upstream() {
+        let mirror = MirrorPackHandler {
+            local: Box::new(MemoryRepo::default()),
+            upstream: Arc::new(DownUpstream),
+        };
+
+        // the local refs are advertised
+        mirror.ensure_ref().await.unwrap();
+        assert_eq!(mirror.head_hash().await.0, ZERO_ID);
+        // a commit missing locally can only come from the upstream
+        assert!(matches!(
+            mirror.full_pack(vec!["1".repeat(40)], None).await,
+            Err(GitError::UpstreamError(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_upstream_responses() {
+        let hash = "8ab686eafeb1f44702738c8b0f24f2567c36da6d";
+        let advertisement = format!(
+            "001e# service=git-upload-pack\n0000{:04x}{} HEAD\0side-band-64k\n{:04x}{} refs/heads/main\n0000",
+            hash.len() + 24,
+            hash,
+            hash.len() + 21,
+            hash
+        );
+        let refs = parse_advertisement(Bytes::from(advertisement)).unwrap();
+        assert_eq!(refs.len(), 1);
+        assert_eq!(refs[0].ref_name, "refs/heads/main");
+        assert!(refs[0].default_branch);
+
+        let response = Bytes::from_static(b"0008NAK\n0009\x01PACK0009\x02done0000");
+        assert_eq!(demux_pack(response).unwrap(), Bytes::from_static(b"PACK"));
+        let response = Bytes::from_static(b"0008NAK\n000a\x03error");
+        assert!(matches!(
+            demux_pack(response),
+            Err(GitError::UpstreamError(_))
+        ));
+    }
+}
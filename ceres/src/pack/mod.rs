@@ -1,3 +1,7 @@
 pub mod handler;
 pub mod import_repo;
+pub mod mirror;
 pub mod monorepo;
+pub mod policy;
+pub mod sparse;
+pub mod upload;
@@ -1,41 +1,143 @@
 use std::{
     collections::{HashMap, HashSet},
-    path::{Component, PathBuf},
+    env,
+    io::{BufRead, Cursor, Write},
+    path::{Component, Path, PathBuf},
     str::FromStr,
     sync::{
         atomic::{AtomicUsize, Ordering},
-        mpsc::{self, Receiver},
+        mpsc,
     },
+    time::Instant,
     vec,
 };
 
 use async_trait::async_trait;
 use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use tokio::io::AsyncRead;
+use tracing::field::Empty;
+use tracing::Span;
 
-use callisto::raw_blob;
-use common::{errors::MegaError, utils::MEGA_BRANCH_NAME};
+use callisto::db_enums::{CommentKind, MergeStatus};
+use callisto::{lfs_objects, raw_blob};
+use common::errors::MegaError;
 use jupiter::context::Context;
-use mercury::internal::pack::encode::PackEncoder;
+use jupiter::storage::object_storage::ObjectStorage;
+use mercury::internal::pack::encode::{DeltaOptions, PackEncoder};
+use mercury::internal::pack::Pack;
 use venus::{
     errors::GitError,
     hash::SHA1,
     internal::{
-        object::{commit::Commit, tree::Tree, types::ObjectType},
+        object::{
+            blob::Blob,
+            commit::Commit,
+            signature::Signature,
+            tag::Tag,
+            tree::{Tree, TreeCounts, TreeItem},
+            types::ObjectType,
+        },
         pack::{
             entry::Entry,
             reference::{RefCommand, Refs},
         },
     },
-    monorepo::mr::MergeRequest,
+    monorepo::{
+        mega_refs::RefInfo,
+        mr::{MergeRequest, MR_REFS_PREFIX},
+        reflog::ReflogEntry,
+        repo_size::RepoSize,
+        storage_tier::StorageTier,
+    },
+};
+
+use crate::lfs::pointer;
+use crate::monorepo::dedup::{self, DedupReport};
+use crate::monorepo::diff::{ChangeKind, FileStat};
+use crate::monorepo::fsck::{Fsck, FsckReport};
+use crate::monorepo::history::FilePatch;
+use crate::monorepo::rename::DiffEntry;
+use crate::monorepo::submodule::{self, Submodule};
+use crate::monorepo::{
+    attributes, diff, fsck, graph, history, import, listing, merge, revert, tags,
 };
+use crate::pack::handler::{ObjectFilter, PackHandler};
+use crate::pack::policy::{PushCheck, PushPolicy};
+use crate::pack::sparse::SparsePatterns;
+use crate::protocol::smart::ServerCapabilities;
 
-use crate::pack::handler::PackHandler;
+/// Record the milliseconds since `start` as the field `phase` of the current span and start the
+/// next phase.
+fn record_phase(phase: &str, start: &mut Instant) {
+    Span::current().record(phase, start.elapsed().as_millis() as u64);
+    *start = Instant::now();
+}
+
+/// The number of objects loaded or saved at once by exports and imports of the object graph.
+const GRAPH_BATCH_SIZE: usize = 1000;
+
+/// The number of commits loaded at once when packing the commits made since a timestamp.
+const COMMIT_PAGE_SIZE: u64 = 1000;
 
 pub struct MonoRepo {
     pub context: Context,
     pub path: PathBuf,
     pub from_hash: Option<String>,
     pub to_hash: Option<String>,
+    /// Limits the blobs of packs to a sparse checkout, trees are always sent in full.
+    pub sparse: Option<SparsePatterns>,
+    pub capabilities: ServerCapabilities,
+    /// How packs sent to clients are delta compressed.
+    pub delta: DeltaOptions,
+    /// Whether full packs are byte-identical for the same state of the repo, see
+    /// [`deterministic_packs`].
+    pub deterministic: bool,
+    /// The `agent` capability of the pushing client, stored on the MR.
+    pub agent: Option<String>,
+    /// The checks pushes have to pass.
+    pub policy: PushPolicy,
+}
+
+/// The delta options of served packs, from `MEGA_PACK_DELTA_WINDOW` and `MEGA_PACK_DELTA_DEPTH`.
+/// Unset values keep the defaults of `git pack-objects`.
+pub fn delta_options() -> DeltaOptions {
+    let var = |key: &str| env::var(key).ok().and_then(|x| x.parse::<usize>().ok());
+    let default = DeltaOptions::default();
+    DeltaOptions {
+        window: var("MEGA_PACK_DELTA_WINDOW").unwrap_or(default.window),
+        depth: var("MEGA_PACK_DELTA_DEPTH").unwrap_or(default.depth),
+    }
+}
+
+/// Whether full packs are built deterministically, from `MEGA_PACK_DETERMINISTIC`. Their objects
+/// are sorted by hash instead of sent in the order the storage returns them, and aren't delta
+/// compressed, so packs can be cached by their content.
+pub fn deterministic_packs() -> bool {
+    env::var("MEGA_PACK_DETERMINISTIC")
+        .ok()
+        .and_then(|x| x.trim().parse::<bool>().ok())
+        .unwrap_or(false)
+}
+
+/// Normalize a monorepo path of the protocol to an absolute one without `.`, `..` or empty
+/// components, e.g. `//a/./b/` to `/a/b`. Paths leaving the root are rejected.
+pub fn normalize_path(path: &Path) -> Result<PathBuf, GitError> {
+    let invalid = || GitError::InvalidPathError(path.to_string_lossy().into_owned());
+    let mut normalized = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return Err(invalid());
+                }
+            }
+            Component::Normal(name) => normalized.push(name),
+            Component::Prefix(_) => return Err(invalid()),
+        }
+    }
+    Ok(normalized)
 }
 
 #[async_trait]
@@ -43,169 +145,296 @@ impl PackHandler for MonoRepo {
     async fn head_hash(&self) -> (String, Vec<Refs>) {
         let storage = self.context.services.mega_storage.clone();
 
-        let result = storage.get_ref(self.path.to_str().unwrap()).await.unwrap();
-        let refs = if result.is_some() {
-            vec![result.unwrap().into()]
-        } else {
-            let target_path = self.path.clone();
-            let refs = storage.get_ref("/").await.unwrap().unwrap();
-            let tree_hash = refs.ref_tree_hash.clone();
+        let refs = storage
+            .get_ref(self.path.to_str().unwrap())
+            .await
+            .unwrap()
+            .map(|x| vec![x.into()])
+            .unwrap_or_default();
+        self.find_head_hash(refs)
+    }
 
-            let mut tree: Tree = storage
-                .get_tree_by_hash(&tree_hash)
-                .await
-                .unwrap()
-                .unwrap()
-                .into();
+    /// A directory of the monorepo gets its ref on first access: a commit holding the
+    /// directory's tree as its root, with the author and message of the monorepo's head.
+    async fn ensure_ref(&self) -> Result<(), GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let path = self.path.to_str().unwrap();
+        if storage.get_ref(path).await?.is_some() {
+            return Ok(());
+        }
 
-            let commit: Commit = storage
-                .get_commit_by_hash(&refs.ref_commit_hash)
-                .await
-                .unwrap()
-                .unwrap()
-                .into();
+        let refs = storage
+            .get_ref("/")
+            .await?
+            .ok_or_else(|| GitError::InvalidPathError("/".to_owned()))?;
+        let mut tree: Tree = storage
+            .get_tree_by_hash(&refs.ref_tree_hash)
+            .await?
+            .ok_or_else(|| GitError::NotFountHashValue(refs.ref_tree_hash.clone()))?
+            .into();
+        let commit: Commit = storage
+            .get_commit_by_hash(&refs.ref_commit_hash)
+            .await?
+            .ok_or_else(|| GitError::NotFountHashValue(refs.ref_commit_hash.clone()))?
+            .into();
 
-            for component in target_path.components() {
-                if component != Component::RootDir {
-                    let path_name = component.as_os_str().to_str().unwrap();
-                    let sha1 = tree
-                        .tree_items
-                        .iter()
-                        .find(|x| x.name == path_name)
-                        .map(|x| x.id);
-                    if let Some(sha1) = sha1 {
-                        tree = storage
-                            .get_trees_by_hashes(vec![sha1.to_plain_str()])
-                            .await
-                            .unwrap()[0]
-                            .clone()
-                            .into();
-                    } else {
-                        return self.find_head_hash(vec![]);
-                    }
-                }
+        for component in self.path.components() {
+            if component != Component::RootDir {
+                let path_name = component.as_os_str().to_str().unwrap();
+                let sha1 = tree
+                    .tree_items
+                    .iter()
+                    .find(|x| x.name == path_name)
+                    .map(|x| x.id);
+                // the directory doesn't exist (yet), there is nothing to create a ref for
+                let Some(sha1) = sha1 else {
+                    return Ok(());
+                };
+                tree = storage
+                    .get_trees_by_hashes(vec![sha1.to_plain_str()])
+                    .await?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| GitError::NotFountHashValue(sha1.to_plain_str()))?
+                    .into();
             }
+        }
 
-            let c = Commit::new(
-                commit.author,
-                commit.committer,
-                tree.id,
-                vec![],
-                &commit.message,
-            );
-            storage
-                .save_ref(
-                    self.path.to_str().unwrap(),
-                    &c.id.to_plain_str(),
-                    &c.tree_id.to_plain_str(),
-                )
-                .await
-                .unwrap();
-            storage.save_mega_commits(vec![c.clone()]).await.unwrap();
-
-            vec![Refs {
-                ref_name: MEGA_BRANCH_NAME.to_string(),
-                ref_hash: c.id.to_plain_str(),
-                default_branch: true,
-                ..Default::default()
-            }]
-        };
-        self.find_head_hash(refs)
+        let c = Commit::new(
+            commit.author,
+            commit.committer,
+            tree.id,
+            vec![],
+            &commit.message,
+        );
+        storage
+            .save_ref(path, &c.id.to_plain_str(), &c.tree_id.to_plain_str())
+            .await?;
+        storage.save_mega_commits(vec![c]).await?;
+        Ok(())
     }
 
+    #[tracing::instrument(
+        skip_all,
+        fields(
+            agent = Empty,
+            objects = Empty,
+            decode_ms = Empty,
+            check_ms = Empty,
+            store_ms = Empty
+        )
+    )]
     async fn unpack(&self, pack_file: Bytes) -> Result<(), GitError> {
-        let receiver = self.pack_decoder(pack_file).unwrap();
+        if let Some(agent) = &self.agent {
+            Span::current().record("agent", agent.as_str());
+        }
+        let mut phase = Instant::now();
+        // a push without any object would only create an empty MR
+        if pack_file.is_empty() || Pack::check_header(&mut Cursor::new(&pack_file))?.0 == 0 {
+            return Err(GitError::EmptyPack);
+        }
+        // the whole push is checked before anything is saved or an MR is created, the pack is
+        // decoded a second time to save it instead of holding every object in memory
+        let decoding = self.pack_decoder(pack_file.clone())?;
+        let mut check = PushCheck::new(self.policy.clone());
+        let mut objects = 0;
+        for entry in decoding.entries.iter() {
+            check.add(&entry)?;
+            objects += 1;
+        }
+        decoding.finish()?;
+        Span::current().record("objects", objects);
+        record_phase("decode_ms", &mut phase);
 
         let storage = self.context.services.mega_storage.clone();
+        check.finish(storage.as_ref()).await?;
+        record_phase("check_ms", &mut phase);
+        // a retried push already has its MR and objects, unless the MR was closed or merged since
+        let push_key = self.push_key();
+        let retried = storage.get_mr_by_idempotency_key(&push_key).await?;
+        if retried.is_some_and(|x| {
+            x.status == MergeStatus::Open && Some(&x.to_hash) == self.to_hash.as_ref()
+        }) {
+            return Ok(());
+        }
 
-        let (mut mr, mr_exist) = self.get_mr().await;
+        let (mut mr, mr_exist, reopened) = self.get_mr().await;
 
         let mut commit_size = 0;
         if mr_exist {
             if mr.from_hash == self.from_hash.clone().unwrap() {
                 let to_hash = self.to_hash.clone().unwrap();
-                if mr.to_hash != to_hash {
+                let updated = mr.to_hash != to_hash;
+                if updated {
+                    commit_size = self.save_entry(pack_file).await?;
+                }
+                // the comments are only written once the objects of the push are stored
+                if reopened {
+                    storage
+                        .add_mr_comment(
+                            mr.id,
+                            0,
+                            None,
+                            CommentKind::System,
+                            Some("Mega reopened MR due to new push".to_string()),
+                        )
+                        .await
+                        .unwrap();
+                }
+                if updated {
                     let comment = self.comment_for_force_update(&mr.to_hash, &to_hash);
                     mr.to_hash = to_hash;
+                    mr.idempotency_key = push_key;
+                    mr.agent = self.agent.clone();
                     storage
-                        .add_mr_comment(mr.id, 0, Some(comment))
+                        .add_mr_comment(mr.id, 0, None, CommentKind::System, Some(comment))
                         .await
                         .unwrap();
-                    commit_size = self.save_entry(receiver).await;
                 }
             } else {
-                mr.close();
+                mr.close()?;
                 storage
-                    .add_mr_comment(mr.id, 0, Some("Mega closed MR due to conflict".to_string()))
+                    .add_mr_comment(
+                        mr.id,
+                        0,
+                        None,
+                        CommentKind::System,
+                        Some("Mega closed MR due to conflict".to_string()),
+                    )
                     .await
                     .unwrap();
             }
             storage.update_mr(mr.clone()).await.unwrap();
         } else {
-            commit_size = self.save_entry(receiver).await;
+            commit_size = self.save_entry(pack_file).await?;
 
             storage.save_mr(mr.clone()).await.unwrap();
         };
 
         if commit_size > 1 {
-            mr.close();
+            mr.close()?;
             storage
                 .add_mr_comment(
                     mr.id,
                     0,
+                    None,
+                    CommentKind::System,
                     Some("Mega closed MR due to multi commit detected".to_string()),
                 )
                 .await
                 .unwrap();
         }
+        record_phase("store_ms", &mut phase);
         Ok(())
     }
 
     // monorepo full pack should follow the shallow clone command 'git clone --depth=1'
-    async fn full_pack(&self) -> Result<Vec<u8>, GitError> {
+    #[tracing::instrument(skip_all, fields(objects = Empty, count_ms = Empty, encode_ms = Empty))]
+    async fn full_pack(
+        &self,
+        want: Vec<String>,
+        filter: Option<ObjectFilter>,
+    ) -> Result<Vec<u8>, GitError> {
         let storage = self.context.services.mega_storage.clone();
         let obj_num = AtomicUsize::new(0);
+        let mut phase = Instant::now();
 
-        let refs = storage
-            .get_ref(self.path.to_str().unwrap())
-            .await
-            .unwrap()
-            .unwrap();
-        let commit: Commit = storage
-            .get_commit_by_hash(&refs.ref_commit_hash)
-            .await
-            .unwrap()
-            .unwrap()
-            .into();
-        let tree: Tree = storage
-            .get_tree_by_hash(&refs.ref_tree_hash)
-            .await
-            .unwrap()
-            .unwrap()
-            .into();
-        self.traverse_for_count(tree.clone(), &HashSet::new(), &obj_num)
-            .await;
+        // the commits asked for without their history, the head of the directory by default
+        let mut want = self.resolve_wants(want).await?;
+        if want.is_empty() {
+            let refs = storage
+                .get_ref(self.path.to_str().unwrap())
+                .await
+                .unwrap()
+                .unwrap();
+            want.push(refs.ref_commit_hash);
+        }
+        want.sort();
+        want.dedup();
+        let mut roots = Vec::with_capacity(want.len());
+        for hash in want {
+            let commit: Commit = storage
+                .get_commit_by_hash(&hash)
+                .await?
+                .ok_or(GitError::NotFountHashValue(hash))?
+                .into();
+            let tree_id = commit.tree_id.to_plain_str();
+            let tree: Tree = storage
+                .get_tree_by_hash(&tree_id)
+                .await?
+                .ok_or(GitError::NotFountHashValue(tree_id))?
+                .into();
+            roots.push((commit, tree));
+        }
+
+        let mut counted = HashSet::new();
+        for (_, tree) in &roots {
+            self.traverse_for_count(tree.clone(), "", &mut counted, &obj_num, filter)
+                .await?;
+        }
 
-        obj_num.fetch_add(1, Ordering::SeqCst);
+        obj_num.fetch_add(roots.len(), Ordering::SeqCst);
+        let obj_num = obj_num.into_inner();
+        Span::current().record("objects", obj_num);
+        record_phase("count_ms", &mut phase);
 
         let (sender, receiver) = mpsc::channel();
-        let encoder = PackEncoder::new(obj_num.into_inner(), 0);
+        if self.deterministic {
+            // all objects are collected first, the order of the storage isn't stable
+            let mut sent = HashSet::new();
+            for (commit, tree) in roots {
+                self.traverse(tree, "", &mut sent, Some(&sender), filter)
+                    .await?;
+                sender.send(commit.into()).unwrap();
+            }
+            drop(sender);
+            let mut entries: Vec<Entry> = receiver.into_iter().collect();
+            entries.sort_by_key(|x| x.hash);
+
+            let (sender, receiver) = mpsc::channel();
+            for entry in entries {
+                sender.send(entry).unwrap();
+            }
+            drop(sender);
+            let options = DeltaOptions {
+                window: 0,
+                ..self.delta
+            };
+            let data = PackEncoder::with_options(obj_num, options).encode(receiver)?;
+            record_phase("encode_ms", &mut phase);
+            return Ok(data);
+        }
+        let encoder = PackEncoder::with_options(obj_num, self.delta);
         let data = encoder.encode_async(receiver).unwrap();
 
-        self.traverse(tree, &mut HashSet::new(), Some(&sender))
-            .await;
-        sender.send(commit.into()).unwrap();
+        let mut sent = HashSet::new();
+        for (commit, tree) in roots {
+            self.traverse(tree, "", &mut sent, Some(&sender), filter)
+                .await?;
+            sender.send(commit.into()).unwrap();
+        }
         drop(sender);
-        Ok(data.join().unwrap())
+        let data = data.join().unwrap();
+        record_phase("encode_ms", &mut phase);
+        Ok(data)
     }
 
+    #[tracing::instrument(
+        skip_all,
+        fields(commits = Empty, objects = Empty, count_ms = Empty, encode_ms = Empty)
+    )]
     async fn incremental_pack(
         &self,
-        mut want: Vec<String>,
+        want: Vec<String>,
         have: Vec<String>,
+        filter: Option<ObjectFilter>,
     ) -> Result<Vec<u8>, GitError> {
+        let mut want = self.resolve_wants(want).await?;
+        let mut want_set: HashSet<String> = want.iter().cloned().collect();
+        let have_set: HashSet<&String> = have.iter().collect();
         let storage = self.context.services.mega_storage.clone();
         let obj_num = AtomicUsize::new(0);
+        let mut phase = Instant::now();
 
         let mut exist_objs = HashSet::new();
 
@@ -223,7 +452,7 @@ impl PackHandler for MonoRepo {
             for p_commit_id in temp.parent_commit_ids {
                 let p_commit_id = p_commit_id.to_plain_str();
 
-                if !have.contains(&p_commit_id) && !want.contains(&p_commit_id) {
+                if !have_set.contains(&p_commit_id) && want_set.insert(p_commit_id.clone()) {
                     let parent: Commit = storage
                         .get_commit_by_hash(&p_commit_id)
                         .await
@@ -257,35 +486,55 @@ impl PackHandler for MonoRepo {
             .await
             .unwrap();
         for have_tree in have_trees {
-            self.traverse(have_tree.into(), &mut exist_objs, None).await;
+            self.traverse(have_tree.into(), "", &mut exist_objs, None, None)
+                .await?;
         }
 
-        // traverse for get obj nums
+        // traverse for get obj nums, objects shared by the want commits are counted once
+        let mut counted = exist_objs.clone();
         for c in want_commits.clone() {
             self.traverse_for_count(
                 want_trees.get(&c.tree_id).unwrap().clone(),
-                &exist_objs,
+                "",
+                &mut counted,
                 &obj_num,
+                filter,
             )
-            .await;
+            .await?;
         }
 
+        let obj_num = obj_num.into_inner();
+        Span::current().record("commits", want_commits.len());
+        Span::current().record("objects", obj_num);
+        record_phase("count_ms", &mut phase);
+
         let (sender, receiver) = mpsc::channel();
-        let encoder = PackEncoder::new(obj_num.into_inner(), 0);
+        let encoder = PackEncoder::with_options(obj_num, self.delta);
         let data = encoder.encode_async(receiver).unwrap();
 
         for c in want_commits {
             self.traverse(
                 want_trees.get(&c.tree_id).unwrap().clone(),
+                "",
                 &mut exist_objs,
                 Some(&sender),
+                filter,
             )
-            .await;
+            .await?;
             sender.send(c.into()).unwrap();
         }
         drop(sender);
+        let data = data.join().unwrap();
+        record_phase("encode_ms", &mut phase);
+        Ok(data)
+    }
+
+    fn sparse_patterns(&self) -> Option<&SparsePatterns> {
+        self.sparse.as_ref()
+    }
 
-        Ok(data.join().unwrap())
+    fn capabilities(&self) -> ServerCapabilities {
+        self.capabilities.clone()
     }
 
     async fn get_trees_by_hashes(&self, hashes: Vec<String>) -> Result<Vec<Tree>, MegaError> {
@@ -301,6 +550,17 @@ impl PackHandler for MonoRepo {
             .collect())
     }
 
+    async fn get_blob_sizes(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<HashMap<String, usize>, MegaError> {
+        self.context
+            .services
+            .mega_storage
+            .get_blob_sizes(hashes)
+            .await
+    }
+
     async fn get_blobs_by_hashes(
         &self,
         hashes: Vec<String>,
@@ -333,50 +593,2921 @@ impl PackHandler for MonoRepo {
 }
 
 impl MonoRepo {
-    async fn get_mr(&self) -> (MergeRequest, bool) {
+    /// The handler of the monorepo directory `path`, normalized with [`normalize_path`].
+    pub fn new(context: Context, path: &Path) -> Result<Self, GitError> {
+        Ok(MonoRepo {
+            context,
+            path: normalize_path(path)?,
+            from_hash: None,
+            to_hash: None,
+            sparse: None,
+            capabilities: ServerCapabilities::default(),
+            delta: delta_options(),
+            deterministic: deterministic_packs(),
+            agent: None,
+            policy: PushPolicy::from_env(),
+        })
+    }
+
+    /// A page of at most `limit` commits from `start` which changed the entry at `path`, newest
+    /// first, and the cursor to pass as `before` for the next page, see [`history::log_path`].
+    pub async fn log_path(
+        &self,
+        start: &str,
+        path: &str,
+        limit: usize,
+        before: Option<String>,
+    ) -> Result<(Vec<Commit>, Option<String>), GitError> {
         let storage = self.context.services.mega_storage.clone();
+        history::log_path(storage.as_ref(), start, path, limit, before.as_deref()).await
+    }
 
-        let mr = storage
-            .get_open_mr(self.path.to_str().unwrap())
-            .await
-            .unwrap();
-        if let Some(mr) = mr {
-            (mr, true)
-        } else {
-            let mr = MergeRequest {
-                path: self.path.to_str().unwrap().to_owned(),
-                from_hash: self.from_hash.clone().unwrap(),
-                to_hash: self.to_hash.clone().unwrap(),
+    /// The tree of the directory `path` in the commit `commit`, `None` if `path` is missing or
+    /// isn't a directory, see [`history::tree_at`].
+    pub async fn tree_at(&self, commit: &str, path: &str) -> Result<Option<Tree>, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        history::tree_at(storage.as_ref(), commit, path).await
+    }
+
+    /// Every commit from `start` which changed the file at `path`, oldest first, with its change
+    /// to the file as a unified diff, see [`history::file_patch_series`].
+    pub async fn file_patch_series(
+        &self,
+        start: &str,
+        path: &str,
+    ) -> Result<Vec<FilePatch>, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let start = self.resolve_commit(start).await?;
+        let tree = history::load_commit(storage.as_ref(), &start)
+            .await?
+            .tree_id;
+        let attributes = attributes::root_attributes(storage.as_ref(), tree).await?;
+        history::file_patch_series(storage.as_ref(), &start.to_plain_str(), path, &attributes).await
+    }
+
+    /// Returns the most recent commit reachable from `start_commit` which changed the entry at
+    /// `path`, following first parents only.
+    pub async fn last_commit_for_path(
+        &self,
+        start_commit: &str,
+        path: &str,
+    ) -> Result<Option<Commit>, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        history::last_commit_for_path(storage.as_ref(), start_commit, path).await
+    }
+
+    /// The names of the tags pointing at `commit_hash`, following annotated tags to their target.
+    /// Only the tags of the commit are loaded: the tag objects pointing at it, generation by
+    /// generation for tags of tags, and the tag refs pointing at the commit or those tags.
+    pub async fn tags_for_commit(&self, commit_hash: &str) -> Result<Vec<String>, GitError> {
+        let commit = SHA1::from_str(commit_hash).map_err(GitError::InvalidHashValue)?;
+        let storage = self.context.services.mega_storage.clone();
+        let mut tags: HashMap<SHA1, Tag> = HashMap::new();
+        let mut targets = vec![commit.to_plain_str()];
+        while !targets.is_empty() {
+            let found: Vec<Tag> = storage
+                .get_tags_by_targets(targets)
+                .await?
+                .into_iter()
+                .map(Tag::from)
+                .filter(|x| !tags.contains_key(&x.id))
+                .collect();
+            targets = found.iter().map(|x| x.id.to_plain_str()).collect();
+            tags.extend(found.into_iter().map(|x| (x.id, x)));
+        }
+
+        let hashes = std::iter::once(&commit)
+            .chain(tags.keys())
+            .map(|x| x.to_plain_str())
+            .collect();
+        let tag_refs: Vec<Refs> = storage
+            .get_tag_refs_by_hashes(hashes)
+            .await?
+            .into_iter()
+            .map(|x| Refs {
+                ref_name: x.path,
+                ref_hash: x.ref_commit_hash,
                 ..Default::default()
-            };
-            (mr, false)
+            })
+            .collect();
+        Ok(tags::tags_for_commit(&commit, &tag_refs, &tags))
+    }
+
+    /// Create an annotated tag `name` of the commit `target` and its ref `refs/tags/<name>`, and
+    /// return the id of the tag object. An existing tag of the same name is only moved with
+    /// `force`.
+    pub async fn create_tag(
+        &self,
+        target: &str,
+        name: &str,
+        tagger: Signature,
+        message: &str,
+        force: bool,
+    ) -> Result<String, GitError> {
+        tags::check_tag_name(name)?;
+        let storage = self.context.services.mega_storage.clone();
+        let target = SHA1::from_str(target).map_err(GitError::InvalidHashValue)?;
+        let commit = history::load_commit(storage.as_ref(), &target).await?;
+        let ref_name = format!("refs/tags/{}", name);
+        let existing = storage.get_ref(&ref_name).await?;
+        if existing.is_some() && !force {
+            return Err(GitError::TagAlreadyExists(name.to_owned()));
         }
+
+        let tag = Tag::new(target, ObjectType::Commit, name, tagger, message);
+        let tag_id = tag.id.to_plain_str();
+        storage.save_mega_tags(vec![tag]).await?;
+        let tree_id = commit.tree_id.to_plain_str();
+        match existing {
+            Some(mut refs) => {
+                refs.ref_commit_hash = tag_id.clone();
+                refs.ref_tree_hash = tree_id;
+                storage.update_ref(refs).await?;
+            }
+            None => storage.save_ref(&ref_name, &tag_id, &tree_id).await?,
+        }
+        Ok(tag_id)
     }
 
-    fn comment_for_force_update(&self, from: &str, to: &str) -> String {
-        format!(
-            "Mega updated the mr automatic from {} to {}",
-            &from[..6],
-            &to[..6]
-        )
+    /// Create the first commit of the repo, holding the empty tree, authored and committed by
+    /// `identity` with `message`, and point the ref of the repo's path, its default branch, at it.
+    /// Returns the hash of the commit, a repo whose ref already exists is refused.
+    pub async fn init_root(&self, identity: Signature, message: &str) -> Result<String, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let path = self.path.to_str().unwrap();
+        if storage.get_ref(path).await?.is_some() {
+            return Err(GitError::RefAlreadyExists(path.to_owned()));
+        }
+
+        let tree = Tree {
+            id: SHA1::from_type_and_data(ObjectType::Tree, &Vec::new()),
+            tree_items: vec![],
+        };
+        let commit = Commit::from_identity(identity, tree.id, vec![], message);
+        let (commit_id, tree_id) = (commit.id.to_plain_str(), tree.id.to_plain_str());
+        storage.save_trees(vec![tree]).await?;
+        storage.save_mega_commits(vec![commit]).await?;
+        storage.save_ref(path, &commit_id, &tree_id).await?;
+        Ok(commit_id)
     }
 
-    async fn save_entry(&self, receiver: Receiver<Entry>) -> i32 {
+    /// Returns the recorded changes of the ref `ref_name`, oldest first. Monorepo refs are named
+    /// by their path, e.g. `/` or `/project/mega`.
+    pub async fn reflog(&self, ref_name: &str) -> Result<Vec<ReflogEntry>, GitError> {
         let storage = self.context.services.mega_storage.clone();
-        let mut entry_list = Vec::new();
+        Ok(storage.get_reflog(ref_name).await?)
+    }
 
-        let mut commit_size = 0;
-        for entry in receiver {
-            if entry.obj_type == ObjectType::Commit {
-                commit_size += 1;
-            }
-            entry_list.push(entry);
-            if entry_list.len() >= 1000 {
-                storage.save_entry(entry_list).await.unwrap();
-                entry_list = Vec::new();
-            }
+    /// The number of objects stored for the monorepo and their total size, for quotas.
+    pub async fn repo_size(&self) -> Result<RepoSize, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        Ok(storage.repo_size().await?)
+    }
+
+    /// Build a commit on top of the current ref of the merge request's path which undoes the
+    /// changes of merge request `mr_id`, and return its hash. Refs are left untouched.
+    pub async fn revert_mr(&self, mr_id: i64) -> Result<String, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let mr = storage
+            .get_mr(mr_id)
+            .await?
+            .ok_or(GitError::InvalidMergeRequest(mr_id))?;
+        let refs = storage
+            .get_ref(&mr.path)
+            .await?
+            .ok_or_else(|| GitError::InvalidPathError(mr.path.clone()))?;
+
+        let from = SHA1::from_str(&mr.from_hash).map_err(GitError::InvalidHashValue)?;
+        let to = SHA1::from_str(&mr.to_hash).map_err(GitError::InvalidHashValue)?;
+        let base = SHA1::from_str(&refs.ref_commit_hash).map_err(GitError::InvalidHashValue)?;
+        let message = format!(
+            "Revert merge request {}\n\nThis reverts commit {}.",
+            mr.id, mr.to_hash
+        );
+        let commit = revert::revert_changes(storage.as_ref(), &from, &to, &base, &message).await?;
+        Ok(commit.id.to_plain_str())
+    }
+
+    /// The changes between the commits `from` and `to`, ordered by path. Files moved with at
+    /// least `rename_threshold` percent of their content unchanged are reported as renames, e.g.
+    /// with [`DEFAULT_RENAME_THRESHOLD`](crate::monorepo::rename::DEFAULT_RENAME_THRESHOLD).
+    pub async fn commit_diff(
+        &self,
+        from: &str,
+        to: &str,
+        rename_threshold: Option<u8>,
+    ) -> Result<Vec<DiffEntry>, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let from = SHA1::from_str(from).map_err(GitError::InvalidHashValue)?;
+        let to = SHA1::from_str(to).map_err(GitError::InvalidHashValue)?;
+        diff::commit_diff(storage.as_ref(), &from, &to, rename_threshold).await
+    }
+
+    /// The paths changed between `a` and `b` and how, like `git diff --name-status a b`. Both
+    /// are either a commit hash or a monorepo ref, i.e. a path like `/project/mega`.
+    pub async fn changed_paths(
+        &self,
+        a: &str,
+        b: &str,
+    ) -> Result<Vec<(String, ChangeKind)>, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let old = self.resolve_commit(a).await?;
+        let new = self.resolve_commit(b).await?;
+        let old = history::load_commit(storage.as_ref(), &old).await?.tree_id;
+        let new = history::load_commit(storage.as_ref(), &new).await?.tree_id;
+        diff::changed_paths(storage.as_ref(), old, new).await
+    }
+
+    /// The files of `working_dir` added, modified or deleted compared to `head`, a commit hash or
+    /// a monorepo ref, for reviewing an import before committing it. Files matched by the
+    /// `.gitignore` of `working_dir` are left out.
+    pub async fn status(
+        &self,
+        working_dir: &Path,
+        head: &str,
+    ) -> Result<Vec<(String, ChangeKind)>, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let head = self.resolve_commit(head).await?;
+        let head = history::load_commit(storage.as_ref(), &head).await?.tree_id;
+        import::working_dir_status(storage.as_ref(), working_dir, head).await
+    }
+
+    /// The lines added and deleted per file between `a` and `b`, like `git diff --numstat a b`,
+    /// with binary files told apart by the `.gitattributes` at the root of `b`. Both are either
+    /// a commit hash or a monorepo ref.
+    pub async fn diff_stat(&self, a: &str, b: &str) -> Result<Vec<FileStat>, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let old = self.resolve_commit(a).await?;
+        let new = self.resolve_commit(b).await?;
+        let old = history::load_commit(storage.as_ref(), &old).await?.tree_id;
+        let new = history::load_commit(storage.as_ref(), &new).await?.tree_id;
+        let attributes = attributes::root_attributes(storage.as_ref(), new).await?;
+        diff::diff_stat(storage.as_ref(), old, new, &attributes).await
+    }
+
+    /// The full hash of the one object whose hash starts with `prefix`, of at least 4 hex digits
+    /// like `git rev-parse --short`, `None` if no object does. A prefix matching several objects
+    /// fails with [`GitError::AmbiguousHash`].
+    pub async fn resolve_short_hash(&self, prefix: &str) -> Result<Option<String>, GitError> {
+        let prefix = prefix.to_ascii_lowercase();
+        if !(4..=40).contains(&prefix.len()) || !prefix.bytes().all(|x| x.is_ascii_hexdigit()) {
+            return Err(GitError::InvalidHashValue(prefix));
         }
-        storage.save_entry(entry_list).await.unwrap();
-        commit_size
+        let storage = self.context.services.mega_storage.clone();
+        // two matches are enough to tell the prefix is ambiguous
+        let mut hashes = storage.get_hashes_by_prefix(&prefix, 2).await?;
+        if hashes.len() > 1 {
+            return Err(GitError::AmbiguousHash(prefix));
+        }
+        Ok(hashes.pop())
+    }
+
+    /// The type of each stored object among `hashes`, looked up with a query per object table
+    /// instead of one per hash. Hashes of missing objects are left out.
+    pub async fn object_types(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<HashMap<String, ObjectType>, MegaError> {
+        let storage = self.context.services.mega_storage.clone();
+        storage.get_object_types(&hashes).await
+    }
+
+    /// Resolve `rev`, a commit hash or the path of a monorepo ref, to a commit hash.
+    async fn resolve_commit(&self, rev: &str) -> Result<SHA1, GitError> {
+        if let Ok(hash) = SHA1::from_str(rev) {
+            return Ok(hash);
+        }
+        let storage = self.context.services.mega_storage.clone();
+        let refs = storage
+            .get_ref(rev)
+            .await?
+            .ok_or_else(|| GitError::InvalidPathError(rev.to_owned()))?;
+        SHA1::from_str(&refs.ref_commit_hash).map_err(GitError::InvalidHashValue)
+    }
+
+    /// Merge the commit `head` into the commit `base` and return the hash of the merge commit,
+    /// whose parents are `base` and `head`. The merge commit and its trees are saved, refs are left
+    /// untouched. Paths changed differently on both sides fail with [`GitError::ConflictError`].
+    pub async fn merge(&self, base: &str, head: &str, message: &str) -> Result<String, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let base = SHA1::from_str(base).map_err(GitError::InvalidHashValue)?;
+        let head = SHA1::from_str(head).map_err(GitError::InvalidHashValue)?;
+        let commit = merge::merge_commits(storage.as_ref(), &base, &head, message).await?;
+        Ok(commit.id.to_plain_str())
+    }
+
+    /// Apply the changes of `commit` on top of `onto` as a new commit and return its hash. Refs
+    /// are left untouched.
+    pub async fn cherry_pick(&self, commit: &str, onto: &str) -> Result<String, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let commit = SHA1::from_str(commit).map_err(GitError::InvalidHashValue)?;
+        let onto = SHA1::from_str(onto).map_err(GitError::InvalidHashValue)?;
+        let picked = merge::cherry_pick_commit(storage.as_ref(), &commit, &onto).await?;
+        Ok(picked.id.to_plain_str())
+    }
+
+    /// A pack of exactly the objects merge request `mr_id` introduced, those reachable from its
+    /// `to_hash` but not from its `from_hash`, for review tooling.
+    pub async fn mr_pack(&self, mr_id: i64) -> Result<Vec<u8>, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let mr = storage
+            .get_mr(mr_id)
+            .await?
+            .ok_or(GitError::InvalidMergeRequest(mr_id))?;
+        self.incremental_pack(vec![mr.to_hash], vec![mr.from_hash], None)
+            .await
+    }
+
+    /// The commits an MR introduces, from after its `from_hash` up to its `to_hash`, parents
+    /// before their children. These are the commits [`MonoRepo::mr_pack`] sends.
+    pub async fn mr_commits(&self, mr_id: i64) -> Result<Vec<Commit>, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let mr = storage
+            .get_mr(mr_id)
+            .await?
+            .ok_or(GitError::InvalidMergeRequest(mr_id))?;
+        let from = SHA1::from_str(&mr.from_hash).map_err(GitError::InvalidHashValue)?;
+        let to = SHA1::from_str(&mr.to_hash).map_err(GitError::InvalidHashValue)?;
+        history::commits_between(storage.as_ref(), &from, &to).await
+    }
+
+    /// The hashes of all commits, trees and blobs reachable from the commits `tips`, e.g. for
+    /// the objects of an MR as the closure of its `to_hash` minus the one of its `from_hash`.
+    pub async fn reachable_closure(&self, tips: &[String]) -> Result<HashSet<String>, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let tips = tips
+            .iter()
+            .map(|x| SHA1::from_str(x).map_err(GitError::InvalidHashValue))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(history::reachable_objects(storage.as_ref(), &tips)
+            .await?
+            .into_iter()
+            .map(|x| x.to_plain_str())
+            .collect())
+    }
+
+    /// Remove the refs of the closed or merged MR `mr_id` and return how many were removed:
+    /// the ones under [`MergeRequest::refs_prefix`], and the path ref of its directory created by
+    /// [`PackHandler::ensure_ref`] unless another MR of the directory is open. The refs of
+    /// subdirectories belong to pushes of their own and are left alone.
+    /// The tree of a merged MR is part of the root tree. The objects of a closed MR are
+    /// retained for reopening it, so they have to be stored, otherwise the refs are kept and
+    /// [`GitError::UnretainedMrObjects`] is returned.
+    pub async fn cleanup_mr_refs(&self, mr_id: i64) -> Result<usize, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let mr = storage
+            .get_mr(mr_id)
+            .await?
+            .ok_or(GitError::InvalidMergeRequest(mr_id))?;
+        if mr.status == MergeStatus::Open {
+            return Err(GitError::InvalidMrTransition(
+                mr.status.to_string(),
+                "clean up the refs of".to_owned(),
+            ));
+        }
+        // the root ref is never removed, and a newer MR of the directory still needs its ref
+        let path_ref_in_use = mr.path == "/" || storage.get_open_mr(&mr.path).await?.is_some();
+        let prefix = mr.refs_prefix();
+        let mr_refs: Vec<_> = storage
+            .get_refs()
+            .await?
+            .into_iter()
+            .filter(|x| x.path.starts_with(&prefix) || (!path_ref_in_use && x.path == mr.path))
+            .collect();
+        if mr_refs.is_empty() {
+            return Ok(0);
+        }
+
+        if mr.status == MergeStatus::Closed
+            && storage.get_commit_by_hash(&mr.to_hash).await?.is_none()
+        {
+            return Err(GitError::UnretainedMrObjects(mr_id));
+        }
+
+        let removed = mr_refs.len();
+        for refs in mr_refs {
+            storage.remove_ref(refs).await?;
+        }
+        Ok(removed)
+    }
+
+    /// Replay the commits of the branch `branch_tip` since it forked off onto `onto`, for rebase
+    /// merges, and return the hash of the new tip. Refs are left untouched.
+    pub async fn rebase(&self, branch_tip: &str, onto: &str) -> Result<String, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let tip = SHA1::from_str(branch_tip).map_err(GitError::InvalidHashValue)?;
+        let onto = SHA1::from_str(onto).map_err(GitError::InvalidHashValue)?;
+        let commit = merge::rebase_commits(storage.as_ref(), &tip, &onto).await?;
+        Ok(commit.id.to_plain_str())
+    }
+
+    /// Squash the commits after `base` up to `tip` into a single commit on top of `base`, for
+    /// squash merges, and return its hash. Refs are left untouched.
+    pub async fn squash(
+        &self,
+        tip: &str,
+        base: &str,
+        message: &str,
+        author: Signature,
+    ) -> Result<String, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let tip = SHA1::from_str(tip).map_err(GitError::InvalidHashValue)?;
+        let base = SHA1::from_str(base).map_err(GitError::InvalidHashValue)?;
+        let commit = merge::squash_commits(storage.as_ref(), &tip, &base, message, author).await?;
+        Ok(commit.id.to_plain_str())
+    }
+
+    /// List the submodules below the monorepo directory `root`, e.g. `/` or `/third_party`, in
+    /// the current state of the monorepo. Paths are relative to `root`.
+    pub async fn list_submodules(&self, root: &str) -> Result<Vec<Submodule>, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let refs = storage
+            .get_ref("/")
+            .await?
+            .ok_or_else(|| GitError::InvalidPathError("/".to_owned()))?;
+        let tree_id = SHA1::from_str(&refs.ref_tree_hash).map_err(GitError::InvalidHashValue)?;
+        let root_id = history::entry_id_at_path(storage.as_ref(), tree_id, root)
+            .await?
+            .ok_or_else(|| GitError::InvalidPathError(root.to_owned()))?;
+        submodule::list_submodules(storage.as_ref(), root_id).await
+    }
+
+    /// The file contents found at several paths in the commits reachable from the root ref of
+    /// the monorepo and the space their copies represent, see [`dedup`].
+    pub async fn dedup_report(&self) -> Result<DedupReport, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let refs = storage
+            .get_ref("/")
+            .await?
+            .ok_or_else(|| GitError::InvalidPathError("/".to_owned()))?;
+        let tip = SHA1::from_str(&refs.ref_commit_hash).map_err(GitError::InvalidHashValue)?;
+        dedup::dedup_report(storage.as_ref(), &[tip], dedup::TOP_DUPLICATES).await
+    }
+
+    /// List a page of at most `limit` entries of the monorepo directory `path`, in the order of
+    /// the tree, starting after the entry named `cursor`. Returns the entries and the cursor of
+    /// the next page, `None` on the last page.
+    pub async fn list_dir_paged(
+        &self,
+        path: &str,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<(Vec<TreeItem>, Option<String>), GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let refs = storage
+            .get_ref("/")
+            .await?
+            .ok_or_else(|| GitError::InvalidPathError("/".to_owned()))?;
+        let tree_id = SHA1::from_str(&refs.ref_tree_hash).map_err(GitError::InvalidHashValue)?;
+        listing::list_dir_page(storage.as_ref(), tree_id, path, cursor.as_deref(), limit).await
+    }
+
+    /// Count the entries of the tree `tree` and of every tree below it, see
+    /// [`listing::recursive_counts`]. Subtrees are loaded a level at a time.
+    pub async fn recursive_counts(&self, tree: &str) -> Result<TreeCounts, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let tree_id = SHA1::from_str(tree).map_err(GitError::InvalidHashValue)?;
+        listing::recursive_counts(storage.as_ref(), tree_id).await
+    }
+
+    /// Every ref of the monorepo with the subject, author and time of the commit it points at,
+    /// tag refs following their tag to the commit. The ref of the repo's own path is the
+    /// default one. The internal refs of MRs are left out.
+    pub async fn list_refs_detailed(&self) -> Result<Vec<RefInfo>, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let refs: Vec<_> = storage
+            .get_refs()
+            .await?
+            .into_iter()
+            .filter(|x| !x.path.starts_with(MR_REFS_PREFIX))
+            .collect();
+        let tag_ids: Vec<String> = refs
+            .iter()
+            .filter(|x| x.path.starts_with("refs/tags/"))
+            .map(|x| x.ref_commit_hash.clone())
+            .collect();
+        let peeled: HashMap<String, String> = if tag_ids.is_empty() {
+            HashMap::new()
+        } else {
+            storage
+                .get_tags_by_hashes(tag_ids)
+                .await?
+                .into_iter()
+                .map(|x| (x.tag_id, x.object_id))
+                .collect()
+        };
+        let targets: Vec<String> = refs
+            .iter()
+            .map(|x| {
+                peeled
+                    .get(&x.ref_commit_hash)
+                    .unwrap_or(&x.ref_commit_hash)
+                    .clone()
+            })
+            .collect();
+        let commits: HashMap<String, Commit> = storage
+            .get_commits_by_hashes(&targets)
+            .await?
+            .into_iter()
+            .map(|x| (x.commit_id.clone(), x.into()))
+            .collect();
+
+        let default = self.path.to_str().unwrap();
+        refs.into_iter()
+            .zip(targets)
+            .map(|(refs, target)| {
+                let commit = commits
+                    .get(&target)
+                    .ok_or_else(|| GitError::NotFountHashValue(target.clone()))?;
+                Ok(RefInfo {
+                    is_default: refs.path == default,
+                    name: refs.path,
+                    commit_hash: target,
+                    subject: commit.subject().to_owned(),
+                    author: commit.author.name.clone(),
+                    timestamp: commit.committer.timestamp,
+                })
+            })
+            .collect()
+    }
+
+    /// Write every object of the monorepo to `writer` as newline delimited JSON, see [`graph`].
+    /// Objects are loaded a batch at a time, refs are not exported.
+    pub async fn export_graph(&self, mut writer: impl Write) -> Result<(), GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        for obj_type in [
+            ObjectType::Commit,
+            ObjectType::Tree,
+            ObjectType::Blob,
+            ObjectType::Tag,
+        ] {
+            let mut batches = storage
+                .stream_object_hashes(Some(obj_type))
+                .chunks(GRAPH_BATCH_SIZE);
+            while let Some(batch) = batches.next().await {
+                let hashes = batch.into_iter().collect::<Result<Vec<String>, _>>()?;
+                let entries: Vec<Entry> = match obj_type {
+                    ObjectType::Commit => storage
+                        .get_commits_by_hashes(&hashes)
+                        .await?
+                        .into_iter()
+                        .map(|x| Commit::from(x).into())
+                        .collect(),
+                    ObjectType::Tree => storage
+                        .get_trees_by_hashes(hashes)
+                        .await?
+                        .into_iter()
+                        .map(|x| Tree::from(x).into())
+                        .collect(),
+                    ObjectType::Blob => storage
+                        .get_raw_blobs_by_hashes(hashes)
+                        .await?
+                        .into_iter()
+                        .map(|x| Blob::from(x).into())
+                        .collect(),
+                    _ => storage
+                        .get_tags_by_hashes(hashes)
+                        .await?
+                        .into_iter()
+                        .map(|x| Tag::from(x).into())
+                        .collect(),
+                };
+                for entry in &entries {
+                    graph::write_record(&mut writer, entry)?;
+                }
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Check every stored object and ref, like `git fsck`, see [`fsck`]. The hashes of all
+    /// objects are read first, then the objects a batch at a time.
+    pub async fn fsck(&self) -> Result<FsckReport, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let types = [
+            ObjectType::Commit,
+            ObjectType::Tree,
+            ObjectType::Blob,
+            ObjectType::Tag,
+        ];
+        let mut fsck = Fsck::default();
+        for obj_type in types {
+            let mut hashes = storage.stream_object_hashes(Some(obj_type));
+            while let Some(hash) = hashes.next().await {
+                fsck.stored(&hash?, obj_type);
+            }
+        }
+
+        for obj_type in types {
+            let mut batches = storage
+                .stream_object_hashes(Some(obj_type))
+                .chunks(GRAPH_BATCH_SIZE);
+            while let Some(batch) = batches.next().await {
+                let hashes = batch.into_iter().collect::<Result<Vec<String>, _>>()?;
+                match obj_type {
+                    ObjectType::Commit => {
+                        for model in storage.get_commits_by_hashes(&hashes).await? {
+                            let hash = model.commit_id.clone();
+                            fsck.check(&hash, fsck::parse_commit(model));
+                        }
+                    }
+                    ObjectType::Tree => {
+                        for model in storage.get_trees_by_hashes(hashes).await? {
+                            let hash = model.tree_id.clone();
+                            fsck.check(&hash, fsck::parse_tree(model));
+                        }
+                    }
+                    ObjectType::Blob => {
+                        let mut missing: HashSet<String> = hashes.iter().cloned().collect();
+                        for model in storage.get_raw_blobs_by_hashes(hashes).await? {
+                            let hash = model.sha1.clone();
+                            missing.remove(&hash);
+                            // cold blobs were read back from the raw object storage
+                            if let Some(blob) = fsck::parse_blob(model).transpose() {
+                                fsck.check(&hash, blob);
+                            }
+                        }
+                        for hash in missing {
+                            let err = GitError::InvalidBlobObject("no content".to_owned());
+                            fsck.check(&hash, Err(err));
+                        }
+                    }
+                    _ => {
+                        for model in storage.get_tags_by_hashes(hashes).await? {
+                            let hash = model.tag_id.clone();
+                            fsck.check(&hash, fsck::parse_tag(model));
+                        }
+                    }
+                }
+            }
+        }
+
+        for refs in storage.get_refs().await? {
+            fsck.check_ref(&refs.path, &refs.ref_commit_hash);
+        }
+        Ok(fsck.finish())
+    }
+
+    /// Save the objects of an export of [`MonoRepo::export_graph`] and return their number.
+    /// Every object is checked against its hash before it is saved.
+    pub async fn import_graph(&self, reader: impl BufRead) -> Result<usize, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let mut count = 0;
+        let mut batch = Vec::new();
+        for entry in graph::read_records(reader) {
+            batch.push(entry?);
+            if batch.len() >= GRAPH_BATCH_SIZE {
+                count += batch.len();
+                storage.save_entry(std::mem::take(&mut batch)).await?;
+            }
+        }
+        if !batch.is_empty() {
+            count += batch.len();
+            storage.save_entry(batch).await?;
+        }
+        Ok(count)
+    }
+
+    /// Stream the hashes of all objects stored in the monorepo, optionally only those of
+    /// `obj_type`. The hashes are read through a database cursor, not loaded all at once.
+    pub fn list_object_hashes(
+        &self,
+        obj_type: Option<ObjectType>,
+    ) -> impl Stream<Item = Result<String, MegaError>> + Send + '_ {
+        self.context
+            .services
+            .mega_storage
+            .stream_object_hashes(obj_type)
+    }
+
+    /// Store the blob content read from `reader` in the raw object storage and return its hash.
+    /// The content is streamed, not loaded into memory, and the blob is recorded as a cold blob,
+    /// so it reads back like any other. Storing it again is a no-op.
+    pub async fn store_blob_stream(
+        &self,
+        mut reader: impl AsyncRead + Unpin + Send,
+    ) -> Result<String, MegaError> {
+        let storage = self.context.services.mega_storage.clone();
+        let (hash, location) = storage
+            .raw_storage
+            .put_object_stream("", &mut reader)
+            .await?;
+        storage.save_cold_blob(&hash, location).await?;
+        Ok(hash)
+    }
+
+    /// Pack the commits with a committer timestamp of at least `since`, in seconds since the
+    /// epoch, and the objects they introduce, for mirrors pulling periodically. Objects reachable
+    /// from their older parents are left out, as for a fetch already having those parents.
+    pub async fn pack_since(&self, since: i64) -> Result<Vec<u8>, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let mut want = Vec::new();
+        let mut want_set = HashSet::new();
+        let mut parents = HashSet::new();
+        let mut after = None;
+        loop {
+            let page = storage
+                .get_commits_since(since, after, COMMIT_PAGE_SIZE)
+                .await?;
+            for commit in &page {
+                want.push(commit.commit_id.clone());
+                want_set.insert(commit.commit_id.clone());
+                parents.extend(commit.parents_id.iter().cloned());
+            }
+            match page.last() {
+                Some(last) if page.len() as u64 == COMMIT_PAGE_SIZE => {
+                    after = Some((last.committer_time, last.id))
+                }
+                _ => break,
+            }
+        }
+        let mut have: Vec<String> = parents
+            .into_iter()
+            .filter(|x| !want_set.contains(x))
+            .collect();
+        have.sort();
+        self.incremental_pack(want, have, None).await
+    }
+
+    /// Move the content of the blob `hash` to the storage `tier`, e.g. rarely read blobs to the
+    /// cheaper cold storage. Reads of the blob are unchanged, cold ones are just slower.
+    pub async fn set_tier(&self, hash: &str, tier: StorageTier) -> Result<(), GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        if !storage.set_blob_tier(hash, tier).await? {
+            return Err(GitError::NotFountHashValue(hash.to_owned()));
+        }
+        Ok(())
+    }
+
+    /// The MR the push goes to, whether it exists and whether it's a closed one reopened by the
+    /// push. Nothing is written, the caller saves the MR once the push is stored.
+    async fn get_mr(&self) -> (MergeRequest, bool, bool) {
+        let storage = self.context.services.mega_storage.clone();
+
+        let mr = storage
+            .get_open_mr(self.path.to_str().unwrap())
+            .await
+            .unwrap();
+        if let Some(mr) = mr {
+            return (mr, true, false);
+        }
+        // a push from the same base as the MR closed last continues it, so the MR is reopened
+        // instead of starting over without its history
+        let closed = storage
+            .get_last_closed_mr(self.path.to_str().unwrap())
+            .await
+            .unwrap();
+        match closed {
+            Some(mut mr) if mr.from_hash == self.from_hash.clone().unwrap() => {
+                mr.reopen().unwrap();
+                (mr, true, true)
+            }
+            _ => {
+                let mr = MergeRequest {
+                    path: self.path.to_str().unwrap().to_owned(),
+                    from_hash: self.from_hash.clone().unwrap(),
+                    to_hash: self.to_hash.clone().unwrap(),
+                    idempotency_key: self.push_key(),
+                    agent: self.agent.clone(),
+                    ..Default::default()
+                };
+                (mr, false, false)
+            }
+        }
+    }
+
+    fn push_key(&self) -> String {
+        MergeRequest::push_key(
+            self.path.to_str().unwrap(),
+            self.from_hash.as_deref().unwrap(),
+            self.to_hash.as_deref().unwrap(),
+        )
+    }
+
+    fn comment_for_force_update(&self, from: &str, to: &str) -> String {
+        format!(
+            "Mega updated the mr automatic from {} to {}",
+            &from[..6],
+            &to[..6]
+        )
+    }
+
+    #[tracing::instrument(
+        skip_all,
+        fields(objects = Empty, commits = Empty, store_ms = Empty)
+    )]
+    async fn save_entry(&self, pack_file: Bytes) -> Result<i32, GitError> {
+        let storage = self.context.services.mega_storage.clone();
+        let decoding = self.pack_decoder(pack_file)?;
+        let mut entry_list = Vec::new();
+        let mut phase = Instant::now();
+
+        let (mut objects, mut commit_size) = (0, 0);
+        while let Ok(entry) = decoding.entries.recv() {
+            objects += 1;
+            if entry.obj_type == ObjectType::Commit {
+                commit_size += 1;
+            }
+            entry_list.push(entry);
+            if entry_list.len() >= 1000 {
+                self.record_lfs_pointers(&entry_list).await?;
+                storage.save_entry(entry_list).await.unwrap();
+                entry_list = Vec::new();
+            }
+        }
+        decoding.finish()?;
+        self.record_lfs_pointers(&entry_list).await?;
+        storage.save_entry(entry_list).await.unwrap();
+        Span::current().record("objects", objects);
+        Span::current().record("commits", commit_size);
+        record_phase("store_ms", &mut phase);
+        Ok(commit_size)
+    }
+
+    /// Record the objects referenced by the LFS pointer blobs among `entries` as not uploaded
+    /// yet. A push only holds the pointers, which are stored like any other blob, the content
+    /// they point to is not part of it and is left to the client to upload to the LFS store.
+    async fn record_lfs_pointers(&self, entries: &[Entry]) -> Result<(), GitError> {
+        let lfs_storage = self.context.services.lfs_storage.clone();
+        for pointer in pointer::find_pointers(entries) {
+            let recorded = lfs_storage.get_lfs_object(pointer.oid.clone()).await?;
+            if recorded.is_none() {
+                let object = lfs_objects::Model {
+                    oid: pointer.oid,
+                    size: pointer.size,
+                    exist: false,
+                };
+                lfs_storage.new_lfs_object(object).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, HashMap, HashSet};
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+    use std::num::NonZeroUsize;
+    use std::path::PathBuf;
+    use std::str::FromStr;
+    use std::sync::{mpsc, Arc, Mutex};
+
+    use bytes::Bytes;
+    use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult, Value};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::{Context as LayerContext, Layer, SubscriberExt};
+    use tracing_subscriber::registry::{LookupSpan, Registry};
+
+    use callisto::db_enums::{CommentKind, ConvType, MergeStatus, StorageType};
+    use callisto::{
+        mega_commit, mega_mr, mega_mr_comment, mega_mr_conv, mega_refs, mega_tag, mega_tree,
+        raw_blob,
+    };
+    use common::utils::ZERO_ID;
+    use jupiter::context::{Context, Service};
+    use jupiter::raw_storage::local_storage::LocalStorage;
+    use jupiter::storage::mega_storage::MegaStorage;
+    use jupiter::storage::object_storage::ObjectStorage;
+    use mercury::internal::pack::encode::{DeltaOptions, PackEncoder};
+    use mercury::internal::pack::Pack;
+    use venus::errors::GitError;
+    use venus::hash::SHA1;
+    use venus::internal::object::blob::Blob;
+    use venus::internal::object::commit::Commit;
+    use venus::internal::object::signature::{Signature, SignatureType};
+    use venus::internal::object::tag::Tag;
+    use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+    use venus::internal::object::types::ObjectType;
+    use venus::internal::object::ObjectTrait;
+    use venus::internal::pack::entry::Entry;
+    use venus::monorepo::mr::{MergeOperation, MergeRequest};
+    use venus::monorepo::storage_tier::StorageTier;
+
+    use crate::monorepo::fsck::FsckProblem;
+    use crate::monorepo::service::MonorepoService;
+    use crate::pack::handler::PackHandler;
+    use crate::pack::monorepo::{normalize_path, MonoRepo};
+    use crate::pack::policy::PushPolicy;
+    use crate::protocol::smart::ServerCapabilities;
+    use crate::test_util::transaction_log;
+
+    /// The numbers recorded on spans, by span and field name.
+    #[derive(Clone, Default)]
+    struct SpanFields(Arc<Mutex<HashMap<String, HashMap<String, u64>>>>);
+
+    struct NumberVisitor<'a>(&'a mut HashMap<String, u64>);
+
+    impl Visit for NumberVisitor<'_> {
+        fn record_u64(&mut self, field: &Field, value: u64) {
+            self.0.insert(field.name().to_owned(), value);
+        }
+
+        fn record_i64(&mut self, field: &Field, value: i64) {
+            self.record_u64(field, value as u64);
+        }
+
+        fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+    }
+
+    impl SpanFields {
+        fn collect<S>(
+            &self,
+            id: &Id,
+            ctx: &LayerContext<'_, S>,
+            record: impl FnOnce(&mut NumberVisitor),
+        ) where
+            S: Subscriber + for<'a> LookupSpan<'a>,
+        {
+            let name = ctx.span(id).unwrap().name().to_owned();
+            let mut spans = self.0.lock().unwrap();
+            record(&mut NumberVisitor(spans.entry(name).or_default()));
+        }
+
+        fn get(&self, span: &str, field: &str) -> Option<u64> {
+            self.0.lock().unwrap().get(span)?.get(field).copied()
+        }
+    }
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for SpanFields {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: LayerContext<'_, S>) {
+            self.collect(id, &ctx, |visitor| attrs.record(visitor));
+        }
+
+        fn on_record(&self, id: &Id, values: &Record<'_>, ctx: LayerContext<'_, S>) {
+            self.collect(id, &ctx, |visitor| values.record(visitor));
+        }
+    }
+
+    // the mock context has no database, so any attempt to save an MR would fail
+    fn mock_repo() -> MonoRepo {
+        MonoRepo {
+            context: Context::mock(),
+            path: PathBuf::from("/project/mega"),
+            from_hash: Some("0".repeat(40)),
+            to_hash: Some("1".repeat(40)),
+            sparse: None,
+            capabilities: ServerCapabilities::default(),
+            delta: DeltaOptions::default(),
+            deterministic: false,
+            agent: None,
+            policy: PushPolicy::default(),
+        }
+    }
+
+    fn repo_with_storage(storage: MegaStorage) -> MonoRepo {
+        let mock = Context::mock();
+        let context = Context {
+            services: Arc::new(Service {
+                mega_storage: Arc::new(storage),
+                ..(*mock.services).clone()
+            }),
+            ..mock
+        };
+        MonoRepo {
+            context,
+            ..mock_repo()
+        }
+    }
+
+    /// A root commit by dev@example.com.
+    fn dev_commit(tree: SHA1, message: &str) -> Commit {
+        let signature = |kind: &str| {
+            let data = format!("{} dev <dev@example.com> 1700000000 +0800", kind);
+            Signature::from_data(data.into_bytes()).unwrap()
+        };
+        Commit::new(
+            signature("author"),
+            signature("committer"),
+            tree,
+            vec![],
+            message,
+        )
+    }
+
+    fn encode_pack(entries: Vec<Entry>) -> Bytes {
+        env::set_var("MEGA_PACK_DECODE_MEM_SIZE", "1");
+        env::set_var("MEGA_PACK_DECODE_CACHE_PATH", "/tmp/.cache_temp");
+        env::set_var("CLEAN_CACHE_AFTER_DECODE", "true");
+
+        let (sender, receiver) = mpsc::channel();
+        let encoder = PackEncoder::new(entries.len(), 0);
+        let data = encoder.encode_async(receiver).unwrap();
+        for entry in entries {
+            sender.send(entry).unwrap();
+        }
+        drop(sender);
+        Bytes::from(data.join().unwrap())
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        for (path, normalized) in [
+            ("//a/./b/", "/a/b"),
+            ("/project/mega", "/project/mega"),
+            ("project//mega/", "/project/mega"),
+            ("/a/b/../c", "/a/c"),
+            ("./a/..", "/"),
+            ("", "/"),
+        ] {
+            assert_eq!(
+                normalize_path(&PathBuf::from(path)).unwrap(),
+                PathBuf::from(normalized),
+                "{}",
+                path
+            );
+        }
+
+        let result = MonoRepo::new(Context::mock(), &PathBuf::from("/a/../../etc"));
+        assert!(matches!(result, Err(GitError::InvalidPathError(_))));
+        let repo = MonoRepo::new(Context::mock(), &PathBuf::from("//a/./b/")).unwrap();
+        assert_eq!(repo.path, PathBuf::from("/a/b"));
+    }
+
+    #[tokio::test]
+    async fn test_unpack_empty_pack() {
+        let repo = mock_repo();
+
+        let result = repo.unpack(Bytes::new()).await;
+        assert!(matches!(result, Err(GitError::EmptyPack)));
+
+        let mut header_only = b"PACK\x00\x00\x00\x02\x00\x00\x00\x00".to_vec();
+        header_only.extend([0; 20]);
+        let result = repo.unpack(Bytes::from(header_only)).await;
+        assert!(matches!(result, Err(GitError::EmptyPack)));
+    }
+
+    #[tokio::test]
+    async fn test_unpack_invalid_pack() {
+        // the mock context has no database, so saving anything would fail
+        let blob = Blob::from_content("hello");
+        let mut pack = encode_pack(vec![blob.into()]).to_vec();
+        // the header declares one more object than the pack has
+        pack[8..12].copy_from_slice(&2u32.to_be_bytes());
+        let result = mock_repo().unpack(Bytes::from(pack)).await;
+        assert!(matches!(
+            result,
+            Err(GitError::PackObjectCountMismatch(2, 1))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reject_oversized_blob() {
+        let blob = Blob::from_content(&"x".repeat(2048));
+        let model = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            blob.id,
+            "model.bin".to_owned(),
+        )])
+        .unwrap();
+        let root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Tree,
+            model.id,
+            "assets".to_owned(),
+        )])
+        .unwrap();
+        let commit = Commit::from_tree_id(root.id, vec![], "add a large file");
+        let pack = encode_pack(vec![commit.into(), root.into(), model.into(), blob.into()]);
+
+        let repo = MonoRepo {
+            policy: PushPolicy {
+                max_blob_size: Some(1024),
+                ..Default::default()
+            },
+            ..mock_repo()
+        };
+        match repo.unpack(pack).await {
+            Err(GitError::BlobTooLarge(path, size, limit)) => {
+                assert_eq!(path, "assets/model.bin");
+                assert_eq!((size, limit), (2048, 1024));
+            }
+            other => panic!("push wasn't rejected: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reject_oversized_tree() {
+        let blob = Blob::from_content("generated");
+        let items = (0..101)
+            .map(|i| TreeItem::new(TreeItemMode::Blob, blob.id, format!("file{}.rs", i)))
+            .collect();
+        let root = Tree::from_tree_items(items).unwrap();
+        let commit = Commit::from_tree_id(root.id, vec![], "generate files");
+        let root_id = root.id;
+        let pack = encode_pack(vec![commit.into(), root.into(), blob.into()]);
+
+        let repo = MonoRepo {
+            policy: PushPolicy {
+                max_tree_entries: Some(100),
+                ..Default::default()
+            },
+            ..mock_repo()
+        };
+        match repo.unpack(pack).await {
+            Err(GitError::TreeTooLarge(hash, limit)) => {
+                assert_eq!(hash, root_id.to_plain_str());
+                assert_eq!(limit, 100);
+            }
+            other => panic!("push wasn't rejected: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reject_disallowed_email() {
+        let blob = Blob::from_content("fn main() {}");
+        let root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            blob.id,
+            "main.rs".to_owned(),
+        )])
+        .unwrap();
+        // committed as admin@mega.org
+        let commit = Commit::from_tree_id(root.id, vec![], "init");
+        let id = commit.id.to_plain_str();
+        let pack = encode_pack(vec![commit.into(), root.into(), blob.into()]);
+
+        let repo = MonoRepo {
+            policy: PushPolicy {
+                allowed_email_domains: Some(vec!["mega.dev".to_owned(), "example.com".to_owned()]),
+                ..Default::default()
+            },
+            ..mock_repo()
+        };
+        match repo.unpack(pack).await {
+            Err(GitError::EmailNotAllowed(commit, email)) => {
+                assert_eq!(commit, id);
+                assert_eq!(email, "admin@mega.org");
+            }
+            other => panic!("push wasn't rejected: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reject_empty_commit() {
+        let blob = Blob::from_content("fn main() {}");
+        let root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            blob.id,
+            "main.rs".to_owned(),
+        )])
+        .unwrap();
+        let init = dev_commit(root.id, "init");
+        let empty = Commit::new(
+            init.author.clone(),
+            init.committer.clone(),
+            root.id,
+            vec![init.id],
+            "nothing",
+        );
+        let id = empty.id.to_plain_str();
+        let pack = encode_pack(vec![init.into(), empty.into(), root.into(), blob.into()]);
+
+        let repo = MonoRepo {
+            policy: PushPolicy {
+                reject_empty_commits: true,
+                ..Default::default()
+            },
+            ..mock_repo()
+        };
+        match repo.unpack(pack).await {
+            Err(GitError::EmptyCommit(commit)) => assert_eq!(commit, id),
+            other => panic!("push wasn't rejected: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reject_case_collision() {
+        let upper = Blob::from_content("# Mega");
+        let lower = Blob::from_content("# mega");
+        let root = Tree::from_tree_items(vec![
+            TreeItem::new(TreeItemMode::Blob, upper.id, "README".to_owned()),
+            TreeItem::new(TreeItemMode::Blob, lower.id, "readme".to_owned()),
+        ])
+        .unwrap();
+        let commit = dev_commit(root.id, "add readme");
+        let pack = encode_pack(vec![
+            commit.into(),
+            root.clone().into(),
+            upper.into(),
+            lower.into(),
+        ]);
+
+        let repo = MonoRepo {
+            policy: PushPolicy {
+                reject_case_collisions: true,
+                ..Default::default()
+            },
+            ..mock_repo()
+        };
+        match repo.unpack(pack).await {
+            Err(GitError::CaseCollision(tree, names)) => {
+                assert_eq!(tree, root.id.to_plain_str());
+                assert_eq!(names, vec!["README", "readme"]);
+            }
+            other => panic!("push wasn't rejected: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reject_commit_in_future() {
+        let blob = Blob::from_content("# mega");
+        let root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            blob.id,
+            "README.md".to_owned(),
+        )])
+        .unwrap();
+        // 3000-01-01
+        let signature = |kind: &str| {
+            let data = format!("{} dev <dev@example.com> 32503680000 +0800", kind);
+            Signature::from_data(data.into_bytes()).unwrap()
+        };
+        let commit = Commit::new(
+            signature("author"),
+            signature("committer"),
+            root.id,
+            vec![],
+            "from the future",
+        );
+        let id = commit.id.to_plain_str();
+        let pack = encode_pack(vec![commit.into(), root.into(), blob.into()]);
+
+        let repo = MonoRepo {
+            policy: PushPolicy {
+                max_commit_time_skew: Some(86400),
+                ..Default::default()
+            },
+            ..mock_repo()
+        };
+        match repo.unpack(pack).await {
+            Err(GitError::CommitInFuture(commit, timestamp)) => {
+                assert_eq!(commit, id);
+                assert_eq!(timestamp, 32503680000);
+            }
+            other => panic!("push wasn't rejected: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reject_tree_mode_for_blob() {
+        let blob = Blob::from_content("not a directory");
+        let root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Tree,
+            blob.id,
+            "src".to_owned(),
+        )])
+        .unwrap();
+        let commit = dev_commit(root.id, "crafted tree");
+        let pack = encode_pack(vec![commit.into(), root.into(), blob.into()]);
+
+        match mock_repo().unpack(pack).await {
+            Err(GitError::TreeItemTypeMismatch(name, mode, actual)) => {
+                assert_eq!(name, "src");
+                assert_eq!(mode, "40000");
+                assert_eq!(actual, "blob");
+            }
+            other => panic!("push wasn't rejected: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retried_push_creates_one_mr() {
+        let blob = Blob::from_content("fn main() {}");
+        let root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            blob.id,
+            "main.rs".to_owned(),
+        )])
+        .unwrap();
+        let commit = dev_commit(root.id, "init");
+        let entries: Vec<Entry> = vec![commit.into(), root.into(), blob.into()];
+
+        let repo = mock_repo();
+        let mr = mega_mr::Model::from(MergeRequest {
+            id: 1,
+            path: "/project/mega".to_owned(),
+            from_hash: repo.from_hash.clone().unwrap(),
+            to_hash: repo.to_hash.clone().unwrap(),
+            idempotency_key: repo.push_key(),
+            ..Default::default()
+        });
+        // the first push finds no MR for its key and no open or closed MR, saves the objects and
+        // creates the MR, the retry finds the MR by its key
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([Vec::<mega_mr::Model>::new(), vec![], vec![]])
+            .append_query_results([vec![mr.clone()], vec![mr]])
+            .into_connection();
+        let repo = repo_with_storage(MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        });
+
+        repo.unpack(encode_pack(entries.clone())).await.unwrap();
+        repo.unpack(encode_pack(entries)).await.unwrap();
+
+        let storage = repo.context.services.mega_storage.clone();
+        drop(repo);
+        let log = transaction_log(storage);
+        let count = |table: &str| {
+            let insert = format!("INSERT INTO \\\"{}\\\"", table);
+            log.iter().filter(|x| x.contains(&insert)).count()
+        };
+        assert_eq!(count("mega_mr"), 1);
+        assert_eq!(count("mega_commit"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_push_records_agent() {
+        let blob = Blob::from_content("fn main() {}");
+        let root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            blob.id,
+            "main.rs".to_owned(),
+        )])
+        .unwrap();
+        let commit = dev_commit(root.id, "init");
+        let entries: Vec<Entry> = vec![commit.into(), root.into(), blob.into()];
+
+        let inserted = MockExecResult {
+            last_insert_id: 0,
+            rows_affected: 1,
+        };
+        // no MR for the key and no open or closed MR, so the push saves the commit, the tree,
+        // the blob and its raw data, then creates the MR
+        let created = MergeRequest {
+            path: "/project/mega".to_owned(),
+            agent: Some("git/2.43".to_owned()),
+            ..Default::default()
+        };
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([Vec::<mega_mr::Model>::new(), vec![], vec![]])
+            .append_exec_results([
+                inserted.clone(),
+                inserted.clone(),
+                inserted.clone(),
+                inserted,
+            ])
+            .append_query_results([vec![mega_mr::Model::from(created)]])
+            .into_connection();
+        let repo = MonoRepo {
+            agent: Some("git/2.43".to_owned()),
+            ..repo_with_storage(MegaStorage {
+                connection: Arc::new(db),
+                ..MegaStorage::mock()
+            })
+        };
+        repo.unpack(encode_pack(entries)).await.unwrap();
+
+        let storage = repo.context.services.mega_storage.clone();
+        drop(repo);
+        let log = transaction_log(storage);
+        let insert = log
+            .iter()
+            .find(|x| x.contains("INSERT INTO \\\"mega_mr\\\""))
+            .unwrap();
+        assert!(insert.contains("git/2.43"));
+    }
+
+    #[tokio::test]
+    async fn test_push_continuing_closed_mr_reopens_it() {
+        let blob = Blob::from_content("fn main() {}");
+        let root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            blob.id,
+            "main.rs".to_owned(),
+        )])
+        .unwrap();
+        let commit = dev_commit(root.id, "init");
+        let entries: Vec<Entry> = vec![commit.into(), root.into(), blob.into()];
+
+        let repo = mock_repo();
+        // closed after a push from the same base to another commit
+        let mut closed = MergeRequest {
+            id: 1,
+            path: "/project/mega".to_owned(),
+            from_hash: repo.from_hash.clone().unwrap(),
+            to_hash: "2".repeat(40),
+            ..Default::default()
+        };
+        closed.close().unwrap();
+        let conv = mega_mr_conv::Model {
+            id: 2,
+            mr_id: 1,
+            user_id: 0,
+            conv_type: ConvType::Comment,
+            created_at: closed.updated_at,
+            updated_at: closed.updated_at,
+        };
+        let comment = mega_mr_comment::Model {
+            id: 3,
+            conv_id: 2,
+            comment: None,
+            edited: false,
+            parent_comment_id: None,
+            kind: CommentKind::System,
+        };
+        let mut reopened = closed.clone();
+        reopened.reopen().unwrap();
+        reopened.to_hash = repo.to_hash.clone().unwrap();
+        // no MR for the key and no open MR, then the closed one, after storing the objects a
+        // comment for the reopening and one for the new commit, and the updated MR
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([Vec::<mega_mr::Model>::new(), vec![]])
+            .append_query_results([vec![mega_mr::Model::from(closed)]])
+            .append_query_results([vec![conv.clone()]])
+            .append_query_results([vec![comment.clone()]])
+            .append_query_results([vec![conv]])
+            .append_query_results([vec![comment]])
+            .append_query_results([vec![mega_mr::Model::from(reopened)]])
+            .into_connection();
+        let repo = repo_with_storage(MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        });
+
+        repo.unpack(encode_pack(entries)).await.unwrap();
+
+        let storage = repo.context.services.mega_storage.clone();
+        drop(repo);
+        let log = transaction_log(storage);
+        assert!(!log
+            .iter()
+            .any(|x| x.contains("INSERT INTO \\\"mega_mr\\\"")));
+        let update = log
+            .iter()
+            .find(|x| x.contains("UPDATE \\\"mega_mr\\\""))
+            .unwrap();
+        assert!(update.contains("\"open\""));
+        assert!(update.contains(&"1".repeat(40)));
+        // the reopening is only recorded once the pushed commit is stored
+        let position = |text: &str| log.iter().position(|x| x.contains(text)).unwrap();
+        assert!(
+            position("INSERT INTO \\\"mega_commit\\\"")
+                < position("Mega reopened MR due to new push")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_identical_push_reopens_closed_mr() {
+        let blob = Blob::from_content("fn main() {}");
+        let root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            blob.id,
+            "main.rs".to_owned(),
+        )])
+        .unwrap();
+        let commit = dev_commit(root.id, "init");
+        let entries: Vec<Entry> = vec![commit.into(), root.into(), blob.into()];
+
+        let repo = mock_repo();
+        // created by the same push, then closed
+        let mut closed = MergeRequest {
+            id: 1,
+            path: "/project/mega".to_owned(),
+            from_hash: repo.from_hash.clone().unwrap(),
+            to_hash: repo.to_hash.clone().unwrap(),
+            idempotency_key: repo.push_key(),
+            ..Default::default()
+        };
+        closed.close().unwrap();
+        let conv = mega_mr_conv::Model {
+            id: 2,
+            mr_id: 1,
+            user_id: 0,
+            conv_type: ConvType::Comment,
+            created_at: closed.updated_at,
+            updated_at: closed.updated_at,
+        };
+        let comment = mega_mr_comment::Model {
+            id: 3,
+            conv_id: 2,
+            comment: None,
+            edited: false,
+            parent_comment_id: None,
+            kind: CommentKind::System,
+        };
+        let mut reopened = closed.clone();
+        reopened.reopen().unwrap();
+        // the closed MR for the key, no open MR, then the closed one again, a comment for the
+        // reopening and the updated MR, the objects are still stored
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![mega_mr::Model::from(closed.clone())]])
+            .append_query_results([Vec::<mega_mr::Model>::new()])
+            .append_query_results([vec![mega_mr::Model::from(closed)]])
+            .append_query_results([vec![conv]])
+            .append_query_results([vec![comment]])
+            .append_query_results([vec![mega_mr::Model::from(reopened)]])
+            .into_connection();
+        let repo = repo_with_storage(MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        });
+
+        repo.unpack(encode_pack(entries)).await.unwrap();
+
+        let storage = repo.context.services.mega_storage.clone();
+        drop(repo);
+        let log = transaction_log(storage);
+        let update = log
+            .iter()
+            .find(|x| x.contains("UPDATE \\\"mega_mr\\\""))
+            .unwrap();
+        assert!(update.contains("\"open\""));
+        assert!(!log
+            .iter()
+            .any(|x| x.contains("INSERT INTO \\\"mega_commit\\\"")));
+    }
+
+    #[tokio::test]
+    async fn test_store_blob_stream() {
+        let dir = env::temp_dir().join("mega_store_blob_stream");
+        let _ = fs::remove_dir_all(&dir);
+        let content = "fn main() {}\n".repeat(10000);
+        let blob = Blob::from_content(&content);
+        let cold = raw_blob::Model {
+            storage_type: StorageType::LocalFs,
+            data: None,
+            local_path: Some(dir.to_string_lossy().into_owned()),
+            ..raw_blob::Model::from(blob.clone())
+        };
+        let inserted = MockExecResult {
+            last_insert_id: 0,
+            rows_affected: 1,
+        };
+        // both stores record the blob, the second one conflicts and inserts nothing, then the
+        // read finds the cold row
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results([
+                inserted,
+                MockExecResult {
+                    last_insert_id: 0,
+                    rows_affected: 0,
+                },
+            ])
+            .append_query_results([vec![cold]])
+            .into_connection();
+        let repo = repo_with_storage(MegaStorage {
+            connection: Arc::new(db),
+            raw_storage: Arc::new(LocalStorage::init(dir.clone())),
+            ..MegaStorage::mock()
+        });
+
+        let first = repo.store_blob_stream(content.as_bytes()).await.unwrap();
+        let second = repo.store_blob_stream(content.as_bytes()).await.unwrap();
+        assert_eq!(first, blob.id.to_plain_str());
+        assert_eq!(first, second);
+
+        let storage = repo.context.services.mega_storage.clone();
+        let read = storage.get_blob(&blob.id).await.unwrap().unwrap();
+        assert_eq!(read.data, content.as_bytes());
+
+        // one object and no temporary files left behind
+        let mut files = Vec::new();
+        let mut dirs = vec![dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    files.push(path);
+                }
+            }
+        }
+        assert_eq!(files.len(), 1);
+        assert!(files[0].ends_with(&first[4..]));
+        assert_eq!(fs::read(&files[0]).unwrap(), content.as_bytes());
+        fs::remove_dir_all(&dir).unwrap();
+
+        drop(repo);
+        let log = transaction_log(storage);
+        let insert = &log[0];
+        assert!(insert.contains("INSERT INTO \\\"raw_blob\\\""));
+        assert!(insert.contains("local_fs"));
+        assert!(insert.contains(&files[0].to_string_lossy().into_owned()));
+    }
+
+    #[tokio::test]
+    async fn test_cold_blob_read_back() {
+        let dir = env::temp_dir().join("mega_cold_blob");
+        let _ = fs::remove_dir_all(&dir);
+        let blob = Blob::from_content("a blob nobody reads\n");
+        let hash = blob.id.to_plain_str();
+        let hot = raw_blob::Model::from(blob.clone());
+        let cold = raw_blob::Model {
+            storage_type: StorageType::LocalFs,
+            data: None,
+            local_path: Some(dir.to_string_lossy().into_owned()),
+            ..hot.clone()
+        };
+        // set_tier finds the blob and updates its row, the read then finds it cold; moving it
+        // back finds and updates it again
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([
+                vec![hot.clone()],
+                vec![cold.clone()],
+                vec![cold.clone()],
+                vec![cold],
+                vec![hot],
+            ])
+            .into_connection();
+        let repo = repo_with_storage(MegaStorage {
+            connection: Arc::new(db),
+            raw_storage: Arc::new(LocalStorage::init(dir.clone())),
+            ..MegaStorage::mock()
+        });
+
+        repo.set_tier(&hash, StorageTier::Cold).await.unwrap();
+        let storage = repo.context.services.mega_storage.clone();
+        let read: Vec<Blob> = storage
+            .get_raw_blobs_by_hashes(vec![hash.clone()])
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|x| x.into())
+            .collect();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].id, blob.id);
+        assert_eq!(read[0].data, blob.data);
+        assert!(storage.raw_storage.exist_object("", &hash));
+
+        // the cold copy is dropped once the row holds the content again
+        repo.set_tier(&hash, StorageTier::Hot).await.unwrap();
+        assert!(!storage.raw_storage.exist_object("", &hash));
+        fs::remove_dir_all(&dir).unwrap();
+
+        drop(repo);
+        let log = transaction_log(storage);
+        let update = &log[1];
+        assert!(update.contains("UPDATE \\\"raw_blob\\\""));
+        assert!(update.contains("local_fs"));
+    }
+
+    #[tokio::test]
+    async fn test_cold_tier_needs_blob_data() {
+        let dir = env::temp_dir().join("mega_cold_tier_data");
+        let _ = fs::remove_dir_all(&dir);
+        let blob = Blob::from_content("a blob nobody reads\n");
+        let hollow = raw_blob::Model {
+            data: None,
+            ..raw_blob::Model::from(blob.clone())
+        };
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![hollow]])
+            .into_connection();
+        let repo = repo_with_storage(MegaStorage {
+            connection: Arc::new(db),
+            raw_storage: Arc::new(LocalStorage::init(dir.clone())),
+            ..MegaStorage::mock()
+        });
+
+        let hash = blob.id.to_plain_str();
+        assert!(repo.set_tier(&hash, StorageTier::Cold).await.is_err());
+        let storage = repo.context.services.mega_storage.clone();
+        assert!(!storage.raw_storage.exist_object("", &hash));
+        let _ = fs::remove_dir_all(&dir);
+
+        // the row is left as it was
+        drop(repo);
+        let log = transaction_log(storage);
+        assert_eq!(log.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_head_hash_is_read_only() {
+        // the directory has no ref yet, only ensure_ref would create one
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([Vec::<mega_refs::Model>::new()])
+            .into_connection();
+        let repo = repo_with_storage(MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        });
+
+        let (head_hash, refs) = repo.head_hash().await;
+        assert_eq!(head_hash, ZERO_ID);
+        assert!(refs.is_empty());
+
+        let storage = repo.context.services.mega_storage.clone();
+        drop(repo);
+        let log = transaction_log(storage);
+        assert_eq!(log.len(), 1);
+        assert!(log[0].contains("SELECT"));
+    }
+
+    #[tokio::test]
+    async fn test_mr_pack() {
+        let readme = Blob::from_content("readme");
+        let base_tree = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            readme.id,
+            "README.md".to_owned(),
+        )])
+        .unwrap();
+        let base = Commit::from_tree_id(base_tree.id, vec![], "base");
+        let main = Blob::from_content("fn main() {}");
+        let mr_tree = Tree::from_tree_items(vec![
+            TreeItem::new(TreeItemMode::Blob, readme.id, "README.md".to_owned()),
+            TreeItem::new(TreeItemMode::Blob, main.id, "main.rs".to_owned()),
+        ])
+        .unwrap();
+        let mr_commit = Commit::from_tree_id(mr_tree.id, vec![base.id], "add main");
+
+        let now = chrono::Utc::now().naive_utc();
+        let mr = mega_mr::Model {
+            id: 1,
+            mr_link: "mr".to_owned(),
+            merge_date: None,
+            status: MergeStatus::Open,
+            path: "/".to_owned(),
+            from_hash: base.id.to_plain_str(),
+            to_hash: mr_commit.id.to_plain_str(),
+            idempotency_key: "mr".to_owned(),
+            build_id: None,
+            agent: None,
+            created_at: now,
+            updated_at: now,
+        };
+        // the queries of incremental_pack, the object cache saves lookups of empty hash lists
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![mr]])
+            .append_query_results([vec![mega_commit::Model::from(mr_commit.clone())]])
+            .append_query_results([vec![mega_tree::Model::from(mr_tree.clone())]])
+            .append_query_results([vec![mega_commit::Model::from(base.clone())]])
+            .append_query_results([vec![mega_tree::Model::from(base_tree.clone())]])
+            .append_query_results([vec![raw_blob::Model::from(main.clone())]])
+            .into_connection();
+        let storage = MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        }
+        .with_object_cache(NonZeroUsize::new(16).unwrap());
+        let repo = repo_with_storage(storage);
+
+        let pack = repo.mr_pack(1).await.unwrap();
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let result = entries.clone();
+        Pack::new(
+            None,
+            Some(1024 * 20),
+            Some(PathBuf::from("/tmp/.cache_temp")),
+            true,
+        )
+        .decode(&mut Cursor::new(pack), move |entry| {
+            result.lock().unwrap().push(entry.hash)
+        })
+        .unwrap();
+        let hashes: HashSet<SHA1> = entries.lock().unwrap().iter().copied().collect();
+        assert_eq!(hashes, HashSet::from([mr_commit.id, mr_tree.id, main.id]));
+    }
+
+    #[tokio::test]
+    async fn test_incremental_pack_want_ref() {
+        let readme = Blob::from_content("readme");
+        let base_tree = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            readme.id,
+            "README.md".to_owned(),
+        )])
+        .unwrap();
+        let base = Commit::from_tree_id(base_tree.id, vec![], "base");
+        let main = Blob::from_content("fn main() {}");
+        let head_tree = Tree::from_tree_items(vec![
+            TreeItem::new(TreeItemMode::Blob, readme.id, "README.md".to_owned()),
+            TreeItem::new(TreeItemMode::Blob, main.id, "main.rs".to_owned()),
+        ])
+        .unwrap();
+        let head = Commit::from_tree_id(head_tree.id, vec![base.id], "add main");
+
+        let now = chrono::Utc::now().naive_utc();
+        let head_ref = mega_refs::Model {
+            id: 1,
+            path: "/project/mega".to_owned(),
+            ref_commit_hash: head.id.to_plain_str(),
+            ref_tree_hash: head_tree.id.to_plain_str(),
+            created_at: now,
+            updated_at: now,
+        };
+        // the ref the branch resolves to, then the queries of incremental_pack
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![head_ref]])
+            .append_query_results([vec![mega_commit::Model::from(head.clone())]])
+            .append_query_results([vec![mega_tree::Model::from(head_tree.clone())]])
+            .append_query_results([vec![mega_commit::Model::from(base.clone())]])
+            .append_query_results([vec![mega_tree::Model::from(base_tree.clone())]])
+            .append_query_results([vec![raw_blob::Model::from(main.clone())]])
+            .into_connection();
+        let storage = MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        }
+        .with_object_cache(NonZeroUsize::new(16).unwrap());
+        let repo = repo_with_storage(storage);
+
+        let pack = repo
+            .incremental_pack(
+                vec!["want-ref refs/heads/main".to_owned()],
+                vec![base.id.to_plain_str()],
+                None,
+            )
+            .await
+            .unwrap();
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let result = entries.clone();
+        Pack::new(
+            None,
+            Some(1024 * 20),
+            Some(PathBuf::from("/tmp/.cache_temp")),
+            true,
+        )
+        .decode(&mut Cursor::new(pack), move |entry| {
+            result.lock().unwrap().push(entry.hash)
+        })
+        .unwrap();
+        let hashes: HashSet<SHA1> = entries.lock().unwrap().iter().copied().collect();
+        assert_eq!(hashes, HashSet::from([head.id, head_tree.id, main.id]));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_unknown_want_ref() {
+        let now = chrono::Utc::now().naive_utc();
+        let head_ref = mega_refs::Model {
+            id: 1,
+            path: "/project/mega".to_owned(),
+            ref_commit_hash: "1".repeat(40),
+            ref_tree_hash: "2".repeat(40),
+            created_at: now,
+            updated_at: now,
+        };
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![head_ref]])
+            .into_connection();
+        let repo = repo_with_storage(MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        });
+
+        // hashes are passed through, only the names are looked up
+        assert_eq!(
+            repo.resolve_wants(vec!["3".repeat(40)]).await.unwrap(),
+            vec!["3".repeat(40)]
+        );
+        assert!(matches!(
+            repo.resolve_wants(vec!["want-ref refs/heads/gone".to_owned()]).await,
+            Err(GitError::RefNotFound(name)) if name == "refs/heads/gone"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_pack_since() {
+        let commit = |tree: SHA1, parents: Vec<SHA1>, time: i64, message: &str| {
+            let signature = |kind: &str| {
+                let data = format!("{} dev <dev@example.com> {} +0800", kind, time);
+                Signature::from_data(data.into_bytes()).unwrap()
+            };
+            Commit::new(
+                signature("author"),
+                signature("committer"),
+                tree,
+                parents,
+                message,
+            )
+        };
+        let readme = Blob::from_content("readme");
+        let main = Blob::from_content("fn main() {}");
+        let lib = Blob::from_content("pub fn run() {}");
+        let items = |blobs: &[(&Blob, &str)]| {
+            let items = blobs
+                .iter()
+                .map(|(blob, name)| TreeItem::new(TreeItemMode::Blob, blob.id, name.to_string()))
+                .collect();
+            Tree::from_tree_items(items).unwrap()
+        };
+        let old_tree = items(&[(&readme, "README.md")]);
+        let old = commit(old_tree.id, vec![], 1000, "old");
+        let main_tree = items(&[(&readme, "README.md"), (&main, "main.rs")]);
+        let add_main = commit(main_tree.id, vec![old.id], 2000, "add main");
+        let lib_tree = items(&[(&lib, "lib.rs"), (&readme, "README.md"), (&main, "main.rs")]);
+        let add_lib = commit(lib_tree.id, vec![add_main.id], 3000, "add lib");
+
+        // the commits since 2000, then the queries of incremental_pack with the old commit as
+        // the one the mirror has
+        let recent = vec![
+            mega_commit::Model::from(add_main.clone()),
+            mega_commit::Model::from(add_lib.clone()),
+        ];
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([recent.clone(), recent])
+            .append_query_results([vec![
+                mega_tree::Model::from(main_tree.clone()),
+                mega_tree::Model::from(lib_tree.clone()),
+            ]])
+            .append_query_results([vec![mega_commit::Model::from(old.clone())]])
+            .append_query_results([vec![mega_tree::Model::from(old_tree.clone())]])
+            .append_query_results([
+                vec![raw_blob::Model::from(main.clone())],
+                vec![raw_blob::Model::from(lib.clone())],
+            ])
+            .into_connection();
+        let storage = MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        }
+        .with_object_cache(NonZeroUsize::new(16).unwrap());
+        let repo = repo_with_storage(storage);
+
+        let pack = repo.pack_since(2000).await.unwrap();
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let result = entries.clone();
+        Pack::new(
+            None,
+            Some(1024 * 20),
+            Some(PathBuf::from("/tmp/.cache_temp")),
+            true,
+        )
+        .decode(&mut Cursor::new(pack), move |entry| {
+            result.lock().unwrap().push(entry.hash)
+        })
+        .unwrap();
+        let hashes: HashSet<SHA1> = entries.lock().unwrap().iter().copied().collect();
+        assert_eq!(
+            hashes,
+            HashSet::from([
+                add_main.id,
+                main_tree.id,
+                main.id,
+                add_lib.id,
+                lib_tree.id,
+                lib.id
+            ])
+        );
+
+        let storage = repo.context.services.mega_storage.clone();
+        drop(repo);
+        let log = transaction_log(storage);
+        assert!(log[0].contains("\\\"committer_time\\\" >= "));
+    }
+
+    #[tokio::test]
+    async fn test_mr_commits() {
+        let tree = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            Blob::from_content("readme").id,
+            "README.md".to_owned(),
+        )])
+        .unwrap();
+        let commit = |parents: Vec<SHA1>, message: &str, timestamp: usize| {
+            let data = format!("committer dev <dev@example.com> {} +0800", timestamp);
+            let committer = Signature::from_data(data.into_bytes()).unwrap();
+            Commit::from_identity(committer, tree.id, parents, message)
+        };
+        let older = commit(vec![], "older", 1000);
+        let root = commit(vec![older.id], "root", 2000);
+        let base = commit(vec![root.id], "base", 3000);
+        let first = commit(vec![base.id], "first", 4000);
+        let second = commit(vec![first.id], "second", 5000);
+
+        let now = chrono::Utc::now().naive_utc();
+        let mr = mega_mr::Model {
+            id: 1,
+            mr_link: "mr".to_owned(),
+            merge_date: None,
+            status: MergeStatus::Open,
+            path: "/".to_owned(),
+            from_hash: base.id.to_plain_str(),
+            to_hash: second.id.to_plain_str(),
+            idempotency_key: "mr".to_owned(),
+            build_id: None,
+            agent: None,
+            created_at: now,
+            updated_at: now,
+        };
+        // the MR, then both sides a generation at a time, the history older than the commits of
+        // the MR is never loaded
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![mr]])
+            .append_query_results([vec![
+                mega_commit::Model::from(second.clone()),
+                mega_commit::Model::from(base.clone()),
+            ]])
+            .append_query_results([vec![
+                mega_commit::Model::from(first.clone()),
+                mega_commit::Model::from(root.clone()),
+            ]])
+            .into_connection();
+        let repo = repo_with_storage(MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        });
+
+        let commits = repo.mr_commits(1).await.unwrap();
+        assert_eq!(commits.len(), 2);
+        assert_eq!(commits[0].id, first.id);
+        assert_eq!(commits[1].id, second.id);
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_mr_refs() {
+        let main = Blob::from_content("fn main() {}");
+        let mega = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            main.id,
+            "main.rs".to_owned(),
+        )])
+        .unwrap();
+        let project = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Tree,
+            mega.id,
+            "mega".to_owned(),
+        )])
+        .unwrap();
+        let root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Tree,
+            project.id,
+            "project".to_owned(),
+        )])
+        .unwrap();
+        let mr_commit = Commit::from_tree_id(mega.id, vec![], "add main");
+        // merging builds a new commit for the root holding the tree of the MR
+        let merged = Commit::from_tree_id(root.id, vec![], "add main");
+
+        let now = chrono::Utc::now().naive_utc();
+        let mr = |status: MergeStatus| mega_mr::Model {
+            id: 1,
+            mr_link: "mr".to_owned(),
+            merge_date: None,
+            status,
+            path: "/project/mega".to_owned(),
+            from_hash: ZERO_ID.to_owned(),
+            to_hash: mr_commit.id.to_plain_str(),
+            idempotency_key: "mr".to_owned(),
+            build_id: None,
+            agent: None,
+            created_at: now,
+            updated_at: now,
+        };
+        let refs = |id: i64, path: &str, hash: SHA1| mega_refs::Model {
+            id,
+            path: path.to_owned(),
+            ref_commit_hash: hash.to_plain_str(),
+            ref_tree_hash: root.id.to_plain_str(),
+            created_at: now,
+            updated_at: now,
+        };
+        let working_ref = MergeRequest {
+            id: 1,
+            ..Default::default()
+        }
+        .working_ref();
+        let root_trees = || {
+            [
+                vec![mega_tree::Model::from(root.clone())],
+                vec![mega_tree::Model::from(project.clone())],
+                vec![mega_tree::Model::from(mega.clone())],
+            ]
+        };
+        let removed = MockExecResult {
+            last_insert_id: 0,
+            rows_affected: 1,
+        };
+        // the MR, no open MR of its directory, the refs, the working ref and the path ref of the
+        // directory are deleted but not the ones below it; the root ref is walked afterwards
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![mr(MergeStatus::Merged)]])
+            .append_query_results([Vec::<mega_mr::Model>::new()])
+            .append_query_results([vec![
+                refs(1, "/", merged.id),
+                refs(2, &working_ref, mr_commit.id),
+                refs(3, "refs/mr/12/head", mr_commit.id),
+                refs(4, "/project/mega", mr_commit.id),
+                refs(5, "/project/mega/src", mr_commit.id),
+                refs(6, "/project/megaphone", mr_commit.id),
+            ]])
+            .append_exec_results([removed.clone(), removed.clone()])
+            .append_query_results([vec![mega_commit::Model::from(merged.clone())]])
+            .append_query_results(root_trees())
+            .into_connection();
+        let repo = repo_with_storage(MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        });
+
+        assert_eq!(repo.cleanup_mr_refs(1).await.unwrap(), 2);
+        let reachable = repo
+            .reachable_closure(&[merged.id.to_plain_str()])
+            .await
+            .unwrap();
+        assert!(reachable.contains(&mega.id.to_plain_str()));
+        assert!(reachable.contains(&main.id.to_plain_str()));
+
+        let storage = repo.context.services.mega_storage.clone();
+        drop(repo);
+        let log = transaction_log(storage);
+        let deletes: Vec<&String> = log
+            .iter()
+            .filter(|x| x.contains("DELETE FROM \\\"mega_refs\\\""))
+            .collect();
+        assert_eq!(deletes.len(), 2);
+        for (delete, id) in deletes.iter().zip([2, 4]) {
+            assert!(delete.contains(&format!("BigInt(Some({}))", id)));
+        }
+
+        // a closed MR whose commit isn't stored can't be reopened, the refs are kept
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![mr(MergeStatus::Closed)]])
+            .append_query_results([Vec::<mega_mr::Model>::new()])
+            .append_query_results([vec![
+                refs(1, "/", merged.id),
+                refs(2, &working_ref, mr_commit.id),
+            ]])
+            .append_query_results([Vec::<mega_commit::Model>::new()])
+            .into_connection();
+        let repo = repo_with_storage(MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        });
+        assert!(matches!(
+            repo.cleanup_mr_refs(1).await,
+            Err(GitError::UnretainedMrObjects(1))
+        ));
+
+        // an open MR still needs its refs
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![mr(MergeStatus::Open)]])
+            .into_connection();
+        let repo = repo_with_storage(MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        });
+        assert!(matches!(
+            repo.cleanup_mr_refs(1).await,
+            Err(GitError::InvalidMrTransition(..))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fsck_reports_broken_link() {
+        let main = Blob::from_content("fn main() {}");
+        let stale = Blob::from_content("left behind");
+        let lost = Blob::from_content("never stored");
+        let root = Tree::from_tree_items(vec![
+            TreeItem::new(TreeItemMode::Blob, lost.id, "lib.rs".to_owned()),
+            TreeItem::new(TreeItemMode::Blob, main.id, "main.rs".to_owned()),
+        ])
+        .unwrap();
+        let commit = dev_commit(root.id, "init");
+        let now = chrono::Utc::now().naive_utc();
+        let raw = |blob: &Blob| raw_blob::Model {
+            id: 0,
+            sha1: blob.id.to_plain_str(),
+            content: None,
+            file_type: None,
+            storage_type: StorageType::Database,
+            data: Some(blob.data.clone()),
+            local_path: None,
+            remote_url: None,
+            created_at: now,
+        };
+        let root_ref = mega_refs::Model {
+            id: 1,
+            path: "/".to_owned(),
+            ref_commit_hash: commit.id.to_plain_str(),
+            ref_tree_hash: root.id.to_plain_str(),
+            created_at: now,
+            updated_at: now,
+        };
+        let rows = |column: &'static str, hashes: &[SHA1]| {
+            hashes
+                .iter()
+                .map(|x| BTreeMap::from([(column, Value::from(x.to_plain_str()))]))
+                .collect::<Vec<_>>()
+        };
+        let hashes = [
+            rows("commit_id", &[commit.id]),
+            rows("tree_id", &[root.id]),
+            rows("blob_id", &[main.id, stale.id]),
+            vec![],
+        ];
+        // the hashes of each type, then each type again with its objects, then the refs
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results(hashes.clone())
+            .append_query_results([hashes[0].clone()])
+            .append_query_results([vec![mega_commit::Model::from(commit.clone())]])
+            .append_query_results([hashes[1].clone()])
+            .append_query_results([vec![mega_tree::Model::from(root.clone())]])
+            .append_query_results([hashes[2].clone()])
+            .append_query_results([vec![raw(&main), raw(&stale)]])
+            .append_query_results([hashes[3].clone()])
+            .append_query_results([vec![root_ref]])
+            .into_connection();
+        let repo = repo_with_storage(MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        });
+
+        let report = repo.fsck().await.unwrap();
+        assert_eq!(report.objects, 4);
+        assert!(!report.is_ok());
+        assert_eq!(
+            report.problems,
+            [FsckProblem::BrokenLink {
+                from: root.id.to_plain_str(),
+                name: "lib.rs".to_owned(),
+                to: lost.id.to_plain_str(),
+            }]
+        );
+        assert_eq!(report.dangling, [stale.id.to_plain_str()]);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_short_hash() {
+        let commit = dev_commit(SHA1::new(&b"tree".to_vec()), "init").id;
+        let commit = commit.to_plain_str();
+        let tree = format!("{}{}", &commit[..7], "0".repeat(33));
+        // the hashes matching in the commits, trees, blobs and tags
+        let repo = |found: [Vec<&str>; 4]| {
+            let results = found.map(|hashes| {
+                hashes
+                    .into_iter()
+                    .map(|x| BTreeMap::from([("hash", Value::from(x))]))
+                    .collect::<Vec<_>>()
+            });
+            let db = MockDatabase::new(DatabaseBackend::Postgres)
+                .append_query_results(results)
+                .into_connection();
+            repo_with_storage(MegaStorage {
+                connection: Arc::new(db),
+                ..MegaStorage::mock()
+            })
+        };
+
+        let unique = repo([vec![commit.as_str()], vec![], vec![], vec![]]);
+        let resolved = unique.resolve_short_hash(&commit[..7].to_uppercase()).await;
+        assert_eq!(resolved.unwrap(), Some(commit.clone()));
+
+        let ambiguous = repo([vec![commit.as_str()], vec![tree.as_str()], vec![], vec![]]);
+        assert!(matches!(
+            ambiguous.resolve_short_hash(&commit[..7]).await,
+            Err(GitError::AmbiguousHash(prefix)) if prefix == commit[..7]
+        ));
+
+        let missing = repo([vec![], vec![], vec![], vec![]]);
+        assert_eq!(missing.resolve_short_hash("abcd").await.unwrap(), None);
+
+        assert!(missing.resolve_short_hash("abc").await.is_err());
+        assert!(missing.resolve_short_hash("xyz0").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_object_types() {
+        let hash = |x: &str| SHA1::new(&x.as_bytes().to_vec()).to_plain_str();
+        let (commit, tree, blob, tag) = (hash("commit"), hash("tree"), hash("blob"), hash("tag"));
+        let missing = hash("missing");
+        // the hashes found in the commits, trees, blobs and tags
+        let results = [
+            vec![commit.as_str()],
+            vec![tree.as_str()],
+            vec![blob.as_str()],
+            vec![tag.as_str()],
+        ]
+        .map(|hashes| {
+            hashes
+                .into_iter()
+                .map(|x| BTreeMap::from([("hash", Value::from(x))]))
+                .collect::<Vec<_>>()
+        });
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results(results)
+            .into_connection();
+        let repo = repo_with_storage(MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        });
+
+        let hashes = vec![
+            tag.clone(),
+            blob.clone(),
+            missing,
+            tree.clone(),
+            commit.clone(),
+            blob.clone(),
+        ];
+        let types = repo.object_types(hashes).await.unwrap();
+        assert_eq!(
+            types,
+            HashMap::from([
+                (commit, ObjectType::Commit),
+                (tree, ObjectType::Tree),
+                (blob, ObjectType::Blob),
+                (tag, ObjectType::Tag),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_init_root() {
+        let identity =
+            Signature::from_data(b"author admin <admin@mega.org> 1700000000 +0800".to_vec())
+                .unwrap();
+        let inserted = MockExecResult {
+            last_insert_id: 0,
+            rows_affected: 1,
+        };
+        let now = chrono::Utc::now().naive_utc();
+        let root_ref = mega_refs::Model {
+            id: 1,
+            path: "/project/mega".to_owned(),
+            ref_commit_hash: String::new(),
+            ref_tree_hash: String::new(),
+            created_at: now,
+            updated_at: now,
+        };
+        // no ref yet, then the tree, the commit, the ref and its reflog entry; the second
+        // attempt finds the ref
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([Vec::<mega_refs::Model>::new()])
+            .append_exec_results([inserted.clone(), inserted.clone()])
+            .append_query_results([vec![root_ref.clone()]])
+            .append_exec_results([inserted])
+            .append_query_results([vec![root_ref]])
+            .into_connection();
+        let repo = repo_with_storage(MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        });
+
+        let commit_id = repo.init_root(identity.clone(), "\ninit\n").await.unwrap();
+        let result = repo.init_root(identity, "\ninit\n").await;
+        assert!(matches!(result, Err(GitError::RefAlreadyExists(_))));
+
+        let storage = repo.context.services.mega_storage.clone();
+        drop(repo);
+        let log: Vec<String> = transaction_log(storage);
+        let empty_tree = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+        assert!(log
+            .iter()
+            .any(|x| x.contains("INSERT INTO \\\"mega_tree\\\"") && x.contains(empty_tree)));
+        let saved = log
+            .iter()
+            .find(|x| x.contains("INSERT INTO \\\"mega_commit\\\""))
+            .unwrap();
+        assert!(saved.contains(&commit_id));
+        let root_ref = log
+            .iter()
+            .find(|x| x.contains("INSERT INTO \\\"mega_refs\\\""))
+            .unwrap();
+        assert!(root_ref.contains("/project/mega") && root_ref.contains(&commit_id));
+
+        // the saved commit is the one of the identity, it parses back with both signatures
+        let commit = Commit::new(
+            Signature::from_data(b"author admin <admin@mega.org> 1700000000 +0800".to_vec())
+                .unwrap(),
+            Signature::from_data(b"committer admin <admin@mega.org> 1700000000 +0800".to_vec())
+                .unwrap(),
+            SHA1::from_str(empty_tree).unwrap(),
+            vec![],
+            "\ninit\n",
+        );
+        assert_eq!(commit.id.to_plain_str(), commit_id);
+        let parsed = Commit::from_bytes(commit.to_data().unwrap(), commit.id).unwrap();
+        assert!(parsed.parent_commit_ids.is_empty());
+        assert_eq!(parsed.author.name, "admin");
+        assert_eq!(parsed.committer.email, "admin@mega.org");
+        assert_eq!(parsed.committer.signature_type, SignatureType::Committer);
+    }
+
+    #[tokio::test]
+    async fn test_create_tag() {
+        let tree = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            Blob::from_content("fn main() {}").id,
+            "main.rs".to_owned(),
+        )])
+        .unwrap();
+        let commit = dev_commit(tree.id, "release");
+        let tagger =
+            Signature::from_data(b"tagger dev <dev@example.com> 1700000000 +0800".to_vec())
+                .unwrap();
+        let now = chrono::Utc::now().naive_utc();
+        let tag_ref = |hash: &str| mega_refs::Model {
+            id: 1,
+            path: "refs/tags/v1.0.0".to_owned(),
+            ref_commit_hash: hash.to_owned(),
+            ref_tree_hash: tree.id.to_plain_str(),
+            created_at: now,
+            updated_at: now,
+        };
+        let inserted = MockExecResult {
+            last_insert_id: 0,
+            rows_affected: 1,
+        };
+        // the target commit and no ref yet, then the tag object, the ref and its reflog entry;
+        // the second attempt finds the ref
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![mega_commit::Model::from(commit.clone())]])
+            .append_query_results([Vec::<mega_refs::Model>::new()])
+            .append_exec_results([inserted.clone()])
+            .append_query_results([vec![tag_ref("tag")]])
+            .append_exec_results([inserted])
+            .append_query_results([vec![mega_commit::Model::from(commit.clone())]])
+            .append_query_results([vec![tag_ref("tag")]])
+            .into_connection();
+        let repo = repo_with_storage(MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        });
+
+        let target = commit.id.to_plain_str();
+        let tag_id = repo
+            .create_tag(&target, "v1.0.0", tagger.clone(), "release 1.0.0\n", false)
+            .await
+            .unwrap();
+        let result = repo
+            .create_tag(&target, "v1.0.0", tagger, "again\n", false)
+            .await;
+        assert!(matches!(result, Err(GitError::TagAlreadyExists(_))));
+
+        let storage = repo.context.services.mega_storage.clone();
+        drop(repo);
+        let log: Vec<String> = transaction_log(storage);
+        let tag = log
+            .iter()
+            .find(|x| x.contains("INSERT INTO \\\"mega_tag\\\""))
+            .unwrap();
+        assert!(tag.contains(&tag_id) && tag.contains(&target));
+        let tag_ref = log
+            .iter()
+            .find(|x| x.contains("INSERT INTO \\\"mega_refs\\\""))
+            .unwrap();
+        assert!(tag_ref.contains("refs/tags/v1.0.0") && tag_ref.contains(&tag_id));
+        assert_eq!(
+            log.iter()
+                .filter(|x| x.contains("INSERT INTO \\\"mega_tag\\\""))
+                .count(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tags_for_commit() {
+        let commit = dev_commit(SHA1::new(&b"tree".to_vec()), "release");
+        let tagger =
+            Signature::from_data(b"tagger dev <dev@example.com> 1700000000 +0800".to_vec())
+                .unwrap();
+        let tag = Tag::new(commit.id, ObjectType::Commit, "v1.0.0", tagger, "release\n");
+        let now = chrono::Utc::now().naive_utc();
+        let tag_ref = |name: &str, hash: SHA1| mega_refs::Model {
+            id: 1,
+            path: format!("refs/tags/{}", name),
+            ref_commit_hash: hash.to_plain_str(),
+            ref_tree_hash: String::new(),
+            created_at: now,
+            updated_at: now,
+        };
+        // the tags of the commit, the tags of those tags, then the refs of the commit and tags
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![mega_tag::Model::from(tag.clone())]])
+            .append_query_results([Vec::<mega_tag::Model>::new()])
+            .append_query_results([vec![
+                tag_ref("latest", commit.id),
+                tag_ref("v1.0.0", tag.id),
+            ]])
+            .into_connection();
+        let repo = repo_with_storage(MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        });
+
+        let names = repo
+            .tags_for_commit(&commit.id.to_plain_str())
+            .await
+            .unwrap();
+        assert_eq!(names, vec!["latest", "v1.0.0"]);
+
+        let storage = repo.context.services.mega_storage.clone();
+        drop(repo);
+        let log: Vec<String> = transaction_log(storage);
+        assert_eq!(log.len(), 3);
+        assert!(log[0].contains(&commit.id.to_plain_str()));
+        assert!(log[1].contains(&tag.id.to_plain_str()));
+    }
+
+    #[tokio::test]
+    async fn test_list_refs_detailed() {
+        let tree = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            Blob::from_content("fn main() {}").id,
+            "main.rs".to_owned(),
+        )])
+        .unwrap();
+        let root = dev_commit(tree.id, "initial import\n\nof the monorepo");
+        let mega = Commit::from_tree_id(tree.id, vec![], "add mega");
+        let tagger =
+            Signature::from_data(b"tagger dev <dev@example.com> 1700000000 +0800".to_vec())
+                .unwrap();
+        let tag = Tag::new(root.id, ObjectType::Commit, "v1.0.0", tagger, "release\n");
+        let now = chrono::Utc::now().naive_utc();
+        let refs = |path: &str, hash: SHA1| mega_refs::Model {
+            id: 1,
+            path: path.to_owned(),
+            ref_commit_hash: hash.to_plain_str(),
+            ref_tree_hash: tree.id.to_plain_str(),
+            created_at: now,
+            updated_at: now,
+        };
+        // the refs, the tag of the tag ref and the commits of all refs but the internal MR ref
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![
+                refs("/", root.id),
+                refs("/project/mega", mega.id),
+                refs("refs/mr/12/head", mega.id),
+                refs("refs/tags/v1.0.0", tag.id),
+            ]])
+            .append_query_results([vec![mega_tag::Model::from(tag)]])
+            .append_query_results([vec![
+                mega_commit::Model::from(root.clone()),
+                mega_commit::Model::from(mega.clone()),
+            ]])
+            .into_connection();
+        let repo = repo_with_storage(MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        });
+
+        let refs = repo.list_refs_detailed().await.unwrap();
+        let names: Vec<&str> = refs.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, ["/", "/project/mega", "refs/tags/v1.0.0"]);
+        let default: Vec<bool> = refs.iter().map(|x| x.is_default).collect();
+        assert_eq!(default, [false, true, false]);
+
+        assert_eq!(refs[1].commit_hash, mega.id.to_plain_str());
+        assert_eq!(refs[1].subject, "add mega");
+        assert_eq!(refs[1].author, mega.author.name);
+        assert_eq!(refs[1].timestamp, mega.committer.timestamp);
+        assert_eq!(refs[0].subject, "initial import");
+        assert_eq!(refs[0].author, "dev");
+        assert_eq!(refs[0].timestamp, 1700000000);
+        assert_eq!(refs[2].commit_hash, root.id.to_plain_str());
+        assert_eq!(refs[2].subject, "initial import");
+    }
+
+    #[tokio::test]
+    async fn test_deterministic_full_pack() {
+        let main = Blob::from_content("fn main() {}");
+        let lib = Blob::from_content("pub fn run() {}");
+        let root = Tree::from_tree_items(vec![
+            TreeItem::new(TreeItemMode::Blob, lib.id, "lib.rs".to_owned()),
+            TreeItem::new(TreeItemMode::Blob, main.id, "main.rs".to_owned()),
+        ])
+        .unwrap();
+        let commit = dev_commit(root.id, "init");
+        let now = chrono::Utc::now().naive_utc();
+        let refs = mega_refs::Model {
+            id: 1,
+            path: "/project/mega".to_owned(),
+            ref_commit_hash: commit.id.to_plain_str(),
+            ref_tree_hash: root.id.to_plain_str(),
+            created_at: now,
+            updated_at: now,
+        };
+        // the second pack finds the commit and the tree in the object cache, and the storage
+        // returns the blobs in another order
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![refs.clone()]])
+            .append_query_results([vec![mega_commit::Model::from(commit)]])
+            .append_query_results([vec![mega_tree::Model::from(root)]])
+            .append_query_results([vec![
+                raw_blob::Model::from(main.clone()),
+                raw_blob::Model::from(lib.clone()),
+            ]])
+            .append_query_results([vec![refs]])
+            .append_query_results([vec![
+                raw_blob::Model::from(lib),
+                raw_blob::Model::from(main),
+            ]])
+            .into_connection();
+        let storage = MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        }
+        .with_object_cache(NonZeroUsize::new(16).unwrap());
+        let repo = MonoRepo {
+            deterministic: true,
+            ..repo_with_storage(storage)
+        };
+
+        let first = repo.full_pack(vec![], None).await.unwrap();
+        let second = repo.full_pack(vec![], None).await.unwrap();
+        assert_eq!(first, second);
+        // the commit, the tree and both blobs
+        assert_eq!(first[8..12], 4u32.to_be_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_full_pack_wanted_commit() {
+        let readme = Blob::from_content("readme");
+        let base_tree = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            readme.id,
+            "README.md".to_owned(),
+        )])
+        .unwrap();
+        let base = dev_commit(base_tree.id, "base");
+        // the client asked for the older commit rather than the head of the directory, the ref
+        // isn't read at all
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![mega_commit::Model::from(base.clone())]])
+            .append_query_results([vec![mega_tree::Model::from(base_tree.clone())]])
+            .append_query_results([vec![raw_blob::Model::from(readme.clone())]])
+            .into_connection();
+        let storage = MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        }
+        .with_object_cache(NonZeroUsize::new(16).unwrap());
+        let repo = repo_with_storage(storage);
+
+        let pack = repo
+            .full_pack(vec![base.id.to_plain_str()], None)
+            .await
+            .unwrap();
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let result = entries.clone();
+        Pack::new(
+            None,
+            Some(1024 * 20),
+            Some(PathBuf::from("/tmp/.cache_temp")),
+            true,
+        )
+        .decode(&mut Cursor::new(pack), move |entry| {
+            result.lock().unwrap().push(entry.hash)
+        })
+        .unwrap();
+        let hashes: HashSet<SHA1> = entries.lock().unwrap().iter().copied().collect();
+        assert_eq!(hashes, HashSet::from([base.id, base_tree.id, readme.id]));
+    }
+
+    #[tokio::test]
+    async fn test_pack_spans() {
+        let blob = Blob::from_content("fn main() {}");
+        let root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            blob.id,
+            "main.rs".to_owned(),
+        )])
+        .unwrap();
+        let commit = dev_commit(root.id, "init");
+        let entries: Vec<Entry> = vec![
+            commit.clone().into(),
+            root.clone().into(),
+            blob.clone().into(),
+        ];
+
+        let repo = mock_repo();
+        let mr = mega_mr::Model::from(MergeRequest {
+            id: 1,
+            path: "/project/mega".to_owned(),
+            from_hash: repo.from_hash.clone().unwrap(),
+            to_hash: repo.to_hash.clone().unwrap(),
+            idempotency_key: repo.push_key(),
+            ..Default::default()
+        });
+        let now = chrono::Utc::now().naive_utc();
+        let refs = mega_refs::Model {
+            id: 1,
+            path: "/project/mega".to_owned(),
+            ref_commit_hash: commit.id.to_plain_str(),
+            ref_tree_hash: root.id.to_plain_str(),
+            created_at: now,
+            updated_at: now,
+        };
+        // the push finds no MR and creates one, then the full pack loads the ref, its commit, its
+        // tree and the blob of the tree
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([Vec::<mega_mr::Model>::new(), vec![], vec![]])
+            .append_query_results([vec![mr]])
+            .append_query_results([vec![refs]])
+            .append_query_results([vec![mega_commit::Model::from(commit)]])
+            .append_query_results([vec![mega_tree::Model::from(root)]])
+            .append_query_results([vec![raw_blob::Model::from(blob)]])
+            .into_connection();
+        let storage = MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        }
+        .with_object_cache(NonZeroUsize::new(16).unwrap());
+        let repo = repo_with_storage(storage);
+
+        let spans = SpanFields::default();
+        let _guard = tracing::subscriber::set_default(Registry::default().with(spans.clone()));
+        repo.unpack(encode_pack(entries)).await.unwrap();
+        repo.full_pack(vec![], None).await.unwrap();
+
+        assert_eq!(spans.get("unpack", "objects"), Some(3));
+        assert_eq!(spans.get("save_entry", "objects"), Some(3));
+        assert_eq!(spans.get("save_entry", "commits"), Some(1));
+        assert_eq!(spans.get("full_pack", "objects"), Some(3));
+        for (span, phase) in [
+            ("unpack", "decode_ms"),
+            ("unpack", "check_ms"),
+            ("unpack", "store_ms"),
+            ("save_entry", "store_ms"),
+            ("full_pack", "count_ms"),
+            ("full_pack", "encode_ms"),
+        ] {
+            assert!(
+                spans.get(span, phase).is_some(),
+                "{} has no {}",
+                span,
+                phase
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_group_writeable_mode_through_mr() {
+        let main = Blob::from_content("fn main() {}");
+        let notes = Blob::from_content("notes");
+        // trees as a client writes them, the 100664 mode isn't what git would normalize it to
+        let tree = |items: &[(&str, &str, SHA1)]| {
+            let mut data = Vec::new();
+            for (mode, name, id) in items {
+                data.extend_from_slice(format!("{} {}\0", mode, name).as_bytes());
+                data.extend_from_slice(&id.0);
+            }
+            let id = SHA1::from_type_and_data(ObjectType::Tree, &data);
+            Tree::from_bytes(data, id).unwrap()
+        };
+        let mega = tree(&[("100644", "main.rs", main.id)]);
+        let project = tree(&[("40000", "mega", mega.id), ("100664", "notes", notes.id)]);
+        let root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Tree,
+            project.id,
+            "project".to_owned(),
+        )])
+        .unwrap();
+        let base = dev_commit(root.id, "base");
+        // the push adds a group-writeable file to the MR's directory
+        let new_mega = tree(&[
+            ("100644", "main.rs", main.id),
+            ("100664", "notes", notes.id),
+        ]);
+        let head = dev_commit(new_mega.id, "add notes");
+        let new_project = tree(&[
+            ("40000", "mega", new_mega.id),
+            ("100664", "notes", notes.id),
+        ]);
+        let new_root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Tree,
+            new_project.id,
+            "project".to_owned(),
+        )])
+        .unwrap();
+        let merged = Commit::new(
+            head.author.clone(),
+            head.committer.clone(),
+            new_root.id,
+            vec![base.id],
+            &head.message,
+        );
+        let entries: Vec<Entry> = vec![
+            head.clone().into(),
+            new_mega.clone().into(),
+            main.clone().into(),
+            notes.clone().into(),
+        ];
+
+        let now = chrono::Utc::now().naive_utc();
+        let refs = |path: &str, commit: &Commit| mega_refs::Model {
+            id: 1,
+            path: path.to_owned(),
+            ref_commit_hash: commit.id.to_plain_str(),
+            ref_tree_hash: commit.tree_id.to_plain_str(),
+            created_at: now,
+            updated_at: now,
+        };
+        let repo = MonoRepo {
+            from_hash: Some(base.id.to_plain_str()),
+            to_hash: Some(head.id.to_plain_str()),
+            ..mock_repo()
+        };
+        let mr = mega_mr::Model::from(MergeRequest {
+            id: 1,
+            path: "/project/mega".to_owned(),
+            from_hash: base.id.to_plain_str(),
+            to_hash: head.id.to_plain_str(),
+            idempotency_key: repo.push_key(),
+            ..Default::default()
+        });
+        let conv = mega_mr_conv::Model {
+            id: 2,
+            mr_id: 1,
+            user_id: 0,
+            conv_type: ConvType::Merged,
+            created_at: now,
+            updated_at: now,
+        };
+        let done = MockExecResult {
+            last_insert_id: 0,
+            rows_affected: 1,
+        };
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            // the push finds no MR and creates one
+            .append_query_results([Vec::<mega_mr::Model>::new(), vec![], vec![]])
+            .append_query_results([vec![mr.clone()]])
+            // the merge loads the MR, the refs and the trees on the way to the MR's directory
+            .append_query_results([vec![mr.clone()]])
+            .append_query_results([vec![refs("/project/mega", &base)]])
+            .append_query_results([vec![mega_commit::Model::from(head.clone())]])
+            .append_query_results([vec![refs("/", &base)]])
+            .append_query_results([vec![mega_tree::Model::from(root)]])
+            .append_query_results([vec![mega_tree::Model::from(project)]])
+            .append_query_results([Vec::<mega_refs::Model>::new()])
+            .append_query_results([vec![refs("/", &base)]])
+            // the objects of the push, then the merge commit, its trees, the ref and its reflog
+            // entry and the removed refs of the directory
+            .append_exec_results(vec![done; 9])
+            .append_query_results([vec![mr]])
+            .append_query_results([vec![conv]])
+            // the full pack of the root after the merge
+            .append_query_results([vec![refs("/", &merged)]])
+            .append_query_results([vec![mega_commit::Model::from(merged.clone())]])
+            .append_query_results([vec![mega_tree::Model::from(new_root.clone())]])
+            .append_query_results([vec![mega_tree::Model::from(new_project.clone())]])
+            .append_query_results([vec![mega_tree::Model::from(new_mega.clone())]])
+            .append_query_results([Vec::<raw_blob::Model>::new()])
+            .append_query_results([vec![raw_blob::Model::from(notes)]])
+            .append_query_results([vec![raw_blob::Model::from(main)]])
+            .into_connection();
+        let storage = MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        }
+        .with_object_cache(NonZeroUsize::new(16).unwrap());
+        let repo = MonoRepo {
+            context: repo_with_storage(storage).context,
+            ..repo
+        };
+
+        repo.unpack(encode_pack(entries)).await.unwrap();
+        let service = MonorepoService {
+            storage: repo.context.services.mega_storage.clone(),
+            build_queue: None,
+        };
+        let result = service
+            .merge_mr(MergeOperation {
+                message: None,
+                mr_id: 1,
+            })
+            .await
+            .unwrap();
+        assert!(result.result, "{}", result.err_message);
+        let root_repo = MonoRepo {
+            path: PathBuf::from("/"),
+            context: repo.context.clone(),
+            ..mock_repo()
+        };
+        let pack = root_repo.full_pack(vec![], None).await.unwrap();
+
+        let objects = Arc::new(Mutex::new(HashMap::new()));
+        let result = objects.clone();
+        Pack::new(
+            None,
+            Some(1024 * 20),
+            Some(PathBuf::from("/tmp/.cache_temp")),
+            true,
+        )
+        .decode(&mut Cursor::new(pack), move |entry| {
+            result.lock().unwrap().insert(entry.hash, entry.data);
+        })
+        .unwrap();
+        let objects = objects.lock().unwrap();
+        assert_eq!(objects.len(), 6);
+        // the trees are sent with the modes they were pushed with, so their ids stay the same
+        for tree in [&new_mega, &new_project, &new_root] {
+            assert_eq!(objects[&tree.id], tree.to_data().unwrap());
+        }
+        assert!(objects[&merged.id]
+            .windows(40)
+            .any(|x| x == new_root.id.to_plain_str().as_bytes()));
+
+        drop(service);
+        drop(root_repo);
+        let storage = repo.context.services.mega_storage.clone();
+        drop(repo);
+        let log: Vec<String> = transaction_log(storage);
+        // the pushed tree was stored, the root ref moved to the merge commit of the same trees
+        assert!(log
+            .iter()
+            .any(|x| x.contains("INSERT INTO \\\"mega_tree\\\"")
+                && x.contains(&new_mega.id.to_plain_str())));
+        assert!(log.iter().any(|x| x.contains("UPDATE \\\"mega_refs\\\"")
+            && x.contains(&merged.id.to_plain_str())
+            && x.contains(&new_root.id.to_plain_str())));
     }
 }
@@ -0,0 +1,520 @@
+//! Checks a push has to pass before any of its objects are saved, configured by environment
+//! variables. Each check is disabled unless its variable is set.
+//!
+//! The objects of a push are checked one at a time with a [`PushCheck`] as they are decoded, so
+//! only their hashes and tree item names are held, never their content.
+use std::{collections::HashMap, env};
+
+use jupiter::storage::object_storage::ObjectStorage;
+use venus::{
+    errors::GitError,
+    hash::SHA1,
+    internal::{
+        object::{commit::Commit, tree::Tree, types::ObjectType, ObjectTrait},
+        pack::entry::Entry,
+    },
+};
+
+use crate::lfs::pointer;
+use crate::monorepo::connectivity::TreeItemCheck;
+
+/// The checks enabled for pushes, see [`PushPolicy::from_env`].
+#[derive(Clone, Debug, Default)]
+pub struct PushPolicy {
+    /// The size limit of a single blob in bytes.
+    pub max_blob_size: Option<usize>,
+    /// The most entries a tree may have.
+    pub max_tree_entries: Option<usize>,
+    /// The email domains commit authors and committers must belong to.
+    pub allowed_email_domains: Option<Vec<String>>,
+    /// A trailer every commit message must have.
+    pub required_trailer: Option<String>,
+    /// Whether trees with names differing only in case are rejected.
+    pub reject_case_collisions: bool,
+    /// How many seconds a committer timestamp may be ahead of the server time.
+    pub max_commit_time_skew: Option<i64>,
+    /// Whether commits with the tree of their single parent are rejected.
+    pub reject_empty_commits: bool,
+}
+
+impl PushPolicy {
+    /// The checks enabled by the environment variables.
+    pub fn from_env() -> Self {
+        PushPolicy {
+            max_blob_size: max_blob_size(),
+            max_tree_entries: max_tree_entries(),
+            allowed_email_domains: allowed_email_domains(),
+            required_trailer: required_trailer(),
+            reject_case_collisions: reject_case_collisions(),
+            max_commit_time_skew: max_commit_time_skew(),
+            reject_empty_commits: reject_empty_commits(),
+        }
+    }
+}
+
+/// The state of checking the objects of a push against a [`PushPolicy`]. Each object is passed
+/// to [`PushCheck::add`] once, the checks needing the whole push run in [`PushCheck::finish`].
+pub struct PushCheck {
+    policy: PushPolicy,
+    now: i64,
+    /// The tree containing each object and the object's name in it, to name oversized blobs.
+    parents: HashMap<SHA1, (SHA1, String)>,
+    /// The first blob above the size limit and its size.
+    oversized: Option<(SHA1, usize)>,
+    /// The tree and parents of each commit, to find empty commits.
+    commits: HashMap<SHA1, (SHA1, Vec<SHA1>)>,
+    items: TreeItemCheck,
+}
+
+impl PushCheck {
+    pub fn new(policy: PushPolicy) -> Self {
+        PushCheck {
+            policy,
+            now: chrono::Utc::now().timestamp(),
+            parents: HashMap::new(),
+            oversized: None,
+            commits: HashMap::new(),
+            items: TreeItemCheck::default(),
+        }
+    }
+
+    /// Check a single object of the push.
+    pub fn add(&mut self, entry: &Entry) -> Result<(), GitError> {
+        self.items.add_object(entry.hash, entry.obj_type);
+        match entry.obj_type {
+            ObjectType::Commit => self.add_commit(entry),
+            ObjectType::Tree => self.add_tree(entry),
+            ObjectType::Blob => {
+                if let Some(limit) = self.policy.max_blob_size {
+                    if self.oversized.is_none() && is_oversized(entry, limit) {
+                        self.oversized = Some((entry.hash, entry.data.len()));
+                    }
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn add_commit(&mut self, entry: &Entry) -> Result<(), GitError> {
+        let commit = Commit::from_bytes(entry.data.clone(), entry.hash)?;
+        if let Some(domains) = &self.policy.allowed_email_domains {
+            check_commit_emails(&commit, domains)?;
+        }
+        if let Some(trailer) = &self.policy.required_trailer {
+            check_trailers(&commit, trailer)?;
+        }
+        if let Some(skew) = self.policy.max_commit_time_skew {
+            check_commit_times(&commit, self.now, skew)?;
+        }
+        if self.policy.reject_empty_commits {
+            self.commits
+                .insert(commit.id, (commit.tree_id, commit.parent_commit_ids));
+        }
+        Ok(())
+    }
+
+    fn add_tree(&mut self, entry: &Entry) -> Result<(), GitError> {
+        // before anything else parses the tree
+        let tree = match self.policy.max_tree_entries {
+            Some(limit) => {
+                Tree::from_bytes_with_limit(entry.data.clone(), entry.hash, Some(limit))?
+            }
+            None => Tree::from_bytes(entry.data.clone(), entry.hash)?,
+        };
+        if self.policy.reject_case_collisions {
+            check_case_collisions(&tree)?;
+        }
+        self.items.add_tree(&tree);
+        if self.policy.max_blob_size.is_some() {
+            for item in tree.tree_items {
+                self.parents.insert(item.id, (tree.id, item.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the checks needing the whole push, objects outside of it have to be in `storage`.
+    pub async fn finish(self, storage: &dyn ObjectStorage) -> Result<(), GitError> {
+        if let (Some((blob, size)), Some(limit)) = (self.oversized, self.policy.max_blob_size) {
+            let path = blob_path(&self.parents, blob);
+            return Err(GitError::BlobTooLarge(path, size, limit));
+        }
+        if self.policy.reject_empty_commits {
+            check_empty_commits(storage, &self.commits).await?;
+        }
+        self.items.finish(storage).await
+    }
+}
+
+/// The size limit of a single blob in a push, in KB, from `MEGA_MAX_BLOB_SIZE`. Unset or 0 means
+/// no limit.
+fn max_blob_size() -> Option<usize> {
+    env::var("MEGA_MAX_BLOB_SIZE")
+        .ok()
+        .and_then(|x| x.parse::<usize>().ok())
+        .filter(|&x| x > 0)
+        .map(|x| x * 1024)
+}
+
+/// Whether `entry` is a blob larger than `limit` bytes, large files belong in LFS.
+fn is_oversized(entry: &Entry, limit: usize) -> bool {
+    entry.obj_type == ObjectType::Blob
+        && entry.data.len() > limit
+        && pointer::LfsPointer::parse(&entry.data).is_none()
+}
+
+/// The path of `blob` in the trees of the push, by the tree and name of each object, or its hash
+/// if no tree contains it.
+fn blob_path(parents: &HashMap<SHA1, (SHA1, String)>, blob: SHA1) -> String {
+    let mut names = Vec::new();
+    let mut id = blob;
+    while let Some((parent, name)) = parents.get(&id) {
+        names.push(name.as_str());
+        id = *parent;
+        if names.len() > parents.len() {
+            break;
+        }
+    }
+    if names.is_empty() {
+        blob.to_plain_str()
+    } else {
+        names.reverse();
+        names.join("/")
+    }
+}
+
+/// The most entries a tree may have, from `MEGA_MAX_TREE_ENTRIES`. Unset or 0 means no limit.
+fn max_tree_entries() -> Option<usize> {
+    env::var("MEGA_MAX_TREE_ENTRIES")
+        .ok()
+        .and_then(|x| x.trim().parse::<usize>().ok())
+        .filter(|&x| x > 0)
+}
+
+/// The email domains commit authors and committers must belong to, from the comma separated
+/// `MEGA_ALLOWED_EMAIL_DOMAINS`.
+fn allowed_email_domains() -> Option<Vec<String>> {
+    let domains: Vec<String> = env::var("MEGA_ALLOWED_EMAIL_DOMAINS")
+        .ok()?
+        .split(',')
+        .map(|x| x.trim().to_ascii_lowercase())
+        .filter(|x| !x.is_empty())
+        .collect();
+    (!domains.is_empty()).then_some(domains)
+}
+
+/// Reject commits whose author or committer email is outside of `domains`.
+pub fn check_commit_emails(commit: &Commit, domains: &[String]) -> Result<(), GitError> {
+    for email in [&commit.author.email, &commit.committer.email] {
+        let allowed = email
+            .rsplit_once('@')
+            .is_some_and(|(_, domain)| domains.contains(&domain.to_ascii_lowercase()));
+        if !allowed {
+            return Err(GitError::EmailNotAllowed(
+                commit.id.to_plain_str(),
+                email.to_owned(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The trailer every commit message of a push must have, from `MEGA_REQUIRED_TRAILER`. Unset or
+/// blank requires none.
+fn required_trailer() -> Option<String> {
+    env::var("MEGA_REQUIRED_TRAILER")
+        .ok()
+        .map(|x| x.trim().to_owned())
+        .filter(|x| !x.is_empty())
+}
+
+/// Reject commits whose message lacks the trailer `key`, e.g. `Signed-off-by`.
+pub fn check_trailers(commit: &Commit, key: &str) -> Result<(), GitError> {
+    let signed = commit
+        .trailers()
+        .iter()
+        .any(|(trailer, _)| trailer.eq_ignore_ascii_case(key));
+    if !signed {
+        return Err(GitError::MissingTrailer(
+            commit.id.to_plain_str(),
+            key.to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Whether pushes of trees with names differing only in case are rejected, from
+/// `MEGA_REJECT_CASE_COLLISIONS`.
+fn reject_case_collisions() -> bool {
+    env::var("MEGA_REJECT_CASE_COLLISIONS")
+        .ok()
+        .and_then(|x| x.trim().parse::<bool>().ok())
+        .unwrap_or(false)
+}
+
+/// Reject trees containing entries whose names only differ in case, like `README` and `readme`,
+/// which can't both be checked out on a case-insensitive filesystem.
+pub fn check_case_collisions(tree: &Tree) -> Result<(), GitError> {
+    let mut names: HashMap<String, Vec<String>> = HashMap::new();
+    for item in &tree.tree_items {
+        names
+            .entry(item.name.to_lowercase())
+            .or_default()
+            .push(item.name.clone());
+    }
+    match names.into_values().find(|x| x.len() > 1) {
+        Some(colliding) => Err(GitError::CaseCollision(tree.id.to_plain_str(), colliding)),
+        None => Ok(()),
+    }
+}
+
+/// How many seconds a committer timestamp may be ahead of the server time, from
+/// `MEGA_MAX_COMMIT_TIME_SKEW`. Unset means no limit.
+fn max_commit_time_skew() -> Option<i64> {
+    env::var("MEGA_MAX_COMMIT_TIME_SKEW")
+        .ok()
+        .and_then(|x| x.trim().parse::<i64>().ok())
+        .filter(|&x| x >= 0)
+}
+
+/// Reject commits whose committer timestamp is more than `skew` seconds after `now`, which
+/// would put them on top of every view sorted by time.
+pub fn check_commit_times(commit: &Commit, now: i64, skew: i64) -> Result<(), GitError> {
+    let timestamp = commit.committer.timestamp as i64;
+    if timestamp > now.saturating_add(skew) {
+        return Err(GitError::CommitInFuture(
+            commit.id.to_plain_str(),
+            timestamp,
+        ));
+    }
+    Ok(())
+}
+
+/// Whether pushes of empty commits are rejected, from `MEGA_REJECT_EMPTY_COMMITS`.
+fn reject_empty_commits() -> bool {
+    env::var("MEGA_REJECT_EMPTY_COMMITS")
+        .ok()
+        .and_then(|x| x.trim().parse::<bool>().ok())
+        .unwrap_or(false)
+}
+
+/// Reject pushes with a commit whose tree is the tree of its single parent, merge commits may
+/// keep the tree. `commits` holds the tree and parents of each commit of the push, parents
+/// outside of it have to be stored already.
+pub async fn check_empty_commits(
+    storage: &dyn ObjectStorage,
+    commits: &HashMap<SHA1, (SHA1, Vec<SHA1>)>,
+) -> Result<(), GitError> {
+    for (id, (tree, parents)) in commits {
+        let [parent] = parents[..] else {
+            continue;
+        };
+        let parent_tree = match commits.get(&parent) {
+            Some((parent_tree, _)) => *parent_tree,
+            None => match storage.get_commit(&parent).await? {
+                Some(parent) => parent.tree_id,
+                None => continue,
+            },
+        };
+        if parent_tree == *tree {
+            return Err(GitError::EmptyCommit(id.to_plain_str()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use jupiter::storage::object_storage::{MemoryStorage, ObjectStorage};
+    use venus::errors::GitError;
+    use venus::hash::SHA1;
+    use venus::internal::object::blob::Blob;
+    use venus::internal::object::commit::Commit;
+    use venus::internal::object::signature::Signature;
+    use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+    use venus::internal::pack::entry::Entry;
+
+    use super::{
+        check_case_collisions, check_commit_emails, check_commit_times, check_empty_commits,
+        check_trailers, PushCheck, PushPolicy,
+    };
+
+    /// Run all checks of `policy` against the objects of a push, like an unpack does while
+    /// decoding them.
+    async fn check_push(
+        storage: &dyn ObjectStorage,
+        policy: PushPolicy,
+        entries: &[Entry],
+    ) -> Result<(), GitError> {
+        let mut check = PushCheck::new(policy);
+        for entry in entries {
+            check.add(entry)?;
+        }
+        check.finish(storage).await
+    }
+
+    fn commit(author: &str, committer: &str) -> Commit {
+        commit_with_message(author, committer, "change")
+    }
+
+    fn commit_with_message(author: &str, committer: &str, message: &str) -> Commit {
+        let signature = |kind: &str, email: &str| {
+            Signature::from_data(format!("{} dev <{}> 1700000000 +0800", kind, email).into_bytes())
+                .unwrap()
+        };
+        Commit::new(
+            signature("author", author),
+            signature("committer", committer),
+            SHA1::default(),
+            vec![],
+            message,
+        )
+    }
+
+    #[test]
+    fn test_check_commit_emails() {
+        let domains = vec!["mega.org".to_owned()];
+        let allowed = commit("dev@mega.org", "ci@MEGA.org");
+        assert!(check_commit_emails(&allowed, &domains).is_ok());
+
+        let denied = commit("dev@mega.org", "dev@gmail.com");
+        let id = denied.id.to_plain_str();
+        match check_commit_emails(&denied, &domains) {
+            Err(GitError::EmailNotAllowed(commit, email)) => {
+                assert_eq!(commit, id);
+                assert_eq!(email, "dev@gmail.com");
+            }
+            other => panic!("commit wasn't rejected: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_check_trailers() {
+        let signed = commit_with_message(
+            "dev@mega.org",
+            "dev@mega.org",
+            "\nFix the parser\r\n\r\nsigned-off-by: dev <dev@mega.org>\r\n",
+        );
+        assert!(check_trailers(&signed, "Signed-off-by").is_ok());
+
+        let unsigned = commit_with_message("dev@mega.org", "dev@mega.org", "\nFix the parser\n");
+        let id = unsigned.id.to_plain_str();
+        match check_trailers(&unsigned, "Signed-off-by") {
+            Err(GitError::MissingTrailer(commit, trailer)) => {
+                assert_eq!(commit, id);
+                assert_eq!(trailer, "Signed-off-by");
+            }
+            other => panic!("commit wasn't rejected: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_blob_sizes() {
+        let blob = Blob::from_content(&"x".repeat(2048));
+        let model = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            blob.id,
+            "model.bin".to_owned(),
+        )])
+        .unwrap();
+        let root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Tree,
+            model.id,
+            "assets".to_owned(),
+        )])
+        .unwrap();
+        // the blob comes first, before the trees naming it
+        let entries = vec![blob.into(), model.into(), root.into()];
+        let storage = MemoryStorage::default();
+
+        let policy = PushPolicy {
+            max_blob_size: Some(4096),
+            ..Default::default()
+        };
+        check_push(&storage, policy, &entries).await.unwrap();
+
+        let policy = PushPolicy {
+            max_blob_size: Some(1024),
+            ..Default::default()
+        };
+        match check_push(&storage, policy, &entries).await {
+            Err(GitError::BlobTooLarge(path, size, limit)) => {
+                assert_eq!(path, "assets/model.bin");
+                assert_eq!((size, limit), (2048, 1024));
+            }
+            other => panic!("blob wasn't rejected: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_empty_commits() {
+        let tree = |x: &str| SHA1::new(&x.as_bytes().to_vec());
+        let hash = |x: &str| SHA1::new(&format!("commit {}", x).into_bytes());
+        let stored = Commit::from_tree_id(tree("base"), vec![], "\nbase\n");
+        let storage = MemoryStorage::default();
+        storage.save_commits(vec![stored.clone()]).await.unwrap();
+
+        // each commit changes the tree of its parent, in the push or stored; the merge and the
+        // commit whose parent is unknown are exempt
+        let commits = HashMap::from([
+            (hash("a"), (tree("a"), vec![stored.id])),
+            (hash("b"), (tree("b"), vec![hash("a")])),
+            (hash("merge"), (tree("b"), vec![hash("b"), stored.id])),
+            (hash("orphan"), (tree("c"), vec![hash("missing")])),
+        ]);
+        check_empty_commits(&storage, &commits).await.unwrap();
+
+        let on_pushed = HashMap::from([
+            (hash("a"), (tree("a"), vec![stored.id])),
+            (hash("b"), (tree("a"), vec![hash("a")])),
+        ]);
+        let result = check_empty_commits(&storage, &on_pushed).await;
+        assert!(matches!(result, Err(GitError::EmptyCommit(id)) if id == hash("b").to_plain_str()));
+
+        let on_stored = HashMap::from([(hash("a"), (tree("base"), vec![stored.id]))]);
+        let result = check_empty_commits(&storage, &on_stored).await;
+        assert!(matches!(result, Err(GitError::EmptyCommit(id)) if id == hash("a").to_plain_str()));
+    }
+
+    #[test]
+    fn test_check_case_collisions() {
+        let blob = Blob::from_content("# mega\n");
+        let item = |name: &str| TreeItem::new(TreeItemMode::Blob, blob.id, name.to_owned());
+        let distinct = Tree::from_tree_items(vec![item("README.md"), item("main.rs")]).unwrap();
+        assert!(check_case_collisions(&distinct).is_ok());
+
+        let colliding =
+            Tree::from_tree_items(vec![item("README.md"), item("main.rs"), item("readme.md")])
+                .unwrap();
+        let id = colliding.id.to_plain_str();
+        match check_case_collisions(&colliding) {
+            Err(GitError::CaseCollision(tree, mut names)) => {
+                assert_eq!(tree, id);
+                names.sort();
+                assert_eq!(names, ["README.md", "readme.md"]);
+            }
+            other => panic!("tree wasn't rejected: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_check_commit_times() {
+        // committed at 1700000000
+        let commit = commit("dev@mega.org", "dev@mega.org");
+        assert!(check_commit_times(&commit, 1_700_000_000, 0).is_ok());
+        assert!(check_commit_times(&commit, 1_699_999_000, 1000).is_ok());
+        assert!(check_commit_times(&commit, i64::MAX, i64::MAX).is_ok());
+
+        let id = commit.id.to_plain_str();
+        match check_commit_times(&commit, 1_699_999_000, 999) {
+            Err(GitError::CommitInFuture(commit, timestamp)) => {
+                assert_eq!(commit, id);
+                assert_eq!(timestamp, 1_700_000_000);
+            }
+            other => panic!("commit wasn't rejected: {:?}", other),
+        }
+    }
+}
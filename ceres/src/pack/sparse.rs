@@ -0,0 +1,151 @@
+//! Sparse checkout patterns, limiting the blobs sent in a pack to a part of the monorepo.
+//!
+//! Only blobs are filtered, every tree is still packed so the client keeps the full directory
+//! structure and can widen the checkout later by fetching the missing blobs.
+
+/// The patterns of a sparse checkout, in either of the two `git sparse-checkout` modes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SparsePatterns {
+    /// Cone mode: a list of directories. Files directly in the root, directly in any parent
+    /// directory of a listed directory, and anywhere below a listed directory are included.
+    Cone(Vec<String>),
+    /// Non-cone mode: gitignore style patterns, the last matching pattern wins and a leading `!`
+    /// excludes the matched paths again.
+    Glob(Vec<String>),
+}
+
+impl SparsePatterns {
+    /// Cone patterns from directory paths like `src/` or `/project/mega`.
+    pub fn cone<S: AsRef<str>>(dirs: &[S]) -> Self {
+        SparsePatterns::Cone(
+            dirs.iter()
+                .map(|x| x.as_ref().trim_matches('/').to_owned())
+                .filter(|x| !x.is_empty())
+                .collect(),
+        )
+    }
+
+    /// Glob patterns, one per line of `content` as in `.git/info/sparse-checkout`. Empty lines
+    /// and comments are skipped.
+    pub fn glob(content: &str) -> Self {
+        SparsePatterns::Glob(
+            content
+                .lines()
+                .map(|x| x.trim())
+                .filter(|x| !x.is_empty() && !x.starts_with('#'))
+                .map(|x| x.to_owned())
+                .collect(),
+        )
+    }
+
+    /// Whether the file at `path`, relative to the repository root, is part of the checkout.
+    pub fn includes(&self, path: &str) -> bool {
+        match self {
+            SparsePatterns::Cone(dirs) => {
+                let parent = path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+                parent.is_empty()
+                    || dirs
+                        .iter()
+                        .any(|dir| is_same_or_below(parent, dir) || is_same_or_below(dir, parent))
+            }
+            SparsePatterns::Glob(patterns) => {
+                let mut included = false;
+                for pattern in patterns {
+                    let (negated, pattern) = match pattern.strip_prefix('!') {
+                        Some(pattern) => (true, pattern),
+                        None => (false, pattern.as_str()),
+                    };
+                    if pattern_matches(pattern, path) {
+                        included = !negated;
+                    }
+                }
+                included
+            }
+        }
+    }
+}
+
+fn is_same_or_below(path: &str, dir: &str) -> bool {
+    path == dir || path.starts_with(dir) && path.as_bytes().get(dir.len()) == Some(&b'/')
+}
+
+/// Match a gitignore style `pattern` against a file path. A pattern with a slash other than a
+/// trailing one is anchored at the root, otherwise it may match at any depth. A trailing slash
+/// only matches directories, i.e. all files below them.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, dir_only) = match pattern.strip_suffix('/') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    let anchored = pattern.contains('/');
+    let pattern = pattern.trim_start_matches('/');
+
+    let components: Vec<&str> = path.split('/').collect();
+    // a file matches if the pattern matches the file or any directory above it
+    let candidates = if dir_only {
+        components.len() - 1
+    } else {
+        components.len()
+    };
+    (0..candidates).any(|end| {
+        if anchored {
+            let prefix = components[..=end].join("/");
+            glob_match(pattern.as_bytes(), prefix.as_bytes())
+        } else {
+            glob_match(pattern.as_bytes(), components[end].as_bytes())
+        }
+    })
+}
+
+/// Glob matching supporting `*` (any run of characters except `/`), `**` (any run of
+/// characters) and `?` (any single character except `/`).
+pub(crate) fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (None, Some(_)) => false,
+        (Some(b'*'), _) => {
+            let double = pattern.get(1) == Some(&b'*');
+            let rest = if double { &pattern[2..] } else { &pattern[1..] };
+            if glob_match(rest, text) {
+                return true;
+            }
+            match text.first() {
+                Some(b'/') if !double => false,
+                Some(_) => glob_match(pattern, &text[1..]),
+                None => false,
+            }
+        }
+        (Some(b'?'), Some(c)) if *c != b'/' => glob_match(&pattern[1..], &text[1..]),
+        (Some(p), Some(c)) if p == c => glob_match(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SparsePatterns;
+
+    #[test]
+    fn test_cone_patterns() {
+        let sparse = SparsePatterns::cone(&["src/", "/project/mega"]);
+        assert!(sparse.includes("README.md"));
+        assert!(sparse.includes("src/main.rs"));
+        assert!(sparse.includes("src/pack/mod.rs"));
+        assert!(sparse.includes("project/Cargo.toml"));
+        assert!(sparse.includes("project/mega/lib.rs"));
+        assert!(!sparse.includes("project/other/lib.rs"));
+        assert!(!sparse.includes("docs/guide.md"));
+        assert!(!sparse.includes("srcs/main.rs"));
+    }
+
+    #[test]
+    fn test_glob_patterns() {
+        let sparse = SparsePatterns::glob("# rust sources\n*.rs\n/docs/\n!docs/draft.md\n");
+        assert!(sparse.includes("main.rs"));
+        assert!(sparse.includes("src/pack/mod.rs"));
+        assert!(sparse.includes("docs/guide.md"));
+        assert!(!sparse.includes("docs/draft.md"));
+        assert!(!sparse.includes("README.md"));
+        assert!(!sparse.includes("src/docs"));
+    }
+}
@@ -0,0 +1,186 @@
+//! Resumable pack uploads.
+//!
+//! A large push over a flaky connection can be sent as labeled chunks, each with its offset in
+//! the pack. Chunks are stored on disk under the id of their upload as they arrive, in any order,
+//! so a client only has to resend the chunks that didn't make it. Once the chunks cover the whole
+//! pack and its trailing checksum matches, the pack is handed to [`PackHandler::unpack`].
+//!
+//! [`PackHandler::unpack`]: crate::pack::handler::PackHandler::unpack
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use bytes::{Bytes, BytesMut};
+use tokio::fs;
+
+use venus::errors::GitError;
+use venus::hash::{HashWriter, SHA1};
+
+/// The partial uploads, one directory per upload holding a file per chunk named by its offset.
+#[derive(Debug, Clone)]
+pub struct PackUploads {
+    dir: PathBuf,
+}
+
+impl PackUploads {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        PackUploads { dir: dir.into() }
+    }
+
+    /// Keep the uploads next to the pack decode cache, in `MEGA_PACK_DECODE_CACHE_PATH/uploads`.
+    pub fn from_env() -> Self {
+        let cache = env::var("MEGA_PACK_DECODE_CACHE_PATH").unwrap();
+        PackUploads::new(PathBuf::from(cache).join("uploads"))
+    }
+
+    fn upload_dir(&self, upload_id: &str) -> Result<PathBuf, GitError> {
+        let valid = !upload_id.is_empty()
+            && upload_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(GitError::InvalidUpload(format!(
+                "`{}` is not a valid upload id",
+                upload_id
+            )));
+        }
+        Ok(self.dir.join(upload_id))
+    }
+
+    /// Store the chunk of `upload_id` starting at `offset`, a chunk sent again replaces the
+    /// earlier one.
+    pub async fn put_chunk(
+        &self,
+        upload_id: &str,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<(), GitError> {
+        let dir = self.upload_dir(upload_id)?;
+        fs::create_dir_all(&dir).await?;
+        fs::write(dir.join(offset.to_string()), data).await?;
+        Ok(())
+    }
+
+    /// The offsets and lengths of the chunks received so far, ordered by offset.
+    async fn chunks(&self, upload_id: &str) -> Result<Vec<(usize, usize)>, GitError> {
+        let dir = self.upload_dir(upload_id)?;
+        let mut chunks = Vec::new();
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(chunks),
+            Err(err) => return Err(err.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let Some(offset) = entry
+                .file_name()
+                .to_str()
+                .and_then(|x| x.parse::<usize>().ok())
+            else {
+                continue;
+            };
+            chunks.push((offset, entry.metadata().await?.len() as usize));
+        }
+        chunks.sort();
+        Ok(chunks)
+    }
+
+    /// Reassemble the pack of `upload_id` if its chunks cover all of its `total` bytes.
+    ///
+    /// Returns `None` while chunks are missing. Chunks overlapping each other or running past
+    /// `total`, and a pack whose trailing checksum doesn't match its content, are rejected.
+    ///
+    /// A complete upload is claimed by renaming its directory, so when the last chunks arrive in
+    /// concurrent requests only one of them gets the pack, the others get `None`. The claimed
+    /// chunks are kept until the pack is unpacked, see [`PackUploads::finish`] and
+    /// [`PackUploads::release`].
+    pub async fn assemble(&self, upload_id: &str, total: usize) -> Result<Option<Bytes>, GitError> {
+        let chunks = self.chunks(upload_id).await?;
+        let mut end = 0;
+        for &(offset, len) in &chunks {
+            let chunk_end = offset
+                .checked_add(len)
+                .filter(|&x| offset >= end && x <= total);
+            let Some(chunk_end) = chunk_end else {
+                return Err(GitError::InvalidUpload(format!(
+                    "the chunk at offset {} of `{}` overlaps another one or exceeds {} bytes",
+                    offset, upload_id, total
+                )));
+            };
+            if offset > end {
+                return Ok(None);
+            }
+            end = chunk_end;
+        }
+        if end < total {
+            return Ok(None);
+        }
+
+        let dir = self.upload_dir(upload_id)?;
+        let claimed = self.claimed_dir(upload_id)?;
+        match fs::rename(&dir, &claimed).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        match Self::read_pack(&claimed, upload_id, &chunks, total).await {
+            Ok(pack) => Ok(Some(pack)),
+            Err(err) => {
+                // give the chunks back, so the broken ones can be sent again
+                let _ = self.release(upload_id).await;
+                Err(err)
+            }
+        }
+    }
+
+    fn claimed_dir(&self, upload_id: &str) -> Result<PathBuf, GitError> {
+        self.upload_dir(upload_id)?;
+        // upload ids have no dots, so the claimed directory can't be another upload
+        Ok(self.dir.join(format!("{}.assembling", upload_id)))
+    }
+
+    /// Concatenate the `chunks` stored in `dir` and check the trailing checksum of the pack.
+    async fn read_pack(
+        dir: &Path,
+        upload_id: &str,
+        chunks: &[(usize, usize)],
+        total: usize,
+    ) -> Result<Bytes, GitError> {
+        let mut pack = BytesMut::with_capacity(total);
+        for (offset, _) in chunks {
+            pack.extend_from_slice(&fs::read(dir.join(offset.to_string())).await?);
+        }
+        if pack.len() < 20 {
+            return Err(GitError::InvalidPackFile(format!(
+                "upload `{}` is too short for a pack",
+                upload_id
+            )));
+        }
+        let (content, checksum) = pack.split_at(pack.len() - 20);
+        let mut hasher = HashWriter::new(io::sink());
+        io::Write::write_all(&mut hasher, content)?;
+        if hasher.finalize() != SHA1::from_bytes(checksum) {
+            return Err(GitError::InvalidPackFile(format!(
+                "checksum mismatch of upload `{}`",
+                upload_id
+            )));
+        }
+        Ok(pack.freeze())
+    }
+
+    /// Give the chunks of `upload_id` claimed by [`PackUploads::assemble`] back, e.g. when
+    /// unpacking the pack failed, so the upload can be completed again.
+    pub async fn release(&self, upload_id: &str) -> Result<(), GitError> {
+        let dir = self.upload_dir(upload_id)?;
+        fs::rename(self.claimed_dir(upload_id)?, dir).await?;
+        Ok(())
+    }
+
+    /// Drop the chunks of `upload_id` claimed by [`PackUploads::assemble`], once its pack was
+    /// unpacked.
+    pub async fn finish(&self, upload_id: &str) -> Result<(), GitError> {
+        match fs::remove_dir_all(self.claimed_dir(upload_id)?).await {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
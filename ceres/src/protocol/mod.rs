@@ -11,10 +11,17 @@ use common::{
     utils::{generate_id, ZERO_ID},
 };
 use jupiter::context::Context;
-use venus::{internal::pack::reference::RefCommand, repo::Repo};
+use venus::{errors::GitError, internal::pack::reference::RefCommand, repo::Repo};
 
-use crate::pack::{handler::PackHandler, import_repo::ImportRepo, monorepo::MonoRepo};
+use crate::pack::{
+    handler::PackHandler,
+    import_repo::ImportRepo,
+    mirror::{self, MirrorPackHandler},
+    monorepo::{self, MonoRepo},
+};
 
+pub mod pktline;
+pub mod side_band;
 pub mod smart;
 
 #[derive(Clone)]
@@ -81,6 +88,9 @@ pub enum Capability {
     OfsDelta,
     DeepenSince,
     DeepenNot,
+    Filter,
+    /// The client and its version, like `git/2.43.0`, sent as `agent=<agent>`.
+    Agent(String),
 }
 
 impl FromStr for Capability {
@@ -98,7 +108,11 @@ impl FromStr for Capability {
             "no-done" => Ok(Capability::NoDone),
             "deepen-since" => Ok(Capability::DeepenSince),
             "deepen-not" => Ok(Capability::DeepenNot),
-            _ => Err(()),
+            "filter" => Ok(Capability::Filter),
+            _ => s
+                .strip_prefix("agent=")
+                .map(|x| Capability::Agent(x.to_owned()))
+                .ok_or(()),
         }
     }
 }
@@ -149,36 +163,41 @@ impl SmartProtocol {
         }
     }
 
-    pub async fn pack_handler(&self) -> Box<dyn PackHandler> {
+    /// The handler of the repository at `path`, an imported repo or a monorepo directory. Paths
+    /// leaving the root are rejected.
+    pub async fn pack_handler(&self) -> Result<Box<dyn PackHandler>, GitError> {
+        let path = monorepo::normalize_path(&self.path)?;
         let import_dir = PathBuf::from(env::var("MEGA_IMPORT_DIRS").unwrap());
-        if self.path.starts_with(import_dir.clone()) && self.path != import_dir {
+        if path.starts_with(import_dir.clone()) && path != import_dir {
             let storage = self.context.services.git_db_storage.clone();
 
-            let path_str = self.path.to_str().unwrap();
+            let path_str = path.to_str().unwrap();
             let model = storage.find_git_repo(path_str).await.unwrap();
             let repo = if let Some(repo) = model {
                 repo.into()
             } else {
-                let repo_name = self.path.file_name().unwrap().to_str().unwrap().to_owned();
+                let repo_name = path.file_name().unwrap().to_str().unwrap().to_owned();
                 let repo = Repo {
                     repo_id: generate_id(),
-                    repo_path: self.path.to_str().unwrap().to_owned(),
+                    repo_path: path_str.to_owned(),
                     repo_name,
                 };
                 storage.save_git_repo(repo.clone()).await.unwrap();
                 repo
             };
-            Box::new(ImportRepo {
+            let handler = Box::new(ImportRepo {
                 context: self.context.clone(),
                 repo,
+            });
+            Ok(match mirror::mirror_upstream(&path) {
+                Some(upstream) => Box::new(MirrorPackHandler {
+                    local: handler,
+                    upstream,
+                }),
+                None => handler,
             })
         } else {
-            let mut res = Box::new(MonoRepo {
-                context: self.context.clone(),
-                path: self.path.clone(),
-                from_hash: None,
-                to_hash: None,
-            });
+            let mut res = Box::new(MonoRepo::new(self.context.clone(), &path)?);
             if let Some(command) = self
                 .command_list
                 .iter()
@@ -187,7 +206,8 @@ impl SmartProtocol {
                 res.from_hash = Some(command.old_id.clone());
                 res.to_hash = Some(command.new_id.clone());
             }
-            res
+            res.agent = self.client_agent().map(str::to_owned);
+            Ok(res)
         }
     }
 }
@@ -0,0 +1,112 @@
+//! The pkt-line framing shared by the smart http and ssh transports.
+//!
+//! Every packet starts with its total length, including the 4 length bytes, as 4 hex digits.
+//! The length `0000` is the flush packet, which ends a section of the conversation, and `0001`
+//! the delimiter packet, which separates the sections of a response. An empty data packet is
+//! `0004`.
+use venus::errors::GitError;
+
+const FLUSH_PKT: &[u8; 4] = b"0000";
+
+const DELIM_PKT: &[u8; 4] = b"0001";
+
+/// Frame `data` as a single packet.
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut pkt = format!("{:04x}", data.len() + 4).into_bytes();
+    pkt.extend_from_slice(data);
+    pkt
+}
+
+pub fn encode_flush() -> Vec<u8> {
+    FLUSH_PKT.to_vec()
+}
+
+pub fn encode_delim() -> Vec<u8> {
+    DELIM_PKT.to_vec()
+}
+
+/// A packet read by [`decode`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Packet {
+    Data(Vec<u8>),
+    Flush,
+    Delim,
+}
+
+/// Read the packet at the start of `data` and the number of bytes it takes up.
+///
+/// `None` if `data` doesn't hold a complete packet yet, an error if its length is malformed.
+pub fn decode(data: &[u8]) -> Result<Option<(Packet, usize)>, GitError> {
+    if data.len() < 4 {
+        return Ok(None);
+    }
+    let invalid = || GitError::InvalidPktLine(String::from_utf8_lossy(&data[..4]).into_owned());
+    if !data[..4].iter().all(u8::is_ascii_hexdigit) {
+        return Err(invalid());
+    }
+    let len = usize::from_str_radix(std::str::from_utf8(&data[..4]).unwrap(), 16).unwrap();
+    let packet = match len {
+        0 => Packet::Flush,
+        1 => Packet::Delim,
+        2..=3 => return Err(invalid()),
+        _ if data.len() < len => return Ok(None),
+        _ => Packet::Data(data[4..len].to_vec()),
+    };
+    Ok(Some((packet, len.max(4))))
+}
+
+#[cfg(test)]
+mod test {
+    use venus::errors::GitError;
+
+    use super::{decode, encode, encode_delim, encode_flush, Packet};
+
+    #[test]
+    fn test_data_packet() {
+        let pkt = encode(b"want 7bdc783132575d5b3e78400ace9971970ff43a18\n");
+        assert_eq!(&pkt[..4], b"0032");
+        let data = [pkt, b"0009done\n".to_vec()].concat();
+        assert_eq!(
+            decode(&data).unwrap(),
+            Some((
+                Packet::Data(b"want 7bdc783132575d5b3e78400ace9971970ff43a18\n".to_vec()),
+                50
+            ))
+        );
+        assert_eq!(
+            decode(&data[50..]).unwrap(),
+            Some((Packet::Data(b"done\n".to_vec()), 9))
+        );
+        assert_eq!(decode(&data[59..]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_empty_packet() {
+        assert_eq!(encode(b""), b"0004");
+        assert_eq!(decode(b"0004").unwrap(), Some((Packet::Data(vec![]), 4)));
+    }
+
+    #[test]
+    fn test_flush_packet() {
+        assert_eq!(encode_flush(), b"0000");
+        assert_eq!(encode_delim(), b"0001");
+        let data = b"000000010006a\n";
+        assert_eq!(decode(data).unwrap(), Some((Packet::Flush, 4)));
+        assert_eq!(decode(&data[4..]).unwrap(), Some((Packet::Delim, 4)));
+        assert_eq!(
+            decode(&data[8..]).unwrap(),
+            Some((Packet::Data(b"a\n".to_vec()), 6))
+        );
+
+        // a truncated packet needs more data
+        assert_eq!(decode(b"000aab").unwrap(), None);
+        assert_eq!(decode(b"00").unwrap(), None);
+    }
+
+    #[test]
+    fn test_malformed_length() {
+        for data in [&b"0002"[..], b"0003abc", b"00zz", b"+00a"] {
+            assert!(matches!(decode(data), Err(GitError::InvalidPktLine(_))));
+        }
+    }
+}
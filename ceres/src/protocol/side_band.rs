@@ -0,0 +1,150 @@
+//! Multiplexing a response onto the side-band channels.
+//!
+//! With `side-band` or `side-band-64k` the pack is sent in pkt-lines whose first data byte is
+//! the channel, see [`SideBind`]: the pack on channel 1, progress messages the client prints on
+//! channel 2 and a fatal error on channel 3. A packet is at most 1000 bytes with `side-band` and
+//! 65520 bytes with `side-band-64k`, longer data is split over several packets.
+use std::io;
+
+use bytes::BytesMut;
+
+use crate::protocol::{pktline, Capability, SideBind};
+
+/// The most data of a `side-band-64k` packet, after its length and channel.
+pub const MAX_DATA_64K: usize = 65520 - 5;
+
+/// The most data of a `side-band` packet, after its length and channel.
+pub const MAX_DATA: usize = 1000 - 5;
+
+/// Frames the data written to it as side-band packets. Writing with [`io::Write`] sends pack
+/// data.
+pub struct SideBand {
+    buf: BytesMut,
+    max_data: usize,
+}
+
+impl SideBand {
+    /// A writer for the side-band the client asked for in `capabilities`, `None` without one.
+    pub fn new(capabilities: &[Capability]) -> Option<Self> {
+        let max_data = if capabilities.contains(&Capability::SideBand64k) {
+            MAX_DATA_64K
+        } else if capabilities.contains(&Capability::SideBand) {
+            MAX_DATA
+        } else {
+            return None;
+        };
+        Some(SideBand {
+            buf: BytesMut::new(),
+            max_data,
+        })
+    }
+
+    /// Send `data` on the channel `band`, in as many packets as needed.
+    pub fn send(&mut self, band: SideBind, data: &[u8]) {
+        for chunk in data.chunks(self.max_data) {
+            let mut frame = Vec::with_capacity(chunk.len() + 1);
+            frame.push(band.value());
+            frame.extend_from_slice(chunk);
+            self.buf.extend(pktline::encode(&frame));
+        }
+    }
+
+    pub fn pack(&mut self, data: &[u8]) {
+        self.send(SideBind::PackfileData, data);
+    }
+
+    pub fn progress(&mut self, message: &str) {
+        self.send(SideBind::ProgressInfo, message.as_bytes());
+    }
+
+    pub fn error(&mut self, message: &str) {
+        self.send(SideBind::Error, message.as_bytes());
+    }
+
+    /// End the response with a flush packet and return it.
+    pub fn finish(mut self) -> BytesMut {
+        self.buf.extend(pktline::encode_flush());
+        self.buf
+    }
+}
+
+impl io::Write for SideBand {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pack(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use bytes::BytesMut;
+
+    use super::{SideBand, MAX_DATA, MAX_DATA_64K};
+    use crate::protocol::pktline::Packet;
+    use crate::protocol::Capability;
+    use crate::test_util::packets;
+
+    fn frames(data: BytesMut) -> Vec<Vec<u8>> {
+        let mut packets = packets(&data);
+        assert_eq!(
+            packets.pop(),
+            Some(Packet::Flush),
+            "the flush packet ends the response"
+        );
+        packets
+            .into_iter()
+            .map(|x| match x {
+                Packet::Data(frame) => frame,
+                other => panic!("{:?} within the response", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_channels() {
+        assert!(SideBand::new(&[Capability::OfsDelta]).is_none());
+
+        let mut side_band = SideBand::new(&[Capability::SideBand64k]).unwrap();
+        side_band.progress("Counting objects: 3, done.\n");
+        side_band.write_all(b"PACK").unwrap();
+        side_band.error("upload-pack: not our ref");
+        let data = side_band.finish();
+        assert!(data.ends_with(b"0000"));
+        assert_eq!(&data[..4], b"0020");
+        assert_eq!(
+            frames(data),
+            [
+                b"\x02Counting objects: 3, done.\n".to_vec(),
+                b"\x01PACK".to_vec(),
+                b"\x03upload-pack: not our ref".to_vec(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_frames() {
+        let pack: Vec<u8> = (0..MAX_DATA_64K * 2 + 10).map(|x| x as u8).collect();
+        let mut side_band = SideBand::new(&[Capability::SideBand64k]).unwrap();
+        side_band.pack(&pack);
+        let sent = frames(side_band.finish());
+        let sizes: Vec<usize> = sent.iter().map(|x| x.len() - 1).collect();
+        assert_eq!(sizes, [MAX_DATA_64K, MAX_DATA_64K, 10]);
+        assert!(sent.iter().all(|x| x[0] == 1));
+        let joined: Vec<u8> = sent.iter().flat_map(|x| x[1..].to_vec()).collect();
+        assert_eq!(joined, pack);
+
+        // without 64k packets are at most 1000 bytes
+        let mut side_band = SideBand::new(&[Capability::SideBand]).unwrap();
+        side_band.pack(&pack[..MAX_DATA + 1]);
+        let data = side_band.finish();
+        assert_eq!(&data[..4], b"03e8");
+        let sizes: Vec<usize> = frames(data).iter().map(|x| x.len() - 1).collect();
+        assert_eq!(sizes, [MAX_DATA, 1]);
+    }
+}
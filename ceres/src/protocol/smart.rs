@@ -7,7 +7,10 @@ use anyhow::Result;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 
 use callisto::db_enums::RefType;
+use venus::internal::pack::reference::{CommandType, Refs};
 
+use crate::pack::handler::{ObjectFilter, WANT_REF};
+use crate::protocol::pktline::{self, Packet};
 use crate::protocol::ZERO_ID;
 use crate::protocol::{
     Capability, RefCommand, ServiceType, SideBind, SmartProtocol, TransportProtocol,
@@ -23,15 +26,79 @@ pub const PKT_LINE_END_MARKER: &[u8; 4] = b"0000";
 
 // The atomic, report-status, report-status-v2, delete-refs, quiet,
 // and push-cert capabilities are sent and recognized by the receive-pack (push to server) process.
-const RECEIVE_CAP_LIST: &str = "report-status report-status-v2 delete-refs quiet atomic no-thin ";
+const RECEIVE_CAPS: &[&str] = &[
+    "report-status",
+    "report-status-v2",
+    "delete-refs",
+    "quiet",
+    "atomic",
+    "no-thin",
+];
 
 // The ofs-delta and side-band-64k capabilities are sent and recognized by both upload-pack and receive-pack protocols.
 // The agent and session-id capabilities may optionally be sent in both protocols.
-const COMMON_CAP_LIST: &str = "side-band-64k ofs-delta agent=mega/0.1.0";
+const COMMON_CAPS: &[&str] = &["side-band-64k", "ofs-delta"];
 
 // All other capabilities are only recognized by the upload-pack (fetch from server) process.
-const UPLOAD_CAP_LIST: &str =
-    "shallow deepen-since deepen-not deepen-relative multi_ack_detailed no-done include-tag ";
+const UPLOAD_CAPS: &[&str] = &[
+    "shallow",
+    "deepen-since",
+    "deepen-not",
+    "deepen-relative",
+    "multi_ack_detailed",
+    "no-done",
+    "include-tag",
+    "filter",
+];
+
+const DEFAULT_AGENT: &str = concat!("mega/", env!("CARGO_PKG_VERSION"));
+
+/// The capabilities declared behind the first ref of the advertisement, clients negotiate
+/// against these.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerCapabilities {
+    pub upload_pack: Vec<String>,
+    pub receive_pack: Vec<String>,
+    /// Sent as `agent=<agent>` to both services.
+    pub agent: String,
+}
+
+impl Default for ServerCapabilities {
+    fn default() -> Self {
+        let caps = |list: &[&str]| -> Vec<String> {
+            list.iter()
+                .chain(COMMON_CAPS)
+                .map(|x| x.to_string())
+                .collect()
+        };
+        ServerCapabilities {
+            upload_pack: caps(UPLOAD_CAPS),
+            receive_pack: caps(RECEIVE_CAPS),
+            agent: DEFAULT_AGENT.to_owned(),
+        }
+    }
+}
+
+impl ServerCapabilities {
+    pub fn with_agent(mut self, agent: impl Into<String>) -> Self {
+        self.agent = agent.into();
+        self
+    }
+
+    /// The space separated capability list advertised to `service_type` clients.
+    pub fn advertise(&self, service_type: ServiceType) -> String {
+        let caps = match service_type {
+            ServiceType::UploadPack => &self.upload_pack,
+            ServiceType::ReceivePack => &self.receive_pack,
+        };
+        let mut list = caps.join(" ");
+        if !list.is_empty() {
+            list.push(SP);
+        }
+        list.push_str(&format!("agent={}", self.agent));
+        list
+    }
+}
 
 impl SmartProtocol {
     /// # Retrieves the information about Git references (refs) for the specified service type.
@@ -44,7 +111,7 @@ impl SmartProtocol {
     /// the name is set to "capabilities^{}" to include capability declarations behind a NUL on the first ref.
     /// Otherwise, the name is set to "HEAD".
     ///
-    /// The `cap_list` is determined based on the `service_type` and contains the appropriate capability lists.
+    /// The `cap_list` is the handler's `capabilities` advertised for the `service_type`.
     ///
     /// A packet line (`pkt_line`) is constructed using the `object_id`, `name`, `NUL` delimiter, `cap_list`, and line feed (`LF`).
     /// The `pkt_line` is added to the `ref_list`.
@@ -58,66 +125,68 @@ impl SmartProtocol {
     /// Tracing information is logged regarding the response packet line stream.
     ///
     /// Finally, the constructed packet line stream is returned.
-    pub async fn git_info_refs(&self) -> BytesMut {
-        let pack_handler = self.pack_handler().await;
+    pub async fn git_info_refs(&self) -> Result<BytesMut> {
+        let pack_handler = self.pack_handler().await?;
 
         let service_type = self.service_type;
 
-        // The stream MUST include capability declarations behind a NUL on the first ref.
-        let (head_hash, git_refs) = pack_handler.head_hash().await;
-        let name = if head_hash == ZERO_ID {
-            "capabilities^{}"
-        } else {
-            "HEAD"
-        };
-        let cap_list = match service_type {
-            ServiceType::UploadPack => format!("{}{}", UPLOAD_CAP_LIST, COMMON_CAP_LIST),
-            ServiceType::ReceivePack => format!("{}{}", RECEIVE_CAP_LIST, COMMON_CAP_LIST),
-        };
-        let pkt_line = format!("{}{}{}{}{}{}", head_hash, SP, name, NUL, cap_list, LF);
-        let mut ref_list = vec![pkt_line];
-
-        for git_ref in git_refs {
-            let pkt_line = format!("{}{}{}{}", git_ref.ref_hash, SP, git_ref.ref_name, LF);
-            ref_list.push(pkt_line);
+        if let Err(err) = pack_handler.ensure_ref().await {
+            tracing::error!("Failed to create the refs of {:?}: {}", self.path, err);
         }
+        let (head_hash, git_refs) = pack_handler.head_hash().await;
+        let capabilities = pack_handler.capabilities();
+        let ref_list = advertised_refs(service_type, &capabilities, head_hash, git_refs);
         let pkt_line_stream = self.build_smart_reply(&ref_list, service_type.to_string());
         tracing::debug!("git_info_refs response: {:?}", pkt_line_stream);
-        pkt_line_stream
+        Ok(pkt_line_stream)
     }
 
     pub async fn git_upload_pack(
         &mut self,
         upload_request: &mut Bytes,
     ) -> Result<(Vec<u8>, BytesMut)> {
-        let pack_handler = self.pack_handler().await;
+        let pack_handler = self.pack_handler().await?;
 
         let mut want: Vec<String> = Vec::new();
         let mut have: Vec<String> = Vec::new();
         let mut last_common_commit = String::new();
+        let mut filter: Option<ObjectFilter> = None;
 
         let mut read_first_line = false;
         loop {
-            let (bytes_take, pkt_line) = read_pkt_line(upload_request);
-            // read 0000 to continue and read empty str to break
-            if bytes_take == 0 {
-                if upload_request.is_empty() {
-                    break;
-                } else {
-                    continue;
-                }
-            }
-            let dst = pkt_line.to_vec();
-            let commands = &dst[0..4];
+            // the flush packets between the sections are skipped, the request ends with the data
+            let dst = match take_packet(upload_request) {
+                Ok(Some(Packet::Data(line))) => line,
+                Ok(Some(_)) => continue,
+                Ok(None) => break,
+                Err(err) => return Ok((vec![], err)),
+            };
+            let Some(commands) = dst.get(0..4) else {
+                return Ok((vec![], invalid_pkt_line(&dst)));
+            };
 
             match commands {
-                b"want" => {
-                    want.push(String::from_utf8(dst[5..45].to_vec()).unwrap());
-                }
-                b"have" => {
-                    have.push(String::from_utf8(dst[5..45].to_vec()).unwrap());
+                b"want" if dst.starts_with(WANT_REF.as_bytes()) => {
+                    // ref-in-want is a protocol v2 capability, this v0 service doesn't advertise it
+                    let err = "want-ref is only supported by protocol v2";
+                    return Ok((vec![], error_pkt_line(&err)));
                 }
+                b"want" => match pkt_line_hash(&dst) {
+                    Some(hash) => want.push(hash),
+                    None => return Ok((vec![], invalid_pkt_line(&dst))),
+                },
+                b"have" => match pkt_line_hash(&dst) {
+                    Some(hash) => have.push(hash),
+                    None => return Ok((vec![], invalid_pkt_line(&dst))),
+                },
                 b"done" => break,
+                b"filt" => {
+                    match parse_filter(&dst) {
+                        Ok(f) => filter = Some(f),
+                        Err(err) => return Ok((vec![], err)),
+                    }
+                    continue;
+                }
                 other => {
                     tracing::error!(
                         "unsupported command: {:?}",
@@ -127,23 +196,29 @@ impl SmartProtocol {
                 }
             };
             if !read_first_line {
-                self.parse_capabilities(&String::from_utf8(dst[46..].to_vec()).unwrap());
+                // the capabilities follow the hash of the first want
+                let caps = dst.get(46..).unwrap_or_default();
+                self.parse_capabilities(&String::from_utf8_lossy(caps));
                 read_first_line = true;
             }
         }
 
         tracing::info!(
-            "want commands: {:?}\n have commands: {:?}\n caps:{:?}",
+            "want commands: {:?}\n have commands: {:?}\n caps:{:?}\n filter: {:?}",
             want,
             have,
-            self.capabilities
+            self.capabilities,
+            filter
         );
 
         let mut pack_data = vec![];
         let mut buf = BytesMut::new();
 
         if have.is_empty() {
-            pack_data = pack_handler.full_pack().await.unwrap();
+            pack_data = match pack_handler.full_pack(want, filter).await {
+                Ok(data) => data,
+                Err(err) => return Ok((vec![], error_pkt_line(&err))),
+            };
             add_pkt_line_string(&mut buf, String::from("NAK\n"));
         } else {
             if self.capabilities.contains(&Capability::MultiAckDetailed) {
@@ -173,7 +248,10 @@ impl SmartProtocol {
                     }
                 }
 
-                pack_data = pack_handler.incremental_pack(want, have).await.unwrap();
+                pack_data = match pack_handler.incremental_pack(want, have, filter).await {
+                    Ok(data) => data,
+                    Err(err) => return Ok((vec![], error_pkt_line(&err))),
+                };
             } else {
                 tracing::error!("capability unsupported");
             }
@@ -188,48 +266,65 @@ impl SmartProtocol {
         } else {
             tracing::debug!("{} bytes from client", body_bytes.len())
         }
-        while !body_bytes.starts_with(&[b'P', b'A', b'C', b'K']) && !body_bytes.is_empty() {
-            let (bytes_take, mut pkt_line) = read_pkt_line(&mut body_bytes);
-            if bytes_take != 0 {
-                let command = self.parse_ref_command(&mut pkt_line);
-                self.parse_capabilities(&String::from_utf8(pkt_line.to_vec()).unwrap());
-                tracing::debug!("init command: {:?}, caps:{:?}", command, self.capabilities);
-                self.command_list.push(command);
-            }
+        while !body_bytes.starts_with(b"PACK") && !body_bytes.is_empty() {
+            let mut pkt_line = match take_packet(&mut body_bytes) {
+                Ok(Some(Packet::Data(line))) => Bytes::from(line),
+                Ok(_) => continue,
+                Err(err) => return Ok(err.freeze()),
+            };
+            let command = self.parse_ref_command(&mut pkt_line);
+            self.parse_capabilities(&String::from_utf8_lossy(&pkt_line));
+            tracing::debug!("init command: {:?}, caps:{:?}", command, self.capabilities);
+            self.command_list.push(command);
         }
         // handles situation when client send b"0000"
-        if body_bytes.is_empty() {
+        if body_bytes.is_empty() && self.command_list.is_empty() {
             return Ok(body_bytes);
         }
         // After receiving the pack data from the sender, the receiver sends a report
         let mut report_status = BytesMut::new();
-        let pack_handler = self.pack_handler().await;
-        //1. unpack progress
-        let unpack_success = pack_handler.unpack(body_bytes).await.is_ok();
+        let pack_handler = self.pack_handler().await?;
+        //1. unpack progress, pushes only deleting refs come without a pack
+        let unpack_result = if body_bytes.is_empty() {
+            Ok(())
+        } else {
+            pack_handler.unpack(body_bytes).await
+        };
 
-        // write "unpack ok\n to report"
-        add_pkt_line_string(&mut report_status, "unpack ok\n".to_owned());
+        // write "unpack ok\n" or "unpack <error>\n" to the report
+        let unpack_status = match &unpack_result {
+            Ok(()) => "unpack ok\n".to_owned(),
+            Err(err) => format!("unpack {}\n", err),
+        };
+        add_pkt_line_string(&mut report_status, unpack_status);
 
         let mut default_exist = pack_handler.check_default_branch().await;
+        let force_push = pack_handler.force_push_allowed();
 
         //2. update each refs and build report
         for mut command in self.command_list.clone() {
-            if command.ref_type == RefType::Tag {
+            command.force = force_push;
+            if let Err(err) = &unpack_result {
+                // tell the client why the push was rejected, e.g. by a push policy, no ref may
+                // point to objects which were never stored
+                command.failed(err.to_string());
+            } else if command.ref_type == RefType::Tag {
                 // just update if refs type is tag
-                pack_handler.update_refs(&command).await.unwrap();
+                if let Err(err) = pack_handler.update_refs(&command).await {
+                    command.failed(err.to_string());
+                }
             } else {
                 // Updates can be unsuccessful for a number of reasons.
                 // a.The reference can have changed since the reference discovery phase was originally sent, meaning someone pushed in the meantime.
                 // b.The reference being pushed could be a non-fast-forward reference and the update hooks or configuration could be set to not allow that, etc.
                 // c.Also, some references can be updated while others can be rejected.
-                if unpack_success {
-                    if !default_exist {
-                        command.default_branch = true;
-                        default_exist = true;
-                    }
-                    pack_handler.update_refs(&command).await.unwrap();
-                } else {
-                    command.failed(String::from("parse commit tree from obj failed"));
+                if !default_exist && command.command_type != CommandType::Delete {
+                    command.default_branch = true;
+                    default_exist = true;
+                }
+                // e.g. a non-fast-forward update
+                if let Err(err) = pack_handler.update_refs(&command).await {
+                    command.failed(err.to_string());
                 }
             }
             add_pkt_line_string(&mut report_status, command.get_status());
@@ -273,18 +368,17 @@ impl SmartProtocol {
         from_bytes
     }
 
-    pub fn build_smart_reply(&self, ref_list: &Vec<String>, service: String) -> BytesMut {
-        let mut pkt_line_stream = BytesMut::new();
-        if self.transport_protocol == TransportProtocol::Http {
-            add_pkt_line_string(&mut pkt_line_stream, format!("# service={}\n", service));
-            pkt_line_stream.put(&PKT_LINE_END_MARKER[..]);
-        }
+    pub fn build_smart_reply(&self, ref_list: &[String], service: String) -> BytesMut {
+        let service = (self.transport_protocol == TransportProtocol::Http).then_some(service);
+        build_pkt_line_stream(ref_list, service.as_deref())
+    }
 
-        for ref_line in ref_list {
-            add_pkt_line_string(&mut pkt_line_stream, ref_line.to_string());
-        }
-        pkt_line_stream.put(&PKT_LINE_END_MARKER[..]);
-        pkt_line_stream
+    /// The client as it sent in the `agent` capability, if it did.
+    pub fn client_agent(&self) -> Option<&str> {
+        self.capabilities.iter().find_map(|x| match x {
+            Capability::Agent(agent) => Some(agent.as_str()),
+            _ => None,
+        })
     }
 
     pub fn parse_capabilities(&mut self, cap_str: &str) {
@@ -307,6 +401,82 @@ impl SmartProtocol {
     }
 }
 
+/// The `ERR` packet ending a conversation which failed with `err`.
+fn error_pkt_line(err: &impl std::fmt::Display) -> BytesMut {
+    let mut buf = BytesMut::new();
+    add_pkt_line_string(&mut buf, format!("ERR {}\n", err));
+    buf
+}
+
+/// The `ERR` packet answering a request line which is too short or malformed.
+fn invalid_pkt_line(line: &[u8]) -> BytesMut {
+    error_pkt_line(&format!(
+        "invalid pkt-line {:?}",
+        String::from_utf8_lossy(line).trim_end()
+    ))
+}
+
+/// The filter of a `filter <filter-spec>` line, only sent when the filter capability is enabled.
+/// A spec which isn't supported gives the `ERR` packet to answer the fetch with, like git does,
+/// rather than sending more than the client asked for.
+fn parse_filter(line: &[u8]) -> Result<ObjectFilter, BytesMut> {
+    let Some(spec) = line.strip_prefix(b"filter ") else {
+        return Err(invalid_pkt_line(line));
+    };
+    let spec = String::from_utf8_lossy(spec);
+    let spec = spec.trim();
+    spec.parse::<ObjectFilter>()
+        .map_err(|_| error_pkt_line(&format!("unsupported filter {}", spec)))
+}
+
+/// The object id of a `want` or `have` line, following the command and a space.
+fn pkt_line_hash(line: &[u8]) -> Option<String> {
+    let hash = std::str::from_utf8(line.get(5..45)?).ok()?;
+    hash.bytes()
+        .all(|x| x.is_ascii_hexdigit())
+        .then(|| hash.to_owned())
+}
+
+/// The ref lines advertised to `service_type` clients, the stream MUST include capability
+/// declarations behind a NUL on the first ref.
+pub(crate) fn advertised_refs(
+    service_type: ServiceType,
+    capabilities: &ServerCapabilities,
+    head_hash: String,
+    git_refs: Vec<Refs>,
+) -> Vec<String> {
+    let name = if head_hash == ZERO_ID {
+        "capabilities^{}"
+    } else {
+        "HEAD"
+    };
+    let cap_list = capabilities.advertise(service_type);
+    let pkt_line = format!("{}{}{}{}{}{}", head_hash, SP, name, NUL, cap_list, LF);
+    let mut ref_list = vec![pkt_line];
+
+    for git_ref in git_refs {
+        let pkt_line = format!("{}{}{}{}", git_ref.ref_hash, SP, git_ref.ref_name, LF);
+        ref_list.push(pkt_line);
+    }
+    ref_list
+}
+
+/// Frame `ref_list` as pkt-lines ending with a flush packet, preceded by the `# service=` header
+/// smart http clients expect if `service` is given.
+pub(crate) fn build_pkt_line_stream(ref_list: &[String], service: Option<&str>) -> BytesMut {
+    let mut pkt_line_stream = BytesMut::new();
+    if let Some(service) = service {
+        add_pkt_line_string(&mut pkt_line_stream, format!("# service={}\n", service));
+        pkt_line_stream.put(&pktline::encode_flush()[..]);
+    }
+
+    for ref_line in ref_list {
+        add_pkt_line_string(&mut pkt_line_stream, ref_line.to_string());
+    }
+    pkt_line_stream.put(&pktline::encode_flush()[..]);
+    pkt_line_stream
+}
+
 fn read_until_white_space(bytes: &mut Bytes) -> String {
     let mut buf = Vec::new();
     while bytes.has_remaining() {
@@ -320,74 +490,87 @@ fn read_until_white_space(bytes: &mut Bytes) -> String {
 }
 
 fn add_pkt_line_string(pkt_line_stream: &mut BytesMut, buf_str: String) {
-    let buf_str_length = buf_str.len() + 4;
-    pkt_line_stream.put(Bytes::from(format!("{buf_str_length:04x}")));
-    pkt_line_stream.put(buf_str.as_bytes());
+    pkt_line_stream.put(&pktline::encode(buf_str.as_bytes())[..]);
 }
-/// Read a single pkt-format line from the `bytes` buffer and return the line length and line bytes.
-///
-/// If the `bytes` buffer is empty, indicating no more data is available, the function returns a line length of 0 and an empty `Bytes` object.
-///
-/// The pkt-format line consists of a 4-byte length field followed by the line content. The length field specifies the total length of the line, including the length field itself. The line content is returned as a `Bytes` object.
-///
-/// The function first reads the 4-byte length field from the `bytes` buffer. The length value is then parsed as a hexadecimal string and converted into a `usize` value.
-///
-/// If the resulting line length is 0, indicating an empty line, the function returns a line length of 0 and an empty `Bytes` object.
-///
-/// If the line length is non-zero, the function extracts the line content from the `bytes` buffer. The extracted line content is returned as a `Bytes` object.
-/// Note that this operation modifies the `bytes` buffer, consuming the bytes up to the end of the line.
-///
-/// # Arguments
-///
-/// * `bytes` - A mutable reference to a `Bytes` object representing the buffer containing pkt-format data.
-///
-/// # Returns
-///
-/// A tuple `(usize, Bytes)` representing the line length and line bytes respectively. If there is no more data available in the `bytes` buffer, the line length is 0 and an empty `Bytes` object is returned.
-///
-/// # Examples
-///
-/// ```
-/// use bytes::Bytes;
-/// use ceres::protocol::smart::read_pkt_line;
-///
-/// let mut bytes = Bytes::from_static(b"000Bexample");
-/// let (length, line) = read_pkt_line(&mut bytes);
-/// assert_eq!(length, 11);
-/// assert_eq!(line, Bytes::from_static(b"example"));
-/// ```
-pub fn read_pkt_line(bytes: &mut Bytes) -> (usize, Bytes) {
+
+/// Take the next packet of a request off `bytes`, `None` once it is read to the end. A truncated
+/// or malformed packet gives the `ERR` packet to answer the request with instead.
+fn take_packet(bytes: &mut Bytes) -> Result<Option<Packet>, BytesMut> {
     if bytes.is_empty() {
-        return (0, Bytes::new());
+        return Ok(None);
     }
-    let pkt_length = bytes.copy_to_bytes(4);
-    let pkt_length = usize::from_str_radix(&String::from_utf8(pkt_length.to_vec()).unwrap(), 16)
-        .unwrap_or_else(|_| panic!("{:?} is not a valid digit?", pkt_length));
-    if pkt_length == 0 {
-        return (0, Bytes::new());
+    match pktline::decode(bytes) {
+        Ok(Some((packet, len))) => {
+            bytes.advance(len);
+            Ok(Some(packet))
+        }
+        Ok(None) => Err(error_pkt_line(&"truncated pkt-line")),
+        Err(err) => Err(error_pkt_line(&err)),
     }
-    // this operation will change the original bytes
-    let pkt_line = bytes.copy_to_bytes(pkt_length - 4);
-    tracing::debug!("pkt line: {:?}", pkt_line);
-
-    (pkt_length, pkt_line)
 }
 
 #[cfg(test)]
 pub mod test {
     use bytes::{Bytes, BytesMut};
+
     use callisto::db_enums::RefType;
     use venus::internal::pack::reference::{CommandType, RefCommand};
 
-    use crate::protocol::smart::{add_pkt_line_string, read_pkt_line, read_until_white_space};
+    use crate::pack::handler::ObjectFilter;
+    use crate::protocol::pktline::Packet;
+    use crate::protocol::smart::{
+        add_pkt_line_string, invalid_pkt_line, parse_filter, pkt_line_hash, read_until_white_space,
+        take_packet,
+    };
     use crate::protocol::{Capability, SmartProtocol};
+    use crate::test_util::packets;
 
     #[test]
-    pub fn test_read_pkt_line() {
-        let mut bytes = Bytes::from_static(b"001e# service=git-upload-pack\n");
-        let (pkt_length, pkt_line) = read_pkt_line(&mut bytes);
-        assert_eq!(pkt_length, 30);
-        assert_eq!(&pkt_line[..], b"# service=git-upload-pack\n");
+    pub fn test_take_packet() {
+        let mut bytes = Bytes::from_static(b"001e# service=git-upload-pack\n0000");
+        assert_eq!(
+            take_packet(&mut bytes).unwrap(),
+            Some(Packet::Data(b"# service=git-upload-pack\n".to_vec()))
+        );
+        assert_eq!(take_packet(&mut bytes).unwrap(), Some(Packet::Flush));
+        assert_eq!(take_packet(&mut bytes).unwrap(), None);
+
+        // malformed or truncated requests are answered with an error rather than read further
+        for request in [&b"zzzz"[..], b"0002", b"0032want 7bdc7831", b"00"] {
+            let err = take_packet(&mut Bytes::from_static(request)).unwrap_err();
+            assert!(err.starts_with(b"00") && err[4..].starts_with(b"ERR "));
+        }
+    }
+
+    #[test]
+    pub fn test_pkt_line_hash() {
+        let hash = "7bdc783132575d5b3e78400ace9971970ff43a18";
+        let want = format!("want {} side-band-64k\n", hash);
+        assert_eq!(pkt_line_hash(want.as_bytes()), Some(hash.to_owned()));
+        assert_eq!(pkt_line_hash(b"want 7bdc7831\n"), None);
+        assert_eq!(pkt_line_hash(b"have"), None);
+        assert_eq!(
+            pkt_line_hash(format!("have {}", "z".repeat(40)).as_bytes()),
+            None
+        );
+
+        assert_eq!(
+            &invalid_pkt_line(b"don\n")[..],
+            b"001fERR invalid pkt-line \"don\"\n"
+        );
+    }
+
+    #[test]
+    pub fn test_parse_filter() {
+        assert_eq!(
+            parse_filter(b"filter blob:none\n"),
+            Ok(ObjectFilter::BlobNone)
+        );
+        assert_eq!(
+            packets(&parse_filter(b"filter tree:0\n").unwrap_err()),
+            vec![Packet::Data(b"ERR unsupported filter tree:0\n".to_vec())]
+        );
+        assert!(parse_filter(b"filte").is_err());
     }
 
     #[test]
@@ -444,7 +627,8 @@ pub mod test {
             error_msg: String::new(),
             command_type: CommandType::Create,
             ref_type: RefType::Branch,
-            default_branch: false
+            default_branch: false,
+            force: false,
         };
         assert_eq!(result, command);
     }
@@ -457,5 +641,13 @@ pub mod test {
             mock.capabilities,
             vec![Capability::ReportStatusv2, Capability::SideBand64k]
         );
+        assert_eq!(mock.client_agent(), None);
+    }
+
+    #[test]
+    pub fn test_parse_agent() {
+        let mut mock = SmartProtocol::mock();
+        mock.parse_capabilities("report-status side-band-64k agent=git/2.43\n");
+        assert_eq!(mock.client_agent(), Some("git/2.43"));
     }
 }
@@ -0,0 +1,50 @@
+use jupiter::storage::object_storage::MemoryStorage;
+pub(crate) use jupiter::test_util::transaction_log;
+use venus::internal::object::blob::Blob;
+use venus::internal::object::commit::Commit;
+use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+
+use crate::monorepo::diff::{build_tree, FlatTree};
+use crate::protocol::pktline::{self, Packet};
+
+/// The packets framed in `data`, which must hold nothing else.
+pub(crate) fn packets(data: &[u8]) -> Vec<Packet> {
+    let mut rest = data;
+    let mut packets = Vec::new();
+    while let Some((packet, len)) = pktline::decode(rest).unwrap() {
+        packets.push(packet);
+        rest = &rest[len..];
+    }
+    assert!(rest.is_empty(), "{:?} is no packet", rest);
+    packets
+}
+
+/// Save the trees for `files`, given as (path, content) pairs, and return the root tree.
+pub(crate) fn save_tree(storage: &MemoryStorage, files: &[(&str, &str)]) -> Tree {
+    let flat: FlatTree = files
+        .iter()
+        .map(|(path, content)| {
+            let blob = Blob::from_content(content);
+            let item = TreeItem::new(TreeItemMode::Blob, blob.id, String::new());
+            (path.to_string(), item)
+        })
+        .collect();
+    let (_, trees) = build_tree(&flat).unwrap();
+    for tree in &trees {
+        storage.insert_tree(tree.clone());
+    }
+    trees.last().unwrap().clone()
+}
+
+/// Save a commit whose tree contains `files`, given as (path, content) pairs.
+pub(crate) fn save_commit(
+    storage: &MemoryStorage,
+    files: &[(&str, &str)],
+    parents: &[&Commit],
+) -> Commit {
+    let tree_id = save_tree(storage, files).id;
+    let parents = parents.iter().map(|x| x.id).collect();
+    let commit = Commit::from_tree_id(tree_id, parents, "commit");
+    storage.insert_commit(commit.clone());
+    commit
+}
@@ -10,6 +10,7 @@ use crate::lfs::{
     tools::{
         constant_table::{git_attributes_table, track_prompt_message},
         git_attributes_manager::{DefaultGitAttributesManager, GitAttributesManger},
+        gitattributes,
     },
 };
 
@@ -48,9 +49,16 @@ fn remove_lfs_attributes<'a,I>(lines:I) -> Vec<String>
         .collect()
 }
 
+fn warn_conflicts(attributes: &[String]) {
+    for conflict in gitattributes::validate(&attributes.join("\n")) {
+        eprintln!("warning: {}", conflict);
+    }
+}
+
 pub fn track_command(manager: &DefaultGitAttributesManager,pattern: Option<String>) -> Result<(),DefaultGitAttributesError> {
     if let Some(p) = pattern {
-        manager.add_pattern(&p)?
+        manager.add_pattern(&p)?;
+        warn_conflicts(&manager.read_attributes()?);
     } else {
         print!("{}", gettext(
             track_prompt_message::TrackPromptMsgCharacters::get(
@@ -58,6 +66,7 @@ pub fn track_command(manager: &DefaultGitAttributesManager,pattern: Option<Strin
             )
         ));
         let attributes = manager.read_attributes()?;
+        warn_conflicts(&attributes);
         let filtered_attributes = filter_lfs_attribute(&attributes);
         let cleaned_attributes = remove_lfs_attributes(filtered_attributes.iter().copied());
         print_attributes(cleaned_attributes.iter().map(String::as_str))?;
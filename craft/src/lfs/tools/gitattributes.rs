@@ -0,0 +1,200 @@
+//! Consistency checks for `.gitattributes` content.
+//!
+//! `lfs track` appends lines like `*.bin filter=lfs diff=lfs merge=lfs -text`, but users can end
+//! up with another line covering the same files which unsets or overrides the filter
+//! (e.g. `*.bin -filter`). Git applies the last matching line, so such files silently stop
+//! going through LFS. `validate` reports these pairs so the caller can warn about them.
+use std::fmt;
+
+/// The state of the `filter` attribute as set by a single `.gitattributes` line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterSetting {
+    /// `filter=<driver>`
+    Set(String),
+    /// `-filter`
+    Unset,
+    /// `!filter`
+    Unspecified,
+}
+
+impl fmt::Display for FilterSetting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilterSetting::Set(driver) => write!(f, "filter={}", driver),
+            FilterSetting::Unset => write!(f, "-filter"),
+            FilterSetting::Unspecified => write!(f, "!filter"),
+        }
+    }
+}
+
+/// Two lines whose patterns overlap but which disagree on the `filter` attribute.
+/// Line numbers are 1-based, `first` always precedes `second`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeConflict {
+    pub first_line: usize,
+    pub first_pattern: String,
+    pub first_filter: FilterSetting,
+    pub second_line: usize,
+    pub second_pattern: String,
+    pub second_filter: FilterSetting,
+}
+
+impl fmt::Display for AttributeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}: `{} {}` conflicts with line {}: `{} {}`",
+            self.first_line,
+            self.first_pattern,
+            self.first_filter,
+            self.second_line,
+            self.second_pattern,
+            self.second_filter
+        )
+    }
+}
+
+struct FilterLine {
+    line: usize,
+    pattern: String,
+    filter: FilterSetting,
+}
+
+/// Parse a `.gitattributes` line, returning the pattern and its filter setting if the line
+/// touches the `filter` attribute at all.
+fn parse_line(line: &str) -> Option<(String, FilterSetting)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let mut fields = line.split_whitespace();
+    let pattern = fields.next()?;
+    let mut filter = None;
+    // later attributes on the same line override earlier ones
+    for attr in fields {
+        if let Some(driver) = attr.strip_prefix("filter=") {
+            filter = Some(FilterSetting::Set(driver.to_string()));
+        } else if attr == "-filter" {
+            filter = Some(FilterSetting::Unset);
+        } else if attr == "!filter" {
+            filter = Some(FilterSetting::Unspecified);
+        } else if attr == "filter" {
+            filter = Some(FilterSetting::Set(String::new()));
+        }
+    }
+    filter.map(|f| (pattern.to_string(), f))
+}
+
+/// Simple glob matching supporting `*` (any run of characters except `/`), `**` (any run of
+/// characters) and `?` (any single character except `/`).
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (None, Some(_)) => false,
+        (Some(b'*'), _) => {
+            let double = pattern.get(1) == Some(&b'*');
+            let rest = if double { &pattern[2..] } else { &pattern[1..] };
+            if glob_match(rest, text) {
+                return true;
+            }
+            match text.first() {
+                Some(b'/') if !double => false,
+                Some(_) => glob_match(pattern, &text[1..]),
+                None => false,
+            }
+        }
+        (Some(b'?'), Some(c)) if *c != b'/' => glob_match(&pattern[1..], &text[1..]),
+        (Some(p), Some(c)) if p == c => glob_match(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+/// Whether `pattern` matches `path` taken literally. As in git, a pattern without a slash is
+/// matched against the last path component only.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    if pattern.contains('/') {
+        glob_match(pattern.as_bytes(), path.as_bytes())
+    } else {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        glob_match(pattern.as_bytes(), name.as_bytes())
+    }
+}
+
+/// Two patterns overlap if they are identical, or if one of them matches the other taken
+/// literally (e.g. `*.bin` and `assets/logo.bin`).
+fn patterns_overlap(a: &str, b: &str) -> bool {
+    let a = a.trim_start_matches('/');
+    let b = b.trim_start_matches('/');
+    a == b || pattern_matches(a, b) || pattern_matches(b, a)
+}
+
+fn filters_conflict(a: &FilterSetting, b: &FilterSetting) -> bool {
+    match (a, b) {
+        (FilterSetting::Set(x), FilterSetting::Set(y)) => x != y,
+        (FilterSetting::Set(_), _) | (_, FilterSetting::Set(_)) => true,
+        _ => false,
+    }
+}
+
+/// Report pairs of lines in `content` whose patterns overlap while one of them enables a filter
+/// (e.g. `filter=lfs`) and the other unsets, unspecifies or changes it.
+pub fn validate(content: &str) -> Vec<AttributeConflict> {
+    let lines: Vec<FilterLine> = content
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            parse_line(line).map(|(pattern, filter)| FilterLine {
+                line: i + 1,
+                pattern,
+                filter,
+            })
+        })
+        .collect();
+
+    let mut conflicts = Vec::new();
+    for (i, first) in lines.iter().enumerate() {
+        for second in &lines[i + 1..] {
+            if patterns_overlap(&first.pattern, &second.pattern)
+                && filters_conflict(&first.filter, &second.filter)
+            {
+                conflicts.push(AttributeConflict {
+                    first_line: first.line,
+                    first_pattern: first.pattern.clone(),
+                    first_filter: first.filter.clone(),
+                    second_line: second.line,
+                    second_pattern: second.pattern.clone(),
+                    second_filter: second.filter.clone(),
+                });
+            }
+        }
+    }
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate, FilterSetting};
+
+    #[test]
+    fn test_validate_conflicting_filter() {
+        let content = "*.bin filter=lfs diff=lfs merge=lfs -text\n*.bin -filter\n";
+        let conflicts = validate(content);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].first_line, 1);
+        assert_eq!(conflicts[0].second_line, 2);
+        assert_eq!(conflicts[0].first_filter, FilterSetting::Set("lfs".to_string()));
+        assert_eq!(conflicts[0].second_filter, FilterSetting::Unset);
+    }
+
+    #[test]
+    fn test_validate_overlapping_glob() {
+        let content = "*.bin filter=lfs -text\n# comment\nassets/logo.bin !filter\n";
+        assert_eq!(validate(content).len(), 1);
+    }
+
+    #[test]
+    fn test_validate_no_conflict() {
+        let content = "*.bin filter=lfs -text\n*.png filter=lfs -text\n*.txt -filter\n*.bin diff=lfs\n";
+        assert!(validate(content).is_empty());
+    }
+}
@@ -0,0 +1,248 @@
+//! A minimal reader for git config files, enough to find the commands of a filter driver like
+//! `filter.lfs.clean` next to the `.gitattributes` which select it.
+//!
+//! Supported are sections with and without subsections (`[filter "lfs"]`, the legacy
+//! `[filter.lfs]`), `#` and `;` comments, quoted values with escapes, line continuations and
+//! keys without a value, which are booleans set to `true`. Lines which can't be read are skipped,
+//! as are includes.
+
+/// A variable of a config file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Variable {
+    /// Lowercase, like the key.
+    section: String,
+    /// Case sensitive, except for the legacy `[section.subsection]` syntax.
+    subsection: Option<String>,
+    key: String,
+    value: String,
+}
+
+/// The variables of a config file in the order they appear.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    variables: Vec<Variable>,
+}
+
+impl Config {
+    /// The last value of `key` in `section`, which is how git resolves a key set more than once.
+    /// A subsection is given after the first dot, e.g. `get("filter.lfs", "clean")`.
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.get_all(section, key).pop()
+    }
+
+    /// Every value of the multi-valued `key` in `section`, in file order.
+    pub fn get_all(&self, section: &str, key: &str) -> Vec<&str> {
+        let (section, subsection) = match section.split_once('.') {
+            Some((section, subsection)) => (section, Some(subsection)),
+            None => (section, None),
+        };
+        self.variables
+            .iter()
+            .filter(|x| {
+                x.section.eq_ignore_ascii_case(section)
+                    && x.subsection.as_deref() == subsection
+                    && x.key.eq_ignore_ascii_case(key)
+            })
+            .map(|x| x.value.as_str())
+            .collect()
+    }
+}
+
+/// Parse the content of a config file.
+pub fn parse(content: &str) -> Config {
+    let mut config = Config::default();
+    let mut section: Option<(String, Option<String>)> = None;
+    let mut lines = content.lines();
+    while let Some(line) = lines.next() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            section = parse_section(header);
+            continue;
+        }
+        let Some((name, subsection)) = &section else {
+            continue;
+        };
+        let (key, raw) = match line.split_once('=') {
+            Some((key, raw)) => (key.trim(), Some(raw.to_owned())),
+            None => (line.split([' ', '\t', '#', ';']).next().unwrap_or(""), None),
+        };
+        if !is_valid_key(key) {
+            continue;
+        }
+        let value = match raw {
+            Some(mut raw) => {
+                // a value ending in an unescaped backslash continues on the next line
+                while ends_with_continuation(&raw) {
+                    raw.pop();
+                    match lines.next() {
+                        Some(next) => raw.push_str(next),
+                        None => break,
+                    }
+                }
+                parse_value(&raw)
+            }
+            None => "true".to_owned(),
+        };
+        config.variables.push(Variable {
+            section: name.clone(),
+            subsection: subsection.clone(),
+            key: key.to_ascii_lowercase(),
+            value,
+        });
+    }
+    config
+}
+
+fn is_valid_key(key: &str) -> bool {
+    key.starts_with(|c: char| c.is_ascii_alphabetic())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+fn ends_with_continuation(raw: &str) -> bool {
+    let backslashes = raw.bytes().rev().take_while(|x| *x == b'\\').count();
+    backslashes % 2 == 1
+}
+
+/// Parse a section header after its `[`, `None` if it's malformed.
+fn parse_section(header: &str) -> Option<(String, Option<String>)> {
+    let header = header.trim_start();
+    let end = header.find([' ', '\t', ']'])?;
+    let name = &header[..end];
+    let rest = header[end..].trim_start();
+    if let Some(rest) = rest.strip_prefix('"') {
+        // [section "subsection"], where only \" and \\ are escapes
+        let mut subsection = String::new();
+        let mut chars = rest.chars();
+        loop {
+            match chars.next()? {
+                '"' => break,
+                '\\' => subsection.push(chars.next()?),
+                c => subsection.push(c),
+            }
+        }
+        if !chars.as_str().trim_start().starts_with(']') {
+            return None;
+        }
+        return Some((name.to_ascii_lowercase(), Some(subsection)));
+    }
+    if !rest.starts_with(']') {
+        return None;
+    }
+    let name = name.to_ascii_lowercase();
+    Some(match name.split_once('.') {
+        Some((section, subsection)) => (section.to_owned(), Some(subsection.to_owned())),
+        None => (name, None),
+    })
+}
+
+/// Unquote a value, dropping its comment and the whitespace around it.
+fn parse_value(raw: &str) -> String {
+    let mut value = String::new();
+    // whitespace outside quotes is only kept between words
+    let mut pending_space = String::new();
+    let mut quoted = false;
+    let mut chars = raw.trim_start().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                quoted = !quoted;
+            }
+            '#' | ';' if !quoted => break,
+            ' ' | '\t' if !quoted => pending_space.push(c),
+            '\\' => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('b') => {
+                        value.pop();
+                    }
+                    Some(c) => value.push(c),
+                    None => {}
+                }
+            }
+            c => {
+                value.push_str(&pending_space);
+                pending_space.clear();
+                value.push(c);
+            }
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn test_parse_lfs_filter() {
+        let config = parse(
+            r#"
+# installed by git lfs install
+[core]
+	repositoryformatversion = 0
+	bare
+[filter "lfs"]
+	clean = git-lfs clean -- %f
+	smudge = "git-lfs smudge -- %f" ; quoted
+	process = git-lfs filter-process
+	required = true
+[remote "origin"]
+	fetch = +refs/heads/*:refs/remotes/origin/*
+	fetch = +refs/tags/*:refs/tags/*
+"#,
+        );
+        assert_eq!(
+            config.get("filter.lfs", "clean"),
+            Some("git-lfs clean -- %f")
+        );
+        assert_eq!(
+            config.get("filter.lfs", "smudge"),
+            Some("git-lfs smudge -- %f")
+        );
+        assert_eq!(
+            config.get("filter.lfs", "process"),
+            Some("git-lfs filter-process")
+        );
+        assert_eq!(config.get("Filter.lfs", "Required"), Some("true"));
+        assert_eq!(config.get("filter.LFS", "clean"), None);
+        assert_eq!(config.get("filter", "clean"), None);
+        assert_eq!(config.get("core", "bare"), Some("true"));
+        assert_eq!(config.get("core", "repositoryformatversion"), Some("0"));
+        assert_eq!(
+            config.get_all("remote.origin", "fetch"),
+            [
+                "+refs/heads/*:refs/remotes/origin/*",
+                "+refs/tags/*:refs/tags/*"
+            ]
+        );
+        assert_eq!(
+            config.get("remote.origin", "fetch"),
+            Some("+refs/tags/*:refs/tags/*")
+        );
+    }
+
+    #[test]
+    fn test_parse_values() {
+        let config = parse(
+            "[filter.lfs]\n\
+             clean = \"a # b\" # comment\n\
+             smudge = one \\\n  two\n\
+             process = tab\\there\n\
+             [broken\n\
+             dangling = ignored\n\
+             ; [filter \"lfs\"]\n",
+        );
+        assert_eq!(config.get("filter.lfs", "clean"), Some("a # b"));
+        assert_eq!(config.get("filter.lfs", "smudge"), Some("one   two"));
+        assert_eq!(config.get("filter.lfs", "process"), Some("tab\there"));
+        assert_eq!(config.get("filter.lfs", "dangling"), None);
+    }
+}
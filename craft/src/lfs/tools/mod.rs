@@ -1,6 +1,8 @@
 pub mod locale_tools;
 mod git_repository_checker;
 pub mod git_attributes_manager;
+pub mod gitattributes;
+pub mod gitconfig;
 pub mod constant_table;
 mod constant_table_factory;
 pub mod env_utils;
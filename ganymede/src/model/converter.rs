@@ -6,6 +6,7 @@ use common::utils::generate_id;
 use venus::hash::SHA1;
 use venus::internal::object::blob::Blob;
 use venus::internal::object::commit::Commit;
+use venus::internal::object::signature::Signature;
 use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
 
 pub fn generate_git_keep() -> Blob {
@@ -94,10 +95,11 @@ impl MegaModelConverter {
         }
     }
 
-    pub fn init() -> Self {
+    /// The objects and the root ref of a new monorepo, committed by `identity`.
+    pub fn init(identity: Signature) -> Self {
         let git_keep = generate_git_keep();
         let (tree_maps, root_tree) = init_trees(&git_keep);
-        let commit = Commit::from_tree_id(root_tree.id, vec![], "Init Mega Directory");
+        let commit = Commit::from_identity(identity, root_tree.id, vec![], "Init Mega Directory");
         let mut blob_maps = HashMap::new();
         blob_maps.insert(git_keep.id, git_keep);
 
@@ -130,13 +132,21 @@ mod test {
 
     use std::str::FromStr;
 
-    use venus::{hash::SHA1, internal::object::commit::Commit};
+    use venus::{
+        hash::SHA1,
+        internal::object::{commit::Commit, signature::Signature},
+    };
 
     use crate::model::converter::MegaModelConverter;
 
     #[test]
     pub fn test_init_mega_dir() {
-        let converter = MegaModelConverter::init();
+        let identity =
+            Signature::from_data(b"committer mega <admin@mega.org> 1700000000 +0800".to_vec())
+                .unwrap();
+        let converter = MegaModelConverter::init(identity);
+        assert_eq!(converter.commit.author.name, "mega");
+        assert_eq!(converter.commit.committer.timestamp, 1700000000);
         let mega_trees = converter.mega_trees.borrow().clone();
         let mega_blobs = converter.mega_blobs.borrow().clone();
         let raw_blob = converter.raw_blobs.borrow().clone();
@@ -11,6 +11,7 @@ use axum::{
 use ganymede::model::create_file::CreateFileInfo;
 use git::internal::pack::counter::GitTypeCounter;
 use ceres::monorepo::service::MonorepoService;
+use venus::errors::GitError;
 use venus::monorepo::mr::{MergeOperation, MergeResult};
 
 use crate::{
@@ -78,8 +79,15 @@ async fn get_count_nums(
     state.object_service.count_object_num(repo_path).await
 }
 
-async fn init(state: State<ApiServiceState>) {
-    state.monorepo_service.init_monorepo().await
+async fn init(state: State<ApiServiceState>) -> Result<(), (StatusCode, String)> {
+    state
+        .monorepo_service
+        .init_monorepo()
+        .await
+        .map_err(|err| match err {
+            GitError::RefAlreadyExists(_) => (StatusCode::CONFLICT, err.to_string()),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+        })
 }
 
 async fn create_file(
@@ -17,7 +17,7 @@ use russh_keys::key;
 use tokio::io::AsyncReadExt;
 
 use ceres::lfs::lfs_structs::Link;
-use ceres::protocol::smart::{self};
+use ceres::protocol::pktline;
 use ceres::protocol::ServiceType;
 use ceres::protocol::{SmartProtocol, TransportProtocol};
 use jupiter::context::Context;
@@ -95,10 +95,17 @@ impl server::Handler for SshServer {
         match command[0] {
             "git-upload-pack" | "git-receive-pack" => {
                 smart_protocol.service_type = ServiceType::from_str(command[0]).unwrap();
-                let res = smart_protocol.git_info_refs().await;
-                self.smart_protocol = Some(smart_protocol);
-                session.data(channel, res.to_vec().into());
-                session.channel_success(channel);
+                match smart_protocol.git_info_refs().await {
+                    Ok(res) => {
+                        self.smart_protocol = Some(smart_protocol);
+                        session.data(channel, res.to_vec().into());
+                        session.channel_success(channel);
+                    }
+                    Err(err) => {
+                        tracing::error!("Failed to list the refs of {}: {}", path, err);
+                        session.channel_failure(channel);
+                    }
+                }
             }
             //Note that currently mega does not support pure ssh to transfer files, still relay on the https server.
             //see https://github.com/git-lfs/git-lfs/blob/main/docs/proposals/ssh_adapter.md for more details about pure ssh file transfer.
@@ -216,7 +223,7 @@ impl SshServer {
             temp.reserve(65500);
             let length = reader.read_buf(&mut temp).await.unwrap();
             if temp.is_empty() {
-                session.data(channel, smart::PKT_LINE_END_MARKER.to_vec().into());
+                session.data(channel, pktline::encode_flush().into());
                 return;
             }
             let bytes_out = smart_protocol.build_side_band_format(temp, length);
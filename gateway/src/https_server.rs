@@ -24,6 +24,7 @@ use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
 
 use ceres::lfs::LfsConfig;
+use ceres::monorepo::build_hook;
 use ceres::monorepo::service::MonorepoService;
 use ceres::protocol::{SmartProtocol, TransportProtocol};
 use common::model::{CommonOptions, GetParams};
@@ -106,6 +107,7 @@ pub async fn start_server(options: &HttpOptions) {
         },
         monorepo_service: MonorepoService {
             storage: state.context.services.mega_storage.clone(),
+            build_queue: build_hook::build_queue(state.context.services.mega_storage.clone()),
         },
     };
 
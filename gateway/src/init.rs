@@ -1,5 +1,6 @@
 use clap::Args;
 
+use ceres::monorepo::service::MonorepoService;
 use common::enums::DataSource;
 use jupiter::context::Context;
 
@@ -11,6 +12,10 @@ pub struct InitOptions {
 
 pub async fn init_monorepo(options: &InitOptions) -> Result<(), Box<dyn std::error::Error>> {
     let context = Context::new(&options.data_source).await;
-    context.services.mega_storage.init_monorepo().await;
+    let service = MonorepoService {
+        storage: context.services.mega_storage.clone(),
+        build_queue: None,
+    };
+    service.init_monorepo().await?;
     Ok(())
 }
@@ -4,6 +4,7 @@
 //!
 
 use std::fmt::Display;
+use std::path::{Path, PathBuf};
 
 use bstr::ByteSlice;
 use colored::Colorize;
@@ -89,10 +90,31 @@ impl Hash {
     pub fn to_data(self) -> Vec<u8> {
         self.0.repeatn(1)
     }
+
+    /// The path of the loose object with this hash below `objects_dir`, in the
+    /// `objects/<first 2 hex chars>/<remaining 38 hex chars>` fanout layout of Git.
+    /// # Example
+    /// ```
+    /// use std::path::{Path, PathBuf};
+    /// use git::hash::Hash;
+    ///
+    /// let hash = Hash::new_from_str("8ab686eafeb1f44702738c8b0f24f2567c36da6d");
+    /// assert_eq!(
+    ///     hash.loose_path(Path::new(".git/objects")),
+    ///     PathBuf::from(".git/objects/8a/b686eafeb1f44702738c8b0f24f2567c36da6d")
+    /// );
+    /// ```
+    pub fn loose_path(&self, objects_dir: &Path) -> PathBuf {
+        let hex = self.to_plain_str();
+        let (folder, file) = hex.split_at(2);
+        objects_dir.join(folder).join(file)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::path::{Path, PathBuf};
+
     use crate::hash::Hash;
 
     #[test]
@@ -137,6 +159,15 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_hash_loose_path() {
+        let hash = Hash::new_from_str("8ab686eafeb1f44702738c8b0f24f2567c36da6d");
+        assert_eq!(
+            hash.loose_path(Path::new("tests/data/objects")),
+            PathBuf::from("tests/data/objects/8a/b686eafeb1f44702738c8b0f24f2567c36da6d")
+        );
+    }
+
     #[test]
     fn test_hash_from_bytes() {
         let hash = Hash::new_from_bytes(&[
@@ -7,7 +7,7 @@
 use std::fs::{create_dir_all, File};
 use std::io::prelude::*;
 use std::io::BufReader;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 use bstr::ByteSlice;
@@ -18,6 +18,9 @@ use crate::errors::GitError;
 use crate::hash::Hash;
 use crate::internal::ObjectType;
 
+/// The longest loose object header, `commit ` followed by the digits of a `usize`.
+const MAX_HEADER_LEN: usize = 32;
+
 /// `Meta` struct that provides metadata information for Git objects, including object type,
 /// object ID (represented by a Hash struct), object size, object data, and delta header.
 ///
@@ -96,6 +99,14 @@ impl Meta {
         }
     }
 
+    /// Build the meta of an object held in memory, e.g. received over the network, from its
+    /// type and data. The id is computed as for a loose object, without touching the file system.
+    /// Delta objects only exist in packs and are rejected.
+    pub fn new_from_bytes(object_type: ObjectType, data: Vec<u8>) -> Result<Self, GitError> {
+        object_type.to_data()?;
+        Ok(Meta::new_from_data_with_object_type(object_type, data))
+    }
+
     #[allow(unused)]
     pub fn new_from_data(bytes: Vec<u8>) -> Result<Self, GitError> {
         let type_index = bytes.find_byte(0x20).unwrap();
@@ -138,14 +149,50 @@ impl Meta {
         Self::new_from_data(decoded)
     }
 
+    /// Read the type and size of the loose object at `path` from its `<type> <size>\0` header.
+    ///
+    /// Only the header is inflated, so this is cheap even for large blobs, e.g. for
+    /// `cat-file -t` and `cat-file -s`.
+    pub fn peek_header(path: &Path) -> Result<(ObjectType, usize), GitError> {
+        let file = File::open(path).map_err(|e| GitError::InvalidObjectInfo(e.to_string()))?;
+        let mut decoder = ZlibDecoder::new(BufReader::new(file));
+
+        let mut header = Vec::new();
+        let mut byte = [0; 1];
+        while header.len() < MAX_HEADER_LEN {
+            decoder
+                .read_exact(&mut byte)
+                .map_err(|e| GitError::InvalidObjectInfo(e.to_string()))?;
+            if byte[0] == b'\0' {
+                let header = String::from_utf8_lossy(&header);
+                let (object_type, size) = header
+                    .split_once(' ')
+                    .ok_or_else(|| GitError::InvalidObjectInfo(header.to_string()))?;
+                let size = size
+                    .parse::<usize>()
+                    .map_err(|_| GitError::InvalidObjectInfo(header.to_string()))?;
+                return Ok((ObjectType::from_string(object_type)?, size));
+            }
+            header.push(byte[0]);
+        }
+        Err(GitError::InvalidObjectInfo(format!(
+            "No object header in {}",
+            path.display()
+        )))
+    }
+
+    /// The folder of the loose object below the objects directory, see [`Hash::loose_path`].
     #[allow(unused)]
     pub fn to_folder_name(&self) -> String {
-        self.id.to_plain_str()[..2].to_string()
+        let path = self.id.loose_path(Path::new(""));
+        path.parent().unwrap().to_string_lossy().into_owned()
     }
 
+    /// The file name of the loose object in its folder, see [`Hash::loose_path`].
     #[allow(unused)]
     pub fn to_file_name(&self) -> String {
-        self.id.to_plain_str()[2..].to_string()
+        let path = self.id.loose_path(Path::new(""));
+        path.file_name().unwrap().to_string_lossy().into_owned()
     }
 
     /// Write the object to the file system with crate folder at the same time.
@@ -175,15 +222,12 @@ impl Meta {
         let c = e.finish().unwrap();
 
         // Create the folder
-        let mut path = PathBuf::from(root);
-        path.push(&self.to_folder_name());
-        create_dir_all(&path)
-            .with_context(|| format!("Failed to create directory: {}", path.display()))
+        let path = self.id.loose_path(Path::new(root));
+        let folder = path.parent().unwrap();
+        create_dir_all(folder)
+            .with_context(|| format!("Failed to create directory: {}", folder.display()))
             .unwrap();
 
-        // Add filename to the path
-        path.push(&self.to_file_name());
-
         // Create the file
         let mut file = File::create(&path)
             .with_context(|| format!("Failed to create file: {}", path.display()))
@@ -202,10 +246,12 @@ impl Meta {
 mod tests {
 
     use std::env;
-    use std::fs::remove_file;
+    use std::fs::{remove_file, OpenOptions};
     use std::path::PathBuf;
 
+    use crate::hash::Hash;
     use crate::internal::object::meta::Meta;
+    use crate::internal::object::tree::{Tree, TreeItem, TreeItemMode};
     use crate::internal::ObjectType;
 
     #[test]
@@ -231,6 +277,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_new_from_bytes_matches_file() {
+        let mut source = PathBuf::from(env::current_dir().unwrap().parent().unwrap());
+        source.push("tests/data/objects/f9/a1667a0dfce06819394c2aad557a04e9a13e56");
+        let from_file = Meta::new_from_file(source.to_str().unwrap()).unwrap();
+
+        let item = TreeItem::new(
+            TreeItemMode::Blob,
+            Hash::new_from_str("8ab686eafeb1f44702738c8b0f24f2567c36da6d"),
+            "hello-world".to_string(),
+        );
+        let tree = Tree::new_from_tree_items(vec![item]).unwrap();
+        let meta = Meta::new_from_bytes(ObjectType::Tree, tree.to_data().unwrap()).unwrap();
+        assert_eq!(meta, from_file);
+        assert_eq!(tree.id, from_file.id);
+
+        assert!(Meta::new_from_bytes(ObjectType::OffsetDelta, vec![]).is_err());
+    }
+
     #[test]
     fn test_to_folder_name() {
         let meta = Meta::new_from_data_with_object_type(
@@ -304,4 +369,22 @@ mod tests {
 
         assert!(file.exists());
     }
+
+    #[test]
+    fn test_peek_header() {
+        let meta = Meta::new_from_data_with_object_type(ObjectType::Blob, vec![b'a'; 8 << 20]);
+        let root = env::temp_dir().join("mega_peek_header");
+        let path = meta.to_file(root.to_str().unwrap()).unwrap();
+
+        // cut the compressed body off, the header is all that's read
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(64)
+            .unwrap();
+        let header = Meta::peek_header(&path);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(header.unwrap(), (ObjectType::Blob, 8 << 20));
+    }
 }
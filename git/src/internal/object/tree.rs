@@ -23,6 +23,7 @@ use entity::objects;
 
 use crate::errors::GitError;
 use crate::hash::Hash;
+use crate::internal::object::meta::Meta;
 use crate::internal::object::ObjectT;
 use crate::internal::ObjectType;
 
@@ -269,9 +270,8 @@ impl Tree {
         for item in &tree_items {
             data.extend_from_slice(item.to_data().as_slice());
         }
-        //TODO : Fixme : deal with the hash value
         Ok(Tree {
-            id: Hash::new(&data),
+            id: Meta::new_from_bytes(ObjectType::Tree, data)?.id,
             tree_items,
         })
     }
@@ -4,6 +4,7 @@ use std::{collections::HashSet, sync::Arc};
 
 use anyhow::Result;
 use async_recursion::async_recursion;
+use futures::future::try_join_all;
 use itertools::Itertools;
 use sea_orm::ActiveValue::NotSet;
 use sea_orm::{DbErr, Set, TransactionTrait};
@@ -22,6 +23,15 @@ use crate::internal::object::ObjectT;
 use crate::internal::pack::encode::pack_encode;
 use crate::protocol::PackProtocol;
 use crate::structure::nodes::NodeBuilder;
+use crate::utils;
+
+/// The number of batches [`PackProtocol::traverse_want_trees`] loads at once, read from
+/// `GIT_INTERNAL_TRAVERSE_CONCURRENCY`.
+fn traverse_concurrency() -> usize {
+    let mut concurrency = 8;
+    utils::get_env_number("GIT_INTERNAL_TRAVERSE_CONCURRENCY", &mut concurrency);
+    concurrency
+}
 
 impl PackProtocol {
     /// Asynchronously retrieves the full pack data for the specified repository path.
@@ -38,6 +48,7 @@ impl PackProtocol {
     pub async fn get_full_pack_data(&self, repo_path: &Path) -> Result<Vec<u8>, GitError> {
         // container for reserve all commit,blob and tree objs
         let mut hash_meta: HashMap<Hash, Arc<dyn ObjectT>> = HashMap::new();
+        let concurrency = traverse_concurrency();
         let all_commits: Vec<Commit> = self
             .storage
             .get_all_commits_by_path(repo_path.to_str().unwrap())
@@ -63,6 +74,7 @@ impl PackProtocol {
                 all_trees.get(&c.tree_id.to_plain_str()).unwrap(),
                 &mut hash_meta,
                 &HashSet::new(),
+                concurrency,
             )
             .await;
             hash_meta.insert(c.id, Arc::new(c));
@@ -89,6 +101,7 @@ impl PackProtocol {
         have: Vec<String>,
     ) -> Result<Vec<u8>, GitError> {
         let mut hash_meta: HashMap<Hash, Arc<dyn ObjectT>> = HashMap::new();
+        let concurrency = traverse_concurrency();
         let mut commit_id = String::new();
         let exist_want_objs = self.storage.get_obj_data_by_ids(want).await.unwrap();
         for obj in exist_want_objs {
@@ -184,6 +197,7 @@ impl PackProtocol {
                 want_trees.get(&c.tree_id.to_plain_str()).unwrap(),
                 &mut hash_meta,
                 &exist_objs,
+                concurrency,
             )
             .await;
             hash_meta.insert(c.id, Arc::new(c));
@@ -264,40 +278,52 @@ impl PackProtocol {
         exist_objs.insert(t.id);
     }
 
-    // retrieve all sub trees recursively
-    #[async_recursion]
+    // retrieve all sub trees level by level, the unseen entries of a level are loaded in at
+    // most `concurrency` batches at once
     async fn traverse_want_trees(
         &self,
         want_t: &objects::Model,
         all_objects: &mut HashMap<Hash, Arc<dyn ObjectT>>,
         exist_objs: &HashSet<Hash>,
+        concurrency: usize,
     ) {
-        let mut t = Tree::new_from_data(want_t.data.clone());
-        t.set_hash(Hash::new_from_str(&want_t.git_id));
-
-        let mut search_child_ids = vec![];
-        for item in &t.tree_items {
-            if !all_objects.contains_key(&item.id) && !exist_objs.contains(&item.id) {
-                search_child_ids.push(item.id.to_plain_str());
+        let mut level = vec![want_t.clone()];
+        while !level.is_empty() {
+            let mut search_child_ids = vec![];
+            let mut searched = HashSet::new();
+            for want_t in level {
+                let mut t = Tree::new_from_data(want_t.data);
+                t.set_hash(Hash::new_from_str(&want_t.git_id));
+                for item in &t.tree_items {
+                    // the same subtree may be shared by several trees of a level
+                    if !all_objects.contains_key(&item.id)
+                        && !exist_objs.contains(&item.id)
+                        && searched.insert(item.id)
+                    {
+                        search_child_ids.push(item.id.to_plain_str());
+                    }
+                }
+                all_objects.insert(t.id, Arc::new(t));
             }
-        }
-        let objs = self
-            .storage
-            .get_obj_data_by_ids(search_child_ids)
-            .await
-            .unwrap();
-        for obj in objs {
-            if obj.object_type == "tree" {
-                self.traverse_want_trees(&obj, all_objects, exist_objs)
-                    .await;
-            } else {
-                let mut blob = Blob::new_from_data(obj.data.clone());
-                let blob_id = Hash::new_from_str(&obj.git_id.clone());
-                blob.set_hash(blob_id);
-                all_objects.insert(blob_id, Arc::new(blob));
+
+            let batch_size = search_child_ids.len().div_ceil(concurrency.max(1)).max(1);
+            let batches = search_child_ids
+                .chunks(batch_size)
+                .map(|ids| self.storage.get_obj_data_by_ids(ids.to_vec()));
+            let objs = try_join_all(batches).await.unwrap();
+
+            level = vec![];
+            for obj in objs.into_iter().flatten() {
+                if obj.object_type == "tree" {
+                    level.push(obj);
+                } else {
+                    let mut blob = Blob::new_from_data(obj.data);
+                    let blob_id = Hash::new_from_str(&obj.git_id);
+                    blob.set_hash(blob_id);
+                    all_objects.insert(blob_id, Arc::new(blob));
+                }
             }
         }
-        all_objects.insert(t.id, Arc::new(t));
     }
 
     // TODO: Consider the scenario of deleting a repo
@@ -608,3 +634,146 @@ pub async fn get_objects_vec_from_mr<T: ObjectT>(
         .collect();
     result
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use sea_orm::{DatabaseConnection, DatabaseTransaction};
+
+    use common::errors::MegaError;
+    use entity::{commit, objects, refs};
+    use storage::driver::database::storage::ObjectStorage;
+
+    use crate::hash::Hash;
+    use crate::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+    use crate::protocol::{PackProtocol, Protocol};
+
+    /// Serves objects from a map and records the ids asked for, the other queries are never
+    /// made.
+    #[derive(Default)]
+    struct MapStorage {
+        connection: DatabaseConnection,
+        objects: HashMap<String, objects::Model>,
+        requested: Mutex<Vec<String>>,
+    }
+
+    impl MapStorage {
+        fn insert(&mut self, id: Hash, object_type: &str, data: Vec<u8>) {
+            let model = objects::Model {
+                id: self.objects.len() as i64,
+                git_id: id.to_plain_str(),
+                object_type: object_type.to_owned(),
+                data,
+                link: None,
+            };
+            self.objects.insert(model.git_id.clone(), model);
+        }
+
+        fn insert_tree(&mut self, items: Vec<TreeItem>) -> Hash {
+            let tree = Tree::new_from_tree_items(items).unwrap();
+            self.insert(tree.id, "tree", tree.to_data().unwrap());
+            tree.id
+        }
+    }
+
+    #[async_trait]
+    impl ObjectStorage for MapStorage {
+        fn get_connection(&self) -> &DatabaseConnection {
+            &self.connection
+        }
+
+        async fn save_obj_data_to_db(
+            &self,
+            _txn: Option<&DatabaseTransaction>,
+            _obj_data: Vec<objects::ActiveModel>,
+        ) -> Result<bool, MegaError> {
+            unimplemented!()
+        }
+
+        async fn get_obj_data_by_ids(
+            &self,
+            git_ids: Vec<String>,
+        ) -> Result<Vec<objects::Model>, MegaError> {
+            self.requested.lock().unwrap().extend(git_ids.clone());
+            Ok(git_ids
+                .iter()
+                .filter_map(|x| self.objects.get(x).cloned())
+                .collect())
+        }
+
+        async fn search_refs(&self, _path_str: &str) -> Result<Vec<refs::Model>, MegaError> {
+            unimplemented!()
+        }
+
+        async fn search_commits(&self, _path_str: &str) -> Result<Vec<commit::Model>, MegaError> {
+            unimplemented!()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_traverse_want_trees_concurrently() {
+        let mut storage = MapStorage::default();
+        let blob = |storage: &mut MapStorage, content: &str| {
+            let id = Hash::new(&content.as_bytes().to_vec());
+            storage.insert(id, "blob", content.as_bytes().to_vec());
+            id
+        };
+        // every project shares the same license directory
+        let license_blob = blob(&mut storage, "MIT");
+        let license = storage.insert_tree(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            license_blob,
+            "LICENSE".to_owned(),
+        )]);
+        let existing = blob(&mut storage, "fetched before");
+        let mut root_items = vec![];
+        let mut expected = HashSet::from([license_blob, license]);
+        for i in 0..10 {
+            let main = blob(&mut storage, &format!("fn main() {{ {} }}", i));
+            let project = storage.insert_tree(vec![
+                TreeItem::new(TreeItemMode::Blob, existing, "CHANGELOG".to_owned()),
+                TreeItem::new(TreeItemMode::Tree, license, "license".to_owned()),
+                TreeItem::new(TreeItemMode::Blob, main, "main.rs".to_owned()),
+            ]);
+            root_items.push(TreeItem::new(
+                TreeItemMode::Tree,
+                project,
+                format!("project{}", i),
+            ));
+            expected.extend([main, project]);
+        }
+        let root = storage.insert_tree(root_items);
+        expected.insert(root);
+        let want_tree = storage.objects[&root.to_plain_str()].clone();
+        // the client has the changelog, it is neither loaded nor sent
+        let exist_objs = HashSet::from([existing]);
+
+        let storage = Arc::new(storage);
+        let protocol =
+            PackProtocol::compatibility_new(PathBuf::new(), storage.clone(), Protocol::Http);
+        for concurrency in [1, 4] {
+            let mut all_objects = HashMap::new();
+            protocol
+                .traverse_want_trees(&want_tree, &mut all_objects, &exist_objs, concurrency)
+                .await;
+            assert_eq!(
+                all_objects.keys().copied().collect::<HashSet<_>>(),
+                expected
+            );
+            for (id, object) in &all_objects {
+                assert_eq!(object.get_hash(), *id);
+            }
+            // the shared license tree and blob are loaded once
+            let requested = std::mem::take(&mut *storage.requested.lock().unwrap());
+            assert_eq!(requested.len(), expected.len() - 1);
+            assert_eq!(
+                requested.iter().collect::<HashSet<_>>().len(),
+                requested.len()
+            );
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 use sea_orm::{DeriveActiveEnum, EnumIter};
 
 #[derive(Clone, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
@@ -32,6 +34,17 @@ pub enum MergeStatus {
     Closed,
 }
 
+impl Display for MergeStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let status = match self {
+            MergeStatus::Open => "open",
+            MergeStatus::Merged => "merged",
+            MergeStatus::Closed => "closed",
+        };
+        write!(f, "{}", status)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
 #[sea_orm(rs_type = "String", db_type = "String(Some(1))")]
 pub enum RefType {
@@ -64,3 +77,26 @@ pub enum ConvType {
     #[sea_orm(string_value = "merged")]
     Merged,
 }
+
+#[derive(Clone, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(1))")]
+pub enum CommentKind {
+    #[sea_orm(string_value = "human")]
+    Human,
+    #[sea_orm(string_value = "bot")]
+    Bot,
+    /// Messages Mega writes itself, like force update notices.
+    #[sea_orm(string_value = "system")]
+    System,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Copy)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(1))")]
+pub enum BuildState {
+    #[sea_orm(string_value = "pending")]
+    Pending,
+    #[sea_orm(string_value = "success")]
+    Success,
+    #[sea_orm(string_value = "failed")]
+    Failed,
+}
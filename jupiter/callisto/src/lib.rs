@@ -17,8 +17,10 @@ pub mod mega_blob;
 pub mod mega_commit;
 pub mod mega_issue;
 pub mod mega_mr;
+pub mod mega_mr_build;
 pub mod mega_mr_comment;
 pub mod mega_mr_conv;
+pub mod mega_reflog;
 pub mod mega_refs;
 pub mod mega_tag;
 pub mod mega_tree;
@@ -11,7 +11,7 @@ pub struct Model {
     pub blob_id: String,
     pub commit_id: String,
     pub name: String,
-    pub size: i32,
+    pub size: i64,
     pub created_at: DateTime,
 }
 
@@ -15,6 +15,8 @@ pub struct Model {
     pub author: Option<String>,
     #[sea_orm(column_type = "Text", nullable)]
     pub committer: Option<String>,
+    /// The committer timestamp in seconds since the epoch, indexed for time range queries.
+    pub committer_time: i64,
     #[sea_orm(column_type = "Text", nullable)]
     pub content: Option<String>,
     pub created_at: DateTime,
@@ -16,6 +16,13 @@ pub struct Model {
     pub path: String,
     pub from_hash: String,
     pub to_hash: String,
+    /// Identifies the push which created or last updated the MR, see
+    /// `MergeRequest::push_key`.
+    pub idempotency_key: String,
+    /// The build started after the MR merged, if any.
+    pub build_id: Option<String>,
+    /// The `agent` capability of the client of that push, like `git/2.43.0`.
+    pub agent: Option<String>,
     pub created_at: DateTime,
     pub updated_at: DateTime,
 }
@@ -2,6 +2,8 @@
 
 use sea_orm::entity::prelude::*;
 
+use crate::db_enums::CommentKind;
+
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
 #[sea_orm(table_name = "mega_mr_comment")]
 pub struct Model {
@@ -11,6 +13,9 @@ pub struct Model {
     #[sea_orm(column_type = "Text")]
     pub comment: Option<String>,
     pub edited: bool,
+    /// The comment this one replies to, `None` for the start of a thread.
+    pub parent_comment_id: Option<i64>,
+    pub kind: CommentKind,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
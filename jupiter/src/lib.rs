@@ -2,3 +2,6 @@ pub mod context;
 pub mod raw_storage;
 pub mod storage;
 pub mod utils;
+
+#[cfg(any(test, feature = "mock"))]
+pub mod test_util;
@@ -0,0 +1,87 @@
+//! Compression of the objects kept in the raw storage.
+//!
+//! This only concerns how mega stores objects itself, packs and loose objects exchanged with git
+//! stay zlib compressed. The codec of an object is recorded in the [`Codec::extension`] of its
+//! file rather than in its data, so objects written with different codecs, or uncompressed, can
+//! be read side by side whatever their content.
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+/// The zstd level, the default of the zstd command line tool.
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Zlib,
+    Zstd,
+}
+
+impl Codec {
+    pub const ALL: [Codec; 2] = [Codec::Zlib, Codec::Zstd];
+
+    /// The extension of the files of objects compressed with this codec.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Codec::Zlib => "zz",
+            Codec::Zstd => "zst",
+        }
+    }
+
+    /// Compress `data`.
+    pub fn encode(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::Zlib => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Codec::Zstd => zstd::stream::encode_all(data, ZSTD_LEVEL),
+        }
+    }
+
+    /// Decompress `stored`, compressed with this codec.
+    pub fn decode(self, stored: &[u8]) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        match self {
+            Codec::Zlib => {
+                ZlibDecoder::new(stored).read_to_end(&mut data)?;
+            }
+            Codec::Zstd => zstd::stream::copy_decode(stored, &mut data)?,
+        }
+        Ok(data)
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "zlib" => Ok(Codec::Zlib),
+            "zstd" => Ok(Codec::Zstd),
+            _ => Err(format!("Unknown object codec `{}`", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Codec;
+
+    #[test]
+    fn test_zstd_round_trip() {
+        let data = "fn main() {}\n".repeat(1000).into_bytes();
+        let encoded = Codec::Zstd.encode(&data).unwrap();
+        assert!(encoded.len() < data.len() / 10);
+        assert_eq!(Codec::Zstd.decode(&encoded).unwrap(), data);
+
+        let encoded = Codec::Zlib.encode(&data).unwrap();
+        assert_eq!(Codec::Zlib.decode(&encoded).unwrap(), data);
+        assert!(Codec::Zstd.decode(&encoded).is_err());
+        assert_eq!("ZSTD".parse::<Codec>(), Ok(Codec::Zstd));
+    }
+}
@@ -1,24 +1,58 @@
 use std::fs::{self, OpenOptions};
-use std::io::prelude::*;
+use std::io::{self, prelude::*};
 use std::path::{Path, PathBuf};
 
 use async_trait::async_trait;
 use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 use callisto::db_enums::StorageType;
 use common::errors::MegaError;
+use common::utils::generate_id;
+use venus::hash::HashWriter;
 
+use crate::raw_storage::codec::Codec;
 use crate::raw_storage::RawStorage;
 
 #[derive(Default)]
 pub struct LocalStorage {
     base_path: PathBuf,
+    /// Compresses objects put from now on, `None` stores them as they are.
+    codec: Option<Codec>,
 }
 
 impl LocalStorage {
     pub fn init(base_path: PathBuf) -> LocalStorage {
         fs::create_dir_all(&base_path).expect("Create directory failed!");
-        LocalStorage { base_path }
+        LocalStorage {
+            base_path,
+            codec: None,
+        }
+    }
+
+    pub fn with_codec(mut self, codec: Option<Codec>) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// The path of the object `object_id` when stored uncompressed.
+    fn object_path(&self, repo_name: &str, object_id: &str) -> PathBuf {
+        Path::new(&self.base_path)
+            .join(repo_name)
+            .join("objects")
+            .join(self.transform_path(object_id))
+    }
+
+    /// The file of the object `object_id` and the codec it was compressed with, if stored.
+    fn find_object(&self, repo_name: &str, object_id: &str) -> Option<(PathBuf, Option<Codec>)> {
+        let path = self.object_path(repo_name, object_id);
+        if path.exists() {
+            return Some((path, None));
+        }
+        Codec::ALL.into_iter().find_map(|codec| {
+            let path = path.with_extension(codec.extension());
+            path.exists().then_some((path, Some(codec)))
+        })
     }
 }
 
@@ -68,15 +102,17 @@ impl RawStorage for LocalStorage {
     }
 
     async fn get_object(&self, repo_name: &str, object_id: &str) -> Result<Bytes, MegaError> {
-        let path = Path::new(&self.base_path)
-            .join(repo_name)
-            .join("objects")
-            .join(self.transform_path(object_id));
+        let (path, codec) = self
+            .find_object(repo_name, object_id)
+            .unwrap_or_else(|| (self.object_path(repo_name, object_id), None));
         let mut file =
             fs::File::open(&path).unwrap_or_else(|_| panic!("Open file:{:?} failed!", path));
         let mut buffer = Vec::new();
         file.read_to_end(&mut buffer).unwrap();
-        Ok(Bytes::from(buffer))
+        match codec {
+            Some(codec) => Ok(Bytes::from(codec.decode(&buffer)?)),
+            None => Ok(Bytes::from(buffer)),
+        }
     }
 
     async fn put_object(
@@ -85,25 +121,94 @@ impl RawStorage for LocalStorage {
         object_id: &str,
         body_content: &[u8],
     ) -> Result<String, MegaError> {
-        let path = Path::new(&self.base_path)
-            .join(repo_name)
-            .join("objects")
-            .join(self.transform_path(object_id));
+        let mut path = self.object_path(repo_name, object_id);
         let dir = path.parent().unwrap();
         fs::create_dir_all(dir).expect("Create directory failed!");
 
+        let data = match self.codec {
+            Some(codec) => {
+                path.set_extension(codec.extension());
+                codec.encode(body_content)?
+            }
+            None => body_content.to_vec(),
+        };
         let mut file = fs::File::create(&path).expect("Open file failed");
-        file.write_all(body_content).expect("Write file failed");
+        file.write_all(&data).expect("Write file failed");
         Ok(path.to_str().unwrap().to_string())
     }
 
+    async fn delete_object(&self, repo_name: &str, object_id: &str) -> Result<(), MegaError> {
+        if let Some((path, _)) = self.find_object(repo_name, object_id) {
+            if let Err(err) = fs::remove_file(path) {
+                if err.kind() != io::ErrorKind::NotFound {
+                    return Err(err.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn put_object_stream(
+        &self,
+        repo_name: &str,
+        reader: &mut (dyn AsyncRead + Unpin + Send),
+    ) -> Result<(String, String), MegaError> {
+        // streamed blobs are too large to compress in memory, they are stored uncompressed
+        let objects = Path::new(&self.base_path).join(repo_name).join("objects");
+        fs::create_dir_all(&objects)?;
+        // on the same filesystem as the objects, so moving it into place is atomic
+        let tmp_path = objects.join(format!("tmp_{}", generate_id()));
+        let size = match write_stream(&tmp_path, reader).await {
+            Ok(size) => size,
+            Err(err) => {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(err.into());
+            }
+        };
+
+        let mut hasher = HashWriter::new(io::sink());
+        write!(hasher, "blob {}\0", size)?;
+        io::copy(&mut fs::File::open(&tmp_path)?, &mut hasher)?;
+        let object_id = hasher.finalize().to_plain_str();
+
+        let path = match self.find_object(repo_name, &object_id) {
+            Some((path, _)) => {
+                fs::remove_file(&tmp_path)?;
+                path
+            }
+            None => {
+                let path = objects.join(self.transform_path(&object_id));
+                fs::create_dir_all(path.parent().unwrap())?;
+                fs::rename(&tmp_path, &path)?;
+                path
+            }
+        };
+        Ok((object_id, path.to_str().unwrap().to_string()))
+    }
+
     fn exist_object(&self, repo_name: &str, object_id: &str) -> bool {
-        let path = Path::new(&self.base_path)
-            .join(repo_name)
-            .join("objects")
-            .join(self.transform_path(object_id));
-        Path::exists(&path)
+        self.find_object(repo_name, object_id).is_some()
+    }
+}
+
+/// Copy `reader` to a new file at `path`, returning the number of bytes written.
+async fn write_stream(
+    path: &Path,
+    reader: &mut (dyn AsyncRead + Unpin + Send),
+) -> io::Result<usize> {
+    let mut file = fs::File::create(path)?;
+    let mut buf = vec![0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        file.write_all(&buf[..n])?;
+        size += n;
     }
+    file.sync_all()?;
+    Ok(size)
 }
 
 #[cfg(test)]
@@ -112,6 +217,7 @@ mod tests {
     use std::path::Path;
     use std::{env, path::PathBuf};
 
+    use crate::raw_storage::codec::Codec;
     use crate::raw_storage::{local_storage::LocalStorage, RawStorage};
 
     // #[test]
@@ -129,6 +235,31 @@ mod tests {
         assert!(local_storage.exist_object("", &oid));
     }
 
+    #[tokio::test]
+    async fn test_compressed_object() {
+        let dir = env::temp_dir().join("mega_compressed_object");
+        let _ = fs::remove_dir_all(&dir);
+        let oid = "8ab686eafeb1f44702738c8b0f24f2567c36da6d";
+        let content = "fn main() {}\n".repeat(100).into_bytes();
+
+        let storage = LocalStorage::init(dir.clone()).with_codec(Some(Codec::Zstd));
+        let path = storage.put_object("", oid, &content).await.unwrap();
+        assert!(fs::read(path).unwrap().len() < content.len());
+        assert_eq!(storage.get_object("", oid).await.unwrap(), content);
+
+        // objects stored before compression was enabled are still read, even those whose data
+        // looks compressed
+        let oid = "3b18e512dba79e4c8300dd08aeb37f8e728b8dad";
+        let looks_compressed = Codec::Zstd.encode(b"hello world").unwrap();
+        LocalStorage::init(dir.clone())
+            .put_object("", oid, &looks_compressed)
+            .await
+            .unwrap();
+        assert_eq!(storage.get_object("", oid).await.unwrap(), looks_compressed);
+        assert!(storage.exist_object("", oid));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
     #[tokio::test]
     async fn test_put_ref() {
         let test_path = PathBuf::from(env::current_dir().unwrap().parent().unwrap()).join("test");
@@ -9,13 +9,16 @@ use std::{
 use async_trait::async_trait;
 use bytes::Bytes;
 use handlebars::Handlebars;
+use tokio::io::AsyncRead;
 
 use callisto::db_enums::StorageType;
 use common::errors::MegaError;
 use venus::internal::pack::entry::Entry;
 
+use crate::raw_storage::codec::Codec;
 use crate::raw_storage::local_storage::LocalStorage;
 
+pub mod codec;
 pub mod local_storage;
 
 #[derive(Debug, Clone, Default)]
@@ -57,6 +60,19 @@ pub trait RawStorage: Sync + Send {
         body_content: &[u8],
     ) -> Result<String, MegaError>;
 
+    /// Delete the object `object_id`, deleting a missing object is a no-op.
+    async fn delete_object(&self, repo_name: &str, object_id: &str) -> Result<(), MegaError>;
+
+    /// Store the blob content read from `reader` and return its object id and its location, as
+    /// [`RawStorage::put_object`] returns it. The content is written to a temporary location
+    /// first and only moved into place once complete, storing a blob which already exists is a
+    /// no-op.
+    async fn put_object_stream(
+        &self,
+        repo_name: &str,
+        reader: &mut (dyn AsyncRead + Unpin + Send),
+    ) -> Result<(String, String), MegaError>;
+
     // async fn parse_blob_link(&self, data: Vec<u8>) -> Result<BlobLink, MegaError> {
     //     let mut reader = BufReader::new(data.as_slice());
     //     let mut blink = BlobLink::default();
@@ -119,11 +135,27 @@ pub trait RawStorage: Sync + Send {
     }
 }
 
+/// The codec new objects are compressed with, from `MEGA_OBJ_CODEC`. Unset, `none` or an
+/// unknown codec stores them uncompressed.
+fn object_codec() -> Option<Codec> {
+    let codec = env::var("MEGA_OBJ_CODEC").ok()?;
+    if codec.is_empty() || codec.eq_ignore_ascii_case("none") {
+        return None;
+    }
+    match codec.parse() {
+        Ok(codec) => Some(codec),
+        Err(err) => {
+            tracing::warn!("{}, storing objects uncompressed", err);
+            None
+        }
+    }
+}
+
 pub async fn init(storage_type: String, path: String) -> Arc<dyn RawStorage> {
     match storage_type.as_str() {
         "LOCAL" => {
             let base_path = PathBuf::from(path);
-            Arc::new(LocalStorage::init(base_path))
+            Arc::new(LocalStorage::init(base_path).with_codec(object_codec()))
         }
         // "REMOTE" => Arc::new(RemoteStorage::init(path).await),
         _ => unreachable!(
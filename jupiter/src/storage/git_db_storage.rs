@@ -43,12 +43,7 @@ impl GitStorageProvider for GitDbStorage {
     }
 
     async fn remove_ref(&self, repo: &Repo, refs: &RefCommand) -> Result<(), MegaError> {
-        import_refs::Entity::delete_many()
-            .filter(import_refs::Column::RepoId.eq(repo.repo_id))
-            .filter(import_refs::Column::RefName.eq(refs.ref_name.clone()))
-            .exec(self.get_connection())
-            .await?;
-        Ok(())
+        self.delete_ref(repo, &refs.ref_name).await
     }
 
     async fn get_ref(&self, repo: &Repo) -> Result<Vec<Refs>, MegaError> {
@@ -95,6 +90,17 @@ impl GitDbStorage {
         }
     }
 
+    /// Delete the ref `ref_name` of `repo`, e.g. `refs/heads/feature`. Deleting a ref which
+    /// doesn't exist does nothing.
+    pub async fn delete_ref(&self, repo: &Repo, ref_name: &str) -> Result<(), MegaError> {
+        import_refs::Entity::delete_many()
+            .filter(import_refs::Column::RepoId.eq(repo.repo_id))
+            .filter(import_refs::Column::RefName.eq(ref_name))
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
     pub fn mock() -> Self {
         GitDbStorage {
             connection: Arc::new(DatabaseConnection::default()),
@@ -250,6 +256,18 @@ impl GitDbStorage {
             .unwrap())
     }
 
+    pub async fn get_blobs_by_hashes(
+        &self,
+        repo: &Repo,
+        hashes: Vec<String>,
+    ) -> Result<Vec<git_blob::Model>, MegaError> {
+        Ok(git_blob::Entity::find()
+            .filter(git_blob::Column::RepoId.eq(repo.repo_id))
+            .filter(git_blob::Column::BlobId.is_in(hashes))
+            .all(self.get_connection())
+            .await?)
+    }
+
     pub async fn get_tags_by_repo_id(&self, repo: &Repo) -> Result<Vec<git_tag::Model>, MegaError> {
         Ok(git_tag::Entity::find()
             .filter(git_tag::Column::RepoId.eq(repo.repo_id))
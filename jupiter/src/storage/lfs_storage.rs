@@ -31,8 +31,7 @@ impl LfsStorage {
     ) -> Result<InsertResult<lfs_objects::ActiveModel>, MegaError> {
         Ok(lfs_objects::Entity::insert(object.into_active_model())
             .exec(self.get_connection())
-            .await
-            .unwrap())
+            .await?)
     }
 
     pub async fn get_lfs_object(
@@ -41,8 +40,7 @@ impl LfsStorage {
     ) -> Result<Option<lfs_objects::Model>, MegaError> {
         let result = lfs_objects::Entity::find_by_id(oid)
             .one(self.get_connection())
-            .await
-            .unwrap();
+            .await?;
         Ok(result)
     }
 
@@ -1,37 +1,56 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
+use std::num::NonZeroUsize;
 use std::rc::Rc;
 use std::{env, sync::Arc};
 
-use sea_orm::ActiveValue::NotSet;
+use futures::stream::{self, BoxStream};
+use futures::{future, Stream, StreamExt};
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::ActiveValue::{NotSet, Set};
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, QueryFilter,
-    QuerySelect,
+    ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait, FromQueryResult,
+    IntoActiveModel, QueryFilter, QueryOrder, QuerySelect,
 };
+use tokio::sync::broadcast;
 
-use callisto::db_enums::{ConvType, MergeStatus};
+use callisto::db_enums::{CommentKind, ConvType, MergeStatus, StorageType};
 use callisto::{
-    mega_commit, mega_mr, mega_mr_comment, mega_mr_conv, mega_refs, mega_tree, raw_blob,
+    mega_blob, mega_commit, mega_mr, mega_mr_build, mega_mr_comment, mega_mr_conv, mega_reflog,
+    mega_refs, mega_tag, mega_tree, raw_blob,
 };
 use common::errors::MegaError;
-use common::utils::generate_id;
+use common::utils::{generate_id, ZERO_ID};
 use ganymede::mega_node::MegaNode;
 use ganymede::model::converter::MegaModelConverter;
 use ganymede::model::create_file::CreateFileInfo;
+use venus::internal::object::signature::Signature;
+use venus::internal::object::tag::Tag;
+use venus::internal::object::types::ObjectType;
 use venus::internal::object::MegaObjectModel;
 use venus::internal::{object::commit::Commit, pack::entry::Entry};
+use venus::monorepo::event::RefUpdateEvent;
 use venus::monorepo::mega_refs::MegaRefs;
-use venus::monorepo::mr::MergeRequest;
+use venus::monorepo::mr::{BuildStatus, MergeRequest};
+use venus::monorepo::reflog::ReflogEntry;
+use venus::monorepo::repo_size::RepoSize;
+use venus::monorepo::storage_tier::StorageTier;
 
 use crate::raw_storage::{self, RawStorage};
 use crate::storage::batch_save_model;
+use crate::storage::object_cache::ObjectCache;
 
 #[derive(Clone)]
 pub struct MegaStorage {
     pub raw_storage: Arc<dyn RawStorage>,
     pub connection: Arc<DatabaseConnection>,
     pub raw_obj_threshold: usize,
+    pub ref_events: broadcast::Sender<RefUpdateEvent>,
+    pub object_cache: Option<Arc<ObjectCache>>,
 }
 
+/// Events not yet received by a slow subscriber are dropped once this many newer ones are sent.
+const REF_EVENT_CAPACITY: usize = 128;
+
 impl MegaStorage {
     pub fn get_connection(&self) -> &DatabaseConnection {
         &self.connection
@@ -44,10 +63,18 @@ impl MegaStorage {
             .unwrap();
         let storage_type = env::var("MEGA_RAW_STORAGE").unwrap();
         let path = env::var("MEGA_OBJ_LOCAL_PATH").unwrap();
+        // the cache is disabled unless a size is configured
+        let object_cache = env::var("MEGA_OBJ_CACHE_SIZE")
+            .ok()
+            .and_then(|x| x.parse::<usize>().ok())
+            .and_then(NonZeroUsize::new)
+            .map(|x| Arc::new(ObjectCache::new(x)));
         MegaStorage {
             connection,
             raw_storage: raw_storage::init(storage_type, path).await,
             raw_obj_threshold,
+            ref_events: broadcast::channel(REF_EVENT_CAPACITY).0,
+            object_cache,
         }
     }
 
@@ -56,9 +83,29 @@ impl MegaStorage {
             connection: Arc::new(DatabaseConnection::default()),
             raw_storage: raw_storage::mock(),
             raw_obj_threshold: 1024,
+            ref_events: broadcast::channel(REF_EVENT_CAPACITY).0,
+            object_cache: None,
         }
     }
 
+    /// Keep up to `capacity` recently loaded commits and trees in memory.
+    pub fn with_object_cache(mut self, capacity: NonZeroUsize) -> Self {
+        self.object_cache = Some(Arc::new(ObjectCache::new(capacity)));
+        self
+    }
+
+    pub(crate) fn invalidate_cached<S: AsRef<str>>(&self, hashes: &[S]) {
+        if let Some(cache) = &self.object_cache {
+            cache.invalidate(hashes);
+        }
+    }
+
+    /// Subscribe to the [`RefUpdateEvent`]s of every ref created or updated from now on,
+    /// including the ones moved by merging an MR.
+    pub fn subscribe_ref_updates(&self) -> broadcast::Receiver<RefUpdateEvent> {
+        self.ref_events.subscribe()
+    }
+
     pub async fn save_ref(
         &self,
         path: &str,
@@ -78,7 +125,8 @@ impl MegaStorage {
             .insert(self.get_connection())
             .await
             .unwrap();
-        Ok(())
+        self.append_reflog(ReflogEntry::new(path, ZERO_ID, ref_commit_hash, "create"))
+            .await
     }
 
     pub async fn remove_refs(&self, path: &str) -> Result<(), MegaError> {
@@ -104,16 +152,112 @@ impl MegaStorage {
         Ok(result.map(|model| model.into()))
     }
 
+    /// Every ref of the monorepo, ordered by path.
+    pub async fn get_refs(&self) -> Result<Vec<MegaRefs>, MegaError> {
+        Ok(mega_refs::Entity::find()
+            .order_by_asc(mega_refs::Column::Path)
+            .all(self.get_connection())
+            .await?
+            .into_iter()
+            .map(|x| x.into())
+            .collect())
+    }
+
+    /// Move the existing ref `refs.id` to the commit and tree of `refs`. The ref is only updated
+    /// while it still holds the value just read, so the reflog records the commit actually
+    /// replaced even when the ref is moved concurrently.
     pub async fn update_ref(&self, refs: MegaRefs) -> Result<(), MegaError> {
-        let ref_data: mega_refs::Model = refs.into();
-        let mut ref_data: mega_refs::ActiveModel = ref_data.into();
-        ref_data.reset(mega_refs::Column::RefCommitHash);
-        ref_data.reset(mega_refs::Column::RefTreeHash);
-        ref_data.reset(mega_refs::Column::UpdatedAt);
-        ref_data.update(self.get_connection()).await.unwrap();
+        loop {
+            let old_hash = mega_refs::Entity::find_by_id(refs.id)
+                .one(self.get_connection())
+                .await?
+                .map(|x| x.ref_commit_hash)
+                .ok_or_else(|| MegaError::with_message(&format!("ref {} not found", refs.path)))?;
+            let result = mega_refs::Entity::update_many()
+                .col_expr(
+                    mega_refs::Column::RefCommitHash,
+                    Expr::value(refs.ref_commit_hash.as_str()),
+                )
+                .col_expr(
+                    mega_refs::Column::RefTreeHash,
+                    Expr::value(refs.ref_tree_hash.as_str()),
+                )
+                .col_expr(
+                    mega_refs::Column::UpdatedAt,
+                    Expr::value(chrono::Utc::now().naive_utc()),
+                )
+                .filter(mega_refs::Column::Id.eq(refs.id))
+                .filter(mega_refs::Column::RefCommitHash.eq(old_hash.as_str()))
+                .exec(self.get_connection())
+                .await?;
+            // otherwise the ref was moved since it was read, read it again
+            if result.rows_affected > 0 {
+                let log = ReflogEntry::new(&refs.path, &old_hash, &refs.ref_commit_hash, "update");
+                return self.append_reflog(log).await;
+            }
+        }
+    }
+
+    /// Move the ref at `path` to a new commit only if it still points to `expected_old`, checked
+    /// and updated in a single statement. Returns `false`, leaving the ref untouched, if the ref
+    /// was moved by someone else in the meantime or doesn't exist.
+    pub async fn save_ref_cas(
+        &self,
+        path: &str,
+        expected_old: &str,
+        ref_commit_hash: &str,
+        ref_tree_hash: &str,
+    ) -> Result<bool, MegaError> {
+        let result = mega_refs::Entity::update_many()
+            .col_expr(
+                mega_refs::Column::RefCommitHash,
+                Expr::value(ref_commit_hash),
+            )
+            .col_expr(mega_refs::Column::RefTreeHash, Expr::value(ref_tree_hash))
+            .col_expr(
+                mega_refs::Column::UpdatedAt,
+                Expr::value(chrono::Utc::now().naive_utc()),
+            )
+            .filter(mega_refs::Column::Path.eq(path))
+            .filter(mega_refs::Column::RefCommitHash.eq(expected_old))
+            .exec(self.get_connection())
+            .await?;
+        if result.rows_affected == 0 {
+            return Ok(false);
+        }
+        self.append_reflog(ReflogEntry::new(
+            path,
+            expected_old,
+            ref_commit_hash,
+            "update",
+        ))
+        .await?;
+        Ok(true)
+    }
+
+    pub async fn append_reflog(&self, entry: ReflogEntry) -> Result<(), MegaError> {
+        let event = RefUpdateEvent::from(&entry);
+        let model: mega_reflog::Model = entry.into();
+        mega_reflog::Entity::insert(model.into_active_model())
+            .exec(self.get_connection())
+            .await?;
+        // sending only fails when nobody is subscribed
+        let _ = self.ref_events.send(event);
         Ok(())
     }
 
+    /// Returns the recorded changes of the ref at `path`, oldest first.
+    pub async fn get_reflog(&self, path: &str) -> Result<Vec<ReflogEntry>, MegaError> {
+        Ok(mega_reflog::Entity::find()
+            .filter(mega_reflog::Column::RefName.eq(path))
+            .order_by_asc(mega_reflog::Column::CreatedAt)
+            .all(self.get_connection())
+            .await?
+            .into_iter()
+            .map(|x| x.into())
+            .collect())
+    }
+
     pub async fn get_open_mr(&self, path: &str) -> Result<Option<MergeRequest>, MegaError> {
         let model = mega_mr::Entity::find()
             .filter(mega_mr::Column::Path.eq(path))
@@ -128,6 +272,36 @@ impl MegaStorage {
         Ok(None)
     }
 
+    /// The MR at `path` which was closed last, if any.
+    pub async fn get_last_closed_mr(&self, path: &str) -> Result<Option<MergeRequest>, MegaError> {
+        let model = mega_mr::Entity::find()
+            .filter(mega_mr::Column::Path.eq(path))
+            .filter(mega_mr::Column::Status.eq(MergeStatus::Closed))
+            .order_by_desc(mega_mr::Column::UpdatedAt)
+            .one(self.get_connection())
+            .await?;
+        Ok(model.map(|x| x.into()))
+    }
+
+    /// The MR created or last updated by the push with `key`, whatever its status.
+    pub async fn get_mr_by_idempotency_key(
+        &self,
+        key: &str,
+    ) -> Result<Option<MergeRequest>, MegaError> {
+        let model = mega_mr::Entity::find()
+            .filter(mega_mr::Column::IdempotencyKey.eq(key))
+            .one(self.get_connection())
+            .await?;
+        Ok(model.map(|x| x.into()))
+    }
+
+    pub async fn get_mr(&self, mr_id: i64) -> Result<Option<MergeRequest>, MegaError> {
+        let model = mega_mr::Entity::find_by_id(mr_id)
+            .one(self.get_connection())
+            .await?;
+        Ok(model.map(|x| x.into()))
+    }
+
     pub async fn get_open_mr_by_id(&self, mr_id: i64) -> Result<Option<MergeRequest>, MegaError> {
         let model = mega_mr::Entity::find_by_id(mr_id)
             .filter(mega_mr::Column::Status.eq(MergeStatus::Open))
@@ -157,6 +331,32 @@ impl MegaStorage {
         Ok(())
     }
 
+    /// Save the status of the build of an MR, replacing the one saved before.
+    pub async fn save_build_status(&self, status: BuildStatus) -> Result<(), MegaError> {
+        let model: mega_mr_build::Model = status.into();
+        mega_mr_build::Entity::insert(model.into_active_model())
+            .on_conflict(
+                OnConflict::column(mega_mr_build::Column::MrId)
+                    .update_columns([
+                        mega_mr_build::Column::BuildId,
+                        mega_mr_build::Column::State,
+                        mega_mr_build::Column::LogUrl,
+                        mega_mr_build::Column::UpdatedAt,
+                    ])
+                    .to_owned(),
+            )
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_build_status(&self, mr_id: i64) -> Result<Option<BuildStatus>, MegaError> {
+        let model = mega_mr_build::Entity::find_by_id(mr_id)
+            .one(self.get_connection())
+            .await?;
+        Ok(model.map(|x| x.into()))
+    }
+
     pub async fn add_mr_conversation(
         &self,
         mr_id: i64,
@@ -176,12 +376,16 @@ impl MegaStorage {
         Ok(res.id)
     }
 
+    /// Add a comment to the MR `mr_id`, as a reply to `parent_comment_id` if given, and return
+    /// the id of the new comment.
     pub async fn add_mr_comment(
         &self,
         mr_id: i64,
         user_id: i64,
+        parent_comment_id: Option<i64>,
+        kind: CommentKind,
         comment: Option<String>,
-    ) -> Result<(), MegaError> {
+    ) -> Result<i64, MegaError> {
         let conv_id = self
             .add_mr_conversation(mr_id, user_id, ConvType::Comment)
             .await
@@ -191,10 +395,12 @@ impl MegaStorage {
             conv_id,
             comment,
             edited: false,
+            parent_comment_id,
+            kind,
         };
         let comment = comment.into_active_model();
-        comment.insert(self.get_connection()).await.unwrap();
-        Ok(())
+        let res = comment.insert(self.get_connection()).await.unwrap();
+        Ok(res.id)
     }
 
     pub async fn save_entry(&self, entry_list: Vec<Entry>) -> Result<(), MegaError> {
@@ -203,13 +409,18 @@ impl MegaStorage {
         let mut blobs = Vec::new();
         let mut raw_blobs = Vec::new();
         let mut tags = Vec::new();
+        let mut hashes = Vec::new();
 
         for entry in entry_list {
             let raw_obj = entry.process_entry();
             let model = raw_obj.convert_to_mega_model();
             match model {
-                MegaObjectModel::Commit(commit) => commits.push(commit.into_active_model()),
+                MegaObjectModel::Commit(commit) => {
+                    hashes.push(commit.commit_id.clone());
+                    commits.push(commit.into_active_model())
+                }
                 MegaObjectModel::Tree(tree) => {
+                    hashes.push(tree.tree_id.clone());
                     trees.push(tree.clone().into_active_model());
                 }
                 MegaObjectModel::Blob(blob, raw) => {
@@ -233,11 +444,13 @@ impl MegaStorage {
             .await
             .unwrap();
         batch_save_model(self.get_connection(), tags).await.unwrap();
+        self.invalidate_cached(&hashes);
         Ok(())
     }
 
-    pub async fn init_monorepo(&self) {
-        let converter = MegaModelConverter::init();
+    /// Save the objects and the root ref of a new monorepo, committed by `identity`.
+    pub async fn init_monorepo(&self, identity: Signature) {
+        let converter = MegaModelConverter::init(identity);
         let commit: mega_commit::Model = converter.commit.into();
         mega_commit::Entity::insert(commit.into_active_model())
             .exec(self.get_connection())
@@ -306,6 +519,7 @@ impl MegaStorage {
     pub async fn save_mega_commits(&self, commits: Vec<Commit>) -> Result<(), MegaError> {
         let mega_commits: Vec<mega_commit::Model> =
             commits.into_iter().map(mega_commit::Model::from).collect();
+        let hashes: Vec<String> = mega_commits.iter().map(|x| x.commit_id.clone()).collect();
         let mut save_models = Vec::new();
         for mega_commit in mega_commits {
             save_models.push(mega_commit.into_active_model());
@@ -313,6 +527,7 @@ impl MegaStorage {
         batch_save_model(self.get_connection(), save_models)
             .await
             .unwrap();
+        self.invalidate_cached(&hashes);
         Ok(())
     }
 
@@ -320,11 +535,18 @@ impl MegaStorage {
         &self,
         hash: &str,
     ) -> Result<Option<mega_commit::Model>, MegaError> {
-        Ok(mega_commit::Entity::find()
+        if let Some(model) = self.object_cache.as_ref().and_then(|x| x.get_commit(hash)) {
+            return Ok(Some(model));
+        }
+        let model = mega_commit::Entity::find()
             .filter(mega_commit::Column::CommitId.eq(hash))
             .one(self.get_connection())
             .await
-            .unwrap())
+            .unwrap();
+        if let (Some(cache), Some(model)) = (&self.object_cache, &model) {
+            cache.put_commit(model.clone());
+        }
+        Ok(model)
     }
 
     pub async fn get_commits_by_hashes(
@@ -339,6 +561,35 @@ impl MegaStorage {
             .unwrap())
     }
 
+    /// A page of at most `limit` commits with a committer timestamp of at least `since`, oldest
+    /// first. `after` is the `(committer_time, id)` of the last commit of the previous page.
+    pub async fn get_commits_since(
+        &self,
+        since: i64,
+        after: Option<(i64, i64)>,
+        limit: u64,
+    ) -> Result<Vec<mega_commit::Model>, MegaError> {
+        let mut query =
+            mega_commit::Entity::find().filter(mega_commit::Column::CommitterTime.gte(since));
+        if let Some((time, id)) = after {
+            query = query.filter(
+                Condition::any()
+                    .add(mega_commit::Column::CommitterTime.gt(time))
+                    .add(
+                        Condition::all()
+                            .add(mega_commit::Column::CommitterTime.eq(time))
+                            .add(mega_commit::Column::Id.gt(id)),
+                    ),
+            );
+        }
+        Ok(query
+            .order_by_asc(mega_commit::Column::CommitterTime)
+            .order_by_asc(mega_commit::Column::Id)
+            .limit(limit)
+            .all(self.get_connection())
+            .await?)
+    }
+
     pub async fn get_commits(&self) -> Result<Vec<mega_commit::Model>, MegaError> {
         Ok(mega_commit::Entity::find()
             .all(self.get_connection())
@@ -350,45 +601,787 @@ impl MegaStorage {
         &self,
         hash: &str,
     ) -> Result<Option<mega_tree::Model>, MegaError> {
-        Ok(mega_tree::Entity::find()
+        if let Some(model) = self.object_cache.as_ref().and_then(|x| x.get_tree(hash)) {
+            return Ok(Some(model));
+        }
+        let model = mega_tree::Entity::find()
             .filter(mega_tree::Column::TreeId.eq(hash))
             .one(self.get_connection())
             .await
-            .unwrap())
+            .unwrap();
+        if let (Some(cache), Some(model)) = (&self.object_cache, &model) {
+            cache.put_tree(model.clone());
+        }
+        Ok(model)
     }
 
     pub async fn get_trees_by_hashes(
         &self,
         hashes: Vec<String>,
     ) -> Result<Vec<mega_tree::Model>, MegaError> {
-        Ok(mega_tree::Entity::find()
-            .filter(mega_tree::Column::TreeId.is_in(hashes))
-            .distinct()
+        let Some(cache) = &self.object_cache else {
+            return Ok(mega_tree::Entity::find()
+                .filter(mega_tree::Column::TreeId.is_in(hashes))
+                .distinct()
+                .all(self.get_connection())
+                .await
+                .unwrap());
+        };
+        let (mut trees, mut missing) = (Vec::new(), Vec::new());
+        for hash in hashes {
+            match cache.get_tree(&hash) {
+                Some(model) => trees.push(model),
+                None => missing.push(hash),
+            }
+        }
+        if !missing.is_empty() {
+            let loaded = mega_tree::Entity::find()
+                .filter(mega_tree::Column::TreeId.is_in(missing))
+                .distinct()
+                .all(self.get_connection())
+                .await
+                .unwrap();
+            for model in loaded {
+                cache.put_tree(model.clone());
+                trees.push(model);
+            }
+        }
+        Ok(trees)
+    }
+
+    pub async fn get_mega_blobs_by_hashes(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<Vec<mega_blob::Model>, MegaError> {
+        Ok(mega_blob::Entity::find()
+            .filter(mega_blob::Column::BlobId.is_in(hashes))
             .all(self.get_connection())
+            .await?)
+    }
+
+    /// Stream the hashes of all stored objects of `obj_type`, or of every type if `None`.
+    /// Each object table is read through a database cursor, so the hashes are never all held
+    /// in memory at once.
+    pub fn stream_object_hashes(
+        &self,
+        obj_type: Option<ObjectType>,
+    ) -> impl Stream<Item = Result<String, MegaError>> + Send + '_ {
+        let types = match obj_type {
+            Some(obj_type) => vec![obj_type],
+            None => vec![
+                ObjectType::Commit,
+                ObjectType::Tree,
+                ObjectType::Blob,
+                ObjectType::Tag,
+            ],
+        };
+        stream::iter(types)
+            .map(|obj_type| self.stream_hashes_of_type(obj_type))
+            .flatten()
+    }
+
+    fn stream_hashes_of_type(
+        &self,
+        obj_type: ObjectType,
+    ) -> BoxStream<'_, Result<String, MegaError>> {
+        // trees and blobs may be stored once per commit which introduced them
+        let selector = match obj_type {
+            ObjectType::Commit => mega_commit::Entity::find()
+                .select_only()
+                .column(mega_commit::Column::CommitId)
+                .into_tuple::<String>(),
+            ObjectType::Tree => mega_tree::Entity::find()
+                .select_only()
+                .column(mega_tree::Column::TreeId)
+                .distinct()
+                .into_tuple::<String>(),
+            ObjectType::Blob => mega_blob::Entity::find()
+                .select_only()
+                .column(mega_blob::Column::BlobId)
+                .distinct()
+                .into_tuple::<String>(),
+            ObjectType::Tag => mega_tag::Entity::find()
+                .select_only()
+                .column(mega_tag::Column::TagId)
+                .into_tuple::<String>(),
+            ObjectType::OffsetDelta | ObjectType::HashDelta => return stream::empty().boxed(),
+        };
+        stream::once(selector.stream(self.get_connection()))
+            .flat_map(|result| match result {
+                Ok(rows) => rows.map(|x| x.map_err(MegaError::from)).boxed(),
+                Err(err) => stream::once(future::ready(Err(err.into()))).boxed(),
+            })
+            .boxed()
+    }
+
+    /// The hashes of the stored objects of any type starting with the lowercase hex `prefix`,
+    /// at most `limit` per type. The prefix is queried as the range of hashes it spans, so the
+    /// indexes of the hash columns are used.
+    pub async fn get_hashes_by_prefix(
+        &self,
+        prefix: &str,
+        limit: u64,
+    ) -> Result<Vec<String>, MegaError> {
+        let pad = 40usize.saturating_sub(prefix.len());
+        let low = format!("{}{}", prefix, "0".repeat(pad));
+        let high = format!("{}{}", prefix, "f".repeat(pad));
+        let conn = self.get_connection();
+        let mut hashes: Vec<String> = mega_commit::Entity::find()
+            .select_only()
+            .column(mega_commit::Column::CommitId)
+            .filter(mega_commit::Column::CommitId.between(low.as_str(), high.as_str()))
+            .limit(limit)
+            .into_tuple()
+            .all(conn)
+            .await?;
+        hashes.extend(
+            mega_tree::Entity::find()
+                .select_only()
+                .column(mega_tree::Column::TreeId)
+                .distinct()
+                .filter(mega_tree::Column::TreeId.between(low.as_str(), high.as_str()))
+                .limit(limit)
+                .into_tuple::<String>()
+                .all(conn)
+                .await?,
+        );
+        hashes.extend(
+            mega_blob::Entity::find()
+                .select_only()
+                .column(mega_blob::Column::BlobId)
+                .distinct()
+                .filter(mega_blob::Column::BlobId.between(low.as_str(), high.as_str()))
+                .limit(limit)
+                .into_tuple::<String>()
+                .all(conn)
+                .await?,
+        );
+        hashes.extend(
+            mega_tag::Entity::find()
+                .select_only()
+                .column(mega_tag::Column::TagId)
+                .filter(mega_tag::Column::TagId.between(low.as_str(), high.as_str()))
+                .limit(limit)
+                .into_tuple::<String>()
+                .all(conn)
+                .await?,
+        );
+        hashes.sort();
+        hashes.dedup();
+        Ok(hashes)
+    }
+
+    /// The types of the stored objects among `hashes`, with a query per object table and chunk
+    /// of 1000 hashes. Hashes which aren't stored are missing from the result.
+    pub async fn get_object_types(
+        &self,
+        hashes: &[String],
+    ) -> Result<HashMap<String, ObjectType>, MegaError> {
+        let conn = self.get_connection();
+        let mut types = HashMap::new();
+        let mut remaining: Vec<String> = hashes.to_vec();
+        remaining.sort();
+        remaining.dedup();
+        for obj_type in [
+            ObjectType::Commit,
+            ObjectType::Tree,
+            ObjectType::Blob,
+            ObjectType::Tag,
+        ] {
+            let mut found: Vec<String> = Vec::new();
+            for chunk in remaining.chunks(1000) {
+                found.extend(match obj_type {
+                    ObjectType::Commit => {
+                        mega_commit::Entity::find()
+                            .select_only()
+                            .column(mega_commit::Column::CommitId)
+                            .filter(mega_commit::Column::CommitId.is_in(chunk))
+                            .into_tuple::<String>()
+                            .all(conn)
+                            .await?
+                    }
+                    ObjectType::Tree => {
+                        mega_tree::Entity::find()
+                            .select_only()
+                            .column(mega_tree::Column::TreeId)
+                            .distinct()
+                            .filter(mega_tree::Column::TreeId.is_in(chunk))
+                            .into_tuple::<String>()
+                            .all(conn)
+                            .await?
+                    }
+                    ObjectType::Blob => {
+                        mega_blob::Entity::find()
+                            .select_only()
+                            .column(mega_blob::Column::BlobId)
+                            .distinct()
+                            .filter(mega_blob::Column::BlobId.is_in(chunk))
+                            .into_tuple::<String>()
+                            .all(conn)
+                            .await?
+                    }
+                    _ => {
+                        mega_tag::Entity::find()
+                            .select_only()
+                            .column(mega_tag::Column::TagId)
+                            .filter(mega_tag::Column::TagId.is_in(chunk))
+                            .into_tuple::<String>()
+                            .all(conn)
+                            .await?
+                    }
+                });
+            }
+            for hash in found {
+                types.insert(hash, obj_type);
+            }
+            remaining.retain(|x| !types.contains_key(x));
+        }
+        Ok(types)
+    }
+
+    pub async fn save_mega_tags(&self, tags: Vec<Tag>) -> Result<(), MegaError> {
+        let save_models: Vec<mega_tag::ActiveModel> = tags
+            .into_iter()
+            .map(|x| mega_tag::Model::from(x).into_active_model())
+            .collect();
+        batch_save_model(self.get_connection(), save_models)
             .await
-            .unwrap())
+            .unwrap();
+        Ok(())
+    }
+
+    pub async fn get_tags(&self) -> Result<Vec<mega_tag::Model>, MegaError> {
+        Ok(mega_tag::Entity::find().all(self.get_connection()).await?)
+    }
+
+    pub async fn get_tags_by_hashes(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<Vec<mega_tag::Model>, MegaError> {
+        Ok(mega_tag::Entity::find()
+            .filter(mega_tag::Column::TagId.is_in(hashes))
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// The tag objects pointing directly at one of the objects `hashes`.
+    pub async fn get_tags_by_targets(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<Vec<mega_tag::Model>, MegaError> {
+        Ok(mega_tag::Entity::find()
+            .filter(mega_tag::Column::ObjectId.is_in(hashes))
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// The `refs/tags/*` refs pointing at one of `hashes`.
+    pub async fn get_tag_refs_by_hashes(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<Vec<MegaRefs>, MegaError> {
+        Ok(mega_refs::Entity::find()
+            .filter(mega_refs::Column::Path.starts_with("refs/tags/"))
+            .filter(mega_refs::Column::RefCommitHash.is_in(hashes))
+            .all(self.get_connection())
+            .await?
+            .into_iter()
+            .map(|x| x.into())
+            .collect())
+    }
+
+    /// The raw blobs among `hashes`, with the content of cold blobs read from the raw storage.
+    /// The sizes in bytes of the blobs `hashes` which are stored, keyed by blob hash, see
+    /// [`MegaStorage::complete_blob_sizes`].
+    pub async fn get_blob_sizes(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<HashMap<String, usize>, MegaError> {
+        let blobs = self.get_mega_blobs_by_hashes(hashes).await?;
+        self.complete_blob_sizes(blobs.into_iter().map(|x| (x.blob_id, x.size)))
+            .await
+    }
+
+    /// Turn the recorded `sizes` of blobs into a map keyed by blob hash. Rows saved before the
+    /// sizes were recorded hold 0, the content of those blobs is loaded to measure them.
+    pub async fn complete_blob_sizes(
+        &self,
+        sizes: impl IntoIterator<Item = (String, i64)>,
+    ) -> Result<HashMap<String, usize>, MegaError> {
+        let mut known = HashMap::new();
+        let mut unknown = vec![];
+        for (hash, size) in sizes {
+            match usize::try_from(size) {
+                Ok(size) if size > 0 => {
+                    known.insert(hash, size);
+                }
+                _ => unknown.push(hash),
+            }
+        }
+        if !unknown.is_empty() {
+            for blob in self.get_raw_blobs_by_hashes(unknown).await? {
+                if let Some(data) = blob.data {
+                    known.insert(blob.sha1, data.len());
+                }
+            }
+        }
+        Ok(known)
     }
 
     pub async fn get_raw_blobs_by_hashes(
         &self,
         hashes: Vec<String>,
     ) -> Result<Vec<raw_blob::Model>, MegaError> {
-        Ok(raw_blob::Entity::find()
+        let models = raw_blob::Entity::find()
             .filter(raw_blob::Column::Sha1.is_in(hashes))
             .all(self.get_connection())
             .await
-            .unwrap())
+            .unwrap();
+        self.load_cold_blobs(models).await
+    }
+
+    /// Fill in the content of the cold blobs among `models`, which is kept in the raw storage
+    /// rather than in the database, see [`MegaStorage::set_blob_tier`].
+    pub async fn load_cold_blobs(
+        &self,
+        mut models: Vec<raw_blob::Model>,
+    ) -> Result<Vec<raw_blob::Model>, MegaError> {
+        for model in models.iter_mut() {
+            if model.data.is_none() && model.storage_type != StorageType::Database {
+                let data = self.raw_storage.get_object("", &model.sha1).await?;
+                model.data = Some(data.to_vec());
+            }
+        }
+        Ok(models)
+    }
+
+    /// Record the blob `hash` whose content the raw storage holds at `location` as a cold blob,
+    /// see [`MegaStorage::set_blob_tier`]. Recording a blob which is already stored is a no-op.
+    pub async fn save_cold_blob(&self, hash: &str, location: String) -> Result<(), MegaError> {
+        let model = raw_blob::Model {
+            id: generate_id(),
+            sha1: hash.to_owned(),
+            content: None,
+            file_type: None,
+            storage_type: self.raw_storage.get_storage_type(),
+            data: None,
+            local_path: Some(location),
+            remote_url: None,
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        raw_blob::Entity::insert(model.into_active_model())
+            .on_conflict(
+                OnConflict::column(raw_blob::Column::Sha1)
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .exec_without_returning(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    /// Move the content of the blob `hash` to the storage of `tier`, returning `false` if the blob
+    /// isn't stored. Cold blobs keep their row with the location of the content in the raw
+    /// storage, reads of them load it from there. Moving a blob back to the hot tier deletes its
+    /// copy in the raw storage. A hot row without data is an error, not an empty blob to move.
+    pub async fn set_blob_tier(&self, hash: &str, tier: StorageTier) -> Result<bool, MegaError> {
+        let Some(model) = raw_blob::Entity::find()
+            .filter(raw_blob::Column::Sha1.eq(hash))
+            .one(self.get_connection())
+            .await?
+        else {
+            return Ok(false);
+        };
+        if StorageTier::from(&model.storage_type) == tier {
+            return Ok(true);
+        }
+        let (storage_type, data, local_path) = match tier {
+            StorageTier::Cold => {
+                let data = model.data.as_ref().ok_or_else(|| {
+                    MegaError::with_message(&format!("blob {} has no data to move", hash))
+                })?;
+                let location = self.raw_storage.put_object("", hash, data).await?;
+                (self.raw_storage.get_storage_type(), None, Some(location))
+            }
+            StorageTier::Hot => {
+                let data = self.raw_storage.get_object("", hash).await?;
+                (StorageType::Database, Some(data.to_vec()), None)
+            }
+        };
+        let mut a_model = model.into_active_model();
+        a_model.storage_type = Set(storage_type);
+        a_model.data = Set(data);
+        a_model.local_path = Set(local_path);
+        a_model.update(self.get_connection()).await?;
+        if tier == StorageTier::Hot {
+            // only once the row holds the content, a failed update leaves the cold copy in use
+            self.raw_storage.delete_object("", hash).await?;
+        }
+        Ok(true)
     }
+
+    /// The number of stored commits, trees and blobs and their size, computed by the database
+    /// without loading any object. A tree is stored once per commit which introduced it and is
+    /// counted as often.
+    pub async fn repo_size(&self) -> Result<RepoSize, MegaError> {
+        let commits = mega_commit::Entity::find()
+            .select_only()
+            .column_as(Expr::col(mega_commit::Column::Id).count(), "objects")
+            .column_as(
+                Expr::cust(
+                    "COALESCE(SUM(COALESCE(OCTET_LENGTH(content), 0) \
+                     + COALESCE(OCTET_LENGTH(author), 0) \
+                     + COALESCE(OCTET_LENGTH(committer), 0)), 0)",
+                ),
+                "bytes",
+            )
+            .into_model::<ObjectStats>()
+            .one(self.get_connection())
+            .await?;
+        let trees = mega_tree::Entity::find()
+            .select_only()
+            .column_as(Expr::col(mega_tree::Column::Id).count(), "objects")
+            .column_as(
+                Expr::cust("COALESCE(SUM(OCTET_LENGTH(sub_trees)), 0)"),
+                "bytes",
+            )
+            .into_model::<ObjectStats>()
+            .one(self.get_connection())
+            .await?;
+        let blobs = mega_blob::Entity::find()
+            .select_only()
+            .column_as(Expr::col(mega_blob::Column::Id).count(), "objects")
+            // SUM of a BIGINT is a NUMERIC, and rows saved before the sizes were recorded
+            // hold 0, those are measured from their content if it's in the database
+            .column_as(
+                Expr::cust(
+                    "CAST(COALESCE(SUM(CASE WHEN size > 0 THEN size ELSE \
+                     (SELECT COALESCE(OCTET_LENGTH(data), 0) FROM raw_blob \
+                     WHERE raw_blob.sha1 = mega_blob.blob_id) END), 0) AS BIGINT)",
+                ),
+                "bytes",
+            )
+            .into_model::<ObjectStats>()
+            .one(self.get_connection())
+            .await?;
+
+        Ok([commits, trees, blobs].into_iter().flatten().fold(
+            RepoSize::default(),
+            |size, stats| RepoSize {
+                object_count: size.object_count + stats.objects,
+                stored_bytes: size.stored_bytes + stats.bytes,
+            },
+        ))
+    }
+}
+
+#[derive(FromQueryResult)]
+struct ObjectStats {
+    objects: i64,
+    bytes: i64,
 }
 
 #[cfg(test)]
 mod test {
+    use std::collections::{BTreeMap, HashSet};
+    use std::num::NonZeroUsize;
     use std::rc::Rc;
+    use std::sync::Arc;
 
+    use futures::TryStreamExt;
+    use sea_orm::{DatabaseBackend, MockDatabase, MockExecResult, Value};
+
+    use callisto::db_enums::{CommentKind, ConvType, StorageType};
+    use callisto::{mega_blob, mega_mr_comment, mega_mr_conv, mega_refs, mega_tree, raw_blob};
+    use common::utils::{MEGA_BRANCH_NAME, ZERO_ID};
     use ganymede::mega_node::MegaNode;
     use ganymede::model::create_file::CreateFileInfo;
+    use venus::internal::object::types::ObjectType;
+    use venus::monorepo::mega_refs::MegaRefs;
+    use venus::monorepo::repo_size::RepoSize;
 
     use crate::storage::mega_storage::MegaStorage;
+    use crate::test_util::transaction_log;
+
+    #[tokio::test]
+    async fn test_reflog_for_ref_updates() {
+        let (c1, c2, tree) = ("1".repeat(40), "2".repeat(40), "f".repeat(40));
+        let c3 = "3".repeat(40);
+        let ref_model = |commit: &str| mega_refs::Model {
+            id: 1,
+            path: "/".to_owned(),
+            ref_commit_hash: commit.to_owned(),
+            ref_tree_hash: tree.clone(),
+            created_at: chrono::Utc::now().naive_utc(),
+            updated_at: chrono::Utc::now().naive_utc(),
+        };
+        let inserted = MockExecResult {
+            last_insert_id: 0,
+            rows_affected: 1,
+        };
+        let moved = MockExecResult {
+            last_insert_id: 0,
+            rows_affected: 0,
+        };
+        // results of the statements in order: save_ref (insert ref, insert log), update_ref
+        // (find old ref, update ref which was moved to c3 meanwhile, find it again, update ref,
+        // insert log)
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![ref_model(&c1)]])
+            .append_exec_results([inserted.clone()])
+            .append_query_results([vec![ref_model(&c1)]])
+            .append_exec_results([moved])
+            .append_query_results([vec![ref_model(&c3)]])
+            .append_exec_results([inserted.clone(), inserted])
+            .into_connection();
+        let storage = MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        };
+
+        storage.save_ref("/", &c1, &tree).await.unwrap();
+        storage
+            .update_ref(MegaRefs {
+                id: 1,
+                path: "/".to_owned(),
+                ref_commit_hash: c2.clone(),
+                ref_tree_hash: tree.clone(),
+            })
+            .await
+            .unwrap();
+
+        let log = transaction_log(storage);
+        let reflog_inserts: Vec<&String> = log
+            .iter()
+            .filter(|x| x.contains("INSERT INTO \\\"mega_reflog\\\""))
+            .collect();
+        assert_eq!(reflog_inserts.len(), 2);
+        assert!(reflog_inserts[0].contains(ZERO_ID) && reflog_inserts[0].contains(&c1));
+        // the reflog records the commit the update replaced
+        assert!(reflog_inserts[1].contains(&c3) && reflog_inserts[1].contains(&c2));
+        let updates: Vec<&String> = log
+            .iter()
+            .filter(|x| x.contains("UPDATE \\\"mega_refs\\\""))
+            .collect();
+        assert_eq!(updates.len(), 2);
+        assert!(updates[0].contains(&c1) && updates[1].contains(&c3));
+    }
+
+    #[tokio::test]
+    async fn test_reply_comment_references_parent() {
+        let conversation = mega_mr_conv::Model {
+            id: 1,
+            mr_id: 10,
+            user_id: 0,
+            conv_type: ConvType::Comment,
+            created_at: chrono::Utc::now().naive_utc(),
+            updated_at: chrono::Utc::now().naive_utc(),
+        };
+        let comment = |id, parent_comment_id, kind| mega_mr_comment::Model {
+            id,
+            conv_id: 1,
+            comment: Some("comment".to_owned()),
+            edited: false,
+            parent_comment_id,
+            kind,
+        };
+        // every comment inserts a conversation and then the comment itself
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![conversation.clone()]])
+            .append_query_results([vec![comment(42, None, CommentKind::System)]])
+            .append_query_results([vec![conversation]])
+            .append_query_results([vec![comment(43, Some(42), CommentKind::Human)]])
+            .into_connection();
+        let storage = MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        };
+
+        let notice = Some("Mega closed MR due to conflict".to_owned());
+        let parent = storage
+            .add_mr_comment(10, 0, None, CommentKind::System, notice)
+            .await
+            .unwrap();
+        assert_eq!(parent, 42);
+        let reply = storage
+            .add_mr_comment(
+                10,
+                7,
+                Some(parent),
+                CommentKind::Human,
+                Some("why?".to_owned()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(reply, 43);
+
+        let log = transaction_log(storage);
+        let comment_inserts: Vec<&String> = log
+            .iter()
+            .filter(|x| x.contains("INSERT INTO \\\"mega_mr_comment\\\""))
+            .collect();
+        assert_eq!(comment_inserts.len(), 2);
+        assert!(
+            comment_inserts[0].contains("BigInt(None)") && comment_inserts[0].contains("system")
+        );
+        assert!(
+            comment_inserts[1].contains("BigInt(Some(42))") && comment_inserts[1].contains("human")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ref_update_events() {
+        let (c1, c2, tree) = ("1".repeat(40), "2".repeat(40), "f".repeat(40));
+        let ref_model = |commit: &str| mega_refs::Model {
+            id: 1,
+            path: "/project".to_owned(),
+            ref_commit_hash: commit.to_owned(),
+            ref_tree_hash: tree.clone(),
+            created_at: chrono::Utc::now().naive_utc(),
+            updated_at: chrono::Utc::now().naive_utc(),
+        };
+        let inserted = MockExecResult {
+            last_insert_id: 0,
+            rows_affected: 1,
+        };
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![ref_model(&c1)]])
+            .append_exec_results([inserted.clone()])
+            .append_query_results([vec![ref_model(&c1)]])
+            .append_exec_results([inserted.clone(), inserted])
+            .into_connection();
+        let storage = MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        };
+        let mut events = storage.subscribe_ref_updates();
+
+        storage.save_ref("/project", &c1, &tree).await.unwrap();
+        storage
+            .update_ref(MegaRefs {
+                id: 1,
+                path: "/project".to_owned(),
+                ref_commit_hash: c2.clone(),
+                ref_tree_hash: tree.clone(),
+            })
+            .await
+            .unwrap();
+
+        let created = events.try_recv().unwrap();
+        assert_eq!(created.old, ZERO_ID);
+        assert_eq!(created.new, c1);
+        let updated = events.try_recv().unwrap();
+        assert_eq!(updated.repo, "/project");
+        assert_eq!(updated.ref_name, MEGA_BRANCH_NAME);
+        assert_eq!((updated.old, updated.new), (c1, c2));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_save_ref_cas() {
+        let (c1, c2, c3, tree) = (
+            "1".repeat(40),
+            "2".repeat(40),
+            "3".repeat(40),
+            "f".repeat(40),
+        );
+        let result = |rows_affected| MockExecResult {
+            last_insert_id: 0,
+            rows_affected,
+        };
+        // the first update matches the current ref and writes a reflog entry, the second one
+        // expects the ref to still be at c1 and matches no row
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_exec_results([result(1), result(1), result(0)])
+            .into_connection();
+        let storage = MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        };
+        let mut events = storage.subscribe_ref_updates();
+
+        assert!(storage.save_ref_cas("/", &c1, &c2, &tree).await.unwrap());
+        assert!(!storage.save_ref_cas("/", &c1, &c3, &tree).await.unwrap());
+
+        let updated = events.try_recv().unwrap();
+        assert_eq!((updated.old, updated.new), (c1.clone(), c2));
+        // the failed update is neither logged nor published
+        assert!(events.try_recv().is_err());
+        let log = transaction_log(storage);
+        assert_eq!(log.len(), 3);
+        assert!(log[2].contains(&c1));
+    }
+
+    #[tokio::test]
+    async fn test_object_cache() {
+        let tree = mega_tree::Model {
+            id: 1,
+            tree_id: "1".repeat(40),
+            sub_trees: vec![],
+            size: 0,
+            commit_id: "2".repeat(40),
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        // only a single query result, a second query would fail
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![tree.clone()]])
+            .into_connection();
+        let storage = MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        }
+        .with_object_cache(NonZeroUsize::new(16).unwrap());
+
+        let first = storage.get_tree_by_hash(&tree.tree_id).await.unwrap();
+        assert_eq!(first, Some(tree.clone()));
+        let second = storage.get_tree_by_hash(&tree.tree_id).await.unwrap();
+        assert_eq!(second, Some(tree.clone()));
+        let trees = storage
+            .get_trees_by_hashes(vec![tree.tree_id.clone()])
+            .await
+            .unwrap();
+        assert_eq!(trees, vec![tree.clone()]);
+
+        let cache = storage.object_cache.clone().unwrap();
+        storage.invalidate_cached(&[&tree.tree_id]);
+        assert!(cache.get_tree(&tree.tree_id).is_none());
+
+        let log = transaction_log(storage);
+        assert_eq!(log.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_stream_object_hashes() {
+        let row = |column: &'static str, hash: &str| BTreeMap::from([(column, Value::from(hash))]);
+        let (c1, t1) = ("1".repeat(40), "2".repeat(40));
+        let (b1, b2) = ("3".repeat(40), "4".repeat(40));
+        // one result per table, in the order commit, tree, blob, tag
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![row("commit_id", &c1)]])
+            .append_query_results([vec![row("tree_id", &t1)]])
+            .append_query_results([vec![row("blob_id", &b1), row("blob_id", &b2)]])
+            .append_query_results([Vec::<BTreeMap<&str, Value>>::new()])
+            .append_query_results([vec![row("blob_id", &b2)]])
+            .into_connection();
+        let storage = MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        };
+
+        let hashes: HashSet<String> = storage
+            .stream_object_hashes(None)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(hashes, HashSet::from([c1, t1, b1, b2.clone()]));
+
+        let blobs: Vec<String> = storage
+            .stream_object_hashes(Some(ObjectType::Blob))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(blobs, vec![b2]);
+    }
 
     #[test]
     pub fn test_node_tree() {
@@ -445,4 +1438,82 @@ mod test {
             print_tree(child.clone(), depth + 1)
         }
     }
+
+    #[tokio::test]
+    async fn test_get_blob_sizes() {
+        let now = chrono::Utc::now().naive_utc();
+        let blob = |hash: &str, size: i64| mega_blob::Model {
+            id: 0,
+            blob_id: hash.to_owned(),
+            commit_id: String::new(),
+            name: String::new(),
+            size,
+            created_at: now,
+        };
+        let legacy = raw_blob::Model {
+            id: 0,
+            sha1: "2".repeat(40),
+            content: None,
+            file_type: None,
+            storage_type: StorageType::Database,
+            data: Some(b"saved before the sizes".to_vec()),
+            local_path: None,
+            remote_url: None,
+            created_at: now,
+        };
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![blob(&"1".repeat(40), 42), blob(&"2".repeat(40), 0)]])
+            .append_query_results([vec![legacy]])
+            .into_connection();
+        let storage = MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        };
+
+        let sizes = storage
+            .get_blob_sizes(vec!["1".repeat(40), "2".repeat(40)])
+            .await
+            .unwrap();
+        assert_eq!(sizes.len(), 2);
+        assert_eq!(sizes[&"1".repeat(40)], 42);
+        assert_eq!(sizes[&"2".repeat(40)], 22);
+
+        // only the blob without a recorded size is loaded
+        let log = transaction_log(storage);
+        assert_eq!(log.len(), 2);
+        assert!(log[1].contains("raw_blob"));
+        assert!(!log[1].contains(&"1".repeat(40)));
+    }
+
+    #[tokio::test]
+    async fn test_repo_size() {
+        let row = |objects: i64, bytes: i64| {
+            BTreeMap::from([
+                ("objects", Value::from(objects)),
+                ("bytes", Value::from(bytes)),
+            ])
+        };
+        // a repo with 2 commits, 3 trees and 4 blobs, one aggregate per table
+        let db = MockDatabase::new(DatabaseBackend::Postgres)
+            .append_query_results([vec![row(2, 410)]])
+            .append_query_results([vec![row(3, 236)]])
+            .append_query_results([vec![row(4, 1024)]])
+            .into_connection();
+        let storage = MegaStorage {
+            connection: Arc::new(db),
+            ..MegaStorage::mock()
+        };
+
+        let size = storage.repo_size().await.unwrap();
+        assert_eq!(
+            size,
+            RepoSize {
+                object_count: 9,
+                stored_bytes: 1670,
+            }
+        );
+
+        let log = transaction_log(storage);
+        assert_eq!(log.len(), 3);
+    }
 }
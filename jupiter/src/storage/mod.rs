@@ -3,6 +3,8 @@ pub mod git_fs_storage;
 pub mod init;
 pub mod lfs_storage;
 pub mod mega_storage;
+pub mod object_cache;
+pub mod object_storage;
 
 use async_trait::async_trait;
 
@@ -0,0 +1,90 @@
+//! In-memory LRU cache of recently loaded commits and trees.
+//!
+//! Pack generation loads the same objects again and again, e.g. the root tree of a ref for every
+//! request. Objects are content addressed, so a cached object never goes stale, but rows which are
+//! written again are evicted so a lookup never returns an older row than the database has.
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use lru::LruCache;
+
+use callisto::{mega_commit, mega_tree};
+
+pub struct ObjectCache {
+    commits: Mutex<LruCache<String, mega_commit::Model>>,
+    trees: Mutex<LruCache<String, mega_tree::Model>>,
+}
+
+impl ObjectCache {
+    /// A cache holding up to `capacity` commits and `capacity` trees.
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        ObjectCache {
+            commits: Mutex::new(LruCache::new(capacity)),
+            trees: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    pub fn get_commit(&self, hash: &str) -> Option<mega_commit::Model> {
+        self.commits.lock().unwrap().get(hash).cloned()
+    }
+
+    pub fn put_commit(&self, model: mega_commit::Model) {
+        self.commits
+            .lock()
+            .unwrap()
+            .put(model.commit_id.clone(), model);
+    }
+
+    pub fn get_tree(&self, hash: &str) -> Option<mega_tree::Model> {
+        self.trees.lock().unwrap().get(hash).cloned()
+    }
+
+    pub fn put_tree(&self, model: mega_tree::Model) {
+        self.trees.lock().unwrap().put(model.tree_id.clone(), model);
+    }
+
+    /// Evict the objects `hashes`, called whenever they are written.
+    pub fn invalidate<S: AsRef<str>>(&self, hashes: &[S]) {
+        let (mut commits, mut trees) = (self.commits.lock().unwrap(), self.trees.lock().unwrap());
+        for hash in hashes {
+            commits.pop(hash.as_ref());
+            trees.pop(hash.as_ref());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use callisto::mega_tree;
+
+    use super::ObjectCache;
+
+    fn tree(id: &str) -> mega_tree::Model {
+        mega_tree::Model {
+            id: 0,
+            tree_id: id.to_owned(),
+            sub_trees: vec![],
+            size: 0,
+            commit_id: String::new(),
+            created_at: chrono::Utc::now().naive_utc(),
+        }
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let cache = ObjectCache::new(NonZeroUsize::new(2).unwrap());
+        cache.put_tree(tree("a"));
+        cache.put_tree(tree("b"));
+        assert!(cache.get_tree("a").is_some());
+        // b is the least recently used now
+        cache.put_tree(tree("c"));
+        assert!(cache.get_tree("b").is_none());
+        assert!(cache.get_tree("a").is_some());
+
+        cache.invalidate(&["a"]);
+        assert!(cache.get_tree("a").is_none());
+        assert!(cache.get_tree("c").is_some());
+    }
+}
@@ -0,0 +1,435 @@
+//! Object level access to the monorepo storage.
+//!
+//! Algorithms that only walk commits and trees (history queries, diffs, merges) are written
+//! against the [`ObjectStorage`] trait instead of [`MegaStorage`] directly, so they work with
+//! the database backed storage in production and with [`MemoryStorage`] in tests.
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+use callisto::{mega_tree, raw_blob};
+use common::errors::MegaError;
+use venus::hash::SHA1;
+use venus::internal::object::blob::Blob;
+use venus::internal::object::commit::Commit;
+use venus::internal::object::tree::Tree;
+use venus::internal::pack::entry::Entry;
+use venus::repo::Repo;
+
+use crate::storage::batch_save_model;
+use crate::storage::git_db_storage::GitDbStorage;
+use crate::storage::mega_storage::MegaStorage;
+
+#[async_trait]
+pub trait ObjectStorage: Send + Sync {
+    async fn get_commit(&self, hash: &SHA1) -> Result<Option<Commit>, MegaError>;
+
+    async fn get_tree(&self, hash: &SHA1) -> Result<Option<Tree>, MegaError>;
+
+    async fn get_blob(&self, hash: &SHA1) -> Result<Option<Blob>, MegaError>;
+
+    /// Load the commits `hashes` which exist, in no particular order.
+    async fn get_commits(&self, hashes: &[SHA1]) -> Result<Vec<Commit>, MegaError> {
+        let mut commits = Vec::new();
+        for hash in hashes {
+            commits.extend(self.get_commit(hash).await?);
+        }
+        Ok(commits)
+    }
+
+    /// Load the trees `hashes` which exist, in no particular order.
+    async fn get_trees(&self, hashes: &[SHA1]) -> Result<Vec<Tree>, MegaError> {
+        let mut trees = Vec::new();
+        for hash in hashes {
+            trees.extend(self.get_tree(hash).await?);
+        }
+        Ok(trees)
+    }
+
+    /// Load the blobs `hashes` which exist, in no particular order.
+    async fn get_blobs(&self, hashes: &[SHA1]) -> Result<Vec<Blob>, MegaError> {
+        let mut blobs = Vec::new();
+        for hash in hashes {
+            blobs.extend(self.get_blob(hash).await?);
+        }
+        Ok(blobs)
+    }
+
+    /// The hashes of `hashes` which are stored blobs, without loading their content.
+    async fn existing_blobs(&self, hashes: &[SHA1]) -> Result<Vec<SHA1>, MegaError> {
+        Ok(self.get_blobs(hashes).await?.iter().map(|x| x.id).collect())
+    }
+
+    /// The sizes of the blobs `hashes` which are stored, without loading their content where the
+    /// sizes are stored apart.
+    async fn blob_sizes(&self, hashes: &[SHA1]) -> Result<HashMap<SHA1, usize>, MegaError> {
+        Ok(self
+            .get_blobs(hashes)
+            .await?
+            .into_iter()
+            .map(|x| (x.id, x.data.len()))
+            .collect())
+    }
+
+    /// Save new commits, commits which already exist are left untouched.
+    async fn save_commits(&self, commits: Vec<Commit>) -> Result<(), MegaError>;
+
+    /// Save new trees, trees which already exist are left untouched.
+    async fn save_trees(&self, trees: Vec<Tree>) -> Result<(), MegaError>;
+}
+
+#[async_trait]
+impl ObjectStorage for MegaStorage {
+    async fn get_commit(&self, hash: &SHA1) -> Result<Option<Commit>, MegaError> {
+        Ok(self
+            .get_commit_by_hash(&hash.to_plain_str())
+            .await?
+            .map(|x| x.into()))
+    }
+
+    async fn get_tree(&self, hash: &SHA1) -> Result<Option<Tree>, MegaError> {
+        Ok(self
+            .get_tree_by_hash(&hash.to_plain_str())
+            .await?
+            .map(|x| x.into()))
+    }
+
+    async fn get_blob(&self, hash: &SHA1) -> Result<Option<Blob>, MegaError> {
+        Ok(self
+            .get_raw_blobs_by_hashes(vec![hash.to_plain_str()])
+            .await?
+            .into_iter()
+            .next()
+            .map(|x| x.into()))
+    }
+
+    async fn get_commits(&self, hashes: &[SHA1]) -> Result<Vec<Commit>, MegaError> {
+        let hashes = hashes.iter().map(|x| x.to_plain_str()).collect();
+        Ok(self
+            .get_commits_by_hashes(&hashes)
+            .await?
+            .into_iter()
+            .map(|x| x.into())
+            .collect())
+    }
+
+    async fn get_trees(&self, hashes: &[SHA1]) -> Result<Vec<Tree>, MegaError> {
+        let hashes = hashes.iter().map(|x| x.to_plain_str()).collect();
+        Ok(self
+            .get_trees_by_hashes(hashes)
+            .await?
+            .into_iter()
+            .map(|x| x.into())
+            .collect())
+    }
+
+    async fn get_blobs(&self, hashes: &[SHA1]) -> Result<Vec<Blob>, MegaError> {
+        let hashes = hashes.iter().map(|x| x.to_plain_str()).collect();
+        Ok(self
+            .get_raw_blobs_by_hashes(hashes)
+            .await?
+            .into_iter()
+            .map(|x| x.into())
+            .collect())
+    }
+
+    async fn existing_blobs(&self, hashes: &[SHA1]) -> Result<Vec<SHA1>, MegaError> {
+        let hashes = hashes.iter().map(|x| x.to_plain_str()).collect();
+        Ok(self
+            .get_mega_blobs_by_hashes(hashes)
+            .await?
+            .into_iter()
+            .filter_map(|x| SHA1::from_str(&x.blob_id).ok())
+            .collect())
+    }
+
+    async fn blob_sizes(&self, hashes: &[SHA1]) -> Result<HashMap<SHA1, usize>, MegaError> {
+        let hashes = hashes.iter().map(|x| x.to_plain_str()).collect();
+        Ok(self
+            .get_blob_sizes(hashes)
+            .await?
+            .into_iter()
+            .filter_map(|(hash, size)| Some((SHA1::from_str(&hash).ok()?, size)))
+            .collect())
+    }
+
+    async fn save_commits(&self, commits: Vec<Commit>) -> Result<(), MegaError> {
+        self.save_mega_commits(commits).await
+    }
+
+    async fn save_trees(&self, trees: Vec<Tree>) -> Result<(), MegaError> {
+        let hashes: Vec<String> = trees.iter().map(|x| x.id.to_plain_str()).collect();
+        let trees: Vec<mega_tree::ActiveModel> = trees
+            .into_iter()
+            .map(|x| mega_tree::Model::from(x).into())
+            .collect();
+        batch_save_model(self.get_connection(), trees).await?;
+        self.invalidate_cached(&hashes);
+        Ok(())
+    }
+}
+
+/// An in-memory [`ObjectStorage`], mainly used to test storage agnostic algorithms.
+#[derive(Default)]
+pub struct MemoryStorage {
+    commits: RwLock<HashMap<SHA1, Commit>>,
+    trees: RwLock<HashMap<SHA1, Tree>>,
+    blobs: RwLock<HashMap<SHA1, Blob>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage::default()
+    }
+
+    pub fn insert_commit(&self, commit: Commit) {
+        self.commits.write().unwrap().insert(commit.id, commit);
+    }
+
+    pub fn insert_tree(&self, tree: Tree) {
+        self.trees.write().unwrap().insert(tree.id, tree);
+    }
+
+    pub fn insert_blob(&self, blob: Blob) {
+        self.blobs.write().unwrap().insert(blob.id, blob);
+    }
+}
+
+#[async_trait]
+impl ObjectStorage for MemoryStorage {
+    async fn get_commit(&self, hash: &SHA1) -> Result<Option<Commit>, MegaError> {
+        Ok(self.commits.read().unwrap().get(hash).cloned())
+    }
+
+    async fn get_tree(&self, hash: &SHA1) -> Result<Option<Tree>, MegaError> {
+        Ok(self.trees.read().unwrap().get(hash).cloned())
+    }
+
+    async fn get_blob(&self, hash: &SHA1) -> Result<Option<Blob>, MegaError> {
+        Ok(self.blobs.read().unwrap().get(hash).cloned())
+    }
+
+    async fn save_commits(&self, commits: Vec<Commit>) -> Result<(), MegaError> {
+        for commit in commits {
+            self.insert_commit(commit);
+        }
+        Ok(())
+    }
+
+    async fn save_trees(&self, trees: Vec<Tree>) -> Result<(), MegaError> {
+        for tree in trees {
+            self.insert_tree(tree);
+        }
+        Ok(())
+    }
+}
+
+/// An [`ObjectStorage`] for a federated monorepo, where some objects live in another backend.
+/// Objects missing in `primary` are looked up in the read-only `secondary`, new objects are only
+/// saved to `primary`.
+pub struct FallbackStorage {
+    pub primary: Arc<dyn ObjectStorage>,
+    pub secondary: Arc<dyn ObjectStorage>,
+}
+
+impl FallbackStorage {
+    pub fn new(primary: Arc<dyn ObjectStorage>, secondary: Arc<dyn ObjectStorage>) -> Self {
+        FallbackStorage { primary, secondary }
+    }
+}
+
+/// The hashes of `hashes` which are not ids of `found`.
+fn missing(hashes: &[SHA1], found: impl Iterator<Item = SHA1>) -> Vec<SHA1> {
+    let found: HashSet<SHA1> = found.collect();
+    hashes
+        .iter()
+        .filter(|x| !found.contains(x))
+        .copied()
+        .collect()
+}
+
+#[async_trait]
+impl ObjectStorage for FallbackStorage {
+    async fn get_commit(&self, hash: &SHA1) -> Result<Option<Commit>, MegaError> {
+        match self.primary.get_commit(hash).await? {
+            Some(commit) => Ok(Some(commit)),
+            None => self.secondary.get_commit(hash).await,
+        }
+    }
+
+    async fn get_tree(&self, hash: &SHA1) -> Result<Option<Tree>, MegaError> {
+        match self.primary.get_tree(hash).await? {
+            Some(tree) => Ok(Some(tree)),
+            None => self.secondary.get_tree(hash).await,
+        }
+    }
+
+    async fn get_blob(&self, hash: &SHA1) -> Result<Option<Blob>, MegaError> {
+        match self.primary.get_blob(hash).await? {
+            Some(blob) => Ok(Some(blob)),
+            None => self.secondary.get_blob(hash).await,
+        }
+    }
+
+    async fn get_commits(&self, hashes: &[SHA1]) -> Result<Vec<Commit>, MegaError> {
+        let mut commits = self.primary.get_commits(hashes).await?;
+        let missing = missing(hashes, commits.iter().map(|x| x.id));
+        if !missing.is_empty() {
+            commits.extend(self.secondary.get_commits(&missing).await?);
+        }
+        Ok(commits)
+    }
+
+    async fn get_trees(&self, hashes: &[SHA1]) -> Result<Vec<Tree>, MegaError> {
+        let mut trees = self.primary.get_trees(hashes).await?;
+        let missing = missing(hashes, trees.iter().map(|x| x.id));
+        if !missing.is_empty() {
+            trees.extend(self.secondary.get_trees(&missing).await?);
+        }
+        Ok(trees)
+    }
+
+    async fn get_blobs(&self, hashes: &[SHA1]) -> Result<Vec<Blob>, MegaError> {
+        let mut blobs = self.primary.get_blobs(hashes).await?;
+        let missing = missing(hashes, blobs.iter().map(|x| x.id));
+        if !missing.is_empty() {
+            blobs.extend(self.secondary.get_blobs(&missing).await?);
+        }
+        Ok(blobs)
+    }
+
+    async fn existing_blobs(&self, hashes: &[SHA1]) -> Result<Vec<SHA1>, MegaError> {
+        let mut blobs = self.primary.existing_blobs(hashes).await?;
+        let missing = missing(hashes, blobs.iter().copied());
+        if !missing.is_empty() {
+            blobs.extend(self.secondary.existing_blobs(&missing).await?);
+        }
+        Ok(blobs)
+    }
+
+    async fn blob_sizes(&self, hashes: &[SHA1]) -> Result<HashMap<SHA1, usize>, MegaError> {
+        let mut sizes = self.primary.blob_sizes(hashes).await?;
+        let missing = missing(hashes, sizes.keys().copied());
+        if !missing.is_empty() {
+            sizes.extend(self.secondary.blob_sizes(&missing).await?);
+        }
+        Ok(sizes)
+    }
+
+    async fn save_commits(&self, commits: Vec<Commit>) -> Result<(), MegaError> {
+        self.primary.save_commits(commits).await
+    }
+
+    async fn save_trees(&self, trees: Vec<Tree>) -> Result<(), MegaError> {
+        self.primary.save_trees(trees).await
+    }
+}
+
+/// An [`ObjectStorage`] for the objects of a single imported git repository.
+pub struct GitRepoStorage {
+    pub storage: Arc<GitDbStorage>,
+    pub repo: Repo,
+}
+
+impl GitRepoStorage {
+    pub fn new(storage: Arc<GitDbStorage>, repo: Repo) -> Self {
+        GitRepoStorage { storage, repo }
+    }
+}
+
+#[async_trait]
+impl ObjectStorage for GitRepoStorage {
+    async fn get_commit(&self, hash: &SHA1) -> Result<Option<Commit>, MegaError> {
+        Ok(self
+            .storage
+            .get_commit_by_hash(&self.repo, &hash.to_plain_str())
+            .await?
+            .map(|x| x.into()))
+    }
+
+    async fn get_tree(&self, hash: &SHA1) -> Result<Option<Tree>, MegaError> {
+        Ok(self.get_trees(&[*hash]).await?.into_iter().next())
+    }
+
+    async fn get_blob(&self, hash: &SHA1) -> Result<Option<Blob>, MegaError> {
+        // the content of blobs is shared by all repositories
+        let model = raw_blob::Entity::find()
+            .filter(raw_blob::Column::Sha1.eq(hash.to_plain_str()))
+            .one(self.storage.get_connection())
+            .await?;
+        Ok(model.map(|x| x.into()))
+    }
+
+    async fn get_trees(&self, hashes: &[SHA1]) -> Result<Vec<Tree>, MegaError> {
+        let hashes = hashes.iter().map(|x| x.to_plain_str()).collect();
+        Ok(self
+            .storage
+            .get_trees_by_hashes(&self.repo, hashes)
+            .await?
+            .into_iter()
+            .map(|x| x.into())
+            .collect())
+    }
+
+    async fn save_commits(&self, commits: Vec<Commit>) -> Result<(), MegaError> {
+        let entries = commits.into_iter().map(Entry::from).collect();
+        self.storage.save_entry(&self.repo, entries).await
+    }
+
+    async fn save_trees(&self, trees: Vec<Tree>) -> Result<(), MegaError> {
+        let entries = trees.into_iter().map(Entry::from).collect();
+        self.storage.save_entry(&self.repo, entries).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use venus::internal::object::blob::Blob;
+    use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+
+    use super::{FallbackStorage, MemoryStorage, ObjectStorage};
+
+    #[tokio::test]
+    async fn test_fallback_storage() {
+        let blob = Blob::from_content("vendored");
+        let vendored = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Blob,
+            blob.id,
+            "lib.rs".to_owned(),
+        )])
+        .unwrap();
+        let root = Tree::from_tree_items(vec![TreeItem::new(
+            TreeItemMode::Tree,
+            vendored.id,
+            "third_party".to_owned(),
+        )])
+        .unwrap();
+
+        let primary = Arc::new(MemoryStorage::new());
+        primary.insert_tree(root.clone());
+        // the vendored tree and its blob only exist in the secondary store
+        let secondary = Arc::new(MemoryStorage::new());
+        secondary.insert_tree(vendored.clone());
+        secondary.insert_blob(blob.clone());
+        let storage = FallbackStorage::new(primary.clone(), secondary);
+
+        assert_eq!(
+            storage.get_tree(&vendored.id).await.unwrap(),
+            Some(vendored.clone())
+        );
+        let trees = storage.get_trees(&[root.id, vendored.id]).await.unwrap();
+        assert_eq!(trees, vec![root, vendored.clone()]);
+        let blobs = storage.get_blobs(&[blob.id]).await.unwrap();
+        assert_eq!(blobs, vec![blob]);
+
+        // saving goes to the primary store only
+        storage.save_trees(vec![vendored.clone()]).await.unwrap();
+        assert!(primary.get_tree(&vendored.id).await.unwrap().is_some());
+    }
+}
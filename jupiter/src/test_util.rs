@@ -0,0 +1,17 @@
+//! Helpers for the tests of this crate and of the crates built on it, with the `mock` feature.
+use std::sync::Arc;
+
+use crate::storage::mega_storage::MegaStorage;
+
+/// The statements run against the mock connection of `storage`, once every other handle on it
+/// has been dropped.
+pub fn transaction_log(storage: impl Into<Arc<MegaStorage>>) -> Vec<String> {
+    Arc::try_unwrap(storage.into())
+        .ok()
+        .and_then(|x| Arc::try_unwrap(x.connection).ok())
+        .unwrap()
+        .into_transaction_log()
+        .iter()
+        .map(|x| format!("{:?}", x))
+        .collect()
+}
@@ -0,0 +1,375 @@
+//! Deltas between versions of a blob, in git's copy/insert delta format.
+//!
+//! A delta starts with the sizes of the base and the target, followed by instructions which
+//! either copy a range of the base or insert literal bytes. Storing a new version of a large file
+//! as a delta against its previous version only costs the changed bytes plus a few instructions.
+//! [`unified_diff`] renders the difference of two versions for people instead.
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::Cursor;
+
+use venus::errors::GitError;
+
+/// A delta is only worth storing if it is at most this share, in percent, of the target size.
+const MAX_DELTA_RATIO: usize = 50;
+
+/// The base is indexed in blocks of this size, shorter matches are inserted instead of copied.
+const BLOCK_SIZE: usize = 16;
+
+/// The most bytes a single insert instruction can carry.
+const MAX_INSERT: usize = 0x7f;
+
+/// The most bytes a single copy instruction can copy.
+const MAX_COPY: usize = 0xffffff;
+
+/// Like git, only the start of a blob is searched for NUL bytes to tell binary from text.
+const BINARY_PROBE_SIZE: usize = 8000;
+
+/// Encode `target` as a delta against `base`.
+///
+/// Unlike [`delta::encode`], which diffs byte by byte and is meant for picking delta bases in
+/// packs, this looks up blocks of the target in an index of the base like git does, so an edit
+/// in a large file only costs the edited bytes.
+pub fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    write_size(&mut delta, base.len());
+    write_size(&mut delta, target.len());
+
+    let mut index: HashMap<&[u8], usize> = HashMap::new();
+    for (i, block) in base.chunks_exact(BLOCK_SIZE).enumerate() {
+        index.entry(block).or_insert(i * BLOCK_SIZE);
+    }
+
+    let (mut pos, mut insert_start) = (0, 0);
+    while pos + BLOCK_SIZE <= target.len() {
+        let Some(&offset) = index.get(&target[pos..pos + BLOCK_SIZE]) else {
+            pos += 1;
+            continue;
+        };
+        // extend the match backwards into the pending insert, then forwards
+        let (mut offset, mut start) = (offset, pos);
+        while offset > 0 && start > insert_start && base[offset - 1] == target[start - 1] {
+            offset -= 1;
+            start -= 1;
+        }
+        let mut end = pos + BLOCK_SIZE;
+        while end < target.len()
+            && offset + (end - start) < base.len()
+            && base[offset + (end - start)] == target[end]
+        {
+            end += 1;
+        }
+
+        write_insert(&mut delta, &target[insert_start..start]);
+        write_copy(&mut delta, offset, end - start);
+        pos = end;
+        insert_start = end;
+    }
+    write_insert(&mut delta, &target[insert_start..]);
+    delta
+}
+
+fn write_size(delta: &mut Vec<u8>, mut size: usize) {
+    loop {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+        if size == 0 {
+            delta.push(byte);
+            return;
+        }
+        delta.push(byte | 0x80);
+    }
+}
+
+fn write_insert(delta: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(MAX_INSERT) {
+        delta.push(chunk.len() as u8);
+        delta.extend_from_slice(chunk);
+    }
+}
+
+fn write_copy(delta: &mut Vec<u8>, mut offset: usize, mut len: usize) {
+    while len > 0 {
+        let size = len.min(MAX_COPY);
+        // the instruction byte flags which of the 4 offset and 3 size bytes follow, zero bytes
+        // are left out
+        let mut instruction = 0x80;
+        let mut args = Vec::with_capacity(7);
+        for (i, byte) in (offset as u32)
+            .to_le_bytes()
+            .into_iter()
+            .chain((size as u32).to_le_bytes().into_iter().take(3))
+            .enumerate()
+        {
+            if byte != 0 {
+                instruction |= 1 << i;
+                args.push(byte);
+            }
+        }
+        delta.push(instruction);
+        delta.extend(args);
+        offset += size;
+        len -= size;
+    }
+}
+
+/// Rebuild the target of `delta` from its `base`.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, GitError> {
+    delta::decode(&mut Cursor::new(delta), base)
+        .map_err(|err| GitError::DeltaObjectError(err.to_string()))
+}
+
+/// Encode `target` as a delta against `base` if the delta is much smaller than `target` itself,
+/// `None` if storing `target` as it is would be about as cheap.
+pub fn encode_delta_if_smaller(base: &[u8], target: &[u8]) -> Option<Vec<u8>> {
+    let delta = encode_delta(base, target);
+    (delta.len() * 100 <= target.len() * MAX_DELTA_RATIO).then_some(delta)
+}
+
+/// A line of a diff, with its index in the side it is taken from.
+#[derive(Clone, Copy)]
+enum Line {
+    Context(usize),
+    Removed(usize),
+    Added(usize),
+}
+
+#[derive(Default)]
+struct LineDiff {
+    lines: Vec<Line>,
+}
+
+impl diffs::Diff for LineDiff {
+    type Error = ();
+
+    fn equal(&mut self, old: usize, _new: usize, len: usize) -> Result<(), ()> {
+        self.lines.extend((old..old + len).map(Line::Context));
+        Ok(())
+    }
+
+    fn delete(&mut self, old: usize, len: usize, _new: usize) -> Result<(), ()> {
+        self.lines.extend((old..old + len).map(Line::Removed));
+        Ok(())
+    }
+
+    fn insert(&mut self, _old: usize, new: usize, new_len: usize) -> Result<(), ()> {
+        self.lines.extend((new..new + new_len).map(Line::Added));
+        Ok(())
+    }
+}
+
+/// Whether `data` looks binary, which git guesses from a NUL byte near its start.
+pub fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(BINARY_PROBE_SIZE).any(|&x| x == 0)
+}
+
+/// The `start,count` of a hunk side, `start` being the 1-based line before the hunk if the side
+/// is empty. git leaves out a count of 1.
+fn hunk_range(before: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", before),
+        1 => format!("{}", before + 1),
+        _ => format!("{},{}", before + 1, count),
+    }
+}
+
+/// Render `old` and `new` as a unified diff like `git diff -U<context>`, with `path` in the file
+/// headers. A missing side, for an added or deleted file, is shown as `/dev/null`. The diff is
+/// empty if both are equal, `None` if either of them is binary.
+pub fn unified_diff(
+    old: Option<&[u8]>,
+    new: Option<&[u8]>,
+    path: &str,
+    context: usize,
+) -> Option<String> {
+    let (old_data, new_data) = (old.unwrap_or_default(), new.unwrap_or_default());
+    if is_binary(old_data) || is_binary(new_data) {
+        return None;
+    }
+    let old_lines: Vec<&[u8]> = old_data.split_inclusive(|&x| x == b'\n').collect();
+    let new_lines: Vec<&[u8]> = new_data.split_inclusive(|&x| x == b'\n').collect();
+    let mut diff = LineDiff::default();
+    diffs::myers::diff(
+        &mut diff,
+        &old_lines,
+        0,
+        old_lines.len(),
+        &new_lines,
+        0,
+        new_lines.len(),
+    )
+    .unwrap();
+    let lines = diff.lines;
+
+    let changes: Vec<usize> = (0..lines.len())
+        .filter(|&i| !matches!(lines[i], Line::Context(_)))
+        .collect();
+    let mut output = String::new();
+    if changes.is_empty() {
+        return Some(output);
+    }
+    let side = |prefix: &str, data: Option<&[u8]>| match data {
+        Some(_) => format!("{}/{}", prefix, path),
+        None => "/dev/null".to_owned(),
+    };
+    writeln!(output, "--- {}\n+++ {}", side("a", old), side("b", new)).unwrap();
+
+    // changes closer than twice the context share a hunk
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for &i in &changes {
+        match groups.last_mut() {
+            Some((_, last)) if i - *last <= 2 * context + 1 => *last = i,
+            _ => groups.push((i, i)),
+        }
+    }
+    for (first, last) in groups {
+        let start = first.saturating_sub(context);
+        let end = (last + context + 1).min(lines.len());
+        let hunk = &lines[start..end];
+        let old_before = lines[..start]
+            .iter()
+            .filter(|x| !matches!(x, Line::Added(_)))
+            .count();
+        let new_before = lines[..start]
+            .iter()
+            .filter(|x| !matches!(x, Line::Removed(_)))
+            .count();
+        let old_count = hunk.iter().filter(|x| !matches!(x, Line::Added(_))).count();
+        let new_count = hunk
+            .iter()
+            .filter(|x| !matches!(x, Line::Removed(_)))
+            .count();
+        writeln!(
+            output,
+            "@@ -{} +{} @@",
+            hunk_range(old_before, old_count),
+            hunk_range(new_before, new_count)
+        )
+        .unwrap();
+        // like git, the removed lines of a change come before the added ones
+        let mut added = Vec::new();
+        for line in hunk {
+            match *line {
+                Line::Context(i) => {
+                    for j in added.drain(..) {
+                        push_line(&mut output, '+', new_lines[j]);
+                    }
+                    push_line(&mut output, ' ', old_lines[i]);
+                }
+                Line::Removed(i) => push_line(&mut output, '-', old_lines[i]),
+                Line::Added(j) => added.push(j),
+            }
+        }
+        for j in added {
+            push_line(&mut output, '+', new_lines[j]);
+        }
+    }
+    Some(output)
+}
+
+fn push_line(output: &mut String, prefix: char, text: &[u8]) {
+    output.push(prefix);
+    output.push_str(&String::from_utf8_lossy(text));
+    if !text.ends_with(b"\n") {
+        output.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_delta, encode_delta, encode_delta_if_smaller, unified_diff};
+
+    fn large_file() -> Vec<u8> {
+        (0..2000)
+            .flat_map(|i| format!("line number {}\n", i).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let base = large_file();
+        let mut target = base[..10000].to_vec();
+        target.extend_from_slice(b"inserted in the middle\n");
+        target.extend_from_slice(&base[12000..]);
+
+        let delta = encode_delta(&base, &target);
+        assert_eq!(apply_delta(&base, &delta).unwrap(), target);
+        // a delta against an empty base only inserts
+        let delta = encode_delta(b"", b"new content");
+        assert_eq!(apply_delta(b"", &delta).unwrap(), b"new content");
+    }
+
+    #[test]
+    fn test_small_edit_gives_small_delta() {
+        let base = large_file();
+        let target = String::from_utf8(base.clone())
+            .unwrap()
+            .replace("line number 1000\n", "line number one thousand\n")
+            .into_bytes();
+
+        let delta = encode_delta_if_smaller(&base, &target).unwrap();
+        assert!(delta.len() < 100, "delta is {} bytes", delta.len());
+        assert_eq!(apply_delta(&base, &delta).unwrap(), target);
+
+        // nothing in common, a delta doesn't pay off
+        assert!(encode_delta_if_smaller(&base, b"completely different").is_none());
+    }
+
+    #[test]
+    fn test_delta_with_long_runs() {
+        // copies longer than a single instruction and inserts of more than 127 bytes
+        let base = vec![b'a'; 0x1000010];
+        let mut target = vec![b'b'; 300];
+        target.extend_from_slice(&base);
+        let delta = encode_delta(&base, &target);
+        assert_eq!(apply_delta(&base, &delta).unwrap(), target);
+    }
+
+    #[test]
+    fn test_apply_delta_to_wrong_base() {
+        let base = large_file();
+        let delta = encode_delta(&base, b"target");
+        assert!(apply_delta(b"another base", &delta).is_err());
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\n";
+        let new = "a\nb\nc\nD\ne\nf\ng\nh\n";
+        let diff =
+            unified_diff(Some(old.as_bytes()), Some(new.as_bytes()), "src/lib.rs", 2).unwrap();
+        assert_eq!(
+            diff,
+            "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -2,5 +2,5 @@\n b\n c\n-d\n+D\n e\n f\n"
+        );
+        // without context, and a line appended without a trailing newline
+        let new = b"a\nb\nc\nd\ne\nf\ng\nh\ni";
+        let diff = unified_diff(Some(old.as_bytes()), Some(new), "x", 0).unwrap();
+        assert_eq!(
+            diff,
+            "--- a/x\n+++ b/x\n@@ -8,0 +9 @@\n+i\n\\ No newline at end of file\n"
+        );
+        assert_eq!(
+            unified_diff(Some(old.as_bytes()), Some(old.as_bytes()), "x", 3).unwrap(),
+            ""
+        );
+        // added and deleted files
+        assert_eq!(
+            unified_diff(None, Some(b"a\n"), "x", 3).unwrap(),
+            "--- /dev/null\n+++ b/x\n@@ -0,0 +1 @@\n+a\n"
+        );
+        assert_eq!(
+            unified_diff(Some(b"a\nb\n"), None, "x", 3).unwrap(),
+            "--- a/x\n+++ /dev/null\n@@ -1,2 +0,0 @@\n-a\n-b\n"
+        );
+    }
+
+    #[test]
+    fn test_unified_diff_of_binary() {
+        assert!(
+            unified_diff(Some(b"\x89PNG\0\0"), Some(b"\x89PNG\0\x01"), "logo.png", 3).is_none()
+        );
+        assert!(unified_diff(Some(b"text\n"), Some(b"\0"), "data", 3).is_none());
+        assert!(unified_diff(None, Some(b"\0"), "data", 3).is_none());
+    }
+}
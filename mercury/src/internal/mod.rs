@@ -3,5 +3,6 @@
 //!
 //!
 
+pub mod blob;
 pub mod pack;
 pub mod model;
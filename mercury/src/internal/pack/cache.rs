@@ -85,14 +85,13 @@ impl Caches {
         Ok(obj)
     }
 
-    /// generate the temp file path, hex string of the hash
+    /// generate the temp file path, in the fanout layout of loose objects
     fn generate_temp_path(tmp_path: &Path, hash: SHA1) -> PathBuf {
-        let mut path = tmp_path.to_path_buf();
-        path.push(&hash.to_plain_str()[..2]); // use first 2 chars as the directory
-        if !path.exists() {
-            fs::create_dir(&path).unwrap();
+        let path = hash.loose_path(tmp_path);
+        let dir = path.parent().unwrap();
+        if !dir.exists() {
+            fs::create_dir(dir).unwrap();
         }
-        path.push(hash.to_plain_str());
         path
     }
 
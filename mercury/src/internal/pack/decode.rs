@@ -4,7 +4,7 @@
 //!
 //!
 //!
-use std::io::{self, BufRead, Cursor, ErrorKind, Read, Seek};
+use std::io::{self, BufRead, Cursor, ErrorKind, Read, Seek, SeekFrom};
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
@@ -78,7 +78,7 @@ impl Pack {
     ///
     /// This function reads the first 12 bytes of a pack file, which include the "PACK" magic identifier,
     /// the version number, and the number of objects in the pack. It verifies that the magic identifier
-    /// is correct and that the version number is 2 (which is the version currently supported by Git).
+    /// is correct and that the version number is 2 or 3 (the versions Git can read).
     /// It also collects these header bytes for later use, such as for hashing the entire pack file.
     ///
     /// # Parameters
@@ -94,8 +94,8 @@ impl Pack {
     ///
     /// # Errors
     /// This function can return an error in the following situations:
-    /// * If the pack file does not start with the "PACK" magic identifier.
-    /// * If the pack file's version number is not 2.
+    /// * If the pack file does not start with the "PACK" magic identifier, [`GitError::InvalidPackSignature`].
+    /// * If the pack file's version number is not 2 or 3, [`GitError::UnsupportedPackVersion`].
     /// * If there are any issues reading from the provided `pack` source.
     pub fn check_header(pack: &mut (impl Read + BufRead)) -> Result<(u32, Vec<u8>), GitError> {
         // A vector to store the header data for hashing later
@@ -112,11 +112,8 @@ impl Pack {
 
                 // Check if the magic bytes match "PACK"
                 if magic != *b"PACK" {
-                    // If not, return an error indicating invalid pack header
-                    return Err(GitError::InvalidPackHeader(format!(
-                        "{},{},{},{}",
-                        magic[0], magic[1], magic[2], magic[3]
-                    )));
+                    // If not, return an error indicating invalid pack signature
+                    return Err(GitError::InvalidPackSignature(magic));
                 }
             },
             Err(_e) => {
@@ -138,12 +135,9 @@ impl Pack {
 
                 // Convert the version bytes to an u32 integer
                 let version = u32::from_be_bytes(version_bytes);
-                if version != 2 {
-                    // Git currently supports version 2, so error if not version 2
-                    return Err(GitError::InvalidPackFile(format!(
-                        "Version Number is {}, not 2",
-                        version
-                    )));
+                if version != 2 && version != 3 {
+                    // Git reads version 2 and 3, which share the same layout
+                    return Err(GitError::UnsupportedPackVersion(version));
                 }
                 // If read is successful, proceed
             },
@@ -333,6 +327,13 @@ impl Pack {
         let callback = Arc::new(callback);
 
         let caches = self.caches.clone();
+        // the length of the pack, to tell where the objects end and the 20 bytes trailer begins
+        let pack_len = {
+            let start = pack.stream_position()?;
+            let end = pack.seek(SeekFrom::End(0))?;
+            pack.seek(SeekFrom::Start(start))?;
+            (end - start) as usize
+        };
         let mut reader = Wrapper::new(io::BufReader::new(pack));
 
         let result = Pack::check_header(&mut reader);
@@ -348,6 +349,10 @@ impl Pack {
         let mut offset: usize = 12;
         let mut i = 0;
         while i < self.number {
+            if offset + 20 >= pack_len {
+                // only the trailer is left, the header declared more objects than the pack has
+                return Err(GitError::PackObjectCountMismatch(self.number, i));
+            }
             // log per 2000&more then 1 se objects
             if i%1000 == 0 {
                 let time_now = time.elapsed().as_millis();
@@ -418,6 +423,14 @@ impl Pack {
             i += 1;
         }
         log_info(i, self);
+        if offset + 20 < pack_len {
+            // more objects than declared, count them for the error without processing them
+            let mut actual = i;
+            while offset + 20 < pack_len && self.decode_pack_object(&mut reader, &mut offset).is_ok() {
+                actual += 1;
+            }
+            return Err(GitError::PackObjectCountMismatch(self.number, actual));
+        }
         let render_hash = reader.final_hash();
         let mut trailer_buf = [0; 20];
         reader.read_exact(&mut trailer_buf).unwrap();
@@ -456,13 +469,15 @@ impl Pack {
     }
 
     /// Decode Pack in a new thread and send the CacheObjects while decoding.
-    /// <br> Attention: It will consume the `pack` and return in JoinHandle
-    pub fn decode_async(mut self, mut pack: (impl Read + BufRead + Seek + Send + 'static), sender: Sender<Entry>) -> JoinHandle<Pack> {
+    /// <br> Attention: It will consume the `pack` and return in JoinHandle, together with the
+    /// decoding error if the pack is invalid. Entries sent before the error are not validated.
+    pub fn decode_async(mut self, mut pack: impl Read + BufRead + Seek + Send + 'static, sender: Sender<Entry>) -> JoinHandle<Result<Pack, GitError>> {
         thread::spawn(move || {
             self.decode(&mut pack, move |entry| {
-                sender.send(entry).unwrap();
-            }).unwrap();
-            self
+                // the receiver may stop listening after an invalid entry
+                let _ = sender.send(entry);
+            })?;
+            Ok(self)
         })
     }
 
@@ -598,7 +613,9 @@ mod tests {
 
     use flate2::write::ZlibEncoder;
     use flate2::Compression;
+    use sha1::{Digest, Sha1};
     use tracing_subscriber::util::SubscriberInitExt;
+    use venus::errors::GitError;
 
     use crate::internal::pack::Pack;
 
@@ -720,7 +737,7 @@ mod tests {
         for _entry in rx {
             cnt += 1; //use entry here
         }
-        let p = handle.join().unwrap();
+        let p = handle.join().unwrap().unwrap();
         assert_eq!(cnt, p.number);
     }
 
@@ -749,4 +766,64 @@ mod tests {
         task1.join().unwrap();
         task2.join().unwrap();
     }
+
+    /// A pack of small `blobs`, whose header declares `version` and `declared` objects.
+    fn blob_pack(version: u32, declared: u32, blobs: &[&[u8]]) -> Vec<u8> {
+        let mut pack = b"PACK".to_vec();
+        pack.extend(version.to_be_bytes());
+        pack.extend(declared.to_be_bytes());
+        for data in blobs {
+            // type 3 (blob), sizes below 16 fit into the first byte
+            assert!(data.len() < 16);
+            pack.push(0b0011_0000 | data.len() as u8);
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(data).unwrap();
+            pack.extend(encoder.finish().unwrap());
+        }
+        let hash = Sha1::digest(&pack);
+        pack.extend(hash);
+        pack
+    }
+
+    fn decode_pack(data: Vec<u8>) -> Result<(), GitError> {
+        let mut p = Pack::new(None, Some(1024*1024*20), Some(PathBuf::from("/tmp/.cache_temp")), true);
+        p.decode(&mut Cursor::new(data), |_|{})
+    }
+
+    #[test]
+    fn test_pack_wrong_signature() {
+        let mut data = blob_pack(2, 1, &[b"hello"]);
+        data[..4].copy_from_slice(b"KCAP");
+        let result = Pack::check_header(&mut Cursor::new(data));
+        assert!(matches!(result, Err(GitError::InvalidPackSignature(sig)) if sig == *b"KCAP"));
+    }
+
+    #[test]
+    fn test_pack_version() {
+        let result = decode_pack(blob_pack(4, 1, &[b"hello"]));
+        assert!(matches!(result, Err(GitError::UnsupportedPackVersion(4))));
+
+        decode_pack(blob_pack(2, 1, &[b"hello"])).unwrap();
+        decode_pack(blob_pack(3, 1, &[b"hello"])).unwrap();
+    }
+
+    #[test]
+    fn test_pack_object_count_mismatch() {
+        let result = decode_pack(blob_pack(2, 3, &[b"hello", b"world"]));
+        assert!(matches!(result, Err(GitError::PackObjectCountMismatch(3, 2))));
+
+        let result = decode_pack(blob_pack(2, 1, &[b"hello", b"world"]));
+        assert!(matches!(result, Err(GitError::PackObjectCountMismatch(1, 2))));
+    }
+
+    #[test]
+    fn test_decode_async_error() {
+        let p = Pack::new(None, Some(1024*1024*20), Some(PathBuf::from("/tmp/.cache_temp")), true);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let handle = p.decode_async(Cursor::new(blob_pack(2, 3, &[b"hello", b"world"])), tx);
+        // the objects decoded before the error may still arrive
+        assert!(rx.iter().count() <= 2);
+        let result = handle.join().unwrap();
+        assert!(matches!(result, Err(GitError::PackObjectCountMismatch(3, 2))));
+    }
 }
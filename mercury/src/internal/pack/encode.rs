@@ -11,13 +11,34 @@ use venus::internal::object::types::ObjectType;
 use venus::{errors::GitError, hash::SHA1, internal::pack::entry::Entry};
 
 const MIN_DELTA_RATE: f64 = 0.5; // minimum delta rate can accept
+const DEFAULT_WINDOW_SIZE: usize = 10; // same as the default of `git pack-objects --window`
+const DEFAULT_DELTA_DEPTH: usize = 50; // same as the default of `git pack-objects --depth`
+
+/// How hard the encoder looks for delta bases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeltaOptions {
+    /// The number of preceding objects tried as the base of a delta, 0 disables deltas.
+    pub window: usize,
+    /// The longest chain of deltas on top of deltas.
+    pub depth: usize,
+}
+
+impl Default for DeltaOptions {
+    fn default() -> Self {
+        DeltaOptions {
+            window: DEFAULT_WINDOW_SIZE,
+            depth: DEFAULT_DELTA_DEPTH,
+        }
+    }
+}
 
 /// A encoder for generating pack files with delta objects.
 pub struct PackEncoder {
     object_number: usize,
     process_index: usize,
     window_size: usize,
-    window: VecDeque<(Entry, usize)>, // entry and offset
+    max_depth: usize,
+    window: VecDeque<(Entry, usize, usize)>, // entry, offset and delta depth
     writer: Vec<u8>,
     inner_offset: usize, // offset of current entry
     inner_hash: Sha1,    // Not SHA1 because need update trait
@@ -69,6 +90,7 @@ impl PackEncoder {
         PackEncoder {
             object_number,
             window_size,
+            max_depth: DEFAULT_DELTA_DEPTH,
             process_index: 0,
             window: VecDeque::with_capacity(window_size),
             writer,
@@ -79,6 +101,16 @@ impl PackEncoder {
         }
     }
 
+    pub fn with_options(object_number: usize, options: DeltaOptions) -> Self {
+        PackEncoder::new(object_number, options.window).with_delta_depth(options.depth)
+    }
+
+    /// Limit chains of deltas to `depth`, longer chains make objects slower to read back.
+    pub fn with_delta_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
     /// Get the hash of the pack file. if the pack file is not finished, return None
     pub fn get_hash(&self) -> Option<SHA1> {
         self.final_hash
@@ -104,8 +136,8 @@ impl PackEncoder {
                     self.process_index += 1;
                     // push window after encode to void diff by self
                     let offset = self.inner_offset;
-                    self.encode_one_object(&entry)?;
-                    self.window.push_back((entry, offset));
+                    let depth = self.encode_one_object(&entry)?;
+                    self.window.push_back((entry, offset, depth));
                     if self.window.len() > self.window_size {
                         self.window.pop_front();
                     }
@@ -128,13 +160,13 @@ impl PackEncoder {
 
     /// Try to encode as delta using objects in window
     /// # Returns
-    /// - Return (delta entry, offset) if success make delta
-    /// - Return (origin Entry,None) if didn't delta,
-    fn try_as_offset_delta(&mut self, entry: &Entry) -> (Entry, Option<usize>) {
-        let mut best_base: Option<&(Entry, usize)> = None;
+    /// - Return (delta entry, offset, depth) if success make delta
+    /// - Return (origin Entry, None, 0) if didn't delta,
+    fn try_as_offset_delta(&mut self, entry: &Entry) -> (Entry, Option<usize>, usize) {
+        let mut best_base: Option<&(Entry, usize, usize)> = None;
         let mut best_rate: f64 = 0.0;
         for try_base in self.window.iter() {
-            if try_base.0.obj_type != entry.obj_type {
+            if try_base.0.obj_type != entry.obj_type || try_base.2 >= self.max_depth {
                 continue;
             }
             let rate = delta::encode_rate(&try_base.0.data, &entry.data);
@@ -154,9 +186,10 @@ impl PackEncoder {
                     ..entry.clone()
                 },
                 Some(offset),
+                best_base.2 + 1,
             )
         } else {
-            (entry.clone(), None)
+            (entry.clone(), None, 0)
         }
     }
 
@@ -167,10 +200,10 @@ impl PackEncoder {
         self.writer.write_all(data).unwrap();
     }
 
-    /// Encode one object, and update the hash
-    fn encode_one_object(&mut self, entry: &Entry) -> Result<(), GitError> {
+    /// Encode one object, and update the hash. Returns the delta depth of the written object.
+    fn encode_one_object(&mut self, entry: &Entry) -> Result<usize, GitError> {
         // try encode as delta
-        let (entry, offset) = self.try_as_offset_delta(entry);
+        let (entry, offset, depth) = self.try_as_offset_delta(entry);
         let obj_data = entry.data;
         let obj_data_len = obj_data.len();
         let obj_type_number = entry.obj_type.to_u8();
@@ -209,7 +242,7 @@ impl PackEncoder {
         inflate.flush().expect("zlib flush should never failed");
         let compressed_data = inflate.finish().expect("zlib compress should never failed");
         self.write_all_and_update(&compressed_data);
-        Ok(())
+        Ok(depth)
     }
 
     /// async version of encode, result data will be returned by JoinHandle.
@@ -221,10 +254,30 @@ impl PackEncoder {
     }
 }
 
+/// Encode `entries` into a single pack, written to `out`, and return the pack checksum.
+/// Meant for bulk exports, which would otherwise write one loose file per object.
+pub fn write_objects_as_pack(entries: Vec<Entry>, out: &mut impl Write) -> Result<SHA1, GitError> {
+    if entries.is_empty() {
+        return Err(GitError::EmptyPack);
+    }
+    let mut encoder = PackEncoder::new(entries.len(), DEFAULT_WINDOW_SIZE);
+    let (tx, rx) = mpsc::channel();
+    for entry in entries {
+        tx.send(entry).unwrap();
+    }
+    drop(tx);
+    let data = encoder.encode(rx)?;
+    out.write_all(&data)?;
+    Ok(encoder.get_hash().unwrap())
+}
+
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Mutex};
     use std::{io::Cursor, path::PathBuf,  usize};
     use venus::internal::object::blob::Blob;
+    use venus::internal::object::commit::Commit;
+    use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
     use crate::internal::pack::Pack;
 
     use super::*;
@@ -268,6 +321,85 @@ mod tests {
         check_format(pack_with_delta);
     }
 
+    #[test]
+    fn test_write_objects_as_pack() {
+        let blobs: Vec<Blob> = ["hello, code,", "hello, world.", "!"]
+            .iter()
+            .map(|x| Blob::from_content(x))
+            .collect();
+        let items = blobs
+            .iter()
+            .enumerate()
+            .map(|(i, blob)| TreeItem::new(TreeItemMode::Blob, blob.id, format!("{}.txt", i)))
+            .collect();
+        let tree = Tree::from_tree_items(items).unwrap();
+        let commit = Commit::from_tree_id(tree.id, vec![], "export");
+
+        let mut entries: Vec<Entry> = blobs.into_iter().map(|x| x.into()).collect();
+        entries.push(tree.into());
+        entries.push(commit.into());
+        let mut expected: Vec<SHA1> = entries.iter().map(|x| x.hash).collect();
+
+        let mut out = Vec::new();
+        let checksum = write_objects_as_pack(entries, &mut out).unwrap();
+        assert_eq!(SHA1::from_bytes(&out[out.len() - 20..]), checksum);
+
+        let decoded = Arc::new(Mutex::new(Vec::new()));
+        let result = decoded.clone();
+        let mut p = Pack::new(
+            None,
+            Some(1024 * 20),
+            Some(PathBuf::from("/tmp/.cache_temp")),
+            true,
+        );
+        p.decode(&mut Cursor::new(out), move |entry| {
+            result.lock().unwrap().push(entry.hash)
+        })
+        .unwrap();
+        assert_eq!(p.signature, checksum);
+
+        let mut decoded = decoded.lock().unwrap().clone();
+        decoded.sort();
+        expected.sort();
+        assert_eq!(decoded, expected);
+
+        assert!(matches!(
+            write_objects_as_pack(vec![], &mut Vec::new()),
+            Err(GitError::EmptyPack)
+        ));
+    }
+
+    #[test]
+    fn test_delta_options_shrink_similar_blobs() {
+        fn encode_with(options: DeltaOptions) -> Vec<u8> {
+            let base: String = (0..200).map(|i| format!("line number {}\n", i)).collect();
+            let blobs: Vec<Blob> = (0..5)
+                .map(|i| Blob::from_content(&format!("{}version {}\n", base, i)))
+                .collect();
+            let mut encoder = PackEncoder::with_options(blobs.len(), options);
+            let (tx, rx) = mpsc::channel::<Entry>();
+            for blob in blobs {
+                tx.send(blob.into()).unwrap();
+            }
+            drop(tx);
+            encoder.encode(rx).unwrap()
+        }
+        let without_delta = encode_with(DeltaOptions { window: 0, depth: 0 });
+        let with_delta = encode_with(DeltaOptions::default());
+        let shallow = encode_with(DeltaOptions { window: 10, depth: 1 });
+        assert!(with_delta.len() < without_delta.len());
+        assert!(shallow.len() < without_delta.len());
+
+        for data in [without_delta, with_delta, shallow] {
+            let mut p = Pack::new(None, Some(1024 * 20), Some(PathBuf::from("/tmp/.cache_temp")), true);
+            let count = Arc::new(Mutex::new(0));
+            let counter = count.clone();
+            p.decode(&mut Cursor::new(data), move |_| *counter.lock().unwrap() += 1)
+                .expect("pack file format error");
+            assert_eq!(*count.lock().unwrap(), 5);
+        }
+    }
+
     #[test]
     fn test_encode_offset() {
         let value = 11013;
@@ -19,6 +19,8 @@ use crate::internal::pack::waitlist::Waitlist;
 
 use self::cache::Caches;
 
+pub use self::encode::write_objects_as_pack;
+
 ///
 /// 
 /// 
@@ -0,0 +1,13 @@
+-- Blob sizes are recorded since the blob:limit filter, rows saved before hold 0.
+ALTER TABLE "mega_blob" ALTER COLUMN "size" TYPE BIGINT;
+ALTER TABLE "git_blob" ALTER COLUMN "size" TYPE BIGINT;
+UPDATE "mega_blob" SET "size" = OCTET_LENGTH("raw_blob"."data")
+FROM "raw_blob"
+WHERE "raw_blob"."sha1" = "mega_blob"."blob_id"
+  AND "mega_blob"."size" = 0
+  AND "raw_blob"."data" IS NOT NULL;
+UPDATE "git_blob" SET "size" = OCTET_LENGTH("raw_blob"."data")
+FROM "raw_blob"
+WHERE "raw_blob"."sha1" = "git_blob"."blob_id"
+  AND "git_blob"."size" = 0
+  AND "raw_blob"."data" IS NOT NULL;
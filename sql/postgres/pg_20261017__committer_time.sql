@@ -0,0 +1,8 @@
+-- The commit time is kept apart to page through the commits after a timestamp.
+ALTER TABLE "mega_commit" ADD COLUMN IF NOT EXISTS "committer_time" BIGINT NOT NULL DEFAULT 0;
+-- The committer is stored as `committer <name> <<email>> <timestamp> <timezone>`.
+UPDATE "mega_commit"
+SET "committer_time" = SUBSTRING("committer" FROM '> ([0-9]+) [+-][0-9]+\s*$')::BIGINT
+WHERE "committer_time" = 0
+  AND "committer" ~ '> [0-9]+ [+-][0-9]+\s*$';
+CREATE INDEX IF NOT EXISTS "idx_mc_committer_time" ON "mega_commit" ("committer_time");
@@ -0,0 +1,28 @@
+-- A retried push finds the MR it opened by the key of the push, older MRs have none.
+ALTER TABLE "mega_mr" ADD COLUMN IF NOT EXISTS "idempotency_key" VARCHAR(40) NOT NULL DEFAULT '';
+CREATE INDEX IF NOT EXISTS "idx_mr_idempotency_key" ON "mega_mr" ("idempotency_key");
+ALTER TABLE "mega_mr" ADD COLUMN IF NOT EXISTS "build_id" VARCHAR(40);
+ALTER TABLE "mega_mr" ADD COLUMN IF NOT EXISTS "agent" TEXT;
+
+CREATE TABLE IF NOT EXISTS "mega_mr_build" (
+  "mr_id" BIGINT PRIMARY KEY,
+  "build_id" VARCHAR(40) NOT NULL,
+  "state" VARCHAR(20) NOT NULL,
+  "log_url" TEXT,
+  "created_at" TIMESTAMP NOT NULL,
+  "updated_at" TIMESTAMP NOT NULL
+);
+
+-- The comments written before replies and bots were all top level ones by people.
+ALTER TABLE "mega_mr_comment" ADD COLUMN IF NOT EXISTS "parent_comment_id" BIGINT;
+ALTER TABLE "mega_mr_comment" ADD COLUMN IF NOT EXISTS "kind" VARCHAR(20) NOT NULL DEFAULT 'human';
+
+CREATE TABLE IF NOT EXISTS "mega_reflog" (
+  "id" BIGINT PRIMARY KEY,
+  "ref_name" TEXT NOT NULL,
+  "old_hash" VARCHAR(40) NOT NULL,
+  "new_hash" VARCHAR(40) NOT NULL,
+  "reason" TEXT NOT NULL,
+  "created_at" TIMESTAMP NOT NULL
+);
+CREATE INDEX IF NOT EXISTS "idx_reflog_ref_name" ON "mega_reflog" ("ref_name");
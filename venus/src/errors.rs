@@ -7,6 +7,8 @@ use std::string::FromUtf8Error;
 
 use thiserror::Error;
 
+use common::errors::MegaError;
+
 #[derive(Error, Debug)]
 #[allow(unused)]
 pub enum GitError {
@@ -43,6 +45,15 @@ pub enum GitError {
     #[error("The `{0}` is not a valid pack header.")]
     InvalidPackHeader(String),
 
+    #[error("The pack signature `{0:?}` is not `PACK`.")]
+    InvalidPackSignature([u8; 4]),
+
+    #[error("The pack version {0} is not supported, only version 2 and 3 are.")]
+    UnsupportedPackVersion(u32),
+
+    #[error("The pack header declares {0} objects, but the pack contains {1}.")]
+    PackObjectCountMismatch(usize, usize),
+
     #[error("The `{0}` is not a valid index file.")]
     InvalidIndexFile(String),
 
@@ -70,6 +81,9 @@ pub enum GitError {
     #[error("Can't encode the object which id [{0}] to bytes")]
     EncodeObjectError(String),
 
+    #[error("The content of the object {0} doesn't match its hash")]
+    ObjectHashMismatch(String),
+
     #[error("UTF-8 conversion error: {0}")]
     ConversionError(String),
 
@@ -78,6 +92,99 @@ pub enum GitError {
 
     #[error("Can't encode entries to pack: {0}")]
     PackEncodeError(String),
+
+    #[error("The `{0}` is not a valid merge request id.")]
+    InvalidMergeRequest(i64),
+
+    #[error("Conflicts in paths: {}", .0.join(", "))]
+    ConflictError(Vec<String>),
+
+    #[error("The pack contains no objects")]
+    EmptyPack,
+
+    #[error("The ref `{0}` was updated concurrently")]
+    StaleRef(String),
+
+    #[error("Unsupported service: {0}")]
+    UnsupportedService(String),
+
+    #[error("The `{0}` is not a valid pkt-line length")]
+    InvalidPktLine(String),
+
+    #[error("The blob `{0}` of {1} bytes exceeds the limit of {2} bytes, use LFS for large files")]
+    BlobTooLarge(String, usize, usize),
+
+    #[error("Commit {0} has the email `{1}` outside of the allowed domains")]
+    EmailNotAllowed(String, String),
+
+    #[error("Commit {0} has no `{1}` trailer")]
+    MissingTrailer(String, String),
+
+    #[error("Commit {0} has the committer timestamp {1}, too far in the future")]
+    CommitInFuture(String, i64),
+
+    #[error("Commit {0} is empty, its tree is the one of its parent")]
+    EmptyCommit(String),
+
+    #[error("Can't {1} the {0} merge request")]
+    InvalidMrTransition(String, String),
+
+    #[error("Storage error: {0}")]
+    StorageError(String),
+
+    #[error("The tree item `{0}` has mode {1} but refers to a {2}")]
+    TreeItemTypeMismatch(String, String, String),
+
+    #[error("The tree item `{0}` refers to the missing object {1}")]
+    MissingTreeItemObject(String, String),
+
+    #[error("The operation was cancelled")]
+    Cancelled,
+
+    #[error("`{0}` is not a valid ref name")]
+    InvalidRefName(String),
+
+    #[error("The tag `{0}` already exists")]
+    TagAlreadyExists(String),
+
+    #[error("The update of `{0}` is not a fast-forward")]
+    NonFastForward(String),
+
+    #[error("Can't fetch from the upstream: {0}")]
+    UpstreamError(String),
+
+    #[error("The default branch `{0}` can't be deleted")]
+    DefaultBranchDeletion(String),
+
+    #[error("The tree {0} has entries differing only in case: {}", .1.join(", "))]
+    CaseCollision(String, Vec<String>),
+
+    #[error("Invalid pack upload: {0}")]
+    InvalidUpload(String),
+
+    #[error("The objects of merge request {0} are neither reachable nor retained")]
+    UnretainedMrObjects(i64),
+
+    #[error("The ref `{0}` already exists")]
+    RefAlreadyExists(String),
+
+    #[error("The ref `{0}` doesn't exist")]
+    RefNotFound(String),
+
+    #[error("The short hash `{0}` matches more than one object")]
+    AmbiguousHash(String),
+
+    #[error("The tree {0} has more than {1} entries")]
+    TreeTooLarge(String, usize),
+
+    #[error("The submodule `{0}` has the unsafe url `{1}`")]
+    UnsafeSubmoduleUrl(String, String),
+
+    #[error("The symlink `{0}` points outside of the checkout to `{1}`")]
+    UnsafeSymlink(String, String),
+
+    #[error("The tree entry `{0}` can't be checked out safely")]
+    UnsafeEntryName(String),
 }
 
 impl From<FromUtf8Error> for GitError {
@@ -86,3 +193,9 @@ impl From<FromUtf8Error> for GitError {
         GitError::ConversionError(err.to_string())
     }
 }
+
+impl From<MegaError> for GitError {
+    fn from(err: MegaError) -> Self {
+        GitError::StorageError(err.to_string())
+    }
+}
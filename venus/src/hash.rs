@@ -4,6 +4,8 @@
 //!
 
 use std::fmt::Display;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
 use colored::Colorize;
 use serde::{Deserialize, Serialize};
@@ -131,6 +133,51 @@ impl SHA1 {
     pub fn to_data(self) -> Vec<u8> {
         self.0.to_vec()
     }
+
+    /// The path of the object with this hash below `objects_dir`, in the
+    /// `<first 2 hex chars>/<remaining 38 hex chars>` fanout layout of Git loose objects.
+    pub fn loose_path(&self, objects_dir: &Path) -> PathBuf {
+        let hex = self.to_plain_str();
+        let (folder, file) = hex.split_at(2);
+        objects_dir.join(folder).join(file)
+    }
+}
+
+/// A writer which hashes everything written through it, so data can be hashed while it is
+/// streamed to `inner` instead of being held in memory.
+pub struct HashWriter<W> {
+    inner: W,
+    hasher: sha1_smol::Sha1,
+}
+
+impl<W: Write> HashWriter<W> {
+    pub fn new(inner: W) -> Self {
+        HashWriter {
+            inner,
+            hasher: sha1_smol::Sha1::new(),
+        }
+    }
+
+    /// The hash of everything written so far.
+    pub fn finalize(&self) -> SHA1 {
+        SHA1(self.hasher.digest().bytes())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
 }
 
 #[cfg(test)]
@@ -139,10 +186,12 @@ mod tests {
     use std::io::Read;
     use std::io::Seek;
     use std::io::SeekFrom;
+    use std::io::Write;
     use std::str::FromStr;
     use std::{env, path::PathBuf};
 
-    use crate::hash::SHA1;
+    use crate::hash::{HashWriter, SHA1};
+    use crate::internal::object::types::ObjectType;
 
     #[test]
     fn test_sha1_new() {
@@ -219,6 +268,15 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_sha1_loose_path() {
+        let hash = SHA1::from_str("8ab686eafeb1f44702738c8b0f24f2567c36da6d").unwrap();
+        assert_eq!(
+            hash.loose_path(&PathBuf::from("/tmp/cache")),
+            PathBuf::from("/tmp/cache/8a/b686eafeb1f44702738c8b0f24f2567c36da6d")
+        );
+    }
+
     #[test]
     fn test_sha1_to_data() {
         let hash_str = "8ab686eafeb1f44702738c8b0f24f2567c36da6d";
@@ -236,4 +294,19 @@ mod tests {
             Err(e) => println!("Error: {}", e),
         }
     }
+
+    #[test]
+    fn test_hash_writer() {
+        let data = b"fn main() {}\n".to_vec();
+        let mut writer = HashWriter::new(Vec::new());
+        write!(writer, "blob {}\0", data.len()).unwrap();
+        for chunk in data.chunks(4) {
+            writer.write_all(chunk).unwrap();
+        }
+        assert_eq!(
+            writer.finalize(),
+            SHA1::from_type_and_data(ObjectType::Blob, &data)
+        );
+        assert!(writer.into_inner().ends_with(&data));
+    }
 }
@@ -28,13 +28,16 @@
 //! objects to form a version history of the repository.
 //!
 use std::fmt::Display;
-use std::io::Cursor;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Write};
+use std::path::Path;
 
 use crate::errors::GitError;
-use crate::hash::SHA1;
+use crate::hash::{HashWriter, SHA1};
 use crate::internal::object::types::ObjectType;
 use crate::internal::object::utils;
 use crate::internal::object::ObjectTrait;
+use crate::internal::pack::entry::Entry;
 use crate::internal::zlib::stream::inflate::ReadBoxed;
 
 /// **The Blob Object**
@@ -88,4 +91,77 @@ impl Blob {
         let mut buf = ReadBoxed::new(blob_content, ObjectType::Blob, content.len());
         Blob::from_buf_read(&mut buf, content.len())
     }
+
+    /// Read the file at `path` into a blob entry ready to be stored. The object header and the
+    /// content are hashed chunk by chunk as the file is read, so the content is never copied to
+    /// be hashed. The mode of the file is given by [`TreeItemMode::from_metadata`].
+    ///
+    /// [`TreeItemMode::from_metadata`]: crate::internal::object::tree::TreeItemMode::from_metadata
+    pub fn from_path(path: &Path) -> Result<(SHA1, Entry), GitError> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut hasher = HashWriter::new(io::sink());
+        hasher.write_all(&ObjectType::Blob.to_data()?)?;
+        write!(hasher, " {}\0", size)?;
+
+        let mut data = Vec::with_capacity(size as usize);
+        let mut reader = file.take(size);
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.write_all(&buf[..n])?;
+            data.extend_from_slice(&buf[..n]);
+        }
+        // the file shrank while it was read
+        if data.len() as u64 != size {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        let hash = hasher.finalize();
+        let entry = Entry {
+            obj_type: ObjectType::Blob,
+            data,
+            hash,
+        };
+        Ok((hash, entry))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use crate::internal::object::blob::Blob;
+    use crate::internal::object::tree::TreeItemMode;
+    use crate::internal::object::types::ObjectType;
+
+    #[test]
+    fn test_from_path() {
+        let path = env::temp_dir().join("mega_blob_from_path.txt");
+        fs::write(&path, "Hello, world!\n").unwrap();
+
+        let (hash, entry) = Blob::from_path(&path).unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // printf 'Hello, world!\n' | git hash-object --stdin
+        assert_eq!(
+            hash.to_plain_str(),
+            "af5626b4a114abcb82d63db7c8082c3c4756e51b"
+        );
+        assert_eq!(entry.hash, hash);
+        assert_eq!(entry.obj_type, ObjectType::Blob);
+        assert_eq!(entry.data, b"Hello, world!\n");
+        #[cfg(unix)]
+        assert_eq!(
+            TreeItemMode::from_metadata(&metadata),
+            Some(TreeItemMode::Blob)
+        );
+        #[cfg(not(unix))]
+        assert_eq!(TreeItemMode::from_metadata(&metadata), None);
+    }
 }
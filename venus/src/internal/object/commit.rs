@@ -21,7 +21,7 @@ use bstr::ByteSlice;
 
 use crate::errors::GitError;
 use crate::hash::SHA1;
-use crate::internal::object::signature::Signature;
+use crate::internal::object::signature::{Signature, SignatureType};
 use crate::internal::object::ObjectTrait;
 use crate::internal::object::ObjectType;
 
@@ -78,9 +78,9 @@ impl Commit {
             data.extend(&[0x0a]);
         }
 
-        data.extend(self.author.to_data()?);
+        self.author.to_bytes_into(&mut data);
         data.extend(&[0x0a]);
-        data.extend(self.committer.to_data()?);
+        self.committer.to_bytes_into(&mut data);
         data.extend(&[0x0a]);
         data.extend(self.message.as_bytes());
 
@@ -101,6 +101,24 @@ impl Commit {
         commit
     }
 
+    /// A commit authored and committed by `identity`, whose signature type is ignored.
+    pub fn from_identity(
+        identity: Signature,
+        tree_id: SHA1,
+        parent_commit_ids: Vec<SHA1>,
+        message: &str,
+    ) -> Commit {
+        let author = Signature {
+            signature_type: SignatureType::Author,
+            ..identity.clone()
+        };
+        let committer = Signature {
+            signature_type: SignatureType::Committer,
+            ..identity
+        };
+        Commit::new(author, committer, tree_id, parent_commit_ids, message)
+    }
+
     pub fn from_tree_id(tree_id: SHA1, parent_commit_ids: Vec<SHA1>, message: &str) -> Commit {
         let author = Signature::from_data(
             format!(
@@ -122,6 +140,104 @@ impl Commit {
         .unwrap();
         Commit::new(author, committer, tree_id, parent_commit_ids, message)
     }
+
+    /// The trailers of the message, like `Signed-off-by: Name <email>`, in order of appearance.
+    ///
+    /// Trailers are the `Key: value` lines of the last paragraph of a message which has more than
+    /// the subject, a value may continue on indented lines. If any line of that paragraph is not a
+    /// trailer, the message has none.
+    pub fn trailers(&self) -> Vec<(String, String)> {
+        let message = self.message.replace("\r\n", "\n");
+        let mut lines = message.lines().peekable();
+        // skip headers like `gpgsig` which are stored along with the message
+        if lines
+            .peek()
+            .is_some_and(|x| x.starts_with("gpgsig ") || x.starts_with("mergetag "))
+        {
+            lines.by_ref().find(|x| x.is_empty());
+        }
+
+        let mut paragraphs: Vec<Vec<&str>> = vec![];
+        let mut current = vec![];
+        for line in lines {
+            if line.trim().is_empty() {
+                if !current.is_empty() {
+                    paragraphs.push(std::mem::take(&mut current));
+                }
+            } else {
+                current.push(line.trim_end());
+            }
+        }
+        if !current.is_empty() {
+            paragraphs.push(current);
+        }
+        if paragraphs.len() < 2 {
+            return vec![];
+        }
+
+        let mut trailers: Vec<(String, String)> = vec![];
+        for line in paragraphs.last().unwrap() {
+            if line.starts_with([' ', '\t']) {
+                match trailers.last_mut() {
+                    Some((_, value)) => {
+                        value.push(' ');
+                        value.push_str(line.trim());
+                    }
+                    None => return vec![],
+                }
+                continue;
+            }
+            match line.split_once(':') {
+                Some((key, value))
+                    if !key.is_empty()
+                        && key.chars().all(|x| x.is_ascii_alphanumeric() || x == '-') =>
+                {
+                    trailers.push((key.to_owned(), value.trim().to_owned()));
+                }
+                _ => return vec![],
+            }
+        }
+        trailers
+    }
+
+    /// The first line of the message.
+    pub fn subject(&self) -> &str {
+        let text = self.text();
+        let end = text.find('\n').unwrap_or(text.len());
+        text[..end].trim_end()
+    }
+
+    /// The message after the subject and the blank line following it, empty if the subject isn't
+    /// followed by a blank line.
+    pub fn body(&self) -> &str {
+        let text = self.text();
+        let Some((_, rest)) = text.split_once('\n') else {
+            return "";
+        };
+        match rest.split_once('\n') {
+            Some((blank, body)) if blank.trim().is_empty() => {
+                body.trim_start_matches(['\r', '\n']).trim_end()
+            }
+            _ => "",
+        }
+    }
+
+    /// The message without headers like `gpgsig` stored along with it and without leading
+    /// line breaks.
+    fn text(&self) -> &str {
+        let mut message = self.message.as_str();
+        if message.starts_with("gpgsig ") || message.starts_with("mergetag ") {
+            let mut offset = 0;
+            for line in message.split_inclusive('\n') {
+                offset += line.len();
+                if line.trim().is_empty() {
+                    break;
+                }
+            }
+            message = &message[offset..];
+        }
+        message.trim_start_matches(['\r', '\n'])
+    }
 }
 
 impl ObjectTrait for Commit {
@@ -157,11 +273,9 @@ impl ObjectTrait for Commit {
         commit = commit[author_begin..].to_vec();
 
         // Find the author and committer and remove them from the data
-        let author =
-            Signature::from_data(commit[..commit.find_byte(0x0a).unwrap()].to_vec()).unwrap();
+        let author = Signature::from_data(commit[..commit.find_byte(0x0a).unwrap()].to_vec())?;
         commit = commit[commit.find_byte(0x0a).unwrap() + 1..].to_vec();
-        let committer =
-            Signature::from_data(commit[..commit.find_byte(0x0a).unwrap()].to_vec()).unwrap();
+        let committer = Signature::from_data(commit[..commit.find_byte(0x0a).unwrap()].to_vec())?;
 
         // The rest is the message
         let message = unsafe {
@@ -186,3 +300,65 @@ impl ObjectTrait for Commit {
         0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::hash::SHA1;
+    use crate::internal::object::commit::Commit;
+
+    fn trailers(message: &str) -> Vec<(String, String)> {
+        Commit::from_tree_id(SHA1::default(), vec![], message).trailers()
+    }
+
+    #[test]
+    fn test_trailers() {
+        let message = "\nFix the parser\n\nThe body explains\nthe change.\n\n\
+            Signed-off-by: Alice <alice@mega.org>\n\
+            Reviewed-by: Bob\n  <bob@mega.org>\n";
+        assert_eq!(
+            trailers(message),
+            vec![
+                (
+                    "Signed-off-by".to_owned(),
+                    "Alice <alice@mega.org>".to_owned()
+                ),
+                ("Reviewed-by".to_owned(), "Bob <bob@mega.org>".to_owned()),
+            ]
+        );
+
+        let crlf = "\r\nFix the parser\r\n\r\nSigned-off-by: Alice <alice@mega.org>\r\n";
+        assert_eq!(
+            trailers(crlf),
+            vec![(
+                "Signed-off-by".to_owned(),
+                "Alice <alice@mega.org>".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_no_trailers() {
+        // a subject is never a trailer
+        assert!(trailers("\nFix: the parser\n").is_empty());
+        // neither is a paragraph of prose
+        assert!(trailers("\nFix\n\nSee: the issue\nfor details\n").is_empty());
+        assert!(trailers("\nFix\n\nThe body.\n").is_empty());
+    }
+
+    #[test]
+    fn test_subject_and_body() {
+        let commit = |message| Commit::from_tree_id(SHA1::default(), vec![], message);
+
+        let one_line = commit("\nFix the parser\n");
+        assert_eq!(one_line.subject(), "Fix the parser");
+        assert_eq!(one_line.body(), "");
+
+        let with_body = commit("\r\nFix the parser\r\n\r\nThe body explains\r\nthe change.\r\n");
+        assert_eq!(with_body.subject(), "Fix the parser");
+        assert_eq!(with_body.body(), "The body explains\r\nthe change.");
+
+        let with_trailer = commit("\nFix the parser\nSigned-off-by: Alice <alice@mega.org>\n");
+        assert_eq!(with_trailer.subject(), "Fix the parser");
+        assert_eq!(with_trailer.body(), "");
+    }
+}
@@ -9,7 +9,7 @@
 //! - Timezone: The timezone offset of the author's local time from Coordinated Universal Time (UTC),
 //! encoded as a string in the format "+HHMM" or "-HHMM".
 //!
-use std::{fmt::Display, str::FromStr};
+use std::{fmt::Display, io::Write, str::FromStr};
 
 use bstr::ByteSlice;
 
@@ -93,53 +93,35 @@ impl Display for Signature {
 }
 
 impl Signature {
+    /// Parse a signature in the `<type> <name> <<email>> <timestamp> <timezone>` format, failing
+    /// instead of panicking on malformed data like rows written by older versions.
     pub fn from_data(data: Vec<u8>) -> Result<Signature, GitError> {
-        // Make a mutable copy of the input data vector.
-        let mut sign = data;
-
-        // Find the index of the first space byte in the data vector.
-        let name_start = sign.find_byte(0x20).unwrap();
-
-        // Parse the author name from the bytes up to the first space byte.
-        // If the parsing fails, unwrap will panic.
-        let signature_type = SignatureType::from_data(sign[..name_start].to_vec()).unwrap();
-
-        let (name, email) = {
-            let email_start = sign.find_byte(0x3C).unwrap();
-            let email_end = sign.find_byte(0x3E).unwrap();
-
-            unsafe {
-                (
-                    sign[name_start + 1..email_start - 1]
-                        .to_str_unchecked()
-                        .to_string(),
-                    sign[email_start + 1..email_end]
-                        .to_str_unchecked()
-                        .to_string(),
-                )
-            }
-        };
-
-        // Update the data vector to remove the author and email bytes.
-        sign = sign[sign.find_byte(0x3E).unwrap() + 2..].to_vec();
-
-        // Find the index of the second space byte in the updated data vector.
-        let timestamp_split = sign.find_byte(0x20).unwrap();
-
-        // Parse the timestamp integer from the bytes up to the second space byte.
-        // If the parsing fails, unwrap will panic.
-        let timestamp = unsafe {
-            sign[0..timestamp_split]
-                .to_str_unchecked()
-                .parse::<usize>()
-                .unwrap()
-        };
-
-        // Parse the timezone string from the bytes after the second space byte.
-        // If the parsing fails, unwrap will panic.
-        let timezone = unsafe { sign[timestamp_split + 1..].to_str_unchecked().to_string() };
+        let invalid =
+            || GitError::InvalidSignatureType(String::from_utf8_lossy(&data).into_owned());
+
+        // The signature type is everything up to the first space byte.
+        let name_start = data.find_byte(0x20).ok_or_else(invalid)?;
+        let signature_type = SignatureType::from_data(data[..name_start].to_vec())?;
+
+        // The name is followed by the email enclosed in angle brackets.
+        let email_start = data.find_byte(0x3C).ok_or_else(invalid)?;
+        let email_end = data.find_byte(0x3E).ok_or_else(invalid)?;
+        if email_start < name_start || email_end < email_start {
+            return Err(invalid());
+        }
+        let name = String::from_utf8(data[name_start + 1..email_start].trim_end().to_vec())?;
+        let email = String::from_utf8(data[email_start + 1..email_end].to_vec())?;
+
+        // The timestamp and the timezone follow the email, separated by a space byte.
+        let rest = data[email_end + 1..].trim_start();
+        let timestamp_split = rest.find_byte(0x20).ok_or_else(invalid)?;
+        let timestamp = rest[..timestamp_split]
+            .to_str()
+            .ok()
+            .and_then(|x| x.parse::<usize>().ok())
+            .ok_or_else(invalid)?;
+        let timezone = String::from_utf8(rest[timestamp_split + 1..].to_vec())?;
 
-        // Return a Result object indicating success
         Ok(Signature {
             signature_type,
             name,
@@ -152,30 +134,53 @@ impl Signature {
     ///
     #[allow(unused)]
     pub fn to_data(&self) -> Result<Vec<u8>, GitError> {
-        // Create a new empty vector to store the encoded data.
         let mut sign = Vec::new();
+        self.to_bytes_into(&mut sign);
+        Ok(sign)
+    }
 
-        // Append the author name bytes to the data vector, followed by a space byte.
-        sign.extend_from_slice(&self.signature_type.to_bytes());
-        sign.extend_from_slice(&[0x20]);
+    /// Append the encoded signature to `buf` without any intermediate allocation, in the
+    /// `<type> <name> <<email>> <timestamp> <timezone>` format.
+    pub fn to_bytes_into(&self, buf: &mut Vec<u8>) {
+        // Append the signature type bytes to the data vector, followed by a space byte.
+        let signature_type: &[u8] = match self.signature_type {
+            SignatureType::Author => b"author",
+            SignatureType::Committer => b"committer",
+            SignatureType::Tagger => b"tagger",
+        };
+        buf.extend_from_slice(signature_type);
+        buf.push(0x20);
 
         // Append the name bytes to the data vector, followed by a space byte.
-        sign.extend_from_slice(self.name.as_bytes());
-        sign.extend_from_slice(&[0x20]);
+        buf.extend_from_slice(self.name.as_bytes());
+        buf.push(0x20);
 
         // Append the email address bytes to the data vector, enclosed in angle brackets.
-        sign.extend_from_slice(format!("<{}>", self.email).as_bytes());
-        sign.extend_from_slice(&[0x20]);
+        buf.push(b'<');
+        buf.extend_from_slice(self.email.as_bytes());
+        buf.push(b'>');
+        buf.push(0x20);
 
         // Append the timestamp integer bytes to the data vector, followed by a space byte.
-        sign.extend_from_slice(self.timestamp.to_string().as_bytes());
-        sign.extend_from_slice(&[0x20]);
+        write!(buf, "{}", self.timestamp).unwrap();
+        buf.push(0x20);
 
         // Append the timezone string bytes to the data vector.
-        sign.extend_from_slice(self.timezone.as_bytes());
+        buf.extend_from_slice(self.timezone.as_bytes());
+    }
 
-        // Return the data vector as a Result object indicating success.
-        Ok(sign)
+    /// Whether both signatures are of the same person at the same time, no matter whether one is
+    /// e.g. the author and the other the committer.
+    pub fn identity_eq(&self, other: &Signature) -> bool {
+        self.name == other.name
+            && self.email == other.email
+            && self.timestamp == other.timestamp
+            && self.timezone == other.timezone
+    }
+
+    /// The name and email of the person, for grouping signatures by who made them.
+    pub fn identity_key(&self) -> (String, String) {
+        (self.name.clone(), self.email.clone())
     }
 }
 
@@ -258,4 +263,45 @@ mod tests {
                 .into_bytes()
         );
     }
+
+    #[test]
+    fn test_signature_identity_eq() {
+        let author =
+            Signature::from_data(b"author Quanyi Ma <eli@patch.sh> 1678101573 +0800".to_vec())
+                .unwrap();
+        let committer =
+            Signature::from_data(b"committer Quanyi Ma <eli@patch.sh> 1678101573 +0800".to_vec())
+                .unwrap();
+        let later =
+            Signature::from_data(b"committer Quanyi Ma <eli@patch.sh> 1678101574 +0800".to_vec())
+                .unwrap();
+
+        assert_ne!(author, committer);
+        assert!(author.identity_eq(&committer));
+        assert!(!committer.identity_eq(&later));
+        assert_eq!(author.identity_key(), later.identity_key());
+        assert_eq!(
+            author.identity_key(),
+            ("Quanyi Ma".to_owned(), "eli@patch.sh".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_signature_from_malformed_data() {
+        for data in [
+            "author",
+            "author Quanyi Ma",
+            "author Quanyi Ma <eli@patch.sh",
+            "author Quanyi Ma eli@patch.sh> 1678101573 +0800",
+            "author Quanyi Ma <eli@patch.sh>",
+            "author Quanyi Ma <eli@patch.sh> now +0800",
+            "writer Quanyi Ma <eli@patch.sh> 1678101573 +0800",
+            "Quanyi Ma <eli@patch.sh>\nDate: 1678101573\n",
+        ] {
+            assert!(Signature::from_data(data.into()).is_err(), "{}", data);
+        }
+
+        let unnamed = Signature::from_data(b"author <eli@patch.sh> 1678101573 +0800".to_vec());
+        assert_eq!(unnamed.unwrap().name, "");
+    }
 }
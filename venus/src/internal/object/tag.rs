@@ -69,6 +69,27 @@ impl Display for Tag {
 }
 
 impl Tag {
+    /// Create the annotated tag `tag_name` of the object `object_hash`. Like in parsed tags, the
+    /// message keeps the blank line separating it from the tagger.
+    pub fn new(
+        object_hash: SHA1,
+        object_type: ObjectType,
+        tag_name: &str,
+        tagger: Signature,
+        message: &str,
+    ) -> Tag {
+        let mut tag = Tag {
+            id: SHA1::default(),
+            object_hash,
+            object_type,
+            tag_name: tag_name.to_owned(),
+            tagger,
+            message: format!("\n{}", message),
+        };
+        tag.id = SHA1::from_type_and_data(ObjectType::Tag, &tag.to_data().unwrap());
+        tag
+    }
+
     // pub fn new_from_meta(meta: Meta) -> Result<Tag, GitError> {
     //     Ok(Tag::new_from_data(meta.data))
     // }
@@ -147,7 +168,7 @@ impl ObjectTrait for Tag {
         let tagger_begin = data.find("tagger").unwrap();
         let tagger_end = data.find_byte(0x0a).unwrap();
         let tagger_data = data[tagger_begin..tagger_end].to_vec();
-        let tagger = Signature::from_data(tagger_data).unwrap();
+        let tagger = Signature::from_data(tagger_data)?;
         data = data[data.find_byte(0x0a).unwrap() + 1..].to_vec();
 
         let message = unsafe {
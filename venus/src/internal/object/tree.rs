@@ -15,6 +15,8 @@
 //! operations like merging and rebasing more quickly and accurately.
 //!
 use std::fmt::Display;
+use std::fs::Metadata;
+use std::ops::AddAssign;
 
 use bstr::ByteSlice;
 use colored::Colorize;
@@ -36,6 +38,11 @@ pub enum TreeItemMode {
     Tree,
     Commit,
     Link,
+    /// A regular file writeable by its group, `100664`. Kept apart from [`TreeItemMode::Blob`] so
+    /// the tree is written back as it was read and keeps its hash.
+    BlobGroupWriteable,
+    /// A regular file not readable by others, `100640`.
+    BlobGroupReadable,
 }
 
 impl Display for TreeItemMode {
@@ -46,6 +53,8 @@ impl Display for TreeItemMode {
             TreeItemMode::Tree => "tree",
             TreeItemMode::Commit => "commit",
             TreeItemMode::Link => "link",
+            TreeItemMode::BlobGroupWriteable => "blob group-writeable",
+            TreeItemMode::BlobGroupReadable => "blob group-readable",
         };
 
         write!(f, "{}", String::from(_print).blue())
@@ -58,6 +67,7 @@ impl TreeItemMode {
     /// |0100000000000000| (040000)| Directory|
     /// |1000000110100100| (100644)| Regular non-executable file|
     /// |1000000110110100| (100664)| Regular non-executable group-writeable file|
+    /// |1000000110100000| (100640)| Regular non-executable file not readable by others|
     /// |1000000111101101| (100755)| Regular executable file|
     /// |1010000000000000| (120000)| Symbolic link|
     /// |1110000000000000| (160000)| Gitlink|
@@ -88,8 +98,8 @@ impl TreeItemMode {
             b"100755" => TreeItemMode::BlobExecutable,
             b"120000" => TreeItemMode::Link,
             b"160000" => TreeItemMode::Commit,
-            b"100664" => TreeItemMode::Blob,
-            b"100640" => TreeItemMode::Blob,
+            b"100664" => TreeItemMode::BlobGroupWriteable,
+            b"100640" => TreeItemMode::BlobGroupReadable,
             _ => {
                 return Err(GitError::InvalidTreeItem(
                     String::from_utf8(mode.to_vec()).unwrap(),
@@ -110,8 +120,39 @@ impl TreeItemMode {
             TreeItemMode::Link => b"120000",
             TreeItemMode::Tree => b"40000",
             TreeItemMode::Commit => b"160000",
+            TreeItemMode::BlobGroupWriteable => b"100664",
+            TreeItemMode::BlobGroupReadable => b"100640",
         }
     }
+
+    /// Whether the mode is of a regular file, whatever its permissions.
+    pub fn is_blob(self) -> bool {
+        matches!(
+            self,
+            TreeItemMode::Blob
+                | TreeItemMode::BlobExecutable
+                | TreeItemMode::BlobGroupWriteable
+                | TreeItemMode::BlobGroupReadable
+        )
+    }
+
+    /// The mode of a regular file with the permissions of `metadata`, executable if any execute
+    /// bit is set. `None` where the platform has no executable bit, e.g. on Windows.
+    #[cfg(unix)]
+    pub fn from_metadata(metadata: &Metadata) -> Option<TreeItemMode> {
+        use std::os::unix::fs::PermissionsExt;
+
+        if metadata.permissions().mode() & 0o111 != 0 {
+            Some(TreeItemMode::BlobExecutable)
+        } else {
+            Some(TreeItemMode::Blob)
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_metadata(_metadata: &Metadata) -> Option<TreeItemMode> {
+        None
+    }
 }
 
 /// A tree object contains a list of entries, one for each file or directory in the tree. Each entry
@@ -187,6 +228,13 @@ impl TreeItem {
         let mut parts = rest.splitn(2, |b| *b == b'\0');
         let name = parts.next().unwrap();
         let id = parts.next().unwrap();
+        // an empty name can't be joined into a path
+        if name.is_empty() {
+            return Err(GitError::InvalidTreeItem(format!(
+                "empty name for {}",
+                SHA1::from_bytes(id).to_plain_str()
+            )));
+        }
 
         Ok(TreeItem {
             mode: TreeItemMode::tree_item_type_from_bytes(mode)?,
@@ -212,14 +260,83 @@ impl TreeItem {
     #[allow(unused)]
     pub fn to_data(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
+        self.to_bytes_into(&mut bytes);
+        bytes
+    }
 
-        bytes.extend_from_slice(self.mode.to_bytes());
-        bytes.push(b' ');
-        bytes.extend_from_slice(self.name.as_bytes());
-        bytes.push(b'\0');
-        bytes.extend_from_slice(&self.id.to_data());
+    /// Append the serialized TreeItem to `buf`, so callers serializing many items can reuse one
+    /// buffer instead of allocating a new vector per item.
+    pub fn to_bytes_into(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.mode.to_bytes());
+        buf.push(b' ');
+        buf.extend_from_slice(self.name.as_bytes());
+        buf.push(b'\0');
+        buf.extend_from_slice(&self.id.0);
+    }
 
-        bytes
+    /// Guess the content type of the entry for labelling it in listings. Files are judged by the
+    /// extension of `name` only, unknown extensions fall back to `application/octet-stream`.
+    pub fn guess_content_type(&self) -> &'static str {
+        match self.mode {
+            TreeItemMode::Tree => return "inode/directory",
+            TreeItemMode::Link => return "inode/symlink",
+            TreeItemMode::Commit => return "application/x-git-submodule",
+            TreeItemMode::Blob
+            | TreeItemMode::BlobExecutable
+            | TreeItemMode::BlobGroupWriteable
+            | TreeItemMode::BlobGroupReadable => {}
+        }
+        let extension = match self.name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => ext.to_ascii_lowercase(),
+            _ => return "application/octet-stream",
+        };
+        match extension.as_str() {
+            "rs" => "text/rust",
+            "c" | "h" => "text/x-c",
+            "cc" | "cpp" | "hpp" => "text/x-c++",
+            "go" => "text/x-go",
+            "java" => "text/x-java",
+            "py" => "text/x-python",
+            "js" | "mjs" => "text/javascript",
+            "ts" | "tsx" => "text/typescript",
+            "sh" => "text/x-shellscript",
+            "md" => "text/markdown",
+            "txt" => "text/plain",
+            "html" | "htm" => "text/html",
+            "css" => "text/css",
+            "toml" => "application/toml",
+            "json" => "application/json",
+            "yaml" | "yml" => "application/yaml",
+            "xml" => "application/xml",
+            "sql" => "application/sql",
+            "png" => "image/png",
+            "jpg" | "jpeg" => "image/jpeg",
+            "gif" => "image/gif",
+            "svg" => "image/svg+xml",
+            "pdf" => "application/pdf",
+            "zip" => "application/zip",
+            "gz" => "application/gzip",
+            _ => "application/octet-stream",
+        }
+    }
+}
+
+/// The number of entries of a tree by kind, see [`Tree::counts`].
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct TreeCounts {
+    /// Regular files, whatever their permissions.
+    pub files: usize,
+    pub dirs: usize,
+    pub symlinks: usize,
+    pub submodules: usize,
+}
+
+impl AddAssign for TreeCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.files += other.files;
+        self.dirs += other.dirs;
+        self.symlinks += other.symlinks;
+        self.submodules += other.submodules;
     }
 }
 
@@ -251,41 +368,48 @@ impl Tree {
                     .unwrap(),
             ));
         }
+        let mut tree = Tree {
+            id: SHA1::default(),
+            tree_items,
+        };
         let mut data = Vec::new();
-        for item in &tree_items {
-            data.extend_from_slice(item.to_data().as_slice());
-        }
+        tree.to_bytes_into(&mut data);
+        tree.id = SHA1::from_type_and_data(ObjectType::Tree, &data);
 
-        Ok(Tree {
-            id: SHA1::from_type_and_data(ObjectType::Tree, &data),
-            tree_items,
-        })
+        Ok(tree)
     }
 
     pub fn to_data(&self) -> Result<Vec<u8>, GitError> {
         let mut data: Vec<u8> = Vec::new();
+        self.to_bytes_into(&mut data);
+        Ok(data)
+    }
 
+    /// Append the serialized tree items to `buf`. Unlike [`Tree::to_data`] this doesn't allocate
+    /// when `buf` already has enough capacity, which matters when encoding millions of trees.
+    pub fn to_bytes_into(&self, buf: &mut Vec<u8>) {
         for item in &self.tree_items {
-            data.extend_from_slice(item.to_data().as_slice());
-            //data.push(b'\0');
+            item.to_bytes_into(buf);
         }
-
-        Ok(data)
     }
-}
 
-impl ObjectTrait for Tree {
-    fn from_bytes(data: Vec<u8>, hash: SHA1) -> Result<Self, GitError>
-    where
-        Self: Sized,
-    {
+    /// Parse the tree `hash` from `data` like [`ObjectTrait::from_bytes`], failing with
+    /// [`GitError::TreeTooLarge`] as soon as it has more than `limit` entries.
+    pub fn from_bytes_with_limit(
+        data: Vec<u8>,
+        hash: SHA1,
+        limit: Option<usize>,
+    ) -> Result<Self, GitError> {
         let mut tree_items = Vec::new();
         let mut i = 0;
         while i < data.len() {
+            if let Some(limit) = limit.filter(|&x| tree_items.len() >= x) {
+                return Err(GitError::TreeTooLarge(hash.to_plain_str(), limit));
+            }
             let index = data[i..].find_byte(0x00).unwrap();
             let next = i + index + 21;
 
-            tree_items.push(TreeItem::from_bytes(&data[i..next]).unwrap());
+            tree_items.push(TreeItem::from_bytes(&data[i..next])?);
             i = next
         }
 
@@ -295,6 +419,29 @@ impl ObjectTrait for Tree {
         })
     }
 
+    /// Count the entries of this tree by their mode, without looking into subtrees.
+    pub fn counts(&self) -> TreeCounts {
+        let mut counts = TreeCounts::default();
+        for item in &self.tree_items {
+            match item.mode {
+                TreeItemMode::Tree => counts.dirs += 1,
+                TreeItemMode::Link => counts.symlinks += 1,
+                TreeItemMode::Commit => counts.submodules += 1,
+                _ => counts.files += 1,
+            }
+        }
+        counts
+    }
+}
+
+impl ObjectTrait for Tree {
+    fn from_bytes(data: Vec<u8>, hash: SHA1) -> Result<Self, GitError>
+    where
+        Self: Sized,
+    {
+        Tree::from_bytes_with_limit(data, hash, None)
+    }
+
     fn get_type(&self) -> ObjectType {
         ObjectType::Tree
     }
@@ -309,8 +456,10 @@ mod tests {
 
     use std::str::FromStr;
 
+    use crate::errors::GitError;
     use crate::hash::SHA1;
-    use crate::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+    use crate::internal::object::tree::{Tree, TreeCounts, TreeItem, TreeItemMode};
+    use crate::internal::object::ObjectTrait;
 
     #[test]
     fn test_tree_item_new() {
@@ -375,4 +524,116 @@ mod tests {
             tree.id.to_plain_str()
         );
     }
+
+    #[test]
+    fn test_counts() {
+        let id = SHA1::from_str("17288789afffb273c8c394bc65e87d899b92897b").unwrap();
+        let item = |mode, name: &str| TreeItem::new(mode, id, name.to_owned());
+        let tree = Tree::from_tree_items(vec![
+            item(TreeItemMode::Blob, "README.md"),
+            item(TreeItemMode::BlobExecutable, "build.sh"),
+            item(TreeItemMode::BlobGroupWriteable, "notes.txt"),
+            item(TreeItemMode::Tree, "src"),
+            item(TreeItemMode::Tree, "docs"),
+            item(TreeItemMode::Link, "latest"),
+            item(TreeItemMode::Commit, "vendor"),
+        ])
+        .unwrap();
+        assert_eq!(
+            tree.counts(),
+            TreeCounts {
+                files: 3,
+                dirs: 2,
+                symlinks: 1,
+                submodules: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_reject_too_many_entries() {
+        let id = SHA1::from_str("17288789afffb273c8c394bc65e87d899b92897b").unwrap();
+        let items = (0..3)
+            .map(|i| TreeItem::new(TreeItemMode::Blob, id, format!("file{}", i)))
+            .collect();
+        let tree = Tree::from_tree_items(items).unwrap();
+        let data = tree.to_data().unwrap();
+
+        let parsed = Tree::from_bytes_with_limit(data.clone(), tree.id, Some(3)).unwrap();
+        assert_eq!(parsed, tree);
+        match Tree::from_bytes_with_limit(data, tree.id, Some(2)) {
+            Err(GitError::TreeTooLarge(hash, limit)) => {
+                assert_eq!(hash, tree.id.to_plain_str());
+                assert_eq!(limit, 2);
+            }
+            other => panic!("tree wasn't rejected: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_group_permission_modes_round_trip() {
+        for mode in [&b"100664"[..], b"100640"] {
+            let mut bytes = mode.to_vec();
+            bytes.extend_from_slice(b" notes\0");
+            bytes.extend_from_slice(&[7; 20]);
+
+            let item = TreeItem::from_bytes(&bytes).unwrap();
+            assert!(item.mode.is_blob());
+            assert_ne!(item.mode, TreeItemMode::Blob);
+            assert_eq!(item.to_data(), bytes);
+        }
+    }
+
+    #[test]
+    fn test_reject_empty_name() {
+        let mut bytes = b"100644 \0".to_vec();
+        bytes.extend_from_slice(&[7; 20]);
+
+        assert!(matches!(
+            TreeItem::from_bytes(&bytes),
+            Err(GitError::InvalidTreeItem(_))
+        ));
+        assert!(Tree::from_bytes(bytes, SHA1::default()).is_err());
+    }
+
+    #[test]
+    fn test_guess_content_type() {
+        let item = |mode, name: &str| TreeItem::new(mode, SHA1::default(), name.to_string());
+        assert_eq!(
+            item(TreeItemMode::Blob, "main.rs").guess_content_type(),
+            "text/rust"
+        );
+        assert_eq!(
+            item(TreeItemMode::Blob, "logo.PNG").guess_content_type(),
+            "image/png"
+        );
+        assert_eq!(
+            item(TreeItemMode::BlobExecutable, "build.sh").guess_content_type(),
+            "text/x-shellscript"
+        );
+        assert_eq!(
+            item(TreeItemMode::Blob, "README.md").guess_content_type(),
+            "text/markdown"
+        );
+        assert_eq!(
+            item(TreeItemMode::Tree, "src.rs").guess_content_type(),
+            "inode/directory"
+        );
+        assert_eq!(
+            item(TreeItemMode::Link, "current.txt").guess_content_type(),
+            "inode/symlink"
+        );
+        assert_eq!(
+            item(TreeItemMode::Commit, "libgit2").guess_content_type(),
+            "application/x-git-submodule"
+        );
+    }
+
+    #[test]
+    fn test_guess_content_type_unknown() {
+        for name in ["data.unknownext", "Makefile", ".gitignore", "archive."] {
+            let item = TreeItem::new(TreeItemMode::Blob, SHA1::default(), name.to_string());
+            assert_eq!(item.guess_content_type(), "application/octet-stream");
+        }
+    }
 }
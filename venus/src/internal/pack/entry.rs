@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 use serde::{Deserialize, Serialize};
 
 use crate::hash::SHA1;
@@ -18,7 +20,42 @@ pub struct Entry {
     pub hash: SHA1,
 }
 
+/// A short description of an [`Entry`], for dumping what is inside a pack.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EntrySummary {
+    pub obj_type: ObjectType,
+    pub hash: SHA1,
+    /// The size of the uncompressed object data in bytes
+    pub size: usize,
+}
+
+impl Display for EntrySummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} bytes",
+            self.hash.to_plain_str(),
+            self.obj_type,
+            self.size
+        )
+    }
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.summary())
+    }
+}
+
 impl Entry {
+    pub fn summary(&self) -> EntrySummary {
+        EntrySummary {
+            obj_type: self.obj_type,
+            hash: self.hash,
+            size: self.data.len(),
+        }
+    }
+
     pub fn process_entry(&self) -> GitObject {
         match self.obj_type {
             ObjectType::Commit => {
@@ -77,3 +114,35 @@ impl From<Tag> for Entry {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::hash::SHA1;
+    use crate::internal::object::commit::Commit;
+    use crate::internal::object::signature::Signature;
+    use crate::internal::object::types::ObjectType;
+    use crate::internal::pack::entry::Entry;
+
+    #[test]
+    fn test_entry_summary() {
+        let author =
+            Signature::from_data(b"author Quanyi Ma <eli@patch.sh> 1678101573 +0800".to_vec())
+                .unwrap();
+        let committer =
+            Signature::from_data(b"committer Quanyi Ma <eli@patch.sh> 1678101573 +0800".to_vec())
+                .unwrap();
+        let commit = Commit::new(author, committer, SHA1::default(), vec![], "init");
+        let entry: Entry = commit.clone().into();
+
+        let summary = entry.summary();
+        assert_eq!(summary.obj_type, ObjectType::Commit);
+        assert_eq!(summary.hash, commit.id);
+        // tree line (46) + author line (49) + committer line (52) + message (4)
+        assert_eq!(summary.size, 151);
+        assert_eq!(summary.size, commit.to_data().unwrap().len());
+        assert_eq!(
+            entry.to_string(),
+            format!("{} commit 151 bytes", commit.id.to_plain_str())
+        );
+    }
+}
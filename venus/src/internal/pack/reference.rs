@@ -43,6 +43,8 @@ pub struct RefCommand {
     pub command_type: CommandType,
     pub ref_type: RefType,
     pub default_branch: bool,
+    /// Allow an update which isn't a fast-forward, discarding commits of the old value.
+    pub force: bool,
 }
 
 pub const SP: char = ' ';
@@ -73,6 +75,7 @@ impl RefCommand {
                 RefType::Branch
             },
             default_branch: false,
+            force: false,
         }
     }
 
@@ -10,7 +10,7 @@ impl From<Blob> for mega_blob::Model {
         mega_blob::Model {
             id: generate_id(),
             blob_id: value.id.to_plain_str(),
-            size: 0,
+            size: blob_size(&value.data),
             commit_id: String::new(),
             name: String::new(),
             created_at: chrono::Utc::now().naive_utc(),
@@ -24,7 +24,7 @@ impl From<Blob> for git_blob::Model {
             id: generate_id(),
             repo_id: 0,
             blob_id: value.id.to_plain_str(),
-            size: 0,
+            size: blob_size(&value.data),
             commit_id: String::new(),
             name: String::new(),
             created_at: chrono::Utc::now().naive_utc(),
@@ -32,6 +32,11 @@ impl From<Blob> for git_blob::Model {
     }
 }
 
+/// The size of a blob as stored in the `size` columns, which are `BIGINT`.
+fn blob_size(data: &[u8]) -> i64 {
+    i64::try_from(data.len()).expect("blob size exceeds i64::MAX")
+}
+
 impl From<Blob> for raw_blob::Model {
     fn from(value: Blob) -> Self {
         raw_blob::Model {
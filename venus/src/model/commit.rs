@@ -57,6 +57,7 @@ impl From<Commit> for mega_commit::Model {
             committer: Some(
                 String::from_utf8_lossy(&value.committer.to_data().unwrap()).to_string(),
             ),
+            committer_time: value.committer.timestamp as i64,
             content: Some(value.message.clone()),
             created_at: chrono::Utc::now().naive_utc(),
         }
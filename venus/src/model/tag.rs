@@ -3,7 +3,7 @@ use std::str::FromStr;
 use callisto::{git_tag, mega_tag};
 use common::utils::generate_id;
 
-use crate::{hash::SHA1, internal::object::{signature::Signature, tag::Tag, types::ObjectType}};
+use crate::{errors::GitError, hash::SHA1, internal::object::{signature::Signature, tag::Tag, types::ObjectType}};
 
 impl From<Tag> for mega_tag::Model {
     fn from(value: Tag) -> Self {
@@ -13,7 +13,7 @@ impl From<Tag> for mega_tag::Model {
             object_id: value.object_hash.to_plain_str(),
             object_type: value.object_type.to_string(),
             tag_name: value.tag_name,
-            tagger: value.tagger.to_string(),
+            tagger: String::from_utf8_lossy(&value.tagger.to_data().unwrap()).to_string(),
             message: value.message,
             created_at: chrono::Utc::now().naive_utc(),
         }
@@ -29,13 +29,29 @@ impl From<Tag> for git_tag::Model {
             object_id: value.object_hash.to_plain_str(),
             object_type: value.object_type.to_string(),
             tag_name: value.tag_name,
-            tagger: value.tagger.to_string(),
+            tagger: String::from_utf8_lossy(&value.tagger.to_data().unwrap()).to_string(),
             message: value.message,
             created_at: chrono::Utc::now().naive_utc(),
         }
     }
 }
 
+/// Parse the stored tagger of a tag. Taggers are stored in the signature format, rows written
+/// before hold the `Display` output of the signature, `<name> <<email>>\nDate: <timestamp>\n`,
+/// which has no timezone and is read as UTC.
+pub fn parse_tagger(tagger: &str) -> Result<Signature, GitError> {
+    if tagger.starts_with("tagger ") {
+        return Signature::from_data(tagger.as_bytes().to_vec());
+    }
+    match tagger.split_once("\nDate: ") {
+        Some((identity, timestamp)) => {
+            let data = format!("tagger {} {} +0000", identity, timestamp.trim_end());
+            Signature::from_data(data.into_bytes())
+        }
+        None => Err(GitError::InvalidSignatureType(tagger.to_owned())),
+    }
+}
+
 impl From<mega_tag::Model> for Tag {
     fn from(value: mega_tag::Model) -> Self {
         Self {
@@ -43,7 +59,7 @@ impl From<mega_tag::Model> for Tag {
             object_hash: SHA1::from_str(&value.object_id).unwrap(),
             object_type: ObjectType::from_string(&value.object_type).unwrap(),
             tag_name: value.tag_name,
-            tagger: Signature::from_data(value.tagger.into_bytes()).unwrap(),
+            tagger: parse_tagger(&value.tagger).unwrap(),
             message: value.message,
         }
     }
@@ -57,8 +73,33 @@ impl From<git_tag::Model> for Tag {
             object_hash: SHA1::from_str(&value.object_id).unwrap(),
             object_type: ObjectType::from_string(&value.object_type).unwrap(),
             tag_name: value.tag_name,
-            tagger: Signature::from_data(value.tagger.into_bytes()).unwrap(),
+            tagger: parse_tagger(&value.tagger).unwrap(),
             message: value.message,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::internal::object::signature::{Signature, SignatureType};
+
+    use super::parse_tagger;
+
+    #[test]
+    fn test_parse_tagger() {
+        let tagger =
+            Signature::from_data(b"tagger dev <dev@mega.org> 1700000000 +0800".to_vec()).unwrap();
+        let stored = String::from_utf8(tagger.to_data().unwrap()).unwrap();
+        assert_eq!(parse_tagger(&stored).unwrap(), tagger);
+
+        // written before taggers were stored in the signature format
+        let legacy = parse_tagger(&tagger.to_string()).unwrap();
+        assert_eq!(legacy.signature_type, SignatureType::Tagger);
+        assert_eq!(legacy.name, "dev");
+        assert_eq!(legacy.email, "dev@mega.org");
+        assert_eq!(legacy.timestamp, 1700000000);
+        assert_eq!(legacy.timezone, "+0000");
+
+        assert!(parse_tagger("dev").is_err());
+    }
+}
@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+
+use common::utils::MEGA_BRANCH_NAME;
+
+use crate::monorepo::reflog::ReflogEntry;
+
+/// Published whenever a ref is created or moved, e.g. by a push or a merged MR, so consumers
+/// like webhooks or CI triggers can react to it. `old` is `ZERO_ID` for a new ref.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RefUpdateEvent {
+    pub ref_name: String,
+    pub old: String,
+    pub new: String,
+    /// The repository the ref belongs to, for the monorepo this is the path of the ref.
+    pub repo: String,
+}
+
+impl From<&ReflogEntry> for RefUpdateEvent {
+    /// Monorepo refs are stored by path, each of them is seen as the main branch of the
+    /// repository at that path by git clients.
+    fn from(value: &ReflogEntry) -> Self {
+        Self {
+            ref_name: MEGA_BRANCH_NAME.to_owned(),
+            old: value.old_hash.clone(),
+            new: value.new_hash.clone(),
+            repo: value.ref_name.clone(),
+        }
+    }
+}
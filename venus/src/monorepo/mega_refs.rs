@@ -13,6 +13,19 @@ pub struct MegaRefs {
     pub ref_tree_hash: String,
 }
 
+/// A ref with the commit it points at, for listing refs.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RefInfo {
+    pub name: String,
+    pub commit_hash: String,
+    /// The first line of the commit message.
+    pub subject: String,
+    pub author: String,
+    /// When the commit was made, in seconds since the epoch.
+    pub timestamp: usize,
+    pub is_default: bool,
+}
+
 impl From<mega_refs::Model> for MegaRefs {
     fn from(value: mega_refs::Model) -> Self {
         Self {
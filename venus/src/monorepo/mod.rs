@@ -1,2 +1,6 @@
 pub mod mr;
-pub mod mega_refs;
\ No newline at end of file
+pub mod mega_refs;
+pub mod event;
+pub mod reflog;
+pub mod repo_size;
+pub mod storage_tier;
@@ -1,9 +1,16 @@
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 
-use callisto::{db_enums::MergeStatus, mega_mr};
+use callisto::db_enums::{BuildState, MergeStatus};
+use callisto::{mega_mr, mega_mr_build};
 use common::utils::generate_id;
 
+use crate::errors::GitError;
+use crate::hash::SHA1;
+
+/// The prefix of the internal refs of every MR, which aren't listed as refs of the monorepo.
+pub const MR_REFS_PREFIX: &str = "refs/mr/";
+
 #[derive(Clone)]
 pub struct MergeRequest {
     pub id: i64,
@@ -13,6 +20,14 @@ pub struct MergeRequest {
     pub path: String,
     pub from_hash: String,
     pub to_hash: String,
+    pub idempotency_key: String,
+    /// The build started after the MR merged, if any.
+    pub build_id: Option<String>,
+    /// The client of the push which created or last updated the MR, as it sent in the `agent`
+    /// capability.
+    pub agent: Option<String>,
+    /// When the status last changed.
+    pub updated_at: NaiveDateTime,
 }
 
 impl Default for MergeRequest {
@@ -25,18 +40,64 @@ impl Default for MergeRequest {
             path: String::new(),
             from_hash: String::new(),
             to_hash: String::new(),
+            idempotency_key: String::new(),
+            build_id: None,
+            agent: None,
+            updated_at: chrono::Utc::now().naive_utc(),
         }
     }
 }
 
 impl MergeRequest {
-    pub fn close(&mut self) {
-        self.status = MergeStatus::Closed;
+    /// The key of a push of `path` from `from_hash` to `to_hash`. A client retrying the same
+    /// push sends the same key, which is how the retry is told apart from a new push.
+    pub fn push_key(path: &str, from_hash: &str, to_hash: &str) -> String {
+        let push = format!("{}\n{}\n{}", path, from_hash, to_hash);
+        SHA1::new(&push.into_bytes()).to_plain_str()
+    }
+
+    /// The prefix of the internal refs of the MR, like `refs/mr/<id>/`.
+    pub fn refs_prefix(&self) -> String {
+        format!("{}{}/", MR_REFS_PREFIX, self.id)
+    }
+
+    /// The internal ref holding the `to_hash` of the MR while it is under review.
+    pub fn working_ref(&self) -> String {
+        format!("{}head", self.refs_prefix())
+    }
+
+    /// Close an open MR.
+    pub fn close(&mut self) -> Result<(), GitError> {
+        self.transition(MergeStatus::Open, MergeStatus::Closed, "close")
     }
 
-    pub fn merge(&mut self, _: Option<String>) {
-        self.status = MergeStatus::Merged;
-        self.merge_date = Some(chrono::Utc::now().naive_utc())
+    /// Merge an open MR, a closed one has to be reopened first.
+    pub fn merge(&mut self, _: Option<String>) -> Result<(), GitError> {
+        self.transition(MergeStatus::Open, MergeStatus::Merged, "merge")?;
+        self.merge_date = Some(self.updated_at);
+        Ok(())
+    }
+
+    /// Reopen a closed MR, merged ones stay merged.
+    pub fn reopen(&mut self) -> Result<(), GitError> {
+        self.transition(MergeStatus::Closed, MergeStatus::Open, "reopen")
+    }
+
+    fn transition(
+        &mut self,
+        from: MergeStatus,
+        to: MergeStatus,
+        action: &str,
+    ) -> Result<(), GitError> {
+        if self.status != from {
+            return Err(GitError::InvalidMrTransition(
+                self.status.to_string(),
+                action.to_owned(),
+            ));
+        }
+        self.status = to;
+        self.updated_at = chrono::Utc::now().naive_utc();
+        Ok(())
     }
 }
 
@@ -50,8 +111,11 @@ impl From<MergeRequest> for mega_mr::Model {
             path: value.path,
             from_hash: value.from_hash,
             to_hash: value.to_hash,
+            idempotency_key: value.idempotency_key,
+            build_id: value.build_id,
+            agent: value.agent,
             created_at: chrono::Utc::now().naive_utc(),
-            updated_at: chrono::Utc::now().naive_utc(),
+            updated_at: value.updated_at,
         }
     }
 }
@@ -66,6 +130,59 @@ impl From<mega_mr::Model> for MergeRequest {
             path: value.path,
             from_hash: value.from_hash,
             to_hash: value.to_hash,
+            idempotency_key: value.idempotency_key,
+            build_id: value.build_id,
+            agent: value.agent,
+            updated_at: value.updated_at,
+        }
+    }
+}
+
+/// The status of the build started after an MR merged, attached to the MR as a check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BuildStatus {
+    pub mr_id: i64,
+    pub build_id: String,
+    pub state: BuildState,
+    /// Where the log of the build can be read, once the build tells.
+    pub log_url: Option<String>,
+    pub updated_at: NaiveDateTime,
+}
+
+impl BuildStatus {
+    /// A build of the MR `mr_id` which didn't finish yet.
+    pub fn pending(mr_id: i64, build_id: String) -> Self {
+        Self {
+            mr_id,
+            build_id,
+            state: BuildState::Pending,
+            log_url: None,
+            updated_at: chrono::Utc::now().naive_utc(),
+        }
+    }
+}
+
+impl From<BuildStatus> for mega_mr_build::Model {
+    fn from(value: BuildStatus) -> Self {
+        Self {
+            mr_id: value.mr_id,
+            build_id: value.build_id,
+            state: value.state,
+            log_url: value.log_url,
+            created_at: chrono::Utc::now().naive_utc(),
+            updated_at: value.updated_at,
+        }
+    }
+}
+
+impl From<mega_mr_build::Model> for BuildStatus {
+    fn from(value: mega_mr_build::Model) -> Self {
+        Self {
+            mr_id: value.mr_id,
+            build_id: value.build_id,
+            state: value.state,
+            log_url: value.log_url,
+            updated_at: value.updated_at,
         }
     }
 }
@@ -82,3 +199,44 @@ pub struct MergeResult {
     pub result: bool,
     pub err_message: String,
 }
+
+#[cfg(test)]
+mod test {
+    use callisto::db_enums::MergeStatus;
+
+    use super::MergeRequest;
+
+    #[test]
+    fn test_legal_transitions() {
+        let mut mr = MergeRequest::default();
+        mr.close().unwrap();
+        assert_eq!(mr.status, MergeStatus::Closed);
+        let closed_at = mr.updated_at;
+
+        mr.reopen().unwrap();
+        assert_eq!(mr.status, MergeStatus::Open);
+        assert!(mr.updated_at >= closed_at);
+
+        mr.merge(None).unwrap();
+        assert_eq!(mr.status, MergeStatus::Merged);
+        assert_eq!(mr.merge_date, Some(mr.updated_at));
+    }
+
+    #[test]
+    fn test_illegal_transitions() {
+        let mut mr = MergeRequest::default();
+        assert!(mr.reopen().is_err());
+
+        mr.close().unwrap();
+        let err = mr.merge(None).unwrap_err();
+        assert_eq!(err.to_string(), "Can't merge the closed merge request");
+        assert_eq!(mr.status, MergeStatus::Closed);
+        assert!(mr.close().is_err());
+
+        mr.reopen().unwrap();
+        mr.merge(None).unwrap();
+        assert!(mr.reopen().is_err());
+        assert!(mr.close().is_err());
+        assert_eq!(mr.status, MergeStatus::Merged);
+    }
+}
@@ -0,0 +1,53 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use callisto::mega_reflog;
+use common::utils::generate_id;
+
+/// A single change of a monorepo ref, recorded every time the ref is saved or updated.
+/// `old_hash` is `ZERO_ID` when the ref was created.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReflogEntry {
+    pub ref_name: String,
+    pub old_hash: String,
+    pub new_hash: String,
+    pub timestamp: NaiveDateTime,
+    pub reason: String,
+}
+
+impl ReflogEntry {
+    pub fn new(ref_name: &str, old_hash: &str, new_hash: &str, reason: &str) -> Self {
+        Self {
+            ref_name: ref_name.to_owned(),
+            old_hash: old_hash.to_owned(),
+            new_hash: new_hash.to_owned(),
+            timestamp: chrono::Utc::now().naive_utc(),
+            reason: reason.to_owned(),
+        }
+    }
+}
+
+impl From<ReflogEntry> for mega_reflog::Model {
+    fn from(value: ReflogEntry) -> Self {
+        Self {
+            id: generate_id(),
+            ref_name: value.ref_name,
+            old_hash: value.old_hash,
+            new_hash: value.new_hash,
+            reason: value.reason,
+            created_at: value.timestamp,
+        }
+    }
+}
+
+impl From<mega_reflog::Model> for ReflogEntry {
+    fn from(value: mega_reflog::Model) -> Self {
+        Self {
+            ref_name: value.ref_name,
+            old_hash: value.old_hash,
+            new_hash: value.new_hash,
+            timestamp: value.created_at,
+            reason: value.reason,
+        }
+    }
+}
@@ -0,0 +1,9 @@
+use serde::{Deserialize, Serialize};
+
+/// How much the monorepo takes up in storage, e.g. to enforce quotas.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepoSize {
+    pub object_count: i64,
+    /// The size of the stored commits, trees and blobs.
+    pub stored_bytes: i64,
+}
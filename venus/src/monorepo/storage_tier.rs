@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+use callisto::db_enums::StorageType;
+
+/// Where the content of a blob is kept. Hot blobs live in the database next to their metadata,
+/// cold ones in the raw object storage, which is cheaper but slower to read from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StorageTier {
+    Hot,
+    Cold,
+}
+
+impl From<&StorageType> for StorageTier {
+    fn from(value: &StorageType) -> Self {
+        match value {
+            StorageType::Database => StorageTier::Hot,
+            StorageType::LocalFs | StorageType::RemoteUrl => StorageTier::Cold,
+        }
+    }
+}
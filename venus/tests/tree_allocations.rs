@@ -0,0 +1,78 @@
+//! Counting allocations needs a global allocator, which would count in every other test of the
+//! crate too, so these tests get a binary of their own.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use venus::hash::SHA1;
+use venus::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+
+/// Counts the allocations made by the current thread, so tests running in parallel don't
+/// disturb each other.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|x| x.set(x.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|x| x.set(x.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(|x| x.get())
+}
+
+#[test]
+fn test_to_bytes_into_reuses_buffer() {
+    let trees: Vec<Tree> = (0..1000)
+        .map(|i| {
+            let items = (0..16)
+                .map(|j| {
+                    TreeItem::new(
+                        TreeItemMode::Blob,
+                        SHA1::new(&format!("{}-{}", i, j).into_bytes()),
+                        format!("file-{}.rs", j),
+                    )
+                })
+                .collect();
+            Tree::from_tree_items(items).unwrap()
+        })
+        .collect();
+
+    let before = allocations();
+    let mut total = 0;
+    for tree in &trees {
+        total += tree.to_data().unwrap().len();
+    }
+    let fresh = allocations() - before;
+
+    let mut buf = Vec::new();
+    let before = allocations();
+    let mut reused_total = 0;
+    for tree in &trees {
+        buf.clear();
+        tree.to_bytes_into(&mut buf);
+        reused_total += buf.len();
+    }
+    let reused = allocations() - before;
+
+    assert_eq!(total, reused_total);
+    assert!(fresh >= trees.len());
+    // only the first tree(s) grow the buffer
+    assert!(reused < 10, "{} allocations with a reused buffer", reused);
+}